@@ -663,7 +663,7 @@ fn create_tokenizer_json(path: String) -> Result<(), MyError> {
 /// run llm
 async fn run_llm(messages: Vec<ChatMessage>) -> Result<String, MyError> {
     // 获取指定模型的api-key
-    let (api_key, endpoint, model, thinking) = PARAS.api.get_model_by_usize(17)?;
+    let (api_key, endpoint, model, thinking) = PARAS.snapshot().api.get_model_by_usize(17)?;
     // 使用api key初始化
     let mut client = Client::new(api_key.clone());
     client.set_base_url(&endpoint);
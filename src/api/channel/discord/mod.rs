@@ -40,6 +40,10 @@ use tracing::{event, Level};
 use crate::{
     parse_paras::PARAS,
     channel::Channel,
+    tools::pending_approval::{
+        answer,
+        list,
+    },
 };
 
 mod commands;
@@ -351,6 +355,68 @@ async fn role_id(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
     Ok(())
 }
 
+// ------------------------------approval------------------------------
+// 查看并回答等待确认的工具调用，只有bot的所有者可以调用：
+// 输入`~approvals`，bot回复所有等待确认的工具调用
+// 输入`~approve id`、`~deny id`、`~skip id`，允许、拒绝、跳过指定的工具调用，`~skip id, 新指示`跳过并给出新指示
+#[group]
+#[owners_only]
+#[summary = "Answer pending tool approvals"] // Summary only appears when listing multiple groups.
+#[commands(approvals, approve, deny, skip)]
+struct Approval;
+
+#[command]
+#[description("List pending tool approvals.")]
+async fn approvals(ctx: &Context, msg: &Message) -> CommandResult {
+    let pending = list(None);
+    if pending.is_empty() {
+        msg.channel_id.say(&ctx.http, "No pending approvals.").await?;
+    }
+    // 每个确认单独发送，discord每条信息最多2000个字符
+    for p in pending {
+        let response = MessageBuilder::new()
+            .push_bold_line_safe(format!("{} · {} · {}", p.id, p.tool, p.time))
+            .push_line_safe(format!("uuid: {}, user: {}", p.uuid, p.user))
+            .push_codeblock_safe(p.message.chars().take(1500).collect::<String>(), None)
+            .build();
+        msg.channel_id.say(&ctx.http, &response).await?;
+    }
+    Ok(())
+}
+
+#[command]
+#[description("Allow a pending tool call: `~approve id`")]
+async fn approve(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let id = args.single::<String>()?;
+    answer_approval(ctx, msg, &id, "true").await
+}
+
+#[command]
+#[description("Deny a pending tool call, the answer stops: `~deny id`")]
+async fn deny(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let id = args.single::<String>()?;
+    answer_approval(ctx, msg, &id, "false").await
+}
+
+#[command]
+#[description("Skip a pending tool call, optionally with a new instruction: `~skip id` or `~skip id, instruction`")]
+async fn skip(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let id = args.single::<String>()?;
+    let instruction = args.rest().trim();
+    answer_approval(ctx, msg, &id, if instruction.is_empty() { "skip" } else { instruction }).await
+}
+
+/// 回答指定的确认，确认者为`discord:用户名`
+async fn answer_approval(ctx: &Context, msg: &Message, id: &str, decision: &str) -> CommandResult {
+    let reply = if answer(id, decision, &format!("discord:{}", msg.author.name)) {
+        format!("Approval `{}` answered: {}", id, decision)
+    } else {
+        format!("Approval `{}` was already answered or timed out.", id)
+    };
+    msg.reply(&ctx.http, reply).await?;
+    Ok(())
+}
+
 // ------------------------------helper------------------------------
 // 自定义帮助信息，当用户输入`~help`时要执行的代码
 #[help]
@@ -459,7 +525,8 @@ pub async fn run_discord_bot(token: &str) {
         .help(&MY_HELP) // 当用户输入`~help`时要执行的代码
         .group(&GENERAL_GROUP)
         .group(&MATH_GROUP)
-        .group(&OWNER_GROUP);
+        .group(&OWNER_GROUP)
+        .group(&APPROVAL_GROUP);
 
     framework.configure(
         Configuration::new().with_whitespace(true)
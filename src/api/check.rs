@@ -0,0 +1,483 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use ron::de::from_str;
+use serde_json::{Map, Value};
+use tokio::time::{timeout, Duration};
+use url::Url;
+
+use crate::{
+    parse_paras::{get_config_file, try_load_config, Para},
+    tools::{
+        check_json_schema,
+        command_policy::CommandPolicy,
+        http_tools::{render, interpolate_env, parse_json_path},
+    },
+    mcp::stdio::{StdIoServer, StdIoServers},
+    skills::{SkillManager, command_exists},
+};
+
+/// 启动mcp server并完成初始化的超时时间
+const MCP_INIT_TIMEOUT: u64 = 30;
+
+/// 记录检查到的问题，每个问题输出一行：`文件:行号: error/warning: 问题`
+struct Report {
+    errors:   usize,
+    warnings: usize,
+}
+
+impl Report {
+    fn new() -> Self {
+        Report{errors: 0, warnings: 0}
+    }
+
+    /// 错误，`chatsong check`最终返回非0
+    fn error(&mut self, file: &str, line: usize, info: impl Display) {
+        self.errors += 1;
+        if line > 0 {
+            println!("{}:{}: error: {}", file, line, info);
+        } else {
+            println!("{}: error: {}", file, info);
+        }
+    }
+
+    /// 警告，不影响返回值
+    fn warn(&mut self, file: &str, line: usize, info: impl Display) {
+        self.warnings += 1;
+        if line > 0 {
+            println!("{}:{}: warning: {}", file, line, info);
+        } else {
+            println!("{}: warning: {}", file, info);
+        }
+    }
+}
+
+/// `chatsong check`：检查参数文件、外部工具、mcp server、skills，逐项报告问题的文件和行号
+/// 有错误返回1，否则返回0
+pub fn check_config(config: Option<String>, skills: Option<String>) -> i32 {
+    let mut report = Report::new();
+    // 获取参数文件
+    let config_file = match get_config_file(config) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("{}", e);
+            return 1
+        },
+    };
+    let file = config_file.display().to_string();
+    let content = match read_to_string(&config_file) {
+        Ok(c) => c,
+        Err(e) => {
+            report.error(&file, 0, format!("read config file error: {}", e));
+            return 1
+        },
+    };
+    // 解析RON，错误信息中包含行号和列号
+    let para: Para = match from_str(&content) {
+        Ok(p) => p,
+        Err(e) => { // e中已包含`行号:列号`
+            println!("{}:{}", file, e);
+            println!("check {}: RON parse failed, the remaining checks were skipped", file);
+            return 1
+        },
+    };
+    check_models(&para, &content, &file, &mut report);
+    check_prompts(&para, &content, &file, &mut report);
+    check_allowed_path(&para, &content, &file, &mut report);
+    check_upload_limit(&para, &content, &file, &mut report);
+    check_memory(&para, &content, &file, &mut report);
+    check_sandbox(&para, &content, &file, &mut report);
+    check_command_policy(&para, &content, &file, &mut report);
+    check_approval_timeout(&para, &content, &file, &mut report);
+    check_external_tools(&para, &content, &file, &mut report);
+    check_http_tools(&para, &content, &file, &mut report);
+    check_openapi_tools(&para, &content, &file, &mut report);
+    check_mcp_servers(&para, &content, &file, &mut report);
+    // 命令行`-S`优先于参数文件中的skills_path
+    let (skills_path, from_config) = match skills.clone() {
+        Some(s) => (Some(s), false),
+        None => (para.skills_path.clone(), true),
+    };
+    if let Some(s) = skills_path {
+        let line = if from_config { find_line(&content, "skills_path", &s, 0) } else { 0 };
+        check_skills(&s, line, &file, &mut report);
+    }
+    // 逐项检查可能遗漏的问题，用启动服务时相同的流程再解析一次
+    if let Err(e) = try_load_config(&config_file, skills) {
+        report.error(&file, 0, format!("load config failed: {}", e));
+    }
+    println!("check {}: {} error(s), {} warning(s)", file, report.errors, report.warnings);
+    if report.errors > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+/// 检查模型：provider不能重复，只能有1个默认模型，api_key和endpoint不能为空
+fn check_models(para: &Para, content: &str, file: &str, report: &mut Report) {
+    if para.model_config.is_empty() {
+        report.error(file, find_line(content, "model_config", "", 0), "model_config is empty, at least one model is required");
+        return
+    }
+    let mut providers: HashMap<&str, usize> = HashMap::new(); // key: provider, value: 第几次出现，用于定位重复provider的行号
+    let mut defaults: Vec<(&str, usize)> = Vec::new(); // (模型名称, 行号)
+    let mut names: HashMap<&str, usize> = HashMap::new(); // key: 模型名称, value: 出现次数
+    let mut model_num = 0;
+    for c in &para.model_config {
+        let nth = providers.get(c.provider.as_str()).map(|n| n + 1).unwrap_or(0);
+        let line = find_line(content, "provider", &c.provider, nth);
+        if nth > 0 {
+            report.error(file, line, format!("duplicate provider \"{}\", first defined at line {}", c.provider, find_line(content, "provider", &c.provider, 0)));
+        }
+        providers.insert(&c.provider, nth);
+        if c.provider.trim().is_empty() {
+            report.error(file, line, "provider is empty");
+        }
+        if c.api_key.trim().is_empty() {
+            report.warn(file, line, format!("provider \"{}\" api_key is empty", c.provider));
+        }
+        if !(c.endpoint.starts_with("http://") || c.endpoint.starts_with("https://")) {
+            report.error(file, line, format!("provider \"{}\" endpoint must start with http:// or https://, not: \"{}\"", c.provider, c.endpoint));
+        }
+        if c.models.is_empty() {
+            report.warn(file, line, format!("provider \"{}\" has no models", c.provider));
+        }
+        for m in &c.models {
+            model_num += 1;
+            let n = names.entry(&m.name).or_insert(0);
+            let model_line = find_line(content, "name", &m.name, *n);
+            *n += 1;
+            if m.name.trim().is_empty() {
+                report.error(file, model_line, format!("provider \"{}\" has a model with empty name", c.provider));
+            } else if *n == 2 {
+                report.warn(file, model_line, format!("duplicate model name \"{}\", get model by name will always use the first one", m.name));
+            }
+            if m.is_default {
+                defaults.push((&m.name, model_line));
+            }
+            if let Some(image) = &m.image {
+                if image.max_width == 0 || image.max_height == 0 {
+                    report.error(file, model_line, format!("model \"{}\" image max_width and max_height must be greater than 0", m.name));
+                }
+            }
+        }
+    }
+    if model_num == 0 {
+        report.error(file, 0, "no model in model_config");
+    }
+    match defaults.len() {
+        0 => report.error(file, find_line(content, "model_config", "", 0), "no model set `is_default: true`, exactly one default model is required"),
+        1 => (),
+        _ => for (name, line) in &defaults[1..] {
+            report.error(file, *line, format!("the default model permits only one, \"{}\" is also default (first default: \"{}\" at line {})", name, defaults[0].0, defaults[0].1));
+        },
+    }
+}
+
+/// 检查prompts：名称不能为空，不能重复，默认模型必须存在
+fn check_prompts(para: &Para, content: &str, file: &str, report: &mut Report) {
+    let mut names: HashMap<&str, usize> = HashMap::new();
+    for p in &para.prompts {
+        let n = names.entry(&p.name).or_insert(0);
+        let line = find_line(content, "name", &p.name, *n);
+        *n += 1;
+        if p.name.trim().is_empty() {
+            report.warn(file, line, "prompt name is empty");
+        } else if *n == 2 {
+            report.warn(file, line, format!("duplicate prompt name \"{}\"", p.name));
+        }
+        if p.content.trim().is_empty() {
+            report.warn(file, line, format!("prompt \"{}\" content is empty", p.name));
+        }
+        if let Some(m) = &p.model {
+            if !para.model_config.iter().any(|c| c.models.iter().any(|cm| &cm.name == m)) {
+                report.warn(file, find_line(content, "model", m, 0), format!("prompt \"{}\" default model \"{}\" is not in model_config", p.name, m));
+            }
+        }
+    }
+}
+
+/// 检查上传文件的大小限制
+fn check_upload_limit(para: &Para, content: &str, file: &str, report: &mut Report) {
+    let limit = &para.upload_limit;
+    let line = find_line(content, "upload_limit", "", 0);
+    if limit.file_mb == 0 || limit.uuid_mb == 0 {
+        report.error(file, line, "upload_limit file_mb and uuid_mb must be greater than 0");
+    } else if limit.file_mb > limit.uuid_mb {
+        report.warn(file, line, format!("upload_limit file_mb ({}) is greater than uuid_mb ({}), a single file can not exceed uuid_mb", limit.file_mb, limit.uuid_mb));
+    }
+}
+
+/// 检查自动提取和整理记忆使用的模型
+fn check_memory(para: &Para, content: &str, file: &str, report: &mut Report) {
+    if let Some(m) = &para.memory.model {
+        if !para.model_config.iter().any(|c| c.models.iter().any(|cm| &cm.name == m)) {
+            report.error(file, find_line(content, "model", m, 0), format!("memory model \"{}\" is not in model_config", m));
+        }
+    }
+}
+
+/// 检查沙箱：只支持Linux，其他系统在沙箱中运行的命令都会报错
+fn check_sandbox(para: &Para, content: &str, file: &str, report: &mut Report) {
+    let sandbox = &para.sandbox;
+    if sandbox.tools.is_empty() && sandbox.users.is_empty() {
+        return
+    }
+    if !cfg!(target_os = "linux") {
+        report.error(file, find_line(content, "sandbox", "", 0), "sandbox is only supported on Linux, sandboxed commands will fail");
+    }
+}
+
+/// 检查命令策略文件能否解析
+fn check_command_policy(para: &Para, content: &str, file: &str, report: &mut Report) {
+    if let Some(f) = &para.command_policy {
+        match CommandPolicy::from_file(f) {
+            Ok(p) if p.is_empty() => report.warn(file, find_line(content, "command_policy", f, 0), format!("command policy file {} has no rules", f)),
+            Ok(_) => (),
+            Err(e) => report.error(file, find_line(content, "command_policy", f, 0), e),
+        }
+    }
+}
+
+/// 检查等待确认超时后的默认操作
+fn check_approval_timeout(para: &Para, content: &str, file: &str, report: &mut Report) {
+    let action = &para.approval_timeout.default_action;
+    if !["deny", "skip", "allow"].contains(&action.as_str()) {
+        report.error(file, find_line(content, "default_action", action, 0), format!("approval_timeout default_action must be deny, skip or allow, not \"{}\"", action));
+    }
+}
+
+/// 检查allowed_path是否存在
+fn check_allowed_path(para: &Para, content: &str, file: &str, report: &mut Report) {
+    for p in para.allowed_path.split(",").map(|p| p.trim()).filter(|p| !p.is_empty()) {
+        if !Path::new(p).exists() {
+            report.warn(file, find_line(content, "allowed_path", "", 0), format!("allowed path does not exist: {}", p));
+        }
+    }
+}
+
+/// 检查外部工具：名称、程序是否存在、schema是否有效
+fn check_external_tools(para: &Para, content: &str, file: &str, report: &mut Report) {
+    let mut names: HashMap<&str, usize> = HashMap::new();
+    for t in &para.external_tools {
+        let n = names.entry(&t.name).or_insert(0);
+        let line = find_line(content, "name", &t.name, *n);
+        *n += 1;
+        if *n == 2 {
+            report.error(file, line, format!("duplicate external tool name \"{}\"", t.name));
+        }
+        check_tool_name(&t.name, line, file, report);
+        if !program_exists(&t.command) {
+            report.error(file, find_line(content, "command", &t.command, 0), format!("external tool \"{}\" command not found: {}", t.name, t.command));
+        }
+        if t.description.trim().is_empty() {
+            report.warn(file, line, format!("external tool \"{}\" description is empty", t.name));
+        }
+        for problem in check_json_schema(&t.schema) {
+            report.error(file, line, format!("external tool \"{}\" {}", t.name, problem));
+        }
+        if !["", "args", "json"].contains(&t.protocol.as_str()) {
+            report.error(file, line, format!("external tool \"{}\" protocol must be \"args\" or \"json\", not \"{}\"", t.name, t.protocol));
+        }
+        if let Some(dir) = &t.working_dir {
+            if !Path::new(dir).is_dir() {
+                report.error(file, find_line(content, "working_dir", dir, 0), format!("external tool \"{}\" working_dir does not exist: {}", t.name, dir));
+            }
+        }
+        // 外部工具的参数转为`--key value`，只支持string、number、boolean，`protocol: "json"`通过stdin传入json则没有限制
+        if t.protocol == "json" {
+            continue
+        }
+        if let Some(Value::Object(props)) = t.schema.get("properties") {
+            for (k, v) in props {
+                if let Some(Value::String(ty)) = v.get("type") {
+                    if ty == "array" || ty == "object" {
+                        report.warn(file, line, format!("external tool \"{}\" parameter \"{}\" is {}, only string, number and boolean can be passed as `--{} value`", t.name, k, ty, k));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 检查HTTP工具：名称、请求方法、url、环境变量、json_path、schema是否有效
+fn check_http_tools(para: &Para, content: &str, file: &str, report: &mut Report) {
+    let mut names: HashMap<&str, usize> = HashMap::new();
+    for t in &para.http_tools {
+        let n = names.entry(&t.name).or_insert(0);
+        let line = find_line(content, "name", &t.name, *n);
+        *n += 1;
+        if *n == 2 {
+            report.error(file, line, format!("duplicate http tool name \"{}\"", t.name));
+        }
+        check_tool_name(&t.name, line, file, report);
+        if t.description.trim().is_empty() {
+            report.warn(file, line, format!("http tool \"{}\" description is empty", t.name));
+        }
+        for problem in check_json_schema(&t.schema) {
+            report.error(file, line, format!("http tool \"{}\" {}", t.name, problem));
+        }
+        if let Err(e) = t.method() {
+            report.error(file, line, e);
+        }
+        // 参数用占位值替换，环境变量没有设置只警告，运行时设置即可
+        let url = render(&t.url, &Map::new(), &mut Map::new(), |_| "1".to_string());
+        match interpolate_env(&url) {
+            Ok(u) => if let Err(e) = Url::parse(&u) {
+                report.error(file, find_line(content, "url", &t.url, 0), format!("http tool \"{}\" url `{}` error: {}", t.name, t.url, e));
+            },
+            Err(e) => report.warn(file, line, e),
+        }
+        for v in t.headers.values().chain(t.body.iter()) {
+            if let Err(e) = interpolate_env(v) {
+                report.warn(file, line, e);
+            }
+        }
+        if let Some(p) = &t.json_path {
+            if let Err(e) = parse_json_path(p) {
+                report.error(file, find_line(content, "json_path", p, 0), e);
+            }
+        }
+    }
+}
+
+/// 检查OpenAPI spec：文件能否解析，按tag筛选后是否还有operation，生成的schema是否有效
+fn check_openapi_tools(para: &Para, content: &str, file: &str, report: &mut Report) {
+    let mut groups: HashMap<String, usize> = HashMap::new();
+    for s in &para.openapi_tools {
+        let line = find_line(content, "spec", &s.spec, 0);
+        match s.to_http_tools() {
+            Ok(tools) => {
+                if tools.is_empty() {
+                    report.warn(file, line, format!("openapi spec {} has no operation matching the tag filters", s.spec));
+                }
+                if let Some(t) = tools.first() {
+                    let n = groups.entry(t.group.clone()).or_insert(0);
+                    *n += 1;
+                    if *n == 2 {
+                        report.error(file, line, format!("duplicate openapi tool group \"{}\", set a different `name`", t.group));
+                    }
+                }
+                for t in &tools {
+                    for problem in check_json_schema(&t.schema) {
+                        report.warn(file, line, format!("openapi tool \"{}\" {}", t.name, problem));
+                    }
+                }
+            },
+            Err(e) => report.error(file, line, e),
+        }
+        for v in s.headers.values() {
+            if let Err(e) = interpolate_env(v) {
+                report.warn(file, line, e);
+            }
+        }
+    }
+}
+
+/// 检查mcp server：程序是否存在，能否完成initialize，返回的tool schema是否有效
+fn check_mcp_servers(para: &Para, content: &str, file: &str, report: &mut Report) {
+    let mut commands: HashMap<&str, usize> = HashMap::new();
+    for server in &para.mcp_servers {
+        let n = commands.entry(&server.command).or_insert(0);
+        let line = find_line(content, "command", &server.command, *n);
+        *n += 1;
+        if !program_exists(&server.command) {
+            report.error(file, line, format!("MCP server command not found: {}", server.command));
+            continue
+        }
+        match init_mcp_server(server.clone()) {
+            Ok(stdio) => {
+                for (_, s) in stdio.id_map.values() {
+                    println!("{}:{}: ok: MCP server \"{}\" initialized (protocol version {}), {} tools", file, line, s.name, s.protocol_version, stdio.tools.len());
+                }
+                for tool in &stdio.tools {
+                    for problem in check_json_schema(&tool.schema) {
+                        report.warn(file, line, format!("MCP server \"{}\" tool \"{}\" {}", tool.server_name, tool.name, problem));
+                    }
+                }
+                if stdio.tools.is_empty() {
+                    report.warn(file, line, format!("MCP server {} has no tools", server.command));
+                }
+            },
+            Err(e) => report.error(file, line, format!("MCP server {} {} initialize failed: {}", server.command, server.args.join(" "), e)),
+        }
+    }
+}
+
+/// 启动mcp server，完成initialize并获取tools后关闭
+/// 和parse_para一样在单独的线程中创建runtime
+fn init_mcp_server(server: StdIoServer) -> Result<StdIoServers, String> {
+    let handle = thread::spawn(|| {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            match timeout(Duration::from_secs(MCP_INIT_TIMEOUT), StdIoServers::new(vec![server], None)).await {
+                Ok(Ok(stdio)) => {
+                    stdio.close_all().await;
+                    Ok(stdio)
+                },
+                Ok(Err(e)) => Err(e.to_string()),
+                Err(_) => Err(format!("timeout after {} seconds", MCP_INIT_TIMEOUT)),
+            }
+        })
+    });
+    handle.join().unwrap_or_else(|_| Err("MCP initialize thread panicked".to_string()))
+}
+
+/// 检查skills路径下的每个SKILL.md
+fn check_skills(skills_path: &str, line: usize, file: &str, report: &mut Report) {
+    let path = PathBuf::from(skills_path);
+    if !(path.exists() && path.is_dir()) {
+        report.warn(file, line, format!("skills path does not exist: {}", skills_path));
+        return
+    }
+    for (md, md_line, info, is_error) in SkillManager::from_skills_dir(path).check_skills() {
+        let md = md.display().to_string();
+        if is_error {
+            report.error(&md, md_line, info);
+        } else {
+            report.warn(&md, md_line, info);
+        }
+    }
+}
+
+/// 检查tool名称：只能包含字母、数字、`_`、`-`，不能包含`__`（`__`用于拼接`name__id`），长度不超过26
+fn check_tool_name(name: &str, line: usize, file: &str, report: &mut Report) {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        report.error(file, line, format!("tool name \"{}\" can only contain a-z, A-Z, 0-9, `_` and `-`", name));
+    }
+    if name.contains("__") {
+        report.error(file, line, format!("tool name \"{}\" must not contain `__`", name));
+    }
+    if name.chars().count() > 26 {
+        report.error(file, line, format!("tool name \"{}\" length must <= 26", name));
+    }
+}
+
+/// 判断程序是否存在，含有路径分隔符则检查文件，否则从`PATH`中查找
+fn program_exists(command: &str) -> bool {
+    if command.contains('/') || command.contains('\\') {
+        Path::new(command).is_file()
+    } else {
+        command_exists(command)
+    }
+}
+
+/// 获取content中第nth（从0开始）个同时包含`key:`和`"value"`的行号（从1开始），value为空则只匹配key，没找到返回0
+fn find_line(content: &str, key: &str, value: &str, nth: usize) -> usize {
+    let key = format!("{}:", key);
+    let value = format!("\"{}\"", value);
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| {
+            let l = l.split("//").next().unwrap_or_default(); // 去除注释
+            l.contains(&key) && (value == "\"\"" || l.contains(&value))
+        })
+        .nth(nth)
+        .map(|(i, _)| i + 1)
+        .unwrap_or(0)
+}
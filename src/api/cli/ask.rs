@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::fs::{copy, remove_dir_all};
+use std::io::{read_to_string, stdin, IsTerminal};
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{
+    info::{
+        contain_uuid,
+        set_incognito,
+        get_token,
+        get_context_token,
+        get_latest_answer,
+        find_uuid_by_chat_name,
+        set_knowledge,
+    },
+    knowledge::contain_base,
+    parse_paras::{PARAS, AskCmd},
+    error::MyError,
+    tools::{
+        SelectedTools,
+        approval_policy::{
+            ApprovalPolicy,
+            set_policy,
+            remove_policy,
+        },
+    },
+    skills::SelectedSkills,
+    ctrlc::save_before_exit,
+    api::handlers::upload::insert_file_message,
+    cli::{
+        CliModel,
+        resolve_tools,
+        resolve_skills,
+        resolve_prompt,
+        prompt_defaults,
+        new_chat,
+        insert_query,
+        start_answer,
+    },
+};
+
+/// 退出码
+const EXIT_OK: i32 = 0;
+const EXIT_ARGS: i32 = 1;     // 参数错误
+const EXIT_API: i32 = 2;      // 调用模型api出错
+const EXIT_TOOL: i32 = 3;     // 调用tool出错
+const EXIT_APPROVAL: i32 = 4; // 拒绝调用tool
+const EXIT_CONFIG: i32 = 5;   // 参数文件或配置错误
+const EXIT_IO: i32 = 6;       // 读写文件出错
+const EXIT_INTERNAL: i32 = 7; // 回答任务panic或被取消
+
+/// `--json`输出的结果
+#[derive(Serialize)]
+struct AskResult {
+    uuid:      String,
+    model:     String,
+    answer:    Option<String>,
+    usage:     Usage,
+    approvals: Vec<Approval>,
+    error:     Option<String>,
+    #[serde(skip)]
+    code:      i32,
+    #[serde(skip)]
+    saved:     bool,
+}
+
+/// 本次提问的token数
+#[derive(Serialize)]
+struct Usage {
+    input:   u32,
+    output:  u32,
+    context: u32,
+}
+
+/// tool调用的确认结果
+#[derive(Serialize)]
+struct Approval {
+    tool:     String,
+    decision: String, // true, false, skip
+}
+
+/// `chatsong ask`，非交互提问一次，问题来自参数和stdin，回答输出到stdout，错误信息输出到stderr
+/// tool调用的确认由`--approval`或`--policy`决定，不指定`-n`则不保存该对话
+pub async fn run_ask(cmd: &AskCmd) -> i32 {
+    let code = match ask(cmd).await {
+        Ok(result) => {
+            if cmd.json {
+                match serde_json::to_string_pretty(&result) {
+                    Ok(s) => println!("{}", s),
+                    Err(e) => eprintln!("{}", e),
+                }
+            } else if let Some(e) = &result.error {
+                eprintln!("{}", e);
+            } else if let Some(answer) = &result.answer {
+                println!("{}", answer);
+            }
+            if !result.saved {
+                // 不保存的对话，删除uuid文件夹，包括复制的附件
+                let _ = remove_dir_all(format!("{}/{}", PARAS.outpath, result.uuid));
+            }
+            result.code
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            if is_io_error(&e) { EXIT_IO } else { EXIT_ARGS }
+        },
+    };
+    save_before_exit().await;
+    code
+}
+
+/// 准备问题和附件，调用模型回答，返回回答结果，参数错误则返回Err
+async fn ask(cmd: &AskCmd) -> Result<AskResult, MyError> {
+    let prompt = match &cmd.prompt {
+        Some(p) => Some(resolve_prompt(p)?),
+        None => None,
+    };
+    // 没有指定`-m`和`-t`则使用prompt的默认模型和tools
+    let (model, tools) = prompt_defaults(&prompt, &cmd.model, &cmd.tools);
+    let cli_model = CliModel::new(&model)?;
+    let selected_tools: Option<SelectedTools> = match &tools {
+        Some(t) => resolve_tools(t)?,
+        None => None,
+    };
+    let selected_skills: Option<SelectedSkills> = match &cmd.skills {
+        Some(s) => resolve_skills(s)?,
+        None => None,
+    };
+    let policy = match &cmd.policy {
+        Some(f) => ApprovalPolicy::from_file(f)?,
+        None => ApprovalPolicy::all(&cmd.approval)?,
+    };
+    let query = read_query(&cmd.question)?;
+    let vars = parse_vars(&cmd.var)?;
+    // 指定了对话名称则继续该对话，不存在则创建
+    let uuid = match cmd.name.as_ref().and_then(|n| find_uuid_by_chat_name(n)) {
+        Some(u) if contain_uuid(&u) => {
+            if prompt.is_some() {
+                eprintln!("continue the chat {}, ignore the prompt", u);
+            }
+            u
+        },
+        _ => new_chat(&prompt, &cli_model.model, &vars)?,
+    };
+    // 附件复制到uuid文件夹，与页面上传文件相同
+    for file in &cmd.attach {
+        let name = match Path::new(file).file_name() {
+            Some(n) => n.to_string_lossy().to_string(),
+            None => return Err(MyError::ParaError{para: format!("invalid attachment: {}", file)}),
+        };
+        copy(file, format!("{}/{}/{}", PARAS.outpath, uuid, name)).map_err(|e| MyError::ReadFileError{file: file.clone(), error: e})?;
+        insert_file_message(&uuid, &name, &cli_model.model, &query).await?; // 代码压缩包按与问题的相关性选取文件
+    }
+    // 指定的知识库，插入问题时检索
+    if let Some(k) = &cmd.knowledge {
+        let names: Vec<String> = k.split(',').map(|n| n.trim().to_string()).filter(|n| !n.is_empty()).collect();
+        if let Some(n) = names.iter().find(|n| !contain_base(n)) {
+            return Err(MyError::ParaError{para: format!("no such knowledge base: {}", n)})
+        }
+        set_knowledge(&uuid, names);
+    }
+    // 当前问题插入到messages中
+    insert_query(&uuid, query, &cli_model.model, &vars, cmd.name.clone()).await;
+    if cmd.name.is_none() {
+        set_incognito(&uuid); // 无痕模式，退出时不保存
+    }
+    // 开始回答，调用tool时按确认规则决定，不等待确认
+    let use_tools = selected_tools.is_some() || selected_skills.is_some();
+    let token_before = get_token(&uuid);
+    set_policy(&uuid, policy);
+    let (mut receiver, handle) = start_answer(&uuid, &cli_model, selected_tools, selected_skills, cmd.plan);
+    while receiver.recv().await.is_some() {} // 回答记录在服务端，这里不需要流式输出
+    let result = handle.await;
+    let approvals = remove_policy(&uuid).into_iter().map(|(tool, decision)| Approval{tool, decision}).collect();
+    let token_after = get_token(&uuid);
+    let context_token = get_context_token(&uuid);
+    let (answer, error, code) = match result {
+        Ok(Ok(_)) => (Some(get_latest_answer(&uuid).unwrap_or_default()), None, EXIT_OK),
+        Ok(Err(e)) => (None, Some(format!("{}", e)), exit_code(&e, use_tools)),
+        Err(e) => (None, Some(format!("answer task error: {}", e)), EXIT_INTERNAL),
+    };
+    Ok(AskResult{
+        uuid,
+        model: cli_model.model,
+        answer,
+        usage: Usage{
+            input:   token_after[0].saturating_sub(token_before[0]),
+            output:  token_after[1].saturating_sub(token_before[1]),
+            context: context_token,
+        },
+        approvals,
+        error,
+        code,
+        saved: cmd.name.is_some(),
+    })
+}
+
+/// 参数中的问题在前，stdin的内容在后，stdin是终端则不读取
+fn read_query(question: &[String]) -> Result<String, MyError> {
+    let mut query = question.join(" ");
+    if !stdin().is_terminal() {
+        let input = read_to_string(stdin()).map_err(|e| MyError::ParaError{para: format!("read stdin error: {}", e)})?;
+        if !input.trim().is_empty() {
+            if !query.is_empty() {
+                query += "\n\n";
+            }
+            query += &input;
+        }
+    }
+    if query.trim().is_empty() {
+        Err(MyError::ParaError{para: "no question, pass it as arguments or stdin".to_string()})
+    } else {
+        Ok(query)
+    }
+}
+
+/// 解析`--var key=value`指定的模板变量
+fn parse_vars(var: &[String]) -> Result<HashMap<String, String>, MyError> {
+    let mut vars: HashMap<String, String> = HashMap::new();
+    for v in var {
+        match v.split_once('=') {
+            Some((key, value)) => {
+                vars.insert(key.trim().to_string(), value.to_string());
+            },
+            None => return Err(MyError::ParaError{para: format!("invalid template variable: {}, must be key=value", v)}),
+        }
+    }
+    Ok(vars)
+}
+
+/// 根据错误类型返回退出码
+fn exit_code(e: &MyError, use_tools: bool) -> i32 {
+    match e {
+        MyError::ApprovalDeniedError{..} => EXIT_APPROVAL,
+        MyError::ApiError{..} | MyError::ChatCompletionError{..} | MyError::SendRequestError{..} => EXIT_API,
+        MyError::ParaError{..} => EXIT_CONFIG,
+        _ if is_io_error(e) => EXIT_IO,
+        _ if use_tools => EXIT_TOOL,
+        _ => EXIT_API,
+    }
+}
+
+/// 是否为读写文件的错误
+fn is_io_error(e: &MyError) -> bool {
+    matches!(e,
+        MyError::ReadFileError{..} | MyError::OpenFileError{..} | MyError::CreateFileError{..} | MyError::CreateDirAllError{..} |
+        MyError::WriteFileError{..} | MyError::LinesError{..} | MyError::ReadDirError{..} | MyError::RemoveDirError{..} |
+        MyError::RemoveFileError{..} | MyError::DirNotExistError{..} | MyError::FileNotExistError{..}
+    )
+}
@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+use std::io::{stdin, stdout, IsTerminal, Write};
+
+use serde_json::Value;
+use tokio::task::spawn_blocking;
+
+use crate::{
+    info::{
+        contain_uuid,
+        get_log_for_display,
+        save_log,
+    },
+    parse_paras::{PARAS, ChatCmd},
+    error::MyError,
+    tools::{
+        SelectedTools,
+        pending_approval::answer_uuid,
+    },
+    skills::SelectedSkills,
+    ctrlc::save_before_exit,
+    cli::{
+        CliModel,
+        resolve_tools,
+        resolve_skills,
+        resolve_prompt,
+        prompt_defaults,
+        new_chat,
+        insert_query,
+        start_answer,
+        parse_sse,
+    },
+};
+
+/// 终端颜色
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const CYAN: &str = "\x1b[36m";
+const YELLOW: &str = "\x1b[33m";
+const GREEN: &str = "\x1b[32m";
+
+/// `chatsong chat`，在终端对话，与页面使用相同的参数文件、tools、skills、MCP servers，chat记录同样保存在`输出路径/uuid`
+/// 输入`/exit`退出，`/new`开启新对话，`/uuid`显示当前uuid，行尾`\`表示继续输入下一行
+pub async fn run_chat(cmd: &ChatCmd) -> i32 {
+    let code = match chat_loop(cmd).await {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        },
+    };
+    save_before_exit().await;
+    code
+}
+
+/// 读取输入，调用模型，显示回答，直到输入`/exit`或EOF
+async fn chat_loop(cmd: &ChatCmd) -> Result<(), MyError> {
+    let prompt = match &cmd.prompt {
+        Some(p) => Some(resolve_prompt(p)?),
+        None => None,
+    };
+    // 没有指定`-m`和`-t`则使用prompt的默认模型和tools
+    let (model, tools) = prompt_defaults(&prompt, &cmd.model, &cmd.tools);
+    let cli_model = CliModel::new(&model)?;
+    let selected_tools: Option<SelectedTools> = match &tools {
+        Some(t) => resolve_tools(t)?,
+        None => None,
+    };
+    let selected_skills: Option<SelectedSkills> = match &cmd.skills {
+        Some(s) => resolve_skills(s)?,
+        None => None,
+    };
+    let color = stdout().is_terminal();
+    // 继续之前的对话，或开启新对话
+    let mut uuid = match &cmd.uuid {
+        Some(u) => {
+            if !contain_uuid(u) {
+                return Err(MyError::ParaError{para: format!("no chat log of uuid {} in {}", u, PARAS.outpath)})
+            }
+            show_history(u, color);
+            u.clone()
+        },
+        None => new_chat(&prompt, &cli_model.model, &HashMap::new())?,
+    };
+    println!("{}", paint(&format!("uuid: {}, model: {}, /exit to quit, /new to start a new chat", uuid, cli_model.model), DIM, color));
+    loop {
+        let query = match read_query(&paint("> ", GREEN, color)).await {
+            Some(q) => q,
+            None => break, // EOF
+        };
+        match query.trim() {
+            "" => continue,
+            "/exit" | "/quit" => break,
+            "/uuid" => {
+                println!("{}", uuid);
+                continue
+            },
+            "/new" => {
+                uuid = new_chat(&prompt, &cli_model.model, &HashMap::new())?;
+                println!("{}", paint(&format!("uuid: {}", uuid), DIM, color));
+                continue
+            },
+            _ => (),
+        }
+        // 当前问题插入到messages中
+        insert_query(&uuid, query, &cli_model.model, &HashMap::new(), None).await;
+        // 开始回答
+        let (mut receiver, handle) = start_answer(&uuid, &cli_model, selected_tools.clone(), selected_skills.clone(), cmd.plan);
+        let mut render = MarkdownRender::new(color);
+        while let Some(bytes) = receiver.recv().await {
+            let Some((event_name, data)) = parse_sse(&bytes) else {
+                continue
+            };
+            match event_name.as_str() {
+                "maindata" => {
+                    if let Some(approval) = data["approval"].as_str() {
+                        render.flush();
+                        let answer = ask_approval(&approval.replace("srxtzn", "\n"), data["diff"].as_bool().unwrap_or(false), color).await;
+                        if !answer_uuid(&uuid, &answer, "cli") {
+                            eprintln!("{}", paint("approval was already answered elsewhere or timed out", YELLOW, color));
+                        }
+                    } else if data["is_left"].as_bool().unwrap_or(false) {
+                        if data["is_img"].as_bool().unwrap_or(false) {
+                            render.flush();
+                            println!("{}", paint("[image]", DIM, color));
+                        } else if let Some(content) = data["content"].as_str() {
+                            render.push(content);
+                        }
+                    }
+                },
+                "metadata" => {
+                    render.flush();
+                    show_token(&data, color);
+                },
+                _ => (),
+            }
+        }
+        render.flush();
+        match handle.await {
+            Ok(Ok(_)) => (),
+            Ok(Err(e)) => eprintln!("{}", paint(&format!("{}", e), YELLOW, color)),
+            Err(e) => eprintln!("{}", paint(&format!("answer task error: {}", e), YELLOW, color)),
+        }
+        // 每次回答后保存chat记录，终端被关闭或程序被强制结束时不会丢失之前的对话
+        save_log(&uuid);
+    }
+    Ok(())
+}
+
+/// 读取一行输入，行尾`\`则继续读取下一行，EOF返回None
+async fn read_query(prompt: &str) -> Option<String> {
+    let mut query = String::new();
+    let mut current_prompt = prompt.to_string();
+    loop {
+        let line = read_line(&current_prompt).await?;
+        match line.strip_suffix('\\') {
+            Some(l) => {
+                query += l;
+                query += "\n";
+                current_prompt = "  ".to_string();
+            },
+            None => {
+                query += &line;
+                return Some(query)
+            },
+        }
+    }
+}
+
+/// 显示提示符并读取一行，去除末尾换行符，EOF或出错返回None
+async fn read_line(prompt: &str) -> Option<String> {
+    print!("{}", prompt);
+    let _ = stdout().flush();
+    spawn_blocking(|| {
+        let mut line = String::new();
+        match stdin().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim_end_matches(['\n', '\r']).to_string()),
+        }
+    }).await.ok().flatten()
+}
+
+/// 显示调用tool前的确认信息，返回`true`（允许）、`false`（拒绝）、`skip`（跳过）或新的指示，与页面弹窗相同
+async fn ask_approval(msg: &str, is_diff: bool, color: bool) -> String {
+    println!();
+    if is_diff {
+        for line in msg.lines() {
+            if line.starts_with('+') {
+                println!("{}", paint(line, GREEN, color));
+            } else if line.starts_with('-') {
+                println!("{}", paint(line, YELLOW, color));
+            } else {
+                println!("{}", line);
+            }
+        }
+    } else {
+        println!("{}", paint(msg, BOLD, color));
+    }
+    let prompt = if PARAS.english {
+        "[y] allow / [n] deny / [s] skip / or type a new instruction: "
+    } else {
+        "[y] 允许 / [n] 拒绝 / [s] 跳过 / 或输入新的指示: "
+    };
+    match read_line(&paint(prompt, CYAN, color)).await {
+        Some(a) => match a.trim().to_lowercase().as_str() {
+            "y" | "yes" => "true".to_string(),
+            "" | "n" | "no" => "false".to_string(),
+            "s" | "skip" => "skip".to_string(),
+            _ => a.trim().to_string(),
+        },
+        None => "false".to_string(), // EOF视为拒绝
+    }
+}
+
+/// 继续之前的对话时，先显示之前的问答记录
+fn show_history(uuid: &str, color: bool) {
+    for log in get_log_for_display(uuid, false).3 {
+        if log.is_query {
+            println!("{}{}", paint("> ", GREEN, color), log.content.replace("srxtzn", "\n"));
+        } else if log.is_img {
+            println!("{}", paint("[image]", DIM, color));
+        } else {
+            let mut render = MarkdownRender::new(color);
+            render.push(&log.content);
+            render.flush();
+        }
+    }
+}
+
+/// 回答结束后显示token数
+fn show_token(data: &Value, color: bool) {
+    let token = format!("in: {}, out: {}, context: {}", data["in_token"], data["out_token"], data["context_token"]);
+    println!("{}", paint(&token, DIM, color));
+}
+
+/// 终端支持颜色时加上颜色
+fn paint(s: &str, style: &str, color: bool) -> String {
+    if color {
+        format!("{}{}{}", style, s, RESET)
+    } else {
+        s.to_string()
+    }
+}
+
+/// 流式输出的markdown渲染，收到完整一行后渲染并输出，未完成的行先缓存
+struct MarkdownRender {
+    buf:      String, // 未完成的行
+    in_code:  bool,   // 是否在代码块内
+    in_think: bool,   // 是否在思考内容内
+    color:    bool,   // 终端是否支持颜色
+}
+
+impl MarkdownRender {
+    fn new(color: bool) -> Self {
+        MarkdownRender{buf: String::new(), in_code: false, in_think: false, color}
+    }
+
+    /// 加入页面格式的内容，`srxtzn`和`<br>`是换行
+    fn push(&mut self, content: &str) {
+        self.buf += &content.replace("srxtzn", "\n").replace("<br>", "\n");
+        while let Some(pos) = self.buf.find('\n') {
+            let line: String = self.buf.drain(..=pos).collect();
+            let line = self.render_line(line.trim_end_matches('\n'));
+            println!("{}", line);
+        }
+        let _ = stdout().flush();
+    }
+
+    /// 输出缓存的未完成的行
+    fn flush(&mut self) {
+        if !self.buf.is_empty() {
+            let line = std::mem::take(&mut self.buf);
+            println!("{}", self.render_line(&line));
+        }
+    }
+
+    /// 渲染一行：标题、代码块、列表、引用、分割线、加粗、行内代码，以及页面使用的思考内容div
+    fn render_line(&mut self, line: &str) -> String {
+        let mut line = line.to_string();
+        if line.contains("<div class='think'>") {
+            self.in_think = true;
+            line = line.replace("<div class='think'>", "");
+        }
+        if line.contains("</div>") {
+            self.in_think = false;
+            line = line.replace("</div>", "");
+        }
+        if !self.color {
+            return line
+        }
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") {
+            self.in_code = !self.in_code;
+            return paint(&line, DIM, true)
+        }
+        if self.in_code {
+            return paint(&line, YELLOW, true)
+        }
+        if self.in_think {
+            return paint(&line, DIM, true)
+        }
+        if trimmed.starts_with('#') {
+            return paint(trimmed.trim_start_matches('#').trim_start(), &format!("{}{}", BOLD, CYAN), true)
+        }
+        if trimmed == "---" || trimmed == "***" {
+            return paint(&"─".repeat(40), DIM, true)
+        }
+        if let Some(q) = trimmed.strip_prefix("> ") {
+            return format!("{} {}", paint("│", DIM, true), render_inline(q))
+        }
+        let indent = &line[..line.len()-trimmed.len()];
+        if let Some(item) = trimmed.strip_prefix("- ").or_else(|| trimmed.strip_prefix("* ")) {
+            return format!("{}• {}", indent, render_inline(item))
+        }
+        render_inline(&line)
+    }
+}
+
+/// 渲染行内的`**加粗**`和`` `代码` ``
+fn render_inline(line: &str) -> String {
+    let mut out = String::new();
+    let mut rest = line;
+    while !rest.is_empty() {
+        let bold = rest.find("**");
+        let code = rest.find('`');
+        match (bold, code) {
+            (Some(b), c) if c.map_or(true, |c| b < c) => {
+                match rest[b+2..].find("**") {
+                    Some(end) => {
+                        out += &rest[..b];
+                        out += &paint(&rest[b+2..b+2+end], BOLD, true);
+                        rest = &rest[b+2+end+2..];
+                    },
+                    None => {
+                        out += rest;
+                        break
+                    },
+                }
+            },
+            (_, Some(c)) => {
+                match rest[c+1..].find('`') {
+                    Some(end) => {
+                        out += &rest[..c];
+                        out += &paint(&rest[c+1..c+1+end], YELLOW, true);
+                        rest = &rest[c+1+end+1..];
+                    },
+                    None => {
+                        out += rest;
+                        break
+                    },
+                }
+            },
+            _ => {
+                out += rest;
+                break
+            },
+        }
+    }
+    out
+}
@@ -0,0 +1,52 @@
+use crate::{
+    parse_paras::KbCmd,
+    error::MyError,
+    knowledge::{
+        list_bases,
+        contain_base,
+        add_sources,
+        remove_sources,
+        delete_base,
+        reindex,
+    },
+};
+
+/// `chatsong kb`，管理本地知识库，不指定名称则列出所有知识库，否则添加、移除目录或文件后重新索引
+pub async fn run_kb(cmd: &KbCmd) -> i32 {
+    match kb(cmd).await {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        },
+    }
+}
+
+async fn kb(cmd: &KbCmd) -> Result<(), MyError> {
+    let Some(name) = &cmd.name else {
+        for b in list_bases() {
+            println!("{}: {} files, {} chunks", b.name, b.files, b.chunks);
+            for s in b.sources {
+                println!("    {}", s);
+            }
+        }
+        return Ok(())
+    };
+    if cmd.delete {
+        delete_base(name)?;
+        println!("knowledge base {} deleted", name);
+        return Ok(())
+    }
+    if !cmd.add.is_empty() || !contain_base(name) {
+        add_sources(name, &cmd.add)?;
+    }
+    if !cmd.remove.is_empty() {
+        remove_sources(name, &cmd.remove)?;
+    }
+    let stats = reindex(name).await?;
+    println!("knowledge base {}: {} added, {} updated, {} removed, {} unchanged, {} chunks embedded", name, stats.added, stats.updated, stats.removed, stats.unchanged, stats.chunks);
+    for f in stats.failed {
+        eprintln!("failed to index: {}", f);
+    }
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use crate::{
+    parse_paras::{PARAS, MemoryCmd},
+    error::MyError,
+    memory::{
+        GLOBAL_NAMESPACE,
+        evaluate_memory,
+    },
+    api::handlers::memory::get_embedding, // 计算指定字符串的embedding
+};
+
+/// `chatsong memory`，评估记忆检索，输出指定问题会注入哪些记忆，以及每条记忆的BM25、余弦相似度和融合分数
+pub async fn run_memory(cmd: &MemoryCmd) -> i32 {
+    match memory(cmd).await {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        },
+    }
+}
+
+async fn memory(cmd: &MemoryCmd) -> Result<(), MyError> {
+    let query = cmd.query.join(" ");
+    if query.trim().is_empty() {
+        return Err(MyError::ParaError{para: "no query to retrieve memories".to_string()})
+    }
+    let namespaces: Vec<String> = cmd.namespace.as_deref().unwrap_or("").split(',').map(|n| n.trim()).filter(|n| !n.is_empty()).map(|n| n.to_string()).collect();
+    // 与提问时相同，没有embedding模型或出错则只使用BM25
+    let embedding = get_embedding(&PARAS.snapshot().api, &cmd.store, query.clone()).await.unwrap_or_else(|e| {
+        eprintln!("embedding error, only use BM25: {}", e);
+        None
+    });
+    println!("store: {}, namespaces: {}, embedding: {}", cmd.store, namespaces.iter().map(|n| n.as_str()).chain([GLOBAL_NAMESPACE]).collect::<Vec<_>>().join(","), if embedding.is_some() { "yes" } else { "no" });
+    let Some(hits) = evaluate_memory(&cmd.store, &query, embedding, &namespaces) else {
+        return Err(MyError::ParaError{para: format!("no memory file of {}", cmd.store)})
+    };
+    let injected = hits.iter().filter(|h| h.score > 0.0).count().min(cmd.limit);
+    println!("{} of {} memories would be injected", injected, hits.len());
+    for (i, hit) in hits.iter().enumerate() {
+        if i >= injected && !cmd.all {
+            break
+        }
+        println!(
+            "{} {:>3}. score {:.4}  bm25 {:.3}  cosine {}  {} [{}] {}{}",
+            if i < injected { "*" } else { " " },
+            i + 1,
+            hit.score,
+            hit.bm25,
+            hit.cosine.map_or("-".to_string(), |c| format!("{:.3}", c)),
+            hit.note.id,
+            hit.note.namespace,
+            hit.note.summary,
+            hit.note.tags.iter().map(|t| format!(" #{}", t)).collect::<String>(),
+        );
+    }
+    Ok(())
+}
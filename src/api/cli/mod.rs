@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::fs::write;
+
+use chrono::Local;
+use openai_dive::v1::{
+    api::Client,
+    resources::{
+        chat::{
+            ChatCompletionParametersBuilder,
+            ChatCompletionResponseFormat,
+            ChatCompletionStreamOptions,
+            ChatMessage,
+            ChatMessageContent,
+        },
+        shared::ReasoningEffort,
+    },
+};
+use serde_json::Value;
+use tokio::{
+    sync::mpsc::{channel, Receiver},
+    task::JoinHandle,
+};
+use tracing::{event, Level};
+use uuid::Uuid;
+
+use crate::{
+    info::{
+        insert_message,
+        create_uuid_dir,
+        DataType,
+    },
+    parse_paras::{PARAS, SubCommand},
+    error::MyError,
+    openai::for_chat::use_stream,
+    tools::{
+        SelectedTools,
+        run_tools,
+        run_tools_with_plan,
+        audit::set_user,
+    },
+    skills::SelectedSkills,
+    template::render_template,
+    knowledge::retrieve_knowledge,
+    prompt_library::{
+        get_prompt_by_value,
+        find_library_prompt,
+    },
+    api::handlers::{
+        chat::{
+            set_reasoning,
+            parse_selected_tools,
+            parse_selected_skills,
+        },
+        upload::rerank_code_archives,
+    },
+};
+
+pub mod chat;
+pub mod ask;
+pub mod kb;
+pub mod memory;
+
+/// 执行子命令，返回退出码
+pub async fn run_command(command: &SubCommand) -> i32 {
+    match command {
+        SubCommand::Chat(c) => chat::run_chat(c).await,
+        SubCommand::Ask(a) => ask::run_ask(a).await,
+        SubCommand::Kb(k) => kb::run_kb(k).await,
+        SubCommand::Memory(m) => memory::run_memory(m).await,
+        SubCommand::Check(_) => 0, // 解析参数时已经执行并退出，不会到这里
+    }
+}
+
+/// 命令行对话使用的模型
+pub struct CliModel {
+    pub api_key:   String,
+    pub endpoint:  String,
+    pub model:     String, // model name
+    pub reasoning: bool,   // whether model is chain-of-thought
+}
+
+impl CliModel {
+    /// 根据模型名称或序号获取模型，不指定则使用参数文件中的默认模型
+    pub fn new(model: &Option<String>) -> Result<Self, MyError> {
+        let snapshot = PARAS.snapshot();
+        let (api_key, endpoint, model, reasoning) = match model {
+            Some(m) => if snapshot.api.contain_model(m) {
+                snapshot.api.get_model_by_name(m)?
+            } else if m.parse::<usize>().is_ok() {
+                snapshot.api.get_model_by_str(m)?
+            } else {
+                return Err(MyError::ParaError{para: format!("no such model: {}", m)})
+            },
+            None => snapshot.api.get_default_model()?,
+        };
+        Ok(Self{api_key, endpoint, model, reasoning})
+    }
+
+    /// 创建Client和请求参数，与页面提问时的设置相同
+    pub fn prepare(&self) -> (Client, ChatCompletionParametersBuilder) {
+        // 使用api key初始化
+        let mut client = Client::new(self.api_key.clone());
+        client.set_base_url(&self.endpoint);
+        // 准备参数
+        let mut para_builder = ChatCompletionParametersBuilder::default();
+        para_builder.model(self.model.clone());
+        para_builder.response_format(ChatCompletionResponseFormat::Text);
+        set_reasoning(&mut para_builder, &self.model, &self.endpoint, self.reasoning, ReasoningEffort::Low);
+        (client, para_builder)
+    }
+}
+
+/// 解析命令行指定的tools，除了页面下拉选项的值，还可以直接指定内置工具组名称、HTTP工具组名称、tool名称、MCP tool名称或MCP server名称
+pub fn resolve_tools(t: &str) -> Result<Option<SelectedTools>, MyError> {
+    let snapshot = PARAS.snapshot();
+    if t == "all" {
+        Ok(Some(SelectedTools::All))
+    } else if t.starts_with("select_all_") || t.starts_with("built_in_group_") || t.starts_with("http_group_") || t.starts_with("mcp_server_") || t == "not_select_any_tools" {
+        Ok(parse_selected_tools(t))
+    } else if snapshot.tools.contain_group(t) {
+        Ok(Some(SelectedTools::Group(t.to_string())))
+    } else if snapshot.tools.contain_http_group(t) {
+        Ok(Some(SelectedTools::HttpGroup(t.to_string())))
+    } else if snapshot.tools.contain_tool_id(t) {
+        Ok(Some(SelectedTools::Single(t.to_string())))
+    } else if let Some(id) = snapshot.tools.get_tool_id_by_name(t) {
+        Ok(Some(SelectedTools::Single(id)))
+    } else if let Some(name_id) = snapshot.mcp_servers.get_name_id_by_name(t) {
+        Ok(Some(SelectedTools::McpTool(name_id)))
+    } else if let Some(id) = snapshot.mcp_servers.get_server_id_by_name(t) {
+        Ok(Some(SelectedTools::McpServer(id)))
+    } else if t.contains("__") {
+        Ok(Some(SelectedTools::McpTool(t.to_string())))
+    } else {
+        Err(MyError::ParaError{para: format!("no such tool, MCP tool or MCP server: {}", t)})
+    }
+}
+
+/// 解析命令行指定的skills，`all`表示所有可用skills
+pub fn resolve_skills(s: &str) -> Result<Option<SelectedSkills>, MyError> {
+    if s == "all" {
+        Ok(Some(SelectedSkills::All))
+    } else {
+        match parse_selected_skills(s) {
+            Some(selected) => Ok(Some(selected)),
+            None => Err(MyError::ParaError{para: format!("invalid skills: {}, support: all, select_all_available_skills, skill_group_xxx, available-skill-xxx", s)}),
+        }
+    }
+}
+
+/// 根据prompt序号、`lib:id`或名称（不区分大小写，先找参数文件，再找prompt库）获取prompt下拉选项的值
+pub fn resolve_prompt(p: &str) -> Result<String, MyError> {
+    if p.parse::<usize>().is_ok() || p.starts_with("lib:") {
+        return match get_prompt_by_value(p) {
+            Some(_) => Ok(p.to_string()),
+            None => Err(MyError::ParaError{para: format!("no such prompt: {}", p)}),
+        }
+    }
+    let snapshot = PARAS.snapshot();
+    match snapshot.prompt.iter().find(|(_, v)| v.name.to_lowercase() == p.to_lowercase()) {
+        Some((n, _)) => Ok(n.to_string()),
+        None => find_library_prompt(p).ok_or_else(|| MyError::ParaError{para: format!("no such prompt: {}", p)}),
+    }
+}
+
+/// 没有指定模型和tools时，使用prompt的默认模型和tools，返回(模型, tools)
+pub fn prompt_defaults(prompt: &Option<String>, model: &Option<String>, tools: &Option<String>) -> (Option<String>, Option<String>) {
+    let default = prompt.as_ref().and_then(|p| get_prompt_by_value(p));
+    (
+        model.clone().or(default.as_ref().and_then(|d| d.model.clone())),
+        tools.clone().or(default.and_then(|d| d.tools)),
+    )
+}
+
+/// 创建新对话，指定了prompt则与页面开启新会话相同，保存`prompt.txt`并插入替换模板变量后的prompt
+pub fn new_chat(prompt: &Option<String>, model: &str, vars: &HashMap<String, String>) -> Result<String, MyError> {
+    let uuid = Uuid::new_v4().to_string();
+    create_uuid_dir(&uuid)?;
+    if let Some(p) = prompt {
+        let prompt_content = match get_prompt_by_value(p) {
+            Some(pp) => pp.content,
+            None => return Err(MyError::ParaError{para: format!("no such prompt: {}", p)}),
+        };
+        // 保存`prompt.txt`，记录该prompt下拉选项的值
+        let prompt_file = format!("{}/{}/prompt.txt", PARAS.outpath, &uuid);
+        write(&prompt_file, p).map_err(|e| MyError::WriteFileError{file: prompt_file, error: e})?;
+        // prompt插入到messages中
+        let message = ChatMessage::User{
+            content: ChatMessageContent::Text(render_template(&prompt_content, &uuid, model, vars, true, true)),
+            name: None,
+        };
+        insert_message(&uuid, message, None, Local::now().format("%Y-%m-%d %H:%M:%S").to_string(), false, DataType::Normal, None, model, None);
+    }
+    Ok(uuid)
+}
+
+/// 替换问题中的模板变量后插入到messages中，含有模板变量则记录原始问题用于显示
+pub async fn insert_query(uuid: &str, query: String, model: &str, vars: &HashMap<String, String>, chat_name: Option<String>) {
+    let rendered = render_template(&query, uuid, model, vars, true, true);
+    let knowledge_query = rendered.clone();
+    rerank_code_archives(uuid, &rendered).await; // 附件中的代码压缩包按问题选取文件
+    let data = if rendered == query {
+        DataType::Normal
+    } else {
+        DataType::Raw(query)
+    };
+    let message = ChatMessage::User{
+        content: ChatMessageContent::Text(rendered),
+        name: None,
+    };
+    insert_message(uuid, message, None, Local::now().format("%Y-%m-%d %H:%M:%S").to_string(), false, data, None, model, chat_name);
+    retrieve_knowledge(&PARAS.snapshot().api, uuid, &knowledge_query).await; // 检索该对话使用的知识库
+}
+
+/// 开始回答最后一个问题，与页面提问相同：选择了tools或skills则调用run_tools，否则流式输出
+/// 返回接收SSE数据的管道，以及回答任务的handle，回答结束后管道关闭
+pub fn start_answer(uuid: &str, cli_model: &CliModel, selected_tools: Option<SelectedTools>, selected_skills: Option<SelectedSkills>, plan_mode: bool) -> (Receiver<Vec<u8>>, JoinHandle<Result<(), MyError>>) {
+    let (client, mut para_builder) = cli_model.prepare();
+    let (sender, receiver) = channel(100); // 设置管道缓存大小，管道中缓存满了，则send将会阻塞
+    let tmp_uuid = uuid.to_string();
+    let model = cli_model.model.clone();
+    set_user(uuid, "cli".to_string()); // 工具调用审计日志中的用户
+    let snapshot = PARAS.snapshot(); // 本次回答使用的配置
+    let handle = tokio::spawn(async move {
+        if selected_tools.is_some() || selected_skills.is_some() {
+            let result = if plan_mode && selected_skills.is_none() { // 目前计划模式不支持skills
+                run_tools_with_plan(snapshot, selected_tools, tmp_uuid.clone(), sender, client, para_builder, &model).await
+            } else {
+                run_tools(snapshot, selected_tools, selected_skills, tmp_uuid.clone(), sender, client, para_builder, &model, None, true).await
+            };
+            if let Err(e) = &result {
+                event!(Level::ERROR, "{} receive call tool result error: {}", tmp_uuid, e);
+                // 与页面相同，错误信息作为回答插入记录
+                let message = ChatMessage::Assistant{
+                    content: Some(ChatMessageContent::Text(format!("{}", e))),
+                    reasoning: None,
+                    reasoning_content: None,
+                    refusal: None,
+                    name: None,
+                    audio: None,
+                    tool_calls: None,
+                };
+                insert_message(&tmp_uuid, message, None, Local::now().format("%Y-%m-%d %H:%M:%S").to_string(), false, DataType::Normal, None, &model, None);
+            }
+            result
+        } else {
+            // 最后一个chunk包含本次请求的token用量
+            para_builder.stream_options(ChatCompletionStreamOptions{include_usage: Some(true), continuous_usage_stats: None});
+            use_stream(tmp_uuid, sender, client, para_builder, &model, true, None, true, false).await
+        }
+    });
+    (receiver, handle)
+}
+
+/// 解析管道中的SSE数据`event: xxx\ndata: {json}\n\n`，返回(事件名称, json数据)
+pub fn parse_sse(bytes: &[u8]) -> Option<(String, Value)> {
+    let text = String::from_utf8_lossy(bytes);
+    let mut event_name = "message".to_string();
+    let mut data = String::new();
+    for line in text.lines() {
+        if let Some(e) = line.strip_prefix("event: ") {
+            event_name = e.to_string();
+        } else if let Some(d) = line.strip_prefix("data: ") {
+            data += d;
+        }
+    }
+    serde_json::from_str(&data).ok().map(|v| (event_name, v))
+}
@@ -0,0 +1,121 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::SystemTime;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// 默认的token预算，`budget:0`表示不限制
+pub const DEFAULT_BUDGET: usize = 32000;
+
+/// 每个文件在repo map中最多列出的符号数
+const MAX_MAP_SYMBOLS: usize = 20;
+
+/// 常见的入口文件和说明文件，排序时优先
+const KEY_FILES: [&str; 12] = ["readme", "main", "lib", "mod", "index", "__init__", "app", "cargo.toml", "package.json", "pyproject.toml", "go.mod", "setup.py"];
+
+/// 问题中不作为关键词的常见单词
+const STOP_WORDS: [&str; 24] = ["the", "and", "for", "with", "this", "that", "what", "how", "why", "does", "are", "can", "you", "please", "from", "into", "code", "file", "files", "function", "explain", "about", "where", "which"];
+
+/// 匹配常见语言中函数、类、结构体等定义的名称
+pub static SYMBOL_DEF: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?m)^\s*(?:pub(?:\([^)]*\))?\s+)?(?:export\s+)?(?:default\s+)?(?:async\s+)?(?:unsafe\s+)?(?:fn|def|class|struct|enum|trait|interface|type|func|function|impl|module|object)\s+([A-Za-z_][A-Za-z0-9_]*)").unwrap());
+
+/// 待排序的代码文件
+pub struct CodeFile {
+    pub path:    String,      // 结果中显示的相对路径
+    pub token:   usize,       // 估算的token数
+    pub symbols: Vec<String>, // 文件中定义的函数、类等名称
+    modified:    SystemTime,  // 最近修改时间
+    content:     String,      // 小写的代码内容，用于匹配问题中的关键词
+}
+
+impl CodeFile {
+    /// `file`是文件在服务端的路径，用于获取修改时间
+    pub fn new(path: String, code: &str, file: &Path) -> Self {
+        let modified = file.metadata().and_then(|m| m.modified()).unwrap_or(SystemTime::UNIX_EPOCH);
+        let symbols = SYMBOL_DEF.captures_iter(code).map(|c| c[1].to_string()).collect();
+        CodeFile{path, token: estimate_text_token(code), symbols, modified, content: code.to_lowercase()}
+    }
+}
+
+/// 估算文本的token数，ASCII字符约4个1个token，其他字符（例如中文）约1个1个token
+pub fn estimate_text_token(text: &str) -> usize {
+    let (ascii, other) = text.chars().fold((0, 0), |(a, o), c| if c.is_ascii() { (a+1, o) } else { (a, o+1) });
+    ascii.div_ceil(4) + other
+}
+
+/// 从问题中提取关键词：按非字母数字拆分，转小写，去掉太短的词和常见单词
+fn keywords(question: &str) -> Vec<String> {
+    let mut words: Vec<String> = vec![];
+    for w in question.split(|c: char| !(c.is_alphanumeric() || c == '_')) {
+        let w = w.to_lowercase();
+        if w.chars().count() >= 3 && !STOP_WORDS.contains(&w.as_str()) && !words.contains(&w) {
+            words.push(w);
+        }
+    }
+    words
+}
+
+/// 按与问题的相关性对文件排序：文件路径、定义的符号、内容中出现问题中的关键词，入口文件和最近修改的文件优先
+/// 返回排序后的索引
+pub fn rank_files(files: &[CodeFile], question: &str) -> Vec<usize> {
+    let words = keywords(question);
+    // 按修改时间从旧到新的名次，最新的文件加分最多
+    let mut by_time: Vec<usize> = (0..files.len()).collect();
+    by_time.sort_by_key(|i| files[*i].modified);
+    let mut recency = vec![0.0; files.len()];
+    for (rank, i) in by_time.iter().enumerate() {
+        recency[*i] = if files.len() > 1 { 3.0 * rank as f64 / (files.len()-1) as f64 } else { 0.0 };
+    }
+    let scores: Vec<f64> = files.iter().enumerate().map(|(i, f)| {
+        let path = f.path.to_lowercase();
+        let file_name = path.rsplit('/').next().unwrap_or("");
+        let stem = file_name.split('.').next().unwrap_or("");
+        let mut score = recency[i];
+        if KEY_FILES.contains(&file_name) || KEY_FILES.contains(&stem) {
+            score += 3.0;
+        }
+        for w in &words {
+            if path.contains(w.as_str()) {
+                score += 10.0;
+            }
+            if f.symbols.iter().any(|s| s.to_lowercase().contains(w.as_str())) {
+                score += 5.0;
+            }
+            score += f.content.matches(w.as_str()).take(20).count() as f64 * 0.5;
+        }
+        score
+    }).collect();
+    let mut order: Vec<usize> = (0..files.len()).collect();
+    order.sort_by(|a, b| scores[*b].total_cmp(&scores[*a]).then(files[*a].token.cmp(&files[*b].token))); // 分数相同时小文件优先
+    order
+}
+
+/// 按排序依次选取文件，直到用完token预算，放不下的文件跳过，继续尝试后面更小的文件
+/// 返回未选取的文件索引（保持排序）
+pub fn fit_budget(files: &[CodeFile], order: &[usize], budget: usize) -> Vec<usize> {
+    let mut used = 0;
+    let mut omitted: Vec<usize> = vec![];
+    for i in order {
+        if used + files[*i].token <= budget {
+            used += files[*i].token;
+        } else {
+            omitted.push(*i);
+        }
+    }
+    omitted
+}
+
+/// 未选取文件的repo map：每行是文件路径、token数和定义的符号
+pub fn repo_map(files: &[CodeFile], omitted: &[usize]) -> String {
+    omitted.iter().map(|i| {
+        let f = &files[*i];
+        let mut seen: HashSet<&str> = HashSet::new();
+        let symbols: Vec<&str> = f.symbols.iter().map(|s| s.as_str()).filter(|s| seen.insert(*s)).take(MAX_MAP_SYMBOLS).collect();
+        if symbols.is_empty() {
+            format!("{} (~{} tokens)", f.path, f.token)
+        } else {
+            format!("{} (~{} tokens): {}", f.path, f.token, symbols.join(", "))
+        }
+    }).collect::<Vec<_>>().join("\n")
+}
@@ -1,2 +1,3 @@
 pub mod traverse;
 pub mod project;
+pub mod budget;
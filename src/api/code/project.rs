@@ -1,25 +1,34 @@
+use std::collections::HashSet;
 use std::fs::write;
 use std::path::Path;
 
 use glob::Pattern;
-use serde_json::json;
+use serde_json::{json, Value};
 
 /// traverse: 递归获取指定路径下所有文件，并读取其中的代码
 /// error: 定义的错误类型，用于错误传递
 use crate::{
-    code::traverse::{StrucResult, traverse_directory, unzip},
+    code::{
+        traverse::{StrucResult, traverse_directory, archive_stem, extract_archive},
+        budget::{CodeFile, DEFAULT_BUDGET, rank_files, fit_budget, repo_map}, // 按与问题的相关性排序，在token预算内选取文件，其余文件生成repo map
+    },
     graph::copy_file_from_related_uuid, // 如果指定文件不在指定uuid的路径下，则去该uuid所有相关uuid路径下寻找，复制到指定uuid路径下
     error::MyError,
 };
 
+/// `code`模式支持的参数
+const CODE_PARAS: [&str; 8] = ["include:", "exclude:", "check_bytes:", "max_size:", "budget:", "to_json:", "hierarchical:", "contain_tree:"];
+
 /// 从上传的代码压缩包中获取所有脚本的代码，合并到一起，作为提问的问题
-pub fn merge_code(uuid: &str, query: &str, outpath: &str) -> Result<String, MyError> {
+/// 支持zip、tar.gz、tgz、tar.zst、tzst，遵循`.gitignore`和`.ignore`，超过token预算时按与`question`的相关性选取文件，其余文件只列出repo map
+pub fn merge_code(uuid: &str, query: &str, outpath: &str, question: &str) -> Result<String, MyError> {
     // 解析参数，以`code `起始，中间可选的是参数，最后是压缩文件名，全部用空格间隔
-    // 格式：`code [include:xxx] [exclude:xxx] [check_bytes:xxx] [max_size:xxx] [to_json:xxx] [hierarchical:xxx] [contain_tree:xxx] xxx.zip`
+    // 格式：`code [include:xxx] [exclude:xxx] [check_bytes:xxx] [max_size:xxx] [budget:xxx] [to_json:xxx] [hierarchical:xxx] [contain_tree:xxx] xxx.zip`
     // include: 指定的匹配模式，用于获取要包含的文件
     // exclude: 指定的匹配模式，用于获取要排除的文件
     // check_bytes: 检查文件前几个byte来缺失是否是二进制文件，默认50
     // max_size: 要包含的文件大小上限，大小大于该值的文件不读取，支持4种单位b、k(1024b)、m(1024k)、g(1024m)，都是小写，例如：15b、500k、200m、4g，默认1m，0表示无限制（此时单位无所谓）
+    // budget: 代码内容的token预算，超过则按相关性选取文件，默认32000，0表示无限制
     // to_json: 获取为json格式，yes或no，默认yes
     // hierarchical: 是否获取为有层级结构的json格式，yes或no，默认yes
     // contain_tree: 是否在获取的结果中包含脚本文件树结构，yes或no，默认yes
@@ -27,17 +36,21 @@ pub fn merge_code(uuid: &str, query: &str, outpath: &str) -> Result<String, MyEr
     let mut exclude: Vec<(String, Pattern)> = vec![]; // (指定的pattern字符串, 创建的pattern对象)
     let mut check_bytes: usize = 50;
     let mut max_size: u64 = 1024*1024;
+    let mut budget: usize = DEFAULT_BUDGET;
     let mut to_json = true;
     let mut hierarchical = true;
     let mut contain_tree = true;
-    let mut tmp_para: Vec<&str> = query.split(" ").collect();
-    // 最后一项作为zip文件
-    let zip_file = tmp_para.pop().unwrap(); // 肯定不是空向量，因此这里直接unwrap
+    let tmp_para: Vec<&str> = query.split(" ").collect();
+    // 参数之后的内容作为压缩文件，文件名可能含有空格，例如重名时添加序号的`abc (1).zip`
+    let para_num = tmp_para.iter().skip(1).take_while(|p| CODE_PARAS.iter().any(|x| p.starts_with(x))).count();
+    let zip_file = &tmp_para[para_num+1..].join(" ");
+    let tmp_para = &tmp_para[..para_num+1];
     copy_file_from_related_uuid(uuid, zip_file);
     let code_zip: String = format!("{}/{}/{}", outpath, uuid, zip_file); // 肯定不是空向量，因此这里直接unwrap
-    if !code_zip.ends_with(".zip") {
-        return Err(MyError::ParaError{para: format!("The last parameter is not a compressed file: {}", code_zip)})
-    }
+    let code_path = match archive_stem(&code_zip) {
+        Some(p) => p.to_string(),
+        None => return Err(MyError::ParaError{para: format!("The last parameter is not a compressed file (zip, tar.gz, tgz, tar.zst, tzst): {}", code_zip)}),
+    };
     // 检查zip文件是否在服务端
     let tmp_path = Path::new(&code_zip);
     if !(tmp_path.exists() && tmp_path.is_file()) {
@@ -85,6 +98,13 @@ pub fn merge_code(uuid: &str, query: &str, outpath: &str) -> Result<String, MyEr
                     Err(e) => return Err(MyError::ParseStringError{from: num.to_string(), to: "u64".to_string(), error: e}),
                 }
             }
+        } else if para.starts_with("budget:") {
+            let num = para.strip_prefix("budget:").unwrap();
+            budget = match num.parse::<usize>() {
+                Ok(0) => usize::MAX,
+                Ok(b) => b,
+                Err(e) => return Err(MyError::ParseStringError{from: num.to_string(), to: "usize".to_string(), error: e}),
+            };
         } else if *para == "to_json:no" {
             to_json = false;
         } else if *para == "hierarchical:no" {
@@ -96,8 +116,7 @@ pub fn merge_code(uuid: &str, query: &str, outpath: &str) -> Result<String, MyEr
         }
     }
     // 解压缩指定的代码压缩文件
-    unzip(&code_zip, uuid, outpath)?;
-    let code_path = code_zip.strip_suffix(".zip").unwrap();
+    extract_archive(&code_zip, uuid, outpath)?;
 
     // 支持的pattern，用于选取文件
     // https://docs.rs/glob/latest/glob/struct.Pattern.html#method.matches
@@ -111,12 +130,38 @@ pub fn merge_code(uuid: &str, query: &str, outpath: &str) -> Result<String, MyEr
     */
 
     // 递归指定的路径
-    let (tree, result_files) = traverse_directory(code_path, &include, &exclude, false, check_bytes, max_size, to_json, hierarchical)?;
+    let (tree, mut result_files) = traverse_directory(&code_path, &include, &exclude, false, check_bytes, max_size, to_json, hierarchical)?;
+
+    // 超过token预算则按与问题的相关性排序选取文件，未选取的文件生成repo map
+    let mut files: Vec<(String, String)> = vec![]; // (文件的相对路径, 代码内容)
+    match &result_files {
+        StrucResult::Json(v) => collect_json_files(v, &mut files),
+        StrucResult::Text(v) => files = v.clone(),
+    }
+    let files: Vec<CodeFile> = files.into_iter().map(|(path, code)| {
+        let file = format!("{}/{}/{}", outpath, uuid, path); // 相对路径以压缩包解压后的文件夹名起始
+        CodeFile::new(path, &code, Path::new(&file))
+    }).collect();
+    let omitted = fit_budget(&files, &rank_files(&files, question), budget);
+    let map = repo_map(&files, &omitted);
+    if !omitted.is_empty() {
+        let omitted_path: HashSet<&str> = omitted.iter().map(|i| files[*i].path.as_str()).collect();
+        match &mut result_files {
+            StrucResult::Json(v) => retain_json_files(v, &omitted_path),
+            StrucResult::Text(v) => v.retain(|f| !omitted_path.contains(f.0.as_str())),
+        }
+    }
 
     // 获取结果
     let result = match result_files {
         StrucResult::Json(files) => { // 保存为有层级的json格式
-            let json_output = if contain_tree {
+            let json_output = if !map.is_empty() {
+                json!({
+                    "tree": if contain_tree { json!(tree) } else { Value::Null },
+                    "code": files,
+                    "omitted": map,
+                })
+            } else if contain_tree {
                 json!({
                     "tree": tree,
                     "code": files,
@@ -128,7 +173,13 @@ pub fn merge_code(uuid: &str, query: &str, outpath: &str) -> Result<String, MyEr
         },
         StrucResult::Text(files) => { // 保存为没有层级的json格式
             if to_json {
-                let json_output = if contain_tree {
+                let json_output = if !map.is_empty() {
+                    json!({
+                        "tree": if contain_tree { json!(tree) } else { Value::Null },
+                        "code": files,
+                        "omitted": map,
+                    })
+                } else if contain_tree {
                     json!({
                         "tree": tree,
                         "code": files,
@@ -138,16 +189,42 @@ pub fn merge_code(uuid: &str, query: &str, outpath: &str) -> Result<String, MyEr
                 };
                 serde_json::to_string_pretty(&json_output).map_err(|e| MyError::JsonToStringError{error: e.into()})?
             } else { // 保存为普通文本格式
-                format!("Source Tree:\n```\n{}```\n\n{}", tree, files.into_iter().map(|v| format!("`{}`\n{}\n", v.0, v.1)).collect::<Vec<_>>().join("\n"))
+                let mut text = format!("Source Tree:\n```\n{}```\n\n{}", tree, files.into_iter().map(|v| format!("`{}`\n{}\n", v.0, v.1)).collect::<Vec<_>>().join("\n"));
+                if !map.is_empty() {
+                    text += &format!("\nOmitted files (over the token budget, path, tokens and symbols):\n```\n{}\n```\n", map);
+                }
+                text
             }
         },
     };
 
     // 保存至输出文件
-    let outfile = code_zip.replace(".zip", ".txt");
+    let outfile = format!("{}.txt", code_path);
     if let Err(e) = write(&outfile, &result) {
         return Err(MyError::WriteFileError{file: outfile, error: e})
     }
 
     Ok(result)
 }
+
+/// 获取有层级的json中所有文件，每个文件是`[相对路径, 代码内容]`，文件夹是由文件和文件夹组成的数组
+fn collect_json_files(value: &Value, files: &mut Vec<(String, String)>) {
+    if let Value::Array(v) = value {
+        match v.as_slice() {
+            [Value::String(path), Value::String(code)] => files.push((path.clone(), code.clone())),
+            _ => v.iter().for_each(|x| collect_json_files(x, files)),
+        }
+    }
+}
+
+/// 从有层级的json中去除指定路径的文件
+fn retain_json_files(value: &mut Value, omitted: &HashSet<&str>) {
+    if let Value::Array(v) = value {
+        v.retain(|x| !matches!(x.as_array().map(|a| a.as_slice()), Some([Value::String(path), Value::String(_)]) if omitted.contains(path.as_str())));
+        for x in v.iter_mut() {
+            if !matches!(x.as_array().map(|a| a.as_slice()), Some([Value::String(_), Value::String(_)])) {
+                retain_json_files(x, omitted);
+            }
+        }
+    }
+}
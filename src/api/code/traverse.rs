@@ -2,13 +2,18 @@ use std::collections::HashMap;
 use std::fs::{self, read};
 use std::io;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
 
+use chrono::NaiveDate;
+use flate2::read::GzDecoder;
 use glob::Pattern;
 use ignore::WalkBuilder;
 use serde_json::Value;
+use tar::Archive;
 use termtree::Tree;
 use tracing::{event, Level};
 use zip::ZipArchive;
+use zstd::stream::read::Decoder as ZstdDecoder;
 
 /// error: 定义的错误类型，用于错误传递
 use crate::error::MyError;
@@ -19,7 +24,19 @@ pub enum StrucResult {
     Text(Vec<(String, String)>), // 保存为普通文本格式或没有层级的json格式
 }
 
-/// 递归获取指定项目路径下所有文件
+/// 压缩包解压后的最大字节数，避免压缩炸弹占满磁盘
+const MAX_UNPACKED_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// 支持的代码压缩包格式
+pub const ARCHIVE_FORMATS: [&str; 5] = [".zip", ".tar.gz", ".tgz", ".tar.zst", ".tzst"];
+
+/// 去除压缩包后缀，不是支持的压缩包格式则返回None，例如`abc.tar.gz`返回`abc`
+pub fn archive_stem(name: &str) -> Option<&str> {
+    let lowercase_name = name.to_lowercase();
+    ARCHIVE_FORMATS.iter().find(|x| lowercase_name.ends_with(*x)).map(|x| &name[..name.len()-x.len()])
+}
+
+/// 递归获取指定项目路径下所有文件，包含隐藏文件，只遵循该路径中的`.gitignore`和`.ignore`（不需要在git仓库中）
 pub fn traverse_directory(root_path: &str, include: &Vec<(String, Pattern)>, exclude: &Vec<(String, Pattern)>, mat: bool, check_bytes: usize, max_size: u64, is_json: bool, hierarchical: bool) -> Result<(String, StrucResult), MyError> {
     let root_path = Path::new(root_path);
     // 初始化
@@ -36,6 +53,12 @@ pub fn traverse_directory(root_path: &str, include: &Vec<(String, Pattern)>, exc
     // 创建tree
     let tree = WalkBuilder::new(&canonical_root_path)
         .git_ignore(true)
+        .ignore(true)
+        .require_git(false) // 上传的压缩包通常不含`.git`，默认只在git仓库中才遵循`.gitignore`
+        .hidden(false) // 保留`.github/`、`.env.example`等隐藏文件
+        .parents(false) // 只使用压缩包内的`.gitignore`和`.ignore`，不使用输出路径上层的
+        .git_global(false) // 不使用服务端用户的全局排除规则
+        .git_exclude(false)
         .build()
         .filter_map(|e| e.ok())
         .fold(Tree::new(parent_directory.to_owned()), |mut root, entry| { // 遍历指定路径下每一项，以指定路径作为根路径，递归添加子项
@@ -62,7 +85,7 @@ pub fn traverse_directory(root_path: &str, include: &Vec<(String, Pattern)>, exc
                         };
                     }
                     // 当前项如果是文件，且大小在指定上限范围内，则读取代码内容，保存为json，存储到Vec<json>中
-                    if path.is_file() {
+                    if entry.file_type().is_some_and(|t| t.is_file()) { // 不跟随符号链接
                         // 判断文件大小
                         file_size = path.metadata().unwrap().len();
                         if file_size <= max_size {
@@ -199,10 +222,12 @@ fn should_include_file(path: &Path, include_patterns: &[(String, Pattern)], excl
 }
 
 /// 解压缩zip文件，https://github.com/zip-rs/zip2/blob/master/examples/extract.rs
+/// 所有文件解压后累计超过`MAX_UNPACKED_SIZE`则停止并返回错误
 pub fn unzip(zip_file: &str, uuid: &str, outpath: &str) -> Result<(), MyError> {
     let zip_file_path = Path::new(zip_file);
     let file = fs::File::open(zip_file_path).map_err(|e| MyError::OpenFileError{file: zip_file.to_string(), error: e})?;
     let mut archive = ZipArchive::new(file).map_err(|e| MyError::ZipArchiveError{file: zip_file.to_string(), error: e})?;
+    let mut unpacked: u64 = 0; // 已解压的字节数
     // 遍历每项
     for i in 0..archive.len() {
         let mut file = archive.by_index(i).map_err(|e| MyError::ZipArchiveError{file: zip_file.to_string(), error: e})?;
@@ -233,7 +258,16 @@ pub fn unzip(zip_file: &str, uuid: &str, outpath: &str) -> Result<(), MyError> {
             }
             // 保存该文件
             let mut outfile = fs::File::create(&outpath).map_err(|e| MyError::CreateFileError{file: outpath.to_str().unwrap().to_string(), error: e})?;
-            io::copy(&mut file, &mut outfile)?;
+            // 不信任压缩包中记录的大小，从之前所有文件解压的字节数开始累计
+            let mut reader = LimitedReader{inner: &mut file, read: unpacked, limit: MAX_UNPACKED_SIZE};
+            io::copy(&mut reader, &mut outfile).map_err(|e| MyError::UnpackArchiveError{file: zip_file.to_string(), error: e})?;
+            unpacked = reader.read;
+            // 保留压缩包中记录的修改时间，用于按最近修改对文件排序
+            if let Some(t) = file.last_modified() {
+                if let Some(dt) = NaiveDate::from_ymd_opt(t.year() as i32, t.month() as u32, t.day() as u32).and_then(|d| d.and_hms_opt(t.hour() as u32, t.minute() as u32, t.second() as u32)) {
+                    let _ = outfile.set_modified(UNIX_EPOCH + Duration::from_secs(dt.and_utc().timestamp().max(0) as u64));
+                }
+            }
         }
 
         // Get and Set permissions
@@ -247,3 +281,102 @@ pub fn unzip(zip_file: &str, uuid: &str, outpath: &str) -> Result<(), MyError> {
     }
     Ok(())
 }
+
+/// 解压缩tar.gz、tgz、tar.zst、tzst文件到`输出路径/uuid`，保留文件的修改时间，路径含有`..`或绝对路径的项会被跳过
+/// 只解压普通文件和文件夹，跳过符号链接、硬链接等其他项，避免链接到服务端的其他文件
+/// 解压后超过`MAX_UNPACKED_SIZE`则停止并返回错误
+pub fn untar(tar_file: &str, uuid: &str, outpath: &str) -> Result<(), MyError> {
+    let file = fs::File::open(tar_file).map_err(|e| MyError::OpenFileError{file: tar_file.to_string(), error: e})?;
+    let dest: PathBuf = [outpath, uuid].iter().collect();
+    let lowercase_name = tar_file.to_lowercase();
+    let result = if lowercase_name.ends_with(".tar.zst") || lowercase_name.ends_with(".tzst") {
+        let decoder = ZstdDecoder::new(file).map_err(|e| MyError::UnpackArchiveError{file: tar_file.to_string(), error: e})?;
+        unpack_entries(Archive::new(LimitedReader::new(decoder, MAX_UNPACKED_SIZE)), &dest)
+    } else {
+        unpack_entries(Archive::new(LimitedReader::new(GzDecoder::new(file), MAX_UNPACKED_SIZE)), &dest)
+    };
+    result.map_err(|e| MyError::UnpackArchiveError{file: tar_file.to_string(), error: e})
+}
+
+/// 逐项解压tar包中的普通文件和文件夹到指定路径，其他类型的项只记录日志
+fn unpack_entries<R: io::Read>(mut archive: Archive<R>, dest: &Path) -> io::Result<()> {
+    fs::create_dir_all(dest)?;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_file() || entry_type.is_dir() {
+            entry.unpack_in(dest)?; // 路径含有`..`的项返回false，不会解压
+        } else {
+            event!(Level::DEBUG, "[skip]: {:?} entry in tar archive: {}", entry_type, entry.path().map(|p| p.display().to_string()).unwrap_or_default());
+        }
+    }
+    Ok(())
+}
+
+/// 统计解压后读取的字节数，超过上限则返回错误
+struct LimitedReader<R> {
+    inner: R,
+    read:  u64,
+    limit: u64,
+}
+
+impl<R: io::Read> LimitedReader<R> {
+    fn new(inner: R, limit: u64) -> Self {
+        LimitedReader{inner, read: 0, limit}
+    }
+}
+
+impl<R: io::Read> io::Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        if self.read > self.limit {
+            return Err(io::Error::other(format!("decompressed size exceeds {} MB", self.limit / 1024 / 1024)))
+        }
+        Ok(n)
+    }
+}
+
+/// 根据后缀解压缩代码压缩包，支持zip、tar.gz、tgz、tar.zst、tzst
+pub fn extract_archive(archive: &str, uuid: &str, outpath: &str) -> Result<(), MyError> {
+    if archive.to_lowercase().ends_with(".zip") {
+        unzip(archive, uuid, outpath)
+    } else {
+        untar(archive, uuid, outpath)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_limited_reader() {
+        let data = vec![0u8; 4096];
+        let mut out = Vec::new();
+        assert!(LimitedReader::new(data.as_slice(), 4096).read_to_end(&mut out).is_ok());
+        assert_eq!(out.len(), 4096);
+        let mut out = Vec::new();
+        assert!(LimitedReader::new(data.as_slice(), 4095).read_to_end(&mut out).is_err());
+    }
+
+    #[test]
+    fn test_untar_skip_links() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder.append_link(&mut header, "src/secret", "/etc/passwd").unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(2);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, "src/main.rs", &b"//"[..]).unwrap();
+        let data = builder.into_inner().unwrap();
+        let dest = std::env::temp_dir().join(format!("chatsong_untar_{}", std::process::id()));
+        unpack_entries(Archive::new(data.as_slice()), &dest).unwrap();
+        assert!(dest.join("src/main.rs").is_file());
+        assert!(fs::symlink_metadata(dest.join("src/secret")).is_err());
+        fs::remove_dir_all(&dest).unwrap();
+    }
+}
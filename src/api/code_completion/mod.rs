@@ -422,7 +422,7 @@ pub struct ModelForCompletion {
 impl ModelForCompletion {
     /// 默认模型
     pub fn new() -> Self {
-        let (api_key, endpoint, model, thinking) = PARAS.api.get_default_model().unwrap_or(("".to_string(), "".to_string(), "".to_string(), false));
+        let (api_key, endpoint, model, thinking) = PARAS.snapshot().api.get_default_model().unwrap_or(("".to_string(), "".to_string(), "".to_string(), false));
         let lowercase_model = model.to_lowercase();
         Self {
             model,
@@ -435,7 +435,7 @@ impl ModelForCompletion {
 
     /// 根据指定模型序号（1-based）获取模型
     fn from_n(n: usize) -> Option<Self> {
-        if let Ok((api_key, endpoint, model, thinking)) = PARAS.api.get_model_by_usize(n) {
+        if let Ok((api_key, endpoint, model, thinking)) = PARAS.snapshot().api.get_model_by_usize(n) {
             let lowercase_model = model.to_lowercase();
             Some(Self {
                 model,
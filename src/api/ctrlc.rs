@@ -57,10 +57,15 @@ pub async fn wait_for_signal() {
     //println!("start waiting signal ...");
     wait_for_signal_impl().await;
     //println!("do something ...");
+    save_before_exit().await;
+}
+
+/// 退出前保存图文件、chat记录、memory，并关闭mcp servers，`chatsong chat`正常退出时也调用
+pub async fn save_before_exit() {
     save_graph(); // 退出前保存图文件
     save_all_chat(); // 退出前保存每个uuid的chat记录
     // kill mcp servers
-    PARAS.mcp_servers.close_all().await;
+    PARAS.snapshot().mcp_servers.close_all().await;
     // save memory
     let data = MEMORY.lock().unwrap();
     for memory in data.values() {
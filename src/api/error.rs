@@ -21,6 +21,8 @@ use axum::{
     extract::multipart::MultipartError,
 };
 use base64::DecodeError;
+use calamine::Error as calamine_error;
+use csv::Error as csv_error;
 use glob::PatternError;
 use grep::regex::Error as grep_error;
 use openai_dive::v1::{
@@ -40,6 +42,7 @@ use openai_dive::v1::{
     error::APIError,
 };
 use pdf_extract::OutputError;
+use quick_xml::Error as xml_error;
 use reqwest::Error as reqwest_error;
 use regex::Error as regex_error;
 use serde_json::Error as json_error;
@@ -193,6 +196,10 @@ pub enum MyError {
     #[error("Error - ZipArchive::new({file}): {error}")]
     ZipArchiveError{file: String, error: ZipError},
 
+    // 解压缩tar.gz、tar.zst文件错误
+    #[error("Error - unpack {file}: {error}")]
+    UnpackArchiveError{file: String, error: io::Error},
+
     // 创建glob的pattern错误
     #[error("Error - create glob pattern {pattern}: {error}")]
     CreatePatternError{pattern: String, error: PatternError},
@@ -201,6 +208,18 @@ pub enum MyError {
     #[error("Error - extract content from {file}: {error}")]
     ExtractPdfError{file: String, error: OutputError},
 
+    // 解析docx、pptx、odt中的xml错误
+    #[error("Error - parse xml {file}: {error}")]
+    ParseXmlError{file: String, error: xml_error},
+
+    // 读取xlsx、xls、ods表格错误
+    #[error("Error - read spreadsheet {file}: {error}")]
+    ReadSpreadsheetError{file: String, error: calamine_error},
+
+    // 读取csv、tsv表格错误
+    #[error("Error - read csv {file}: {error}")]
+    ReadCsvError{file: String, error: csv_error},
+
     // base64解码为图片错误
     #[error("Error - decode base64 to image {file}: {error}")]
     Base64DecodeError{file: String, error: DecodeError},
@@ -213,6 +232,14 @@ pub enum MyError {
     #[error("Error - {info}")]
     PlanModeError{info: String},
 
+    // 拒绝调用tool
+    #[error("Error - Not allowed to call this tool: {tool}")]
+    ApprovalDeniedError{tool: String},
+
+    // 命令策略拒绝调用tool，告知模型后继续回答
+    #[error("Error - Not allowed to call {tool}: {info}")]
+    PolicyDeniedError{tool: String, info: String},
+
     // run command error
     #[error("Error - {info}")]
     CommandError{info: String},
@@ -322,6 +349,10 @@ pub enum MyError {
     #[error("Error - {para}")]
     ParaError{para: String},
 
+    // cookie值不是服务端生成的uuid
+    #[error("Error - invalid cookie: {cookie}")]
+    InvalidCookieError{cookie: String},
+
     // 常规io::Error，这里可以改为向上面那样将错误传过来，但不知道还能否使用`#[from]`
     #[error("I/O error occurred")]
     IoError(#[from] io::Error),
@@ -330,6 +361,10 @@ pub enum MyError {
 /// 为MyError实现IntoResponse，这样在axum中也可以使用
 impl IntoResponse for MyError {
     fn into_response(self) -> Response {
-        (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", self)).into_response()
+        let status = match self {
+            MyError::InvalidCookieError{..} => StatusCode::BAD_REQUEST, // 客户端发送的cookie不合法
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, format!("{}", self)).into_response()
     }
 }
@@ -1,29 +1,136 @@
 use std::collections::HashMap;
+use std::net::SocketAddr;
 
-use axum::extract::{
-    Query,
-    OriginalUri,
+use axum::{
+    extract::{
+        Query,
+        OriginalUri,
+        ConnectInfo,
+    },
+    http::StatusCode,
+    response::Html,
+    Json,
 };
 use axum_extra::extract::cookie::CookieJar;
 use tracing::{event, Level};
 
-/// info: 记录所有用户的信息
-use crate::info::update_approval;
+/// pending_approval: 等待确认的工具调用，回答时记录点击确认的客户端地址，写入工具调用审计日志
+use crate::tools::pending_approval::{
+    PendingApproval,
+    answer,
+    answer_uuid,
+    get,
+    list,
+    same_user,
+};
 
-/// Handler for `/嵌套的前缀/approval` GET
-pub async fn approval(Query(params): Query<HashMap<String, String>>, uri: OriginalUri, jar: CookieJar) {
-    // 获取uuid
-    if let Some(c) = jar.get("srx-tzn") { // 获取cookie
-        let uuid = c.value().to_string();
-        let approval = match params.get("approval") {
-            Some(p) => {
-                event!(Level::INFO, "{} user approval: {}", uuid, p);
-                p.clone()
+/// Handler for `/嵌套的前缀/approval` POST
+/// 指定了`id`则回答该确认（只能回答同一用户的），否则回答cookie中uuid最早的确认
+pub async fn approval(Query(params): Query<HashMap<String, String>>, ConnectInfo(addr): ConnectInfo<SocketAddr>, uri: OriginalUri, jar: CookieJar) -> Result<(), (StatusCode, String)> {
+    let approver = addr.ip().to_string();
+    let approval = match params.get("approval") {
+        Some(p) => p.clone(),
+        None => "false".to_string(), // 不允许
+    };
+    if let Some(id) = params.get("id") {
+        match get(id) {
+            Some(p) if same_user(&p.user, &approver) => {
+                event!(Level::INFO, "{} user approval {} from {}: {}", p.uuid, id, addr, approval);
+                if answer(id, &approval, &approver) {
+                    Ok(())
+                } else {
+                    Err((StatusCode::GONE, format!("approval {} was already answered or timed out", id)))
+                }
             },
-            None => "false".to_string(), // 不允许
-        };
-        update_approval(&uuid, Some(approval));
+            Some(_) => Err((StatusCode::FORBIDDEN, format!("approval {} belongs to another user", id))),
+            None => Err((StatusCode::GONE, format!("approval {} was already answered or timed out", id))),
+        }
+    } else if let Some(c) = jar.get("srx-tzn") { // 获取cookie
+        let uuid = c.value().to_string();
+        event!(Level::INFO, "{} user approval from {}: {}", uuid, addr, approval);
+        if answer_uuid(&uuid, &approval, &approver) {
+            Ok(())
+        } else {
+            Err((StatusCode::GONE, "approval was already answered or timed out".to_string()))
+        }
     } else {
-        event!(Level::INFO, "GET {}, set approval failed, no cookie", uri.path()); // 注意：`axum::http::Uri`只能捕获到`/hello`，不包含嵌套的`/嵌套的前缀`前缀，使用`OriginalUri`可以
+        event!(Level::INFO, "POST {}, set approval failed, no cookie", uri.path()); // 注意：`axum::http::Uri`只能捕获到`/hello`，不包含嵌套的`/嵌套的前缀`前缀，使用`OriginalUri`可以
+        Err((StatusCode::BAD_REQUEST, "no approval id or cookie".to_string()))
     }
 }
+
+/// Handler for `/嵌套的前缀/approvals` GET
+/// 获取当前用户所有等待确认的工具调用，最早的在前
+pub async fn approvals(ConnectInfo(addr): ConnectInfo<SocketAddr>, uri: OriginalUri) -> Json<Vec<PendingApproval>> {
+    event!(Level::INFO, "GET {} from {}", uri.path(), addr);
+    Json(list(Some(&addr.ip().to_string())))
+}
+
+/// Handler for `/嵌套的前缀/approvals/page` GET
+/// 查看并回答当前用户所有等待确认的工具调用的页面
+pub async fn approvals_page(ConnectInfo(addr): ConnectInfo<SocketAddr>, uri: OriginalUri) -> Html<&'static str> {
+    event!(Level::INFO, "GET {} from {}", uri.path(), addr);
+    Html(APPROVALS_PAGE)
+}
+
+/// 等待确认页面，每3秒从`../approvals`刷新，点击按钮通过`../approval?id=`回答
+const APPROVALS_PAGE: &str = r###"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Pending approvals</title>
+<style>
+    body { font-family: sans-serif; margin: 20px; font-size: 14px; }
+    .approval { border: 1px solid #ccc; border-radius: 6px; padding: 8px 12px; margin-bottom: 12px; }
+    .meta { color: #666; font-size: 12px; margin-bottom: 6px; }
+    pre { white-space: pre-wrap; word-break: break-all; max-height: 300px; overflow: auto; background: #f6f6f6; padding: 6px; }
+    button { margin-right: 8px; padding: 4px 12px; }
+    input { width: 300px; padding: 4px; }
+</style>
+</head>
+<body>
+<h2>Pending approvals</h2>
+<div id="status"></div>
+<div id="list"></div>
+<script>
+function esc(s) {
+    return String(s).replace(/&/g, '&amp;').replace(/</g, '&lt;').replace(/>/g, '&gt;');
+}
+function answer(id, approval) {
+    fetch('../approval?id=' + encodeURIComponent(id) + '&approval=' + encodeURIComponent(approval), { method: 'POST' })
+        .then(r => r.ok ? null : r.text().then(t => { throw new Error(t); }))
+        .catch(error => { alert(error.message); })
+        .finally(loadApprovals);
+}
+function instruct(id) {
+    const v = document.getElementById('new-' + id).value.trim();
+    if (v) answer(id, v);
+}
+let shown = null;
+function loadApprovals() {
+    fetch('../approvals')
+        .then(r => r.ok ? r.json() : r.text().then(t => { throw new Error(t); }))
+        .then(pending => {
+            document.getElementById('status').textContent = pending.length + ' pending';
+            const ids = pending.map(p => p.id).join(',');
+            if (ids === shown) return; // 没有变化则不刷新，避免清空正在输入的新指示
+            shown = ids;
+            document.getElementById('list').innerHTML = pending.map(p => '<div class="approval">'
+                + '<div class="meta">' + esc(p.time) + ' · ' + esc(p.tool) + ' · uuid ' + esc(p.uuid) + ' · ' + esc(p.user)
+                + (p.timeout > 0 ? ' · times out after ' + p.timeout + ' s' : '') + '</div>'
+                + '<pre>' + esc(p.message) + '</pre>'
+                + '<button onclick="answer(\'' + esc(p.id) + '\', \'true\')">Allow</button>'
+                + '<button onclick="answer(\'' + esc(p.id) + '\', \'false\')">Deny</button>'
+                + '<button onclick="answer(\'' + esc(p.id) + '\', \'skip\')">Skip</button>'
+                + '<input id="new-' + esc(p.id) + '" placeholder="or skip with a new instruction">'
+                + '<button onclick="instruct(\'' + esc(p.id) + '\')">Send</button>'
+                + '</div>').join('');
+        })
+        .catch(error => { document.getElementById('status').textContent = 'Error: ' + error.message; });
+}
+loadApprovals();
+setInterval(loadApprovals, 3000);
+</script>
+</body>
+</html>
+"###;
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{
+        Query,
+        OriginalUri,
+        ConnectInfo,
+    },
+    http::StatusCode,
+    response::Html,
+    Json,
+};
+use tracing::{event, Level};
+
+use crate::{
+    api::handlers::chat::check_local, // 审计日志记录了本机执行的命令和修改的文件，只允许服务端所在电脑或局域网访问
+    tools::audit::{
+        AuditEntry,
+        AuditFilter,
+        read_audit,
+    },
+};
+
+/// Handler for `/嵌套的前缀/audit` GET
+/// 筛选工具调用审计日志，最新的在前，参数：date（日期前缀，例如`2024-10-21`）、since、until（`2024-10-21 08:00:00`）、uuid、tool、status（ok、error、denied、skipped）、q（参数或输出包含的内容）、limit（默认200，0不限制）
+pub async fn audit(Query(params): Query<HashMap<String, String>>, ConnectInfo(addr): ConnectInfo<SocketAddr>, uri: OriginalUri) -> Result<Json<Vec<AuditEntry>>, (StatusCode, String)> {
+    event!(Level::INFO, "GET {} from {}", uri.path(), addr);
+    check_local(&addr, "audit log")?;
+    let get = |k: &str| params.get(k).map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+    let limit = match get("limit") {
+        Some(l) => l.parse::<usize>().map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid limit {}: {}", l, e)))?,
+        None => 200,
+    };
+    let filter = AuditFilter {
+        date: get("date"),
+        since: get("since"),
+        until: get("until"),
+        uuid: get("uuid"),
+        tool: get("tool"),
+        status: get("status"),
+        query: get("q"),
+        limit,
+    };
+    Ok(Json(read_audit(&filter)))
+}
+
+/// Handler for `/嵌套的前缀/audit/page` GET
+/// 筛选查看工具调用审计日志的页面
+pub async fn audit_page(ConnectInfo(addr): ConnectInfo<SocketAddr>, uri: OriginalUri) -> Result<Html<&'static str>, (StatusCode, String)> {
+    event!(Level::INFO, "GET {} from {}", uri.path(), addr);
+    check_local(&addr, "audit log")?;
+    Ok(Html(AUDIT_PAGE))
+}
+
+/// 审计日志页面，从`../audit`获取筛选结果
+const AUDIT_PAGE: &str = r###"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Tool call audit</title>
+<style>
+    body { font-family: sans-serif; margin: 20px; font-size: 14px; }
+    .filters input, .filters select { margin: 0 8px 8px 0; padding: 4px; }
+    table { border-collapse: collapse; width: 100%; }
+    th, td { border: 1px solid #ccc; padding: 4px 6px; vertical-align: top; text-align: left; }
+    th { background: #f0f0f0; }
+    pre { margin: 0; white-space: pre-wrap; word-break: break-all; max-height: 200px; overflow: auto; }
+    .error, .denied { color: #c00; }
+    .skipped { color: #888; }
+</style>
+</head>
+<body>
+<h2>Tool call audit</h2>
+<div class="filters">
+    <input id="date" type="date" title="date">
+    <input id="since" placeholder="since 2024-10-21 08:00:00">
+    <input id="until" placeholder="until 2024-10-21 18:00:00">
+    <input id="uuid" placeholder="uuid">
+    <input id="tool" placeholder="tool, e.g. run_command">
+    <select id="status">
+        <option value="">all status</option>
+        <option value="ok">ok</option>
+        <option value="error">error</option>
+        <option value="denied">denied</option>
+        <option value="skipped">skipped</option>
+    </select>
+    <input id="q" placeholder="args or output contains">
+    <input id="limit" type="number" value="200" style="width: 80px" title="limit, 0 for all">
+    <button onclick="loadAudit()">Filter</button>
+    <button onclick="yesterday()">Yesterday</button>
+</div>
+<div id="count"></div>
+<table>
+    <thead><tr><th>time</th><th>user / uuid</th><th>tool</th><th>args</th><th>approval</th><th>duration</th><th>status</th><th>output</th></tr></thead>
+    <tbody id="rows"></tbody>
+</table>
+<script>
+function esc(s) {
+    return String(s).replace(/&/g, '&amp;').replace(/</g, '&lt;').replace(/>/g, '&gt;');
+}
+function yesterday() {
+    const d = new Date(Date.now() - 86400000);
+    document.getElementById('date').value = d.getFullYear() + '-' + String(d.getMonth() + 1).padStart(2, '0') + '-' + String(d.getDate()).padStart(2, '0');
+    loadAudit();
+}
+function loadAudit() {
+    const params = new URLSearchParams();
+    for (const k of ['date', 'since', 'until', 'uuid', 'tool', 'status', 'q', 'limit']) {
+        const v = document.getElementById(k).value.trim();
+        if (v) params.append(k, v);
+    }
+    fetch('../audit?' + params.toString())
+        .then(r => r.ok ? r.json() : r.text().then(t => { throw new Error(t); }))
+        .then(entries => {
+            document.getElementById('count').textContent = entries.length + ' entries';
+            document.getElementById('rows').innerHTML = entries.map(e => '<tr>'
+                + '<td>' + esc(e.time) + '</td>'
+                + '<td>' + esc(e.user) + '<br><small>' + esc(e.uuid) + '</small></td>'
+                + '<td>' + esc(e.tool) + '<br><small>' + esc(e.tool_id) + '</small></td>'
+                + '<td><pre>' + esc(e.args) + '</pre></td>'
+                + '<td>' + esc(e.approval) + (e.approver ? '<br><small>by ' + esc(e.approver) + '</small>' : '') + '</td>'
+                + '<td>' + e.duration_ms + ' ms</td>'
+                + '<td class="' + esc(e.status) + '">' + esc(e.status) + '</td>'
+                + '<td><pre>' + esc(e.output) + '</pre></td>'
+                + '</tr>').join('');
+        })
+        .catch(error => { document.getElementById('count').textContent = 'Error: ' + error.message; });
+}
+loadAudit();
+</script>
+</body>
+</html>
+"###;
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{
+        Json,
+        Multipart,
+        Path,
+        Query,
+        OriginalUri,
+        ConnectInfo,
+    },
+    http::StatusCode,
+};
+use axum_extra::extract::cookie::CookieJar;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::{
+    fs::{File, remove_file},
+    io::{AsyncWriteExt, BufWriter},
+};
+use tracing::{event, Level};
+
+use crate::{
+    info::{
+        get_knowledge, // 获取指定uuid使用的知识库
+        set_knowledge, // 设置指定uuid使用的知识库
+        is_valid_uuid, // cookie值是否是服务端生成的uuid
+    },
+    knowledge::{
+        self,
+        IndexStats,
+    },
+    parse_paras::PARAS,
+    api::handlers::{
+        chat::{
+            is_local_request,
+            check_local, // 修改知识库只允许服务端所在电脑或局域网发起
+        },
+        upload::{
+            sanitize_file_name, // 去除文件名中的路径和非法字符
+            check_content_type, // 根据文件头检查内容是否与后缀一致
+        },
+    },
+};
+
+/// 创建知识库或添加目录、文件的参数
+#[derive(Deserialize)]
+pub struct KnowledgeSources {
+    name:    String,      // 知识库名称，不存在则创建
+    #[serde(default)]
+    sources: Vec<String>, // 服务端的目录或文件路径
+}
+
+/// 知识库名称不合法（例如含有`..`和路径分隔符）返回400
+fn check_name(name: &str) -> Result<(), (StatusCode, String)> {
+    knowledge::check_name(name).map_err(|e| (StatusCode::BAD_REQUEST, format!("{}", e)))
+}
+
+/// 重新索引指定知识库，返回索引结果
+async fn reindex_json(name: &str) -> Result<Json<IndexStats>, (StatusCode, String)> {
+    match knowledge::reindex(name).await {
+        Ok(stats) => Ok(Json(stats)),
+        Err(e) => Err((StatusCode::BAD_REQUEST, format!("{}", e))),
+    }
+}
+
+/// Handler for `/嵌套的前缀/knowledge` GET
+/// 获取所有知识库的概要，以及当前对话使用的知识库：`{"bases": [...], "attached": [...]}`
+/// 知识库的目录和文件是服务端的绝对路径，只返回给服务端所在电脑或局域网
+pub async fn get_bases(ConnectInfo(addr): ConnectInfo<SocketAddr>, uri: OriginalUri, jar: CookieJar) -> Json<Value> {
+    event!(Level::INFO, "GET {} from {}", uri.path(), addr);
+    let attached = jar.get("srx-tzn").map(|c| get_knowledge(c.value())).unwrap_or_default();
+    let mut bases = knowledge::list_bases();
+    if !is_local_request(&addr.ip()) {
+        bases.iter_mut().for_each(|b| b.sources.clear());
+    }
+    Json(json!({"bases": bases, "attached": attached}))
+}
+
+/// Handler for `/嵌套的前缀/knowledge` POST
+/// 创建知识库或添加目录、文件，然后索引，body是json：`{"name": "", "sources": ["服务端路径"]}`，返回索引结果
+pub async fn post_base(ConnectInfo(addr): ConnectInfo<SocketAddr>, uri: OriginalUri, Json(para): Json<KnowledgeSources>) -> Result<Json<IndexStats>, (StatusCode, String)> {
+    event!(Level::INFO, "POST {} from {}", uri.path(), addr);
+    check_local(&addr, "add knowledge base")?;
+    knowledge::add_sources(&para.name, &para.sources).map_err(|e| (StatusCode::BAD_REQUEST, format!("{}", e)))?;
+    reindex_json(&para.name).await
+}
+
+/// Handler for `/嵌套的前缀/knowledge/:name/reindex` POST
+/// 重新索引指定知识库，只处理新增、修改和删除的文件，返回索引结果
+pub async fn reindex_base(Path(name): Path<String>, ConnectInfo(addr): ConnectInfo<SocketAddr>, uri: OriginalUri) -> Result<Json<IndexStats>, (StatusCode, String)> {
+    event!(Level::INFO, "POST {} from {}", uri.path(), addr);
+    check_local(&addr, "reindex knowledge base")?;
+    check_name(&name)?;
+    reindex_json(&name).await
+}
+
+/// Handler for `/嵌套的前缀/knowledge/:name/upload` POST
+/// 上传文件到指定知识库，不存在则创建，同名文件覆盖，然后索引，返回索引结果
+pub async fn upload_to_base(Path(name): Path<String>, ConnectInfo(addr): ConnectInfo<SocketAddr>, uri: OriginalUri, mut multipart: Multipart) -> Result<Json<IndexStats>, (StatusCode, String)> {
+    event!(Level::INFO, "POST {} from {}", uri.path(), addr);
+    check_local(&addr, "upload to knowledge base")?;
+    check_name(&name)?;
+    if !knowledge::contain_base(&name) {
+        knowledge::add_sources(&name, &[]).map_err(|e| (StatusCode::BAD_REQUEST, format!("{}", e)))?;
+    }
+    let dir = knowledge::upload_dir(&name);
+    let file_limit = PARAS.upload_limit.file_mb * 1024 * 1024; // 单个文件最大字节数
+    while let Some(mut field) = multipart.next_field().await.map_err(|e| (StatusCode::BAD_REQUEST, format!("{}", e)))? {
+        let Some(raw_name) = field.file_name().map(|n| n.to_string()) else {
+            continue
+        };
+        let file_name = sanitize_file_name(&raw_name);
+        let upload_file = format!("{}/{}", dir, file_name);
+        let file = File::create(&upload_file).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("create {} error: {}", upload_file, e)))?;
+        let mut file_writer = BufWriter::new(file);
+        let mut size: u64 = 0;
+        let mut error: Option<String> = None;
+        while let Some(chunk) = field.chunk().await.map_err(|e| (StatusCode::BAD_REQUEST, format!("{}", e)))? {
+            size += chunk.len() as u64;
+            if size > file_limit {
+                error = Some(format!("{}: file size exceeds the limit of {} MB", raw_name, PARAS.upload_limit.file_mb));
+                break
+            }
+            file_writer.write_all(&chunk).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("write {} error: {}", upload_file, e)))?;
+        }
+        file_writer.flush().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("write {} error: {}", upload_file, e)))?;
+        drop(file_writer);
+        if error.is_none() {
+            error = check_content_type(&upload_file, &file_name).err();
+        }
+        if let Some(e) = error {
+            event!(Level::WARN, "knowledge base {} reject upload {}: {}", name, raw_name, e);
+            let _ = remove_file(&upload_file).await;
+            return Err((StatusCode::BAD_REQUEST, e))
+        }
+        event!(Level::INFO, "knowledge base {} upload {} done", name, upload_file);
+    }
+    reindex_json(&name).await
+}
+
+/// Handler for `/嵌套的前缀/knowledge/:name` DELETE
+/// 删除指定知识库，包括上传的文件，不会删除添加的服务端目录和文件
+pub async fn delete_base(Path(name): Path<String>, ConnectInfo(addr): ConnectInfo<SocketAddr>, uri: OriginalUri) -> Result<StatusCode, (StatusCode, String)> {
+    event!(Level::INFO, "DELETE {} from {}", uri.path(), addr);
+    check_local(&addr, "delete knowledge base")?;
+    match knowledge::delete_base(&name) {
+        Ok(_) => Ok(StatusCode::OK),
+        Err(e) => Err((StatusCode::NOT_FOUND, format!("{}", e))),
+    }
+}
+
+/// Handler for `/嵌套的前缀/knowledge/attach` GET
+/// `?names=a,b`设置当前对话使用的知识库，多个用逗号间隔，为空则不使用知识库，返回实际使用的知识库
+pub async fn attach_bases(Query(params): Query<HashMap<String, String>>, uri: OriginalUri, jar: CookieJar) -> Result<Json<Vec<String>>, (StatusCode, String)> {
+    let Some(c) = jar.get("srx-tzn") else {
+        event!(Level::INFO, "GET {}, attach knowledge base failed, no cookie", uri.path());
+        return Err((StatusCode::BAD_REQUEST, "no cookie".to_string()))
+    };
+    if !is_valid_uuid(c.value()) {
+        event!(Level::WARN, "GET {}, attach knowledge base failed, invalid cookie {:?}", uri.path(), c.value());
+        return Err((StatusCode::BAD_REQUEST, "invalid cookie".to_string()))
+    }
+    let uuid = c.value().to_string();
+    let mut names: Vec<String> = vec![];
+    for n in params.get("names").map(|n| n.as_str()).unwrap_or("").split(',').map(|n| n.trim()).filter(|n| !n.is_empty()) {
+        check_name(n)?;
+        if !knowledge::contain_base(n) {
+            return Err((StatusCode::NOT_FOUND, format!("no such knowledge base: {}", n)))
+        }
+        if !names.iter().any(|x| x == n) {
+            names.push(n.to_string());
+        }
+    }
+    event!(Level::INFO, "{} GET {}, use knowledge base: {}", uuid, uri.path(), names.join(", "));
+    set_knowledge(&uuid, names.clone());
+    Ok(Json(names))
+}
@@ -23,13 +23,14 @@ use openai_dive::v1::{
 use tracing::{event, Level};
 
 use crate::{
-    parse_paras::PARAS,
+    parse_paras::{PARAS, Api},
     error::MyError,
     info::{
         get_messages, // 获取指定uuid最近的指定数量个message
         update_qa_msg_num, // 客户端下拉选项`上下文消息数`改变时更新限制的问答对数量、限制的消息数量、提问是否包含prompt
         label_remembered,
         update_token,
+        get_memory_namespaces, // 获取对话使用的记忆命名空间
     },
     api::handlers::chat::{
         get_qa_msg_p,
@@ -38,9 +39,12 @@ use crate::{
     memory::{
         MEMORY,
         SimpleMemory,
+        GLOBAL_NAMESPACE,
+    },
+    openai::for_chat::{
+        run_single_llm,
+        get_print_token,
     },
-    tools::built_in_tools::hacker_news::run_single_llm,
-    openai::for_chat::get_print_token,
 };
 
 /// Handler for `/嵌套的前缀/memory` GET
@@ -72,15 +76,16 @@ pub async fn memory(Query(params): Query<HashMap<String, String>>, ConnectInfo(a
                 (m.trim().to_string(), false)
             };
             // 获取用于提取记忆的模型，返回 (api_key, endpoint, model, reasoning)
+            let snapshot = PARAS.snapshot();
             let model_for_memory = match params.get("model") {
-                Some(model) => match PARAS.api.get_model_by_str(&model) {
+                Some(model) => match snapshot.api.get_model_by_str(&model) {
                     Ok(model_for_memory) => model_for_memory,
                     Err(e) => {
                         event!(Level::ERROR, "{} get model for memory error: {}", uuid, e);
                         return Ok(StatusCode::OK)
                     },
                 },
-                None => match PARAS.api.get_default_model() {
+                None => match snapshot.api.get_default_model() {
                     Ok(model_for_memory) => model_for_memory,
                     Err(e) => {
                         event!(Level::ERROR, "{} get default model for memory error: {}", uuid, e);
@@ -88,80 +93,90 @@ pub async fn memory(Query(params): Query<HashMap<String, String>>, ConnectInfo(a
                     },
                 },
             };
-            // 提取记忆
-            let memory_summary = extract_memory(&for_memory, model_for_memory).await?;
-            // 计算 embedding
-            let embedding = get_embedding(&uuid, memory_summary.clone()).await?;
-            // 如果是服务端所在电脑发起的请求，key使用`local`存储到输出路径根路径下的`memory.json`，否则使用各自uuid并存储到各自uuid路径`uuid_memory.json`
-            let key = if is_local {
-                "local".to_string()
+            // 提取记忆并存储
+            remember(&snapshot.api, &uuid, for_memory, model_for_memory, is_local).await?;
+            // 最后将已提取记忆的对话标注为 remembered
+            if from_context {
+                label_remembered(&uuid);
+            }
+        }
+    } else {
+        event!(Level::INFO, "GET {}, set memory failed, no cookie", uri.path()); // 注意：`axum::http::Uri`只能捕获到`/hello`，不包含嵌套的`/嵌套的前缀`前缀，使用`OriginalUri`可以
+    }
+    Ok(StatusCode::OK)
+}
+
+/// 从指定内容提取记忆和标签，存入对话使用的第一个命名空间，没有则存入全局命名空间
+/// 如果是服务端所在电脑发起的请求，key使用`local`存储到输出路径根路径下的`memory.json`，否则使用各自uuid并存储到各自uuid路径`uuid_memory.json`
+/// model_for_memory: (api_key, endpoint, 模型名称, 是否支持深度思考)
+pub async fn remember(api: &Api, uuid: &str, for_memory: String, model_for_memory: (String, String, String, bool), is_local: bool) -> Result<(), MyError> {
+    // 提取记忆和标签
+    let (memory_summary, tags) = extract_memory(&for_memory, model_for_memory).await?;
+    // 新记忆存入对话使用的第一个命名空间，没有则存入全局命名空间
+    let namespace = get_memory_namespaces(uuid).into_iter().next().unwrap_or(GLOBAL_NAMESPACE.to_string());
+    // 计算 embedding
+    let embedding = get_embedding(api, uuid, memory_summary.clone()).await?;
+    let key = if is_local {
+        "local".to_string()
+    } else {
+        uuid.to_string()
+    };
+    let mut data = MEMORY.lock().unwrap();
+    let old = match data.get_mut(&key) {
+        Some(memory) => memory.remember(for_memory, memory_summary, namespace, tags, embedding, is_local),
+        None => {
+            let memory_file = if is_local {
+                format!("{}/memory.json", PARAS.memory_dir)
             } else {
-                uuid.clone()
+                format!("{}/{}/{}_memory.json", PARAS.outpath, key, key)
             };
-            let mut data = MEMORY.lock().unwrap();
-            let old = match data.get_mut(&key) {
-                Some(memory) => memory.remember(for_memory, memory_summary, embedding, is_local),
+            let memory_path = Path::new(&memory_file);
+            if memory_path.exists() && memory_path.is_file() {
+                match SimpleMemory::load_from_file(&memory_file, is_local) {
+                    Ok(mut memory) => {
+                        let old = memory.remember(for_memory, memory_summary, namespace, tags, embedding, is_local);
+                        data.insert(key, memory);
+                        old
+                    },
+                    Err(e) => {
+                        event!(Level::ERROR, "load memory file ({}) error: {}", memory_file, e);
+                        None
+                    },
+                }
+            } else {
+                let mut memory = SimpleMemory::new(100, memory_file); // 设置最多100条记忆
+                let old = memory.remember(for_memory, memory_summary, namespace, tags, embedding, is_local);
+                data.insert(key, memory);
+                old
+            }
+        },
+    };
+    // 如果是本地记忆，且移除了旧记忆，则将旧记忆加到 memory_old.json 中
+    if is_local {
+        if let Some(old_notes) = old {
+            match data.get_mut("old") {
+                Some(memory) => memory.append_memory(old_notes),
                 None => {
-                    let memory_file = if is_local {
-                        format!("{}/memory.json", PARAS.memory_dir)
-                    } else {
-                        format!("{}/{}/{}_memory.json", PARAS.outpath, key, key)
-                    };
+                    let memory_file = format!("{}/memory_old.json", PARAS.memory_dir);
                     let memory_path = Path::new(&memory_file);
                     if memory_path.exists() && memory_path.is_file() {
                         match SimpleMemory::load_from_file(&memory_file, is_local) {
                             Ok(mut memory) => {
-                                let old = memory.remember(for_memory, memory_summary, embedding, is_local);
-                                data.insert(key, memory);
-                                old
-                            },
-                            Err(e) => {
-                                event!(Level::ERROR, "load memory file ({}) error: {}", memory_file, e);
-                                None
+                                memory.append_memory(old_notes);
+                                data.insert("old".to_string(), memory);
                             },
+                            Err(e) => event!(Level::ERROR, "load old memory file ({}) error: {}", memory_file, e),
                         }
                     } else {
-                        let mut memory = SimpleMemory::new(100, memory_file); // 设置最多100条记忆
-                        let old = memory.remember(for_memory, memory_summary, embedding, is_local);
-                        data.insert(key, memory);
-                        old
+                        let mut memory = SimpleMemory::new(usize::MAX, memory_file);
+                        memory.append_memory(old_notes);
+                        data.insert("old".to_string(), memory);
                     }
                 },
-            };
-            // 如果是本地记忆，且移除了旧记忆，则将旧记忆加到 memory_old.json 中
-            if is_local {
-                if let Some(old_notes) = old {
-                    match data.get_mut("old") {
-                        Some(memory) => memory.append_memory(old_notes),
-                        None => {
-                            let memory_file = format!("{}/memory_old.json", PARAS.memory_dir);
-                            let memory_path = Path::new(&memory_file);
-                            if memory_path.exists() && memory_path.is_file() {
-                                match SimpleMemory::load_from_file(&memory_file, is_local) {
-                                    Ok(mut memory) => {
-                                        memory.append_memory(old_notes);
-                                        data.insert("old".to_string(), memory);
-                                    },
-                                    Err(e) => event!(Level::ERROR, "load old memory file ({}) error: {}", memory_file, e),
-                                }
-                            } else {
-                                let mut memory = SimpleMemory::new(usize::MAX, memory_file);
-                                memory.append_memory(old_notes);
-                                data.insert("old".to_string(), memory);
-                            }
-                        },
-                    }
-                }
-            }
-            // 最后将已提取记忆的对话标注为 remembered
-            if from_context {
-                label_remembered(&uuid);
             }
         }
-    } else {
-        event!(Level::INFO, "GET {}, set memory failed, no cookie", uri.path()); // 注意：`axum::http::Uri`只能捕获到`/hello`，不包含嵌套的`/嵌套的前缀`前缀，使用`OriginalUri`可以
     }
-    Ok(StatusCode::OK)
+    Ok(())
 }
 
 /// 提取记忆的prompt
@@ -200,23 +215,37 @@ If multiple memories are possible, choose only the most useful one.
 
 If nothing should be remembered, output an empty string.
 
-Output only the final memory string. Do not include JSON, Markdown, headings, explanations, labels, quotes, or bullet points.
+Output the final memory string on the first line. On the second line, output `tags:` followed by 1 to 3 short lowercase topic tags separated by commas, for example `tags: rust, build`. Do not include JSON, Markdown, headings, explanations, other labels, quotes, or bullet points.
 
 Conversation:
 "###;
 
-/// 调用 LLM 从指定内容中抽取记忆
+/// 调用 LLM 从指定内容中抽取记忆，返回(记忆, 标签)
 /// text: 要提取记忆的原始内容
 /// model_for_memory: (api_key, endpoint, 模型名称, 是否支持深度思考)
-async fn extract_memory(text: &str, model_for_memory: (String, String, String, bool)) -> Result<String, MyError> {
+async fn extract_memory(text: &str, model_for_memory: (String, String, String, bool)) -> Result<(String, Vec<String>), MyError> {
     let content = format!("{}{}", MEMORY_PROMPT, text);
-    run_single_llm("memory", content, model_for_memory.0, model_for_memory.1, model_for_memory.2).await
+    let result = run_single_llm("memory", content, model_for_memory.0, model_for_memory.1, model_for_memory.2).await?;
+    Ok(split_memory_tags(&result))
+}
+
+/// 拆分模型返回的记忆和最后的`tags:`行，没有标签行则标签为空
+fn split_memory_tags(result: &str) -> (String, Vec<String>) {
+    let result = result.trim();
+    match result.rsplit_once('\n') {
+        Some((summary, last)) if last.trim().to_lowercase().starts_with("tags:") => {
+            let tags = last.trim()[5..].split(',').map(|t| t.trim().trim_matches('#').to_lowercase()).filter(|t| !t.is_empty()).collect();
+            (summary.trim().to_string(), tags)
+        },
+        _ if result.to_lowercase().starts_with("tags:") => (String::new(), vec![]), // 只有标签，没有要记住的内容
+        _ => (result.to_string(), vec![]),
+    }
 }
 
 /// 调用 embedding 模型，计算指定字符串的 embedding 向量
-pub async fn get_embedding(uuid: &str, text: String) -> Result<Option<Vec<f64>>, MyError> {
+pub async fn get_embedding(api: &Api, uuid: &str, text: String) -> Result<Option<Vec<f64>>, MyError> {
     // 获取 embedding 模型
-    if let Some((api_key, endpoint, model)) = PARAS.api.get_embedding_modle(None) {
+    if let Some((api_key, endpoint, model)) = api.get_embedding_modle(None) {
         // 使用api key初始化
         let mut client = Client::new(api_key);
         client.set_base_url(&endpoint); // 从0.7.0开始舍弃了new_with_base
@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{
+        Json,
+        Path,
+        Query,
+        OriginalUri,
+        ConnectInfo,
+    },
+    http::StatusCode,
+};
+use axum_extra::extract::cookie::CookieJar;
+use serde::Deserialize;
+use tracing::{event, Level};
+
+use crate::{
+    parse_paras::PARAS,
+    info::{
+        create_uuid_dir, // uuid文件夹不存在则创建
+        is_valid_uuid, // cookie值是否是服务端生成的uuid
+        get_memory_injections, // 获取指定uuid每次回答注入的记忆
+        get_memory_namespaces, // 获取指定uuid使用的记忆命名空间
+        set_memory_namespaces, // 设置指定uuid使用的记忆命名空间
+    },
+    memory::{
+        GLOBAL_NAMESPACE,
+        manage::{
+            self,
+            MemoryInjection,
+            NoteEdit,
+            NoteView,
+        },
+        consolidate::{
+            MemoryChange,
+            consolidate,
+            read_changelog,
+        },
+        auto::memory_model, // 自动提取和整理记忆使用的模型
+    },
+    api::handlers::{
+        chat::is_local_request,
+        memory::get_embedding, // 计算指定字符串的embedding
+    },
+};
+
+/// 合并记忆的参数
+#[derive(Deserialize)]
+pub struct MergeNotes {
+    ids:     Vec<String>, // 要合并的记忆id，合并后保留第一条的id
+    summary: String,      // 合并后的记忆
+}
+
+/// 根据`?store=`获取记忆的存储位置：`local`是`memory.json`，`old`是`memory_old.json`，只允许服务端所在电脑或局域网访问；`uuid`是当前对话的记忆，默认`uuid`，cookie值必须是服务端生成的uuid，避免伪造的cookie访问`local`、`old`或其他路径
+fn store_key(store: Option<&String>, addr: &SocketAddr, jar: &CookieJar) -> Result<String, (StatusCode, String)> {
+    match store.map(|s| s.as_str()).unwrap_or("uuid") {
+        s @ ("local" | "old") => {
+            if is_local_request(&addr.ip()) {
+                Ok(s.to_string())
+            } else {
+                event!(Level::WARN, "reject access {} memory from {}", s, addr);
+                Err((StatusCode::FORBIDDEN, format!("{} memory is only allowed from local network", s)))
+            }
+        },
+        "uuid" => match jar.get("srx-tzn") {
+            Some(c) if is_valid_uuid(c.value()) => Ok(c.value().to_string()),
+            Some(c) => {
+                event!(Level::WARN, "reject invalid cookie {:?} from {}", c.value(), addr);
+                Err((StatusCode::BAD_REQUEST, "invalid cookie".to_string()))
+            },
+            None => Err((StatusCode::BAD_REQUEST, "no cookie".to_string())),
+        },
+        s => Err((StatusCode::BAD_REQUEST, format!("invalid memory store: {}, must be local, old or uuid", s))),
+    }
+}
+
+/// 计算修改后记忆的embedding，没有embedding模型或出错则为None，检索时使用关键词匹配
+async fn note_embedding(key: &str, summary: &str) -> Option<Vec<f64>> {
+    get_embedding(&PARAS.snapshot().api, key, summary.to_string()).await.unwrap_or_else(|e| {
+        event!(Level::WARN, "{} memory embedding error: {}", key, e);
+        None
+    })
+}
+
+/// Handler for `/嵌套的前缀/memory/notes` GET
+/// 获取记忆，`?store=local|old|uuid`指定存储位置，`?q=xxx`只返回与搜索内容相关的记忆并按相关性排序，否则最新的在前，`?namespace=xxx`只返回该命名空间的记忆
+pub async fn list_notes(Query(params): Query<HashMap<String, String>>, ConnectInfo(addr): ConnectInfo<SocketAddr>, uri: OriginalUri, jar: CookieJar) -> Result<Json<Vec<NoteView>>, (StatusCode, String)> {
+    event!(Level::INFO, "GET {} from {}", uri.path(), addr);
+    let key = store_key(params.get("store"), &addr, &jar)?;
+    let query = params.get("q").map(|q| q.trim()).filter(|q| !q.is_empty());
+    let embedding = match query {
+        Some(q) => note_embedding(&key, q).await,
+        None => None,
+    };
+    let namespace = params.get("namespace").map(|n| n.trim()).filter(|n| !n.is_empty());
+    Ok(Json(manage::list_notes(&key, query, embedding, namespace)))
+}
+
+/// Handler for `/嵌套的前缀/memory/notes/:id` PUT
+/// 修改记忆，body是json：`{"summary": "", "raw": "", "reserved": true, "namespace": "", "tags": [""]}`，不指定的字段保持不变，`reserved`为true则固定该记忆，不会因数量上限被移除
+pub async fn update_note(Path(id): Path<String>, Query(params): Query<HashMap<String, String>>, ConnectInfo(addr): ConnectInfo<SocketAddr>, uri: OriginalUri, jar: CookieJar, Json(edit): Json<NoteEdit>) -> Result<StatusCode, (StatusCode, String)> {
+    event!(Level::INFO, "PUT {} from {}", uri.path(), addr);
+    let key = store_key(params.get("store"), &addr, &jar)?;
+    let Some(note) = manage::get_note(&key, &id) else {
+        return Err((StatusCode::NOT_FOUND, format!("no memory {}", id)))
+    };
+    // 修改了记忆内容则重新计算embedding
+    let embedding = match &edit.summary {
+        Some(s) if *s != note.summary => {
+            if s.trim().is_empty() {
+                return Err((StatusCode::BAD_REQUEST, "memory can not be empty".to_string()))
+            }
+            note_embedding(&key, s).await
+        },
+        _ => note.embedding,
+    };
+    manage::update_note(&key, &id, edit, embedding).map_err(|e| (StatusCode::NOT_FOUND, format!("{}", e)))?;
+    Ok(StatusCode::OK)
+}
+
+/// Handler for `/嵌套的前缀/memory/notes/:id` DELETE
+/// 删除记忆
+pub async fn delete_note(Path(id): Path<String>, Query(params): Query<HashMap<String, String>>, ConnectInfo(addr): ConnectInfo<SocketAddr>, uri: OriginalUri, jar: CookieJar) -> Result<StatusCode, (StatusCode, String)> {
+    event!(Level::INFO, "DELETE {} from {}", uri.path(), addr);
+    let key = store_key(params.get("store"), &addr, &jar)?;
+    if manage::delete_notes(&key, &[id.clone()]) == 0 {
+        return Err((StatusCode::NOT_FOUND, format!("no memory {}", id)))
+    }
+    Ok(StatusCode::OK)
+}
+
+/// Handler for `/嵌套的前缀/memory/notes/merge` POST
+/// 合并重复的记忆，body是json：`{"ids": ["", ""], "summary": "合并后的记忆"}`，保留第一条的id，返回该id
+pub async fn merge_notes(Query(params): Query<HashMap<String, String>>, ConnectInfo(addr): ConnectInfo<SocketAddr>, uri: OriginalUri, jar: CookieJar, Json(para): Json<MergeNotes>) -> Result<String, (StatusCode, String)> {
+    event!(Level::INFO, "POST {} from {}", uri.path(), addr);
+    let key = store_key(params.get("store"), &addr, &jar)?;
+    if para.summary.trim().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "memory can not be empty".to_string()))
+    }
+    let embedding = note_embedding(&key, &para.summary).await;
+    manage::merge_notes(&key, &para.ids, para.summary, embedding).map_err(|e| (StatusCode::BAD_REQUEST, format!("{}", e)))
+}
+
+/// Handler for `/嵌套的前缀/memory/notes/:id/move` POST
+/// 将记忆从`?store=`移动到`?to=`，例如在本地记忆和当前对话的记忆之间移动
+pub async fn move_note(Path(id): Path<String>, Query(params): Query<HashMap<String, String>>, ConnectInfo(addr): ConnectInfo<SocketAddr>, uri: OriginalUri, jar: CookieJar) -> Result<StatusCode, (StatusCode, String)> {
+    event!(Level::INFO, "POST {} from {}", uri.path(), addr);
+    let from = store_key(params.get("store"), &addr, &jar)?;
+    let to = store_key(params.get("to"), &addr, &jar)?;
+    if !matches!(to.as_str(), "local" | "old") {
+        create_uuid_dir(&to).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", e)))?;
+    }
+    manage::move_note(&from, &to, &id).map_err(|e| (StatusCode::BAD_REQUEST, format!("{}", e)))?;
+    Ok(StatusCode::OK)
+}
+
+/// Handler for `/嵌套的前缀/memory/injections` GET
+/// 获取当前对话每次回答注入的记忆，最新的在前
+pub async fn injections(uri: OriginalUri, jar: CookieJar) -> Result<Json<Vec<MemoryInjection>>, (StatusCode, String)> {
+    event!(Level::INFO, "GET {}", uri.path());
+    match jar.get("srx-tzn") {
+        Some(c) if is_valid_uuid(c.value()) => Ok(Json(get_memory_injections(c.value()))),
+        Some(c) => {
+            event!(Level::WARN, "GET {}, memory injections failed, invalid cookie {:?}", uri.path(), c.value());
+            Err((StatusCode::BAD_REQUEST, "invalid cookie".to_string()))
+        },
+        None => Err((StatusCode::BAD_REQUEST, "no cookie".to_string())),
+    }
+}
+
+/// Handler for `/嵌套的前缀/memory/namespaces` GET
+/// `?names=a,b`设置当前对话使用的记忆命名空间（项目），多个用逗号间隔，为空则只使用全局命名空间，不指定`names`则只获取，返回当前对话使用的命名空间
+/// 对话只检索这些命名空间和全局命名空间的记忆，新提取的记忆存入第一个命名空间
+pub async fn namespaces(Query(params): Query<HashMap<String, String>>, uri: OriginalUri, jar: CookieJar) -> Result<Json<Vec<String>>, (StatusCode, String)> {
+    let Some(c) = jar.get("srx-tzn") else {
+        event!(Level::INFO, "GET {}, memory namespaces failed, no cookie", uri.path());
+        return Err((StatusCode::BAD_REQUEST, "no cookie".to_string()))
+    };
+    if !is_valid_uuid(c.value()) {
+        event!(Level::WARN, "GET {}, memory namespaces failed, invalid cookie {:?}", uri.path(), c.value());
+        return Err((StatusCode::BAD_REQUEST, "invalid cookie".to_string()))
+    }
+    let uuid = c.value().to_string();
+    if let Some(names) = params.get("names") {
+        let mut namespaces: Vec<String> = vec![];
+        for n in names.split(',').map(|n| n.trim()).filter(|n| !n.is_empty() && *n != GLOBAL_NAMESPACE) {
+            if !namespaces.iter().any(|x| x == n) {
+                namespaces.push(n.to_string());
+            }
+        }
+        event!(Level::INFO, "{} GET {}, use memory namespace: {}", uuid, uri.path(), namespaces.join(", "));
+        set_memory_namespaces(&uuid, namespaces);
+    }
+    Ok(Json(get_memory_namespaces(&uuid)))
+}
+
+/// Handler for `/嵌套的前缀/memory/consolidate` POST
+/// 立即用LLM整理`?store=`的记忆：合并重叠的记忆、解决矛盾（新的记忆优先）、改写记忆，返回本次的变更
+pub async fn consolidate_notes(Query(params): Query<HashMap<String, String>>, ConnectInfo(addr): ConnectInfo<SocketAddr>, uri: OriginalUri, jar: CookieJar) -> Result<Json<Vec<MemoryChange>>, (StatusCode, String)> {
+    event!(Level::INFO, "POST {} from {}", uri.path(), addr);
+    let key = store_key(params.get("store"), &addr, &jar)?;
+    if key == "old" {
+        return Err((StatusCode::BAD_REQUEST, "old memory can not be consolidated".to_string()))
+    }
+    let snapshot = PARAS.snapshot();
+    let model = memory_model(&snapshot.api).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", e)))?;
+    consolidate(&snapshot.api, &key, model, true).await.map(Json).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", e)))
+}
+
+/// Handler for `/嵌套的前缀/memory/changelog` GET
+/// 获取`?store=`的记忆整理变更记录，最新的在前
+pub async fn changelog(Query(params): Query<HashMap<String, String>>, ConnectInfo(addr): ConnectInfo<SocketAddr>, uri: OriginalUri, jar: CookieJar) -> Result<Json<Vec<MemoryChange>>, (StatusCode, String)> {
+    event!(Level::INFO, "GET {} from {}", uri.path(), addr);
+    let key = store_key(params.get("store"), &addr, &jar)?;
+    Ok(Json(read_changelog(&key)))
+}
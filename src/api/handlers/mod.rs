@@ -11,12 +11,18 @@ pub mod index; // `GET /嵌套的前缀`
 pub mod chat; // `GET /嵌套的前缀/chat`
 pub mod save; // `GET /嵌套的前缀/save`
 pub mod delete_message; // `GET /嵌套的前缀/delmsg/:id`
+pub mod pin_message; // `POST /嵌套的前缀/pinmsg/:id`
 pub mod microphone; // `GET /嵌套的前缀/microphone/:id`
 pub mod incognito; // `GET /嵌套的前缀/incognito`
-pub mod approval; // `GET /嵌套的前缀/approval`
+pub mod approval; // `POST /嵌套的前缀/approval`、`GET /嵌套的前缀/approvals`和`GET /嵌套的前缀/approvals/page`
 pub mod new_instruction; // `GET /嵌套的前缀/instruction`
 pub mod goal; // `GET /嵌套的前缀/goal`
 pub mod memory; // `GET /嵌套的前缀/memory`
+pub mod memory_notes; // `GET /嵌套的前缀/memory/notes`、`PUT,DELETE /嵌套的前缀/memory/notes/:id`、`POST /嵌套的前缀/memory/notes/merge`、`POST /嵌套的前缀/memory/notes/:id/move`、`GET /嵌套的前缀/memory/injections`、`GET /嵌套的前缀/memory/namespaces`、`POST /嵌套的前缀/memory/consolidate`和`GET /嵌套的前缀/memory/changelog`
+pub mod audit; // `GET /嵌套的前缀/audit`和`GET /嵌套的前缀/audit/page`
 pub mod upload; // `POST /嵌套的前缀/upload`
+pub mod knowledge; // `GET,POST /嵌套的前缀/knowledge`、`DELETE /嵌套的前缀/knowledge/:name`、`POST /嵌套的前缀/knowledge/:name/reindex`、`POST /嵌套的前缀/knowledge/:name/upload`和`GET /嵌套的前缀/knowledge/attach`
 pub mod usage; // `GET /嵌套的前缀/usage`
+pub mod reload; // `POST /嵌套的前缀/reload`
+pub mod prompts; // `GET,POST /嵌套的前缀/prompts`、`PUT,DELETE /嵌套的前缀/prompts/:id`、`GET /嵌套的前缀/prompts/export`和`POST /嵌套的前缀/prompts/import`
 pub mod fallback; // `NOT_FOUND`
@@ -0,0 +1,31 @@
+use axum::{
+    extract::OriginalUri,
+    http::StatusCode,
+};
+use axum_extra::extract::cookie::CookieJar;
+use tracing::{event, Level};
+
+/// info: 记录所有用户的信息
+use crate::info::pin_msg_by_id; // 固定或取消固定服务端指定uuid中指定id的信息，这里id格式是“p”+序号索引，比如“p0”表示第一条信息
+
+/// Handler for `/嵌套的前缀/pinmsg/:id` POST
+/// url指定要固定或取消固定的信息id，固定的信息始终包含在上下文中，返回切换后是否固定：`true`或`false`
+pub async fn pin_msg(axum::extract::Path(id): axum::extract::Path<String>, uri: OriginalUri, jar: CookieJar) -> (StatusCode, String) {
+    // 获取uuid
+    if let Some(c) = jar.get("srx-tzn") { // 获取cookie
+        let uuid = c.value().to_string();
+        match pin_msg_by_id(&uuid, &id) {
+            Ok(pinned) => {
+                event!(Level::INFO, "{} POST {}, {} message {} success", uuid, uri.path(), if pinned { "pin" } else { "unpin" }, id);
+                (StatusCode::OK, pinned.to_string())
+            },
+            Err(e) => {
+                event!(Level::INFO, "{} POST {}, pin message {} failed: {}", uuid, uri.path(), id, e);
+                (StatusCode::BAD_REQUEST, e)
+            },
+        }
+    } else {
+        event!(Level::INFO, "POST {}, pin message failed, no cookie", uri.path());
+        (StatusCode::BAD_REQUEST, "no cookie".to_string())
+    }
+}
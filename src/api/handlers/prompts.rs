@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{
+        Json,
+        Path,
+        Query,
+        OriginalUri,
+        ConnectInfo,
+    },
+    http::StatusCode,
+};
+use serde_json::{json, Value};
+use tracing::{event, Level};
+
+use crate::{
+    parse_paras::Prompt,
+    prompt_library::{
+        self,
+        PromptItem,
+    },
+    api::handlers::chat::check_local, // 修改prompt库只允许服务端所在电脑或局域网发起
+};
+
+/// Handler for `/嵌套的前缀/prompts` GET
+/// 获取参数文件和prompt库中的所有prompt，`?tag=xxx`只获取含有该tag的prompt，页面用于更新prompt下拉选项
+pub async fn get_prompts(Query(params): Query<HashMap<String, String>>, uri: OriginalUri) -> Json<Vec<PromptItem>> {
+    event!(Level::INFO, "GET {}", uri.path());
+    Json(prompt_library::list_prompts(params.get("tag").map(|t| t.as_str())))
+}
+
+/// Handler for `/嵌套的前缀/prompts` POST
+/// 添加prompt到prompt库，body是json格式的prompt：`{"name": "", "content": "", "tags": [], "model": "", "tools": ""}`，返回id
+pub async fn post_prompt(ConnectInfo(addr): ConnectInfo<SocketAddr>, uri: OriginalUri, Json(prompt): Json<Prompt>) -> Result<Json<Value>, (StatusCode, String)> {
+    event!(Level::INFO, "POST {} from {}", uri.path(), addr);
+    check_local(&addr, "add prompt")?;
+    match prompt_library::add_prompt(prompt) {
+        Ok(id) => Ok(Json(json!({"id": id}))),
+        Err(e) => Err((StatusCode::BAD_REQUEST, format!("{}", e))),
+    }
+}
+
+/// Handler for `/嵌套的前缀/prompts/:id` PUT
+/// 修改prompt库中指定id的prompt，参数文件中的prompt不能修改
+pub async fn put_prompt(Path(id): Path<String>, ConnectInfo(addr): ConnectInfo<SocketAddr>, uri: OriginalUri, Json(prompt): Json<Prompt>) -> Result<StatusCode, (StatusCode, String)> {
+    event!(Level::INFO, "PUT {} from {}", uri.path(), addr);
+    check_local(&addr, "update prompt")?;
+    match prompt_library::update_prompt(&id, prompt) {
+        Ok(_) => Ok(StatusCode::OK),
+        Err(e) => Err((StatusCode::BAD_REQUEST, format!("{}", e))),
+    }
+}
+
+/// Handler for `/嵌套的前缀/prompts/:id` DELETE
+/// 删除prompt库中指定id的prompt，已使用该prompt的对话不受影响
+pub async fn delete_prompt(Path(id): Path<String>, ConnectInfo(addr): ConnectInfo<SocketAddr>, uri: OriginalUri) -> Result<StatusCode, (StatusCode, String)> {
+    event!(Level::INFO, "DELETE {} from {}", uri.path(), addr);
+    check_local(&addr, "delete prompt")?;
+    match prompt_library::delete_prompt(&id) {
+        Ok(_) => Ok(StatusCode::OK),
+        Err(e) => Err((StatusCode::NOT_FOUND, format!("{}", e))),
+    }
+}
+
+/// Handler for `/嵌套的前缀/prompts/export` GET
+/// 导出prompt包，json数组，默认只导出prompt库，`?all=true`包括参数文件中的prompt，`?tag=xxx`只导出含有该tag的prompt
+pub async fn export_prompts(Query(params): Query<HashMap<String, String>>, uri: OriginalUri) -> Json<Vec<Prompt>> {
+    event!(Level::INFO, "GET {}", uri.path());
+    let all = params.get("all").map(|a| a == "true").unwrap_or(false);
+    Json(prompt_library::export_prompts(params.get("tag").map(|t| t.as_str()), all))
+}
+
+/// Handler for `/嵌套的前缀/prompts/import` POST
+/// 导入prompt包，body是导出的json数组，与prompt库中同名的prompt会被覆盖，返回添加和覆盖的数量
+pub async fn import_prompts(ConnectInfo(addr): ConnectInfo<SocketAddr>, uri: OriginalUri, Json(prompts): Json<Vec<Prompt>>) -> Result<Json<Value>, (StatusCode, String)> {
+    event!(Level::INFO, "POST {} from {}", uri.path(), addr);
+    check_local(&addr, "import prompt")?;
+    match prompt_library::import_prompts(prompts) {
+        Ok((added, updated)) => {
+            event!(Level::INFO, "import prompts: {} added, {} updated", added, updated);
+            Ok(Json(json!({"added": added, "updated": updated})))
+        },
+        Err(e) => Err((StatusCode::BAD_REQUEST, format!("{}", e))),
+    }
+}
@@ -0,0 +1,35 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{
+        OriginalUri,
+        ConnectInfo,
+    },
+    http::StatusCode,
+};
+use tracing::{event, Level};
+
+use crate::{
+    parse_paras::PARAS,
+    api::handlers::chat::check_local,
+};
+
+/// Handler for `/嵌套的前缀/reload` POST
+/// 重新读取参数文件，更新Api、prompts、external_tools、http_tools、openapi_tools、skills、mcp_servers，只允许服务端所在电脑或局域网发起
+/// 重载失败时保留旧配置，返回错误信息，不影响服务运行，刷新页面即可看到新的下拉选项
+pub async fn reload(ConnectInfo(addr): ConnectInfo<SocketAddr>, uri: OriginalUri) -> (StatusCode, String) {
+    event!(Level::INFO, "POST {} from {}", uri.path(), addr); // 注意：`axum::http::Uri`只能捕获到`/hello`，不包含嵌套的`/嵌套的前缀`前缀，使用`OriginalUri`可以
+    if let Err(e) = check_local(&addr, "reload config") {
+        return e
+    }
+    match PARAS.reload().await {
+        Ok(summary) => {
+            event!(Level::INFO, "{}", summary);
+            (StatusCode::OK, summary)
+        },
+        Err(e) => {
+            event!(Level::ERROR, "reload config error, keep the old config: {}", e);
+            (StatusCode::BAD_REQUEST, format!("reload config error, keep the old config: {}", e))
+        },
+    }
+}
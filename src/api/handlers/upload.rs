@@ -1,11 +1,15 @@
 use std::collections::HashMap;
 use std::fs::read;
+use std::io::Read;
+use std::path::Path;
 
-use axum::extract::{Multipart, OriginalUri};
+use axum::extract::{Multipart, OriginalUri, Query};
 use axum_extra::extract::cookie::CookieJar;
+use serde::Serialize;
 use tokio::{
-    fs::File,
+    fs::{File, remove_file},
     io::{AsyncWriteExt, BufWriter},
+    task::spawn_blocking,
 };
 use chrono::Local;
 use openai_dive::v1::resources::chat::{
@@ -15,34 +19,82 @@ use openai_dive::v1::resources::chat::{
 //use tokio_util::io::ReaderStream;
 use tracing::{event, Level};
 use uuid::Uuid;
+use walkdir::WalkDir;
 
 /// info: 记录所有用户的信息
 /// error: 定义的错误类型，用于错误传递
 use crate::{
-    code::project::merge_code, // 从上传的代码压缩包中获取所有脚本的代码，合并到一起，作为提问的问题
+    code::{
+        project::merge_code, // 从上传的代码压缩包中获取所有脚本的代码，合并到一起，作为提问的问题
+        traverse::{
+            archive_stem, // 去除压缩包后缀，不是支持的压缩包格式则返回None
+            ARCHIVE_FORMATS, // 支持的代码压缩包格式
+        },
+    },
     error::MyError,
     info::{
         create_cookie, // 根据指定uuid创建cookie
         update_cookie_max_age, // 更新指定CookieJar的max-age
         create_uuid_dir, // uuid文件夹不存在则创建
+        is_valid_uuid, // cookie值是否是服务端生成的uuid
         insert_message, // 将指定message插入到指定uuid的messages中
         DataType, // 存储问答信息的数据
         try_read_file, // 判断指定字符串是否是指定uuid中的文件，如果是则读取内容
-        get_msg_token, // pos>=0表示索引位置，pos<0表示倒数第几个，比如0表示第1个，1表示第2个，-1表示最后一个，-2表示倒数第个
+        set_msg_token, // 更新指定位置message的token数，pos<0表示倒数第几个
+        replace_raw_message, // 替换记录了指定文件名的用户信息的内容
+        push_unranked_code, // 记录还没有按实际的问题选取文件的代码压缩包
+        take_unranked_code, // 取出还没有按实际的问题选取文件的代码压缩包
+    },
+    openai::{
+        for_image::image_to_base64, // 图片转base64，返回base64编码的字符串
+        for_vision::{
+            is_image_file, // 是否是需要处理的图片格式
+            normalize_image, // 按EXIF方向旋转、缩小超过最大宽高的图片、转换格式
+        },
     },
-    openai::for_image::image_to_base64, // 图片转base64，返回base64编码的字符串
     parse_paras::PARAS,
     pdf::extract_pdf_content, // 读取pdf文件，提取文本内容
+    office::{
+        is_office_file, // 是否是支持提取内容的office文档或表格
+        extract_office_content, // 读取docx、pptx、odt文档或xlsx、csv等表格，提取内容转为markdown
+    },
     web::parse_html::parse_single_html_str, // 从html文件提取内容
 };
 
+/// 保存的文件名最大字节数
+const MAX_NAME_LEN: usize = 200;
+
+/// 保留的后缀最大字节数（含`.`），更长的不视为后缀，与文件名一起截断
+const MAX_EXT_LEN: usize = 16;
+
+/// 上传结果，key是页面上传时的文件名
+#[derive(Serialize)]
+struct UploadResult {
+    name:  String,         // 服务端保存的文件名，去除了路径和非法字符，重名时添加序号
+    token: u32,            // 该文件的token数，只有图片计算token数，其他为0
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>, // 被拒绝的原因，例如超过大小限制、内容与后缀不符
+}
+
 /// Handler for `/嵌套的前缀/upload` POST
 /// 将客户的上传的文件保存至服务端指定路径的uuid文件夹中
-pub async fn upload(uri: OriginalUri, jar: CookieJar, mut multipart: Multipart) -> Result<(CookieJar, String), MyError> {
+/// `?model=序号`指定当前选择的模型，图片按该模型的图片参数处理并估算token数，不指定则使用默认模型
+/// 文件之前可以有一个名为`question`的文本字段，是当前输入框中的问题，代码压缩包按与该问题的相关性选取文件
+/// 文件名只保留最后一级并去除非法字符，重名时添加序号；超过单个文件或uuid文件夹的大小限制、内容与后缀不符的文件会被拒绝
+pub async fn upload(Query(params): Query<HashMap<String, String>>, uri: OriginalUri, jar: CookieJar, mut multipart: Multipart) -> Result<(CookieJar, String), MyError> {
     event!(Level::INFO, "POST {}", uri.path()); // 注意：`axum::http::Uri`只能捕获到`/hello`，不包含嵌套的`/嵌套的前缀`前缀，使用`OriginalUri`可以
+    let snapshot = PARAS.snapshot();
+    let model = match params.get("model") {
+        Some(m) => snapshot.api.get_model_by_str(m),
+        None => snapshot.api.get_default_model(),
+    }.map(|m| m.2).unwrap_or_default(); // (api_key, endpoint, 模型名称, 是否支持深度思考)
     // 先判断是否有cookie，cookie值作为服务端uuid文件夹，不存在则生成uuid作为cookie
     let (uuid, cookie_jar) = match jar.get("srx-tzn") {
-        Some(c) => (c.value().to_string(), update_cookie_max_age(jar)), // 仅修改内部cookie的max-age
+        Some(c) if is_valid_uuid(c.value()) => (c.value().to_string(), update_cookie_max_age(jar)), // 仅修改内部cookie的max-age
+        Some(c) => { // cookie值会用作服务端路径，不是服务端生成的uuid则拒绝，避免写到uuid文件夹之外
+            event!(Level::WARN, "POST {}, reject invalid cookie {:?}", uri.path(), c.value());
+            return Err(MyError::InvalidCookieError{cookie: c.value().to_string()})
+        },
         None => {
             let tmp_uuid = Uuid::new_v4().to_string();
             (tmp_uuid.clone(), jar.add(create_cookie(tmp_uuid)))
@@ -50,94 +102,328 @@ pub async fn upload(uri: OriginalUri, jar: CookieJar, mut multipart: Multipart)
     };
     // uuid文件夹不存在则创建
     create_uuid_dir(&uuid)?;
+    let uuid_dir = format!("{}/{}", PARAS.outpath, uuid);
+    let file_limit = PARAS.upload_limit.file_mb * 1024 * 1024; // 单个文件最大字节数
+    let uuid_limit = PARAS.upload_limit.uuid_mb * 1024 * 1024; // uuid文件夹最大字节数
+    let mut used = dir_size(&uuid_dir); // uuid文件夹已使用的字节数
     // 获取上传的每个文件，保存到服务端
-    let mut file_token: HashMap<String, u32> = HashMap::new(); // 存储上传的文件的token数
+    let mut results: HashMap<String, UploadResult> = HashMap::new(); // 存储上传的每个文件的结果
+    let mut question = String::new(); // 当前输入框中的问题
     while let Some(mut field) = multipart.next_field().await.map_err(|e| MyError::ParseMultipartError{error: e})? {
-        // 获取文件名
-        let name = match field.file_name() {
+        // 获取文件名，只保留最后一级，去除非法字符，重名则添加序号
+        let raw_name = match field.file_name() {
             Some(n) => n.to_string(),
+            None if field.name() == Some("question") => {
+                question = field.text().await.map_err(|e| MyError::ParseMultipartError{error: e})?;
+                continue
+            },
             None => return Err(MyError::ParaError{para: "Multipart no file name".to_string()}),
         };
-
-        // 获取上传文件的完整大小
-        //let data = field.bytes().await.map_err(|e| MyError::ParseMultipartError{error: e})?;
+        let name = unique_file_name(&uuid_dir, &sanitize_file_name(&raw_name));
 
         // 在服务端创建文件
-        let upload_file = format!("{}/{}/{}", PARAS.outpath, uuid, name);
+        let upload_file = format!("{}/{}", uuid_dir, name);
         let file = File::create(&upload_file).await.map_err(|e| MyError::CreateFileError{file: upload_file.clone(), error: e})?;
         let mut file_writer = BufWriter::new(file);
 
-        // stream获取
+        // stream获取，超过大小限制则停止写入
+        let mut size: u64 = 0;
+        let mut error: Option<String> = None;
         while let Some(chunk) = field.chunk().await.map_err(|e| MyError::ParseMultipartError{error: e})? {
-            //println!("{}", chunk.len());
-            // Write a byte to the buffer.
-            file_writer.write(&chunk).await.map_err(|e| MyError::ParaError{para: format!("{}", e)})?;
+            size += chunk.len() as u64;
+            if size > file_limit {
+                error = Some(format!("file size exceeds the limit of {} MB", PARAS.upload_limit.file_mb));
+                break
+            }
+            if used + size > uuid_limit {
+                error = Some(format!("total upload size of this chat exceeds the quota of {} MB", PARAS.upload_limit.uuid_mb));
+                break
+            }
+            file_writer.write_all(&chunk).await.map_err(|e| MyError::WriteFileError{file: upload_file.clone(), error: e})?;
         }
         // Flush the buffer before it goes out of scope.
-        file_writer.flush().await.map_err(|e| MyError::ParaError{para: format!("{}", e)})?;
-        event!(Level::INFO, "upload {} done", upload_file);
-
-        // 插入message
-        let lowercase_name = name.to_lowercase();
-        if lowercase_name.ends_with(".png") || lowercase_name.ends_with(".jpg") || lowercase_name.ends_with(".jpeg") {
-            let message = ChatMessage::User{
-                content: ChatMessageContent::Text(name.clone()),
-                name: None,
-            };
-            insert_message(&uuid, message, None, Local::now().format("%Y-%m-%d %H:%M:%S").to_string(), false, DataType::Image(image_to_base64(&uuid, &name)?), None, "", None); // 以图片名称作为用户提问内容，并记录图片的base64字符串
-            file_token.insert(name, get_msg_token(&uuid, -1)); // pos>=0表示索引位置，pos<0表示倒数第几个，比如0表示第1个，1表示第2个，-1表示最后一个，-2表示倒数第个
-        } else if [".flac", ".mp3", ".mp4", ".mpeg", ".mpga", ".m4a", ".ogg", ".wav", ".webm"].iter().any(|x| lowercase_name.ends_with(x)) {
-            file_token.insert(name.clone(), 0);
-            let message = ChatMessage::User{
-                content: ChatMessageContent::Text(name),
-                name: None,
-            };
-            insert_message(&uuid, message, None, Local::now().format("%Y-%m-%d %H:%M:%S").to_string(), false, DataType::Voice, None, "", None); // 以音频文件名称作为用户提问内容
-        } else {
-            let content = if lowercase_name.ends_with(".pdf") {
-                match extract_pdf_content(&uuid, &PARAS.outpath, &name) {
-                    Ok(res) => res,
-                    Err(e) => {
-                        event!(Level::ERROR, "{} pdf error: {}", uuid, e);
-                        format!("extract content from {} error: {}", name, e)
-                    },
-                }
-            } else if lowercase_name.ends_with(".zip") {
-                let command = format!("code {name}");
-                match merge_code(&uuid, &command, &PARAS.outpath) {
-                    Ok(res) => res,
-                    Err(e) => {
-                        event!(Level::ERROR, "{} code error: {}", uuid, e);
-                        format!("extract content from {} error: {}", name, e)
-                    },
-                }
-            } else if lowercase_name.ends_with(".html") {
-                let html_content = String::from_utf8(read(&upload_file)?).map_err(|e| MyError::FileContentToUtf8Error{file: upload_file, error: e})?; // 读取单个html文件，例如使用SingleFile保存的单个html文件
-                match parse_single_html_str(&html_content, false) {
-                    Ok(res) => res,
-                    Err(e) => {
-                        event!(Level::ERROR, "{} extract content from {} error: {}", uuid, name, e);
-                        format!("extract content from {} error: {}", name, e)
-                    },
-                }
-            } else { // 其他格式视为文本文件
-                let file_content = try_read_file(&uuid, &name);
-                if file_content.is_empty() {
-                    event!(Level::INFO, "{} no such file in server: {}", uuid, name);
-                    format!("no such file in server: {}", name)
-                } else {
-                    file_content
-                }
-            };
-            file_token.insert(name.clone(), 0);
-            let message = ChatMessage::User{
-                content: ChatMessageContent::Text(content),
-                name: None,
-            };
-            insert_message(&uuid, message, None, Local::now().format("%Y-%m-%d %H:%M:%S").to_string(), false, DataType::Raw(name), None, "", None); // 以文件名称作为用户提问内容，并记录提取的内容字符串
+        file_writer.flush().await.map_err(|e| MyError::WriteFileError{file: upload_file.clone(), error: e})?;
+        drop(file_writer);
+
+        // 根据文件头检查内容是否与后缀一致
+        if error.is_none() {
+            if let Err(e) = check_content_type(&upload_file, &name) {
+                error = Some(e);
+            }
         }
+
+        let result = match error {
+            Some(e) => {
+                event!(Level::WARN, "{} reject upload {}: {}", uuid, raw_name, e);
+                let _ = remove_file(&upload_file).await;
+                insert_rejected_message(&uuid, &name, &format!("upload {} rejected: {}", raw_name, e)); // 插入说明信息，保持页面与服务端信息id一致
+                UploadResult{name, token: 0, error: Some(e)}
+            },
+            None => {
+                event!(Level::INFO, "upload {} done", upload_file);
+                used += size;
+                // 插入message
+                let token = insert_file_message(&uuid, &name, &model, &question).await?;
+                UploadResult{name, token, error: None}
+            },
+        };
+        results.insert(raw_name, result);
     }
-    let response = serde_json::to_string(&file_token).map_err(|e| MyError::ToJsonStirngError{uuid: uuid, error: e})?;
+    let response = serde_json::to_string(&results).map_err(|e| MyError::ToJsonStirngError{uuid: uuid, error: e})?;
 
     Ok((cookie_jar, response))
 }
+
+/// 提问时按实际的问题重新选取之前上传的代码压缩包中的文件，问题与上传时相同则不需要
+pub async fn rerank_code_archives(uuid: &str, question: &str) {
+    let pending = take_unranked_code(uuid);
+    for (name, used) in pending {
+        if used == question {
+            continue
+        }
+        let (tmp_uuid, command, tmp_question) = (uuid.to_string(), format!("code {name}"), question.to_string());
+        match spawn_blocking(move || merge_code(&tmp_uuid, &command, &PARAS.outpath, &tmp_question)).await {
+            Ok(Ok(content)) => {
+                if replace_raw_message(uuid, &name, content) {
+                    event!(Level::INFO, "{} rerank code archive {} for the question", uuid, name);
+                }
+            },
+            Ok(Err(e)) => event!(Level::WARN, "{} rerank code archive {} error: {}", uuid, name, e),
+            Err(e) => event!(Level::WARN, "{} rerank code archive {} task error: {}", uuid, name, e),
+        }
+    }
+}
+
+/// 去除文件名中的路径和非法字符，避免写到uuid文件夹之外，过长则截断，保留后缀
+pub fn sanitize_file_name(name: &str) -> String {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or("");
+    let cleaned: String = base.chars().filter(|c| !c.is_control() && !['<', '>', ':', '"', '|', '?', '*'].contains(c)).collect();
+    let cleaned = cleaned.trim().trim_start_matches('.').trim(); // 去除开头的`.`，避免`..`和隐藏文件
+    let (stem, ext) = split_extension(cleaned);
+    // 按字符截断，不会截断多字节字符
+    let mut stem = stem.to_string();
+    while stem.len() + ext.len() > MAX_NAME_LEN {
+        stem.pop();
+    }
+    if stem.trim().is_empty() {
+        format!("upload{}", ext)
+    } else {
+        format!("{}{}", stem.trim(), ext)
+    }
+}
+
+/// 拆分文件名和后缀，`.tar.gz`、`.tar.zst`等多段的压缩包后缀整体作为后缀，超过`MAX_EXT_LEN`的不视为后缀
+fn split_extension(name: &str) -> (&str, &str) {
+    let compound = ARCHIVE_FORMATS.iter().filter(|x| x.matches('.').count() > 1).find_map(|x| {
+        let i = name.len().checked_sub(x.len()).filter(|i| *i > 0 && name.is_char_boundary(*i))?;
+        name[i..].eq_ignore_ascii_case(x).then_some(i)
+    });
+    match compound.or_else(|| name.rfind('.')) {
+        Some(i) if i > 0 && name.len() - i <= MAX_EXT_LEN => (&name[..i], &name[i..]),
+        _ => (name, ""),
+    }
+}
+
+/// 文件已存在则在后缀前添加序号，例如`a.txt`已存在则返回`a (1).txt`，`a.tar.gz`已存在则返回`a (1).tar.gz`
+fn unique_file_name(dir: &str, name: &str) -> String {
+    if !Path::new(&format!("{}/{}", dir, name)).exists() {
+        return name.to_string()
+    }
+    let (stem, ext) = split_extension(name);
+    let mut i = 1;
+    loop {
+        let new_name = format!("{} ({}){}", stem, i, ext);
+        if !Path::new(&format!("{}/{}", dir, new_name)).exists() {
+            return new_name
+        }
+        i += 1;
+    }
+}
+
+/// 文件夹中所有文件的总字节数
+fn dir_size(dir: &str) -> u64 {
+    WalkDir::new(dir).into_iter().flatten().filter_map(|e| e.metadata().ok()).filter(|m| m.is_file()).map(|m| m.len()).sum()
+}
+
+/// 根据文件头的magic bytes检查文件内容是否与后缀一致，没有对应magic bytes的格式视为文本文件，不能含有二进制内容
+pub fn check_content_type(file: &str, name: &str) -> Result<(), String> {
+    let mut head = vec![0; 8192];
+    let n = std::fs::File::open(file).and_then(|mut f| f.read(&mut head)).map_err(|e| format!("read {} error: {}", name, e))?;
+    let head = &head[..n];
+    let lowercase_name = name.to_lowercase();
+    let ext = lowercase_name.rsplit_once('.').map(|(_, e)| e).unwrap_or("");
+    let is_zip = head.starts_with(b"PK\x03\x04") || head.starts_with(b"PK\x05\x06");
+    let is_ftyp = head.len() >= 12 && &head[4..8] == b"ftyp"; // mp4、m4a、heic
+    let is_riff = |kind: &[u8]| head.len() >= 12 && head.starts_with(b"RIFF") && &head[8..12] == kind;
+    let matched = match ext {
+        "png" => Some(head.starts_with(b"\x89PNG\r\n\x1a\n")),
+        "jpg" | "jpeg" => Some(head.starts_with(&[0xFF, 0xD8, 0xFF])),
+        "gif" => Some(head.starts_with(b"GIF87a") || head.starts_with(b"GIF89a")),
+        "webp" => Some(is_riff(b"WEBP")),
+        "bmp" => Some(head.starts_with(b"BM")),
+        "tif" | "tiff" => Some(head.starts_with(b"II*\0") || head.starts_with(b"MM\0*")),
+        "heic" | "heif" | "mp4" | "m4a" => Some(is_ftyp),
+        "pdf" => Some(head.starts_with(b"%PDF")),
+        "gz" | "tgz" => Some(head.starts_with(&[0x1F, 0x8B])),
+        "zst" | "tzst" => Some(head.starts_with(&[0x28, 0xB5, 0x2F, 0xFD])),
+        "zip" | "docx" | "pptx" | "xlsx" | "xlsm" | "odt" | "ods" => Some(is_zip),
+        "xls" => Some(head.starts_with(&[0xD0, 0xCF, 0x11, 0xE0])),
+        "wav" => Some(is_riff(b"WAVE")),
+        "flac" => Some(head.starts_with(b"fLaC")),
+        "ogg" => Some(head.starts_with(b"OggS")),
+        "webm" => Some(head.starts_with(&[0x1A, 0x45, 0xDF, 0xA3])),
+        "mp3" | "mpga" | "mpeg" => Some(head.starts_with(b"ID3") || head.starts_with(&[0x00, 0x00, 0x01, 0xBA]) || (head.len() >= 2 && head[0] == 0xFF && head[1] & 0xE0 == 0xE0)),
+        _ => None, // 其他格式视为文本文件
+    };
+    match matched {
+        Some(true) => Ok(()),
+        Some(false) => Err(format!("content of {} does not match its extension", name)),
+        None if head.starts_with(&[0xFF, 0xFE]) || head.starts_with(&[0xFE, 0xFF]) || !head.contains(&0) => Ok(()), // UTF-16有BOM，其余文本不能含有`\0`
+        None => Err(format!("{} is a binary file that is not supported", name)),
+    }
+}
+
+/// 插入被拒绝或无法处理的文件的说明信息，作为用户提问内容
+fn insert_rejected_message(uuid: &str, name: &str, info: &str) {
+    let message = ChatMessage::User{
+        content: ChatMessageContent::Text(info.to_string()),
+        name: None,
+    };
+    insert_message(&uuid, message, None, Local::now().format("%Y-%m-%d %H:%M:%S").to_string(), false, DataType::Raw(name.to_string()), None, "", None);
+}
+
+/// 根据上传文件的格式插入message：图片、音频、pdf、office文档和表格、zip/tar.gz/tar.zst代码压缩包、html，其他格式视为文本文件
+/// 文件已保存在`输出路径/uuid/name`，返回该文件的token数，只有图片按指定模型的图片参数计算token数，其他为0
+/// `question`是当前的问题，代码压缩包超过token预算时按与该问题的相关性选取文件，可以为空
+pub async fn insert_file_message(uuid: &str, name: &str, model: &str, question: &str) -> Result<u32, MyError> {
+    let upload_file = format!("{}/{}/{}", PARAS.outpath, uuid, name);
+    let lowercase_name = name.to_lowercase();
+    if is_image_file(&lowercase_name) {
+        // 按EXIF方向旋转，超过模型最大宽高则等比例缩小，png和jpg以外的格式转为jpg
+        let image_config = PARAS.snapshot().api.get_image_config(model);
+        let (dir, image_file, config) = (format!("{}/{}", PARAS.outpath, uuid), name.to_string(), image_config.clone());
+        let normalized = spawn_blocking(move || normalize_image(&dir, &image_file, &config)).await.map_err(|e| MyError::OtherError{info: format!("normalize image task error: {}", e)})?; // 解码和缩放不阻塞async线程
+        let (image_name, width, height) = match normalized {
+            Ok(res) => res,
+            Err(e) => { // 无法处理的图片，例如HEIC，记录错误信息，不中断其他文件的上传
+                event!(Level::ERROR, "{} image error: {}", uuid, e);
+                insert_rejected_message(uuid, name, &format!("process image {} error: {}", name, e));
+                return Ok(0)
+            },
+        };
+        event!(Level::INFO, "{} image {} -> {} ({}x{})", uuid, name, image_name, width, height);
+        let message = ChatMessage::User{
+            content: ChatMessageContent::Text(image_name.clone()),
+            name: None,
+        };
+        insert_message(&uuid, message, None, Local::now().format("%Y-%m-%d %H:%M:%S").to_string(), false, DataType::Image(image_to_base64(&uuid, &image_name)?), None, "", None); // 以图片名称作为用户提问内容，并记录图片的base64字符串
+        let token = image_config.token.estimate(width, height); // 按当前选择的模型估算图片token数
+        set_msg_token(uuid, -1, token); // pos>=0表示索引位置，pos<0表示倒数第几个，比如0表示第1个，1表示第2个，-1表示最后一个，-2表示倒数第个
+        return Ok(token)
+    } else if [".flac", ".mp3", ".mp4", ".mpeg", ".mpga", ".m4a", ".ogg", ".wav", ".webm"].iter().any(|x| lowercase_name.ends_with(x)) {
+        let message = ChatMessage::User{
+            content: ChatMessageContent::Text(name.to_string()),
+            name: None,
+        };
+        insert_message(&uuid, message, None, Local::now().format("%Y-%m-%d %H:%M:%S").to_string(), false, DataType::Voice, None, "", None); // 以音频文件名称作为用户提问内容
+    } else {
+        let mut unranked = false; // 是否是还需要按实际的问题重新选取文件的代码压缩包
+        let content = if lowercase_name.ends_with(".pdf") {
+            match extract_pdf_content(&uuid, &PARAS.outpath, &name) {
+                Ok(res) => res,
+                Err(e) => {
+                    event!(Level::ERROR, "{} pdf error: {}", uuid, e);
+                    format!("extract content from {} error: {}", name, e)
+                },
+            }
+        } else if is_office_file(&lowercase_name) {
+            match extract_office_content(&uuid, &PARAS.outpath, &name) {
+                Ok(res) => res,
+                Err(e) => {
+                    event!(Level::ERROR, "{} office error: {}", uuid, e);
+                    format!("extract content from {} error: {}", name, e)
+                },
+            }
+        } else if archive_stem(&lowercase_name).is_some() {
+            let (tmp_uuid, command, tmp_question) = (uuid.to_string(), format!("code {name}"), question.to_string());
+            let merged = spawn_blocking(move || merge_code(&tmp_uuid, &command, &PARAS.outpath, &tmp_question)).await.map_err(|e| MyError::OtherError{info: format!("merge code task error: {}", e)})?; // 解压、遍历、选取文件不阻塞async线程
+            match merged {
+                Ok(res) => {
+                    // 上传时输入框中的问题可能为空或之后被修改，提问时按实际的问题重新选取文件
+                    unranked = true;
+                    res
+                },
+                Err(e) => {
+                    event!(Level::ERROR, "{} code error: {}", uuid, e);
+                    format!("extract content from {} error: {}", name, e)
+                },
+            }
+        } else if lowercase_name.ends_with(".html") {
+            let html_content = String::from_utf8(read(&upload_file)?).map_err(|e| MyError::FileContentToUtf8Error{file: upload_file, error: e})?; // 读取单个html文件，例如使用SingleFile保存的单个html文件
+            match parse_single_html_str(&html_content, false) {
+                Ok(res) => res,
+                Err(e) => {
+                    event!(Level::ERROR, "{} extract content from {} error: {}", uuid, name, e);
+                    format!("extract content from {} error: {}", name, e)
+                },
+            }
+        } else { // 其他格式视为文本文件
+            let file_content = try_read_file(&uuid, &name);
+            if file_content.is_empty() {
+                event!(Level::INFO, "{} no such file in server: {}", uuid, name);
+                format!("no such file in server: {}", name)
+            } else {
+                file_content
+            }
+        };
+        let message = ChatMessage::User{
+            content: ChatMessageContent::Text(content),
+            name: None,
+        };
+        insert_message(&uuid, message, None, Local::now().format("%Y-%m-%d %H:%M:%S").to_string(), false, DataType::Raw(name.to_string()), None, "", None); // 以文件名称作为用户提问内容，并记录提取的内容字符串
+        if unranked { // 记录在该对话的Info中，插入message之后Info一定存在，随对话删除
+            push_unranked_code(uuid, name, question);
+        }
+    }
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_long_extension() {
+        let name = format!("a.{}", "x".repeat(300));
+        let sanitized = sanitize_file_name(&name);
+        assert!(sanitized.len() <= MAX_NAME_LEN);
+        assert!(sanitized.starts_with("a.x"));
+        let name = format!("{}.tar.gz", "b".repeat(300));
+        let sanitized = sanitize_file_name(&name);
+        assert!(sanitized.len() <= MAX_NAME_LEN);
+        assert!(sanitized.ends_with("b.tar.gz"));
+        assert!(archive_stem(&sanitized).is_some());
+    }
+
+    #[test]
+    fn test_sanitize_dot_dot() {
+        assert_eq!(sanitize_file_name(".."), "upload");
+        assert_eq!(sanitize_file_name("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_file_name("..\\..\\a.txt"), "a.txt");
+        assert_eq!(sanitize_file_name("../.."), "upload");
+        assert_eq!(sanitize_file_name("...bashrc"), "bashrc");
+    }
+
+    #[test]
+    fn test_sanitize_multibyte() {
+        assert_eq!(sanitize_file_name("文档.pdf"), "文档.pdf");
+        let name = format!("{}.md", "中".repeat(100)); // 300字节
+        let sanitized = sanitize_file_name(&name);
+        assert!(sanitized.len() <= MAX_NAME_LEN);
+        assert!(sanitized.ends_with("中.md"));
+        let name = format!("{}.{}", "测", "试".repeat(100));
+        let sanitized = sanitize_file_name(&name);
+        assert!(sanitized.len() <= MAX_NAME_LEN);
+        assert!(sanitized.starts_with("测."));
+    }
+}
@@ -12,8 +12,11 @@ use crate::{
         DisplayInfo, // 将之前问答记录显示到页面
         is_incognito, // 是否无痕模式
         get_chat_name,
+        get_knowledge, // 获取指定uuid使用的知识库
     },
+    knowledge::list_bases, // 获取所有知识库的概要
     graph::get_all_related_uuid, // 获取与指定uuid相关的所有uuid
+    prompt_library::pulldown_library, // prompt库的下拉选项
     parse_paras::PARAS, // 存储命令行参数的全局变量
 };
 
@@ -336,6 +339,7 @@ struct LeftInfo {
 struct PageInfo {
     prompt:       LeftInfo,    // 指定prompt开启新对话
     name:         LeftInfo,    // 可选填的新对话名称
+    knowledge:    LeftInfo,    // 当前对话使用的知识库
     tool:         LeftInfo,    // call tools
     plan_mode:    LeftInfo,    // plan mode
     skills:       LeftInfo,    // skills
@@ -356,6 +360,7 @@ struct PageInfo {
     voice:        LeftInfo,    // 声音
     copy:         String,      // 点击头像复制
     delete:       [String; 2], // 删除问题和回答
+    pin:          String,      // 固定信息，始终包含在上下文中
     m_qa_token:   [String; 4], // 显示信息数、Q&A对数、token数，4部分，用具体数值拼接
     upload:       String,      // 上传文件的title
     textarea:     String,      // 输入框内的提示信息
@@ -383,6 +388,13 @@ impl PageInfo {
                     option:      None,
                     placeholder: Some("chat name (optional)".to_string()),
                 },
+                knowledge: LeftInfo{ // 当前对话使用的知识库
+                    label:       "knowledge bases".to_string(),
+                    title:       "Local knowledge bases used by the current conversation, multiple names separated by commas. Each question retrieves the most relevant excerpts with their sources as context. Create knowledge bases with `chatsong kb` or the `/knowledge` api".to_string(),
+                    disabled:    None,
+                    option:      None,
+                    placeholder: Some("knowledge base names".to_string()),
+                },
                 tool: LeftInfo{ // call tools
                     label:       "call tools".to_string(),
                     title:       "Choose one or more tools to solve complex problems. When using tools, a plan will be created first, and then implemented item by item. After each execution is completed, it will be judged whether the plan needs to be updated, and finally the final result will be returned. ⚪ not using any tools, 🔴 select all tools, 🟢 select built-in tools, 🟣 select all custom external tools, 🟡 select MCP tools, while other options indicate the selection of a single tool".to_string(),
@@ -519,10 +531,11 @@ impl PageInfo {
                 },
                 copy:       "click to copy".to_string(), // 点击头像复制
                 delete:     ["delete this question".to_string(), "delete this answer".to_string()], // 删除问题和回答
+                pin:        "pin/unpin, pinned messages always stay in context".to_string(), // 固定信息，始终包含在上下文中
                 m_qa_token: ["message ".to_string(), ", Q&A pair ".to_string(), ", ".to_string(), " tokens".to_string()], // 显示信息数、Q&A对数、token数，4部分，用具体数值拼接
                 upload:     "upload files".to_string(), // 上传文件的title
                 textarea:   "Input your query (Press Shift+Enter for line breaks)".to_string(), // 输入框内的提示信息
-                button:     ["switch parameter bar settings".to_string(), "save current chat log".to_string(), "usage".to_string(), "Summarize and compress message records within the specified range of context messages for the current conversation".to_string(), "Insert new instruction during tool calling".to_string(), "Goal mode".to_string(), "Add the current conversation or the unsent content in the input box to memory".to_string()], // 左下角设置、下载、使用说明、压缩总结、插入新指令、开启goal模式、记忆当前结果这7个按钮的title
+                button:     ["switch parameter bar settings".to_string(), "save current chat log".to_string(), "usage".to_string(), "Summarize and compress message records within the specified range of context messages for the current conversation".to_string(), "Insert new instruction during tool calling".to_string(), "Goal mode".to_string(), "Add the current conversation or the unsent content in the input box to memory, right-click to manage memories".to_string()], // 左下角设置、下载、使用说明、压缩总结、插入新指令、开启goal模式、记忆当前结果这7个按钮的title
                 incognito:  ["Activate incognito mode, where the current conversation will not be locally preserved upon program termination and shall be irrevocably discarded, refreshing or reopening the current page will also erase the conversation history".to_string(), "Disable the incognito mode, and your current conversation will be preserved locally upon exiting the application, allowing you to resume seamlessly during your next session".to_string(), "Ac".to_string()], // 左下角无痕模式按钮开启和关闭2个状态的title，以及开启的前2个字符
                 microphone: ["Activate the voice mode, and you can input questions through the microphone".to_string(), "Disable the voice mode, you can only input questions through the keyboard".to_string(), "Ac".to_string()], // 左下角语音模式按钮开启和关闭2个状态的title，以及开启的前2个字符
                 wait:       ["Waiting for answer".to_string(), "Waiting for search".to_string(), "Sending query".to_string()], // 发送问题后等待时，输入框内显示的内容：等待回答、等待搜索、发送问题
@@ -543,6 +556,13 @@ impl PageInfo {
                     option:      None,
                     placeholder: Some("chat name (optional)".to_string()),
                 },
+                knowledge: LeftInfo{ // 当前对话使用的知识库
+                    label:       "知识库".to_string(),
+                    title:       "当前对话使用的本地知识库，多个名称用逗号间隔。每次提问会检索最相关的片段及其来源作为上下文。通过`chatsong kb`或`/knowledge`接口创建知识库".to_string(),
+                    disabled:    None,
+                    option:      None,
+                    placeholder: Some("knowledge base names".to_string()),
+                },
                 tool: LeftInfo{ // call tools
                     label:       "调用工具".to_string(),
                     title:       "选择一个或多个工具解决复杂问题。使用工具时会先制定计划，然后逐条实现，并在每条执行结束后判断是否需要更新计划，最后返回最终结果。⚪表示不使用任何工具，🔴表示选择所有工具，🟢表示选择内置工具，🟣表示选择所有自定义的外部工具，🟡表示选择MCP工具，其他选项表示单选一个工具".to_string(),
@@ -679,10 +699,11 @@ impl PageInfo {
                 },
                 copy:       "点击复制".to_string(), // 点击头像复制
                 delete:     ["删除该问题".to_string(), "删除该回答".to_string()], // 删除问题和回答
+                pin:        "固定/取消固定，固定的信息始终包含在上下文中".to_string(), // 固定信息，始终包含在上下文中
                 m_qa_token: ["第".to_string(), "条信息，第".to_string(), "对问答，".to_string(), "个token".to_string()], // 显示信息数、Q&A对数、token数，4部分，用具体数值拼接
                 upload:     "上传文件".to_string(), // 上传文件的title
                 textarea:   "输入你的问题 (Shift+Enter换行)".to_string(), // 输入框内的提示信息
-                button:     ["切换参数栏设置".to_string(), "保存当前对话html页面".to_string(), "查看使用说明".to_string(), "对当前对话指定&quot;上下文消息数&quot;范围内的消息记录进行总结压缩".to_string(), "在调用工具期间插入新指令".to_string(), "开启/关闭 Goal 模式".to_string(), "将当前对话或输入框内未发送的内容添加到记忆".to_string()], // 左下角设置、下载、使用说明、压缩总结、插入新指令、开启goal模式、这7个按钮的title
+                button:     ["切换参数栏设置".to_string(), "保存当前对话html页面".to_string(), "查看使用说明".to_string(), "对当前对话指定&quot;上下文消息数&quot;范围内的消息记录进行总结压缩".to_string(), "在调用工具期间插入新指令".to_string(), "开启/关闭 Goal 模式".to_string(), "将当前对话或输入框内未发送的内容添加到记忆，右键管理记忆".to_string()], // 左下角设置、下载、使用说明、压缩总结、插入新指令、开启goal模式、这7个按钮的title
                 incognito:  ["开启无痕模式，关闭程序时，当前对话不会被保存在本地，直接舍弃，刷新或重新打开当前页面也将丢弃对话记录".to_string(), "关闭无痕模式，关闭程序时，当前对话会被保存在本地，下次可以接着提问".to_string(), "开启".to_string()], // 左下角无痕模式按钮开启和关闭2个状态的title，以及开启的前2个字符
                 microphone: ["开启语音模式，你可以通过麦克风输入问题".to_string(), "关闭语音模式，你只能通过键盘输入问题".to_string(), "开启".to_string()], // 左下角语音模式按钮开启和关闭2个状态的title，以及开启的前2个字符
                 wait:       ["等待回答".to_string(), "等待搜索".to_string(), "发送问题".to_string()], // 发送问题后等待时，输入框内显示的内容：等待回答、等待搜索、发送问题
@@ -707,6 +728,8 @@ pub fn create_main_page(uuid: &str, v: String) -> String {
     // 页面信息
     let page_data_locked = PAGE.read().unwrap();
     let page_data = page_data_locked.get(&PARAS.english).unwrap();
+    // 当前配置，重载参数文件后下拉选项随之更新
+    let snapshot = PARAS.snapshot();
 
     // 创建包含css和js，并插入chat记录的html页面
     let mut result = r###"<!DOCTYPE html>
@@ -758,12 +781,13 @@ pub fn create_main_page(uuid: &str, v: String) -> String {
 "###;
     let tmp_option = page_data.prompt.option.as_ref().unwrap();
     result += &format!("        <div class='top_add_space' title='{}'>
-            <label>{}</label>
+            <label>{} <span id='manage-prompt' class='for_focus_button' title='prompts'>✎</span></label>
             <select id='select-prompt' class='left_para for_focus' name='prompt'>
                 <option disabled>--{}--</option>
                 <option value='-1' selected>{}</option>
                 <option value='0'>{}</option>\n", page_data.prompt.title, page_data.prompt.label, page_data.prompt.disabled.as_ref().unwrap(), tmp_option[0].0, tmp_option[1].0);
-    result += &PARAS.api.pulldown_prompt;
+    result += &snapshot.api.pulldown_prompt;
+    result += &pulldown_library();
     result += r###"            </select>
         </div>
 
@@ -798,8 +822,8 @@ pub fn create_main_page(uuid: &str, v: String) -> String {
         <!-- select model -->
         <div class='top_add_space' title='{}'>
             <label>{}</label>
-            <select id='select-model' class='left_para for_focus' name='model'>\n", page_data.name.title, page_data.name.label, page_data.name.placeholder.as_ref().unwrap(), page_data.tool.title, page_data.tool.label, PARAS.tools.html, PARAS.mcp_servers.html, page_data.plan_mode.title, page_data.plan_mode.label, page_data.skills.title, page_data.skills.label, PARAS.skills.html, page_data.model.title, page_data.model.label);
-    result += &PARAS.api.pulldown_model;
+            <select id='select-model' class='left_para for_focus' name='model'>\n", page_data.name.title, page_data.name.label, page_data.name.placeholder.as_ref().unwrap(), page_data.tool.title, page_data.tool.label, snapshot.tools.html, snapshot.mcp_servers.html, page_data.plan_mode.title, page_data.plan_mode.label, page_data.skills.title, page_data.skills.label, snapshot.skills.html, page_data.model.title, page_data.model.label);
+    result += &snapshot.api.pulldown_model;
     result += r###"            </select>
         </div>
 
@@ -916,6 +940,13 @@ pub fn create_main_page(uuid: &str, v: String) -> String {
     result += &format!("            </select>
         </div>
 
+        <!-- knowledge base -->
+        <div class='top_add_space' title='{}'>
+            <label>{}</label>
+            <input id='input-knowledge' class='left_para' type='text' name='knowledge' list='knowledge-list' placeholder='{}' value='{}'>
+            <datalist id='knowledge-list'>{}</datalist>
+        </div>
+
         <!-- temperature -->
         <div class='top_add_space' title='{}'>
             <label>{}</label>
@@ -962,7 +993,7 @@ pub fn create_main_page(uuid: &str, v: String) -> String {
     <!-- chat part -->
     <div id='right-part' class='content'>
         <!-- chat content region -->
-        <div id='scrolldown' class='chat-content-area'>", page_data.temp.title, page_data.temp.label, page_data.temp.placeholder.as_ref().unwrap(), page_data.top_p.title, page_data.top_p.label, page_data.top_p.placeholder.as_ref().unwrap(), page_data.stream.title, page_data.stream.label, page_data.voice.title, page_data.voice.label, page_data.voice.disabled.as_ref().unwrap(), tmp_option[0].0, tmp_option[1].0, tmp_option[2].0, tmp_option[3].0, tmp_option[4].0, tmp_option[5].0);
+        <div id='scrolldown' class='chat-content-area'>", page_data.knowledge.title, page_data.knowledge.label, page_data.knowledge.placeholder.as_ref().unwrap(), get_knowledge(uuid).join(","), list_bases().iter().map(|b| format!("<option value='{}'>", b.name)).collect::<Vec<_>>().join(""), page_data.temp.title, page_data.temp.label, page_data.temp.placeholder.as_ref().unwrap(), page_data.top_p.title, page_data.top_p.label, page_data.top_p.placeholder.as_ref().unwrap(), page_data.stream.title, page_data.stream.label, page_data.voice.title, page_data.voice.label, page_data.voice.disabled.as_ref().unwrap(), tmp_option[0].0, tmp_option[1].0, tmp_option[2].0, tmp_option[3].0, tmp_option[4].0, tmp_option[5].0);
 
     let (next_msg_id, m_num, qa_num, logs) = get_log_for_display(uuid, true); // cookie对应的chat记录
     for log in logs.iter() {
@@ -995,12 +1026,13 @@ pub fn create_main_page(uuid: &str, v: String) -> String {
                 <span id='d{}' class='for_focus_button del_btn' title='{}'>
                     {}
                 </span>
+                <span id='p{}' class='pin_btn{}' title='{}'>📌</span>
                 {}{}{}
             </div>
             <div class='user-chat-box'>
                 <div class='q_icon_query'>
                     <div class='chat-txt right' id='m{}' title='{}'></div>
-                    <div class='chat-icon'>\n", log.id, page_data.delete[0], ICON_DELETE, if log.is_web {"🌐 "} else {""}, if log.remembered {"🧠 "} else {""}, log.time, log.id, tmp_title);
+                    <div class='chat-icon'>\n", log.id, page_data.delete[0], ICON_DELETE, log.id, if log.pinned {" pinned"} else {""}, page_data.pin, if log.is_web {"🌐 "} else {""}, if log.remembered {"🧠 "} else {""}, log.time, log.id, tmp_title);
             if log.is_img || log.is_voice {
                 result += &format!("                        <img class='chatgpt-icon for_focus_button' src='{}' />", ICON_USER);
             } else {
@@ -1015,12 +1047,13 @@ pub fn create_main_page(uuid: &str, v: String) -> String {
             result += &format!("            <!-- robot -->
             <div class='left-time'>
                 {}{}
+                <span id='p{}' class='pin_btn{}' title='{}'>📌</span>
                 <span id='d{}' class='for_focus_button del_btn' title='{}'>
                     {}
                 </span>
             </div>
             <div class='gpt-chat-box'>
-                <div class='chat-icon'>\n", log.time, if log.remembered {" 🧠"} else {""}, log.id, page_data.delete[1], ICON_DELETE);
+                <div class='chat-icon'>\n", log.time, if log.remembered {" 🧠"} else {""}, log.id, if log.pinned {" pinned"} else {""}, page_data.pin, log.id, page_data.delete[1], ICON_DELETE);
             if log.is_img || log.is_voice {
                 result += &format!("                    <img class='chatgpt-icon for_focus_button' src='{}' />", ICON_CHATGPT);
             } else {
@@ -1079,6 +1112,66 @@ pub fn create_main_page(uuid: &str, v: String) -> String {
         </div>
     </div>
 
+    <!-- 模板变量表单 -->
+    <div class="modal-overlay" id="templateVarsModal">
+        <div class="modal-box">
+            <h3 class="modal-title">Template variables</h3>
+            <div id="templateVarsFields"></div>
+            <div class="modal-actions">
+                <button class="btn btn-disagree" onclick="closeTemplateVars(false)">Cancel</button>
+                <button class="btn btn-submit" onclick="closeTemplateVars(true)">Submit</button>
+            </div>
+        </div>
+    </div>
+
+    <!-- prompt库管理 -->
+    <div class="modal-overlay" id="promptModal">
+        <div class="modal-box">
+            <h3 class="modal-title">Prompts</h3>
+            <div id="promptList"></div>
+            <input id="promptName" class="prompt-input" type="text" placeholder="Name">
+            <input id="promptTags" class="prompt-input" type="text" placeholder="Tags, separated by commas">
+            <input id="promptModel" class="prompt-input" type="text" placeholder="Default model name (optional)">
+            <select id="promptTools" class="prompt-input"></select>
+            <textarea id="promptContent" class="skip-textarea" rows="6" placeholder="Content, supports {{date}}, {{file:path}}, {{name}} ..."></textarea>
+            <div class="modal-actions">
+                <button class="btn btn-skip" onclick="newPrompt()">New</button>
+                <button class="btn btn-submit" onclick="savePrompt()">Save</button>
+                <button class="btn btn-skip" onclick="exportPrompts()">Export</button>
+                <label class="btn btn-skip" for="importPrompts">Import</label>
+                <input id="importPrompts" type="file" accept=".json">
+                <button class="btn btn-disagree" onclick="closePromptModal()">Close</button>
+            </div>
+        </div>
+    </div>
+
+    <!-- 记忆管理 -->
+    <div class="modal-overlay" id="memoryModal">
+        <div class="modal-box">
+            <h3 class="modal-title">Memories</h3>
+            <div class="memory-bar">
+                <select id="memoryStore" class="prompt-input">
+                    <option value="uuid">current chat</option>
+                    <option value="local">local (memory.json)</option>
+                    <option value="old">old (memory_old.json)</option>
+                </select>
+                <input id="memorySearch" class="prompt-input" type="text" placeholder="Search">
+                <input id="memoryNamespaces" class="prompt-input" type="text" placeholder="namespaces" title="Memory namespaces (projects) used by the current chat, separated by commas. Only memories in these namespaces and the global namespace are retrieved, new memories are stored in the first one">
+            </div>
+            <div id="memoryList"></div>
+            <textarea id="memorySummary" class="skip-textarea" rows="3" placeholder="Select Edit or check several memories to merge"></textarea>
+            <div class="modal-actions">
+                <button class="btn btn-submit" onclick="saveMemory()">Save</button>
+                <button class="btn btn-skip" onclick="mergeMemories()">Merge checked</button>
+                <button class="btn btn-skip" onclick="showInjections()">Injected</button>
+                <button class="btn btn-skip" onclick="consolidateMemories()" title="Merge overlapping memories, resolve contradictions (newer wins) and rewrite memories with LLM, pinned memories are not changed">Consolidate</button>
+                <button class="btn btn-skip" onclick="showChangelog()">Changelog</button>
+                <button class="btn btn-disagree" onclick="closeMemoryModal()">Close</button>
+            </div>
+            <div id="memoryInjections"></div>
+        </div>
+    </div>
+
     <!-- footer -->
     <footer>
 "###;
@@ -1136,7 +1229,7 @@ pub fn create_main_page(uuid: &str, v: String) -> String {
     for log in logs.iter() {
         result += &format!("            var msg = document.getElementById('m{}');
             var tmp = `{}`; // 这里将模板中的chat内容（已将“`”做了转译，“script”结束标签去掉了“<”）存入变量中
-            if (tmp.startsWith('data:image/png;base64,')) {{ // 插入图片
+            if (tmp.startsWith('data:image/png;base64,') || tmp.startsWith('data:image/jpeg;base64,')) {{ // 插入图片
                 let tmp_img = document.createElement('img');
                 tmp_img.src = tmp;
                 msg.appendChild(tmp_img);\n", log.id, log.content);
@@ -1316,6 +1409,27 @@ pub fn create_main_page(uuid: &str, v: String) -> String {
         }});
     }})", page_data.microphone[2], ICON_MICROPHONE1, page_data.microphone[1], ICON_MICROPHONE0, page_data.microphone[0], PARAS.addr_str, PARAS.port, v, PARAS.addr_str, PARAS.port, v, ICON_GOAL1, ICON_GOAL0, PARAS.addr_str, PARAS.port, v);
     result += r###"
+    // 设置当前对话使用的知识库，多个用逗号间隔，为空则不使用
+    document.getElementById('input-knowledge').addEventListener('change', function(event) {
+        const knowledgeInput = event.target;
+"###;
+    result += &format!("        fetch('http://{}:{}{}/knowledge/attach?names='+encodeURIComponent(knowledgeInput.value))", PARAS.addr_str, PARAS.port, v);
+    result += r###"
+            .then(async response => {
+                if (!response.ok) {
+                    throw new Error(await response.text());
+                }
+                return response.json();
+            })
+            .then(names => {
+                knowledgeInput.value = names.join(','); // 去除空格和重复的名称
+            })
+            .catch(error => {
+                console.error('Failed attach knowledge base:', error);
+                alert(error.message);
+            });
+    })"###;
+    result += r###"
     // 监听点击关闭语音模式按钮
     document.getElementById('left-microphone').addEventListener('click', function(event) {
         close_microphone_toggle();
@@ -1349,6 +1463,18 @@ pub fn create_main_page(uuid: &str, v: String) -> String {
     document.addEventListener('click', async function(event) {
         if (event.target.classList.contains('for_focus_button')) { // 点击提交按钮和头像后，自动focus到问题输入框。由于头像消息是动态增加的，因此不能像上面那样，而应该使用事件委托
             document.getElementById('input_query').focus();
+        } else if (event.target.classList.contains('pin_btn')) { // 固定消息按钮
+            const pinBtn = event.target;
+            if (isStopped && Number(pinBtn.id.substring(1)) < 18446744073709551612) { // rust usize最后4个数是示例信息的id，没记录在服务端，不能固定
+"###;
+    result += &format!("                const response = await fetch('http://{}:{}{}/pinmsg/'+pinBtn.id, {{method: 'POST'}});\n", PARAS.addr_str, PARAS.port, v);
+    result += r###"                if (response.ok) {
+                    const pinned = await response.text();
+                    pinBtn.classList.toggle('pinned', pinned === 'true');
+                } else {
+                    console.error('pin message error');
+                }
+            }
         } else { // 删除消息按钮
             const delBtn = event.target.closest('.del_btn'); // 这里要获取最近的del_btn，否则点击删除图标可能无效
             if (delBtn && isStopped) {
@@ -1539,6 +1665,7 @@ pub fn create_main_page(uuid: &str, v: String) -> String {
     document.getElementById("upload-file").onchange = async function(event) {
         uploadedFiles = {};
         const formData = new FormData();
+        formData.append('question', document.getElementById('input_query').value); // 当前输入框中的问题，代码压缩包按与该问题的相关性选取文件，需要在文件之前
         for (let i = 0; i < event.target.files.length; i++) {
             const file = event.target.files[i];
             if (file) {
@@ -1611,30 +1738,50 @@ pub fn create_main_page(uuid: &str, v: String) -> String {
 
         // 上传
 "###;
-    result += &format!("        fetch('http://{}:{}{}/upload', {{", PARAS.addr_str, PARAS.port, v);
+    result += &format!("        fetch('http://{}:{}{}/upload?model='+document.getElementById('select-model').value, {{", PARAS.addr_str, PARAS.port, v); // 图片按当前选择的模型处理并估算token数
     result += r###"
             method: 'POST',
             body: formData
         })
-        .then(response => response.json())
+        .then(response => response.ok ? response.json() : response.text().then(text => { throw new Error(text || response.statusText); }))
         .then(data => {
-            //console.log('服务器返回的上传文件token数:', data);
+            //console.log('服务器返回的上传文件结果:', data); // {上传的文件名: {name: 服务端保存的文件名, token: token数, error: 被拒绝的原因}}
             Object.entries(uploadedFiles).forEach(([key, value]) => {
-                if (data[key] > 0) {
-                    let msg_lr = document.getElementById(value);
+                const res = data[key];
+                if (!res) {
+                    return;
+                }
+                let msg_lr = document.getElementById(value);
+                if (res.error) { // 被拒绝的文件，服务端已插入说明信息，这里显示被拒绝的原因
+                    msg_lr.textContent = key + ': ' + res.error;
+                    msg_lr.classList.add('upload-error');
+                    return;
+                }
+                if (res.name !== key && msg_lr.textContent === key) { // 重名等原因保存的文件名有变化
+                    msg_lr.textContent = res.name;
+                }
+                if (res.token > 0) {
                     const currentTitle = msg_lr.getAttribute("title");
 "###;
-    result += &format!("                    msg_lr.setAttribute('title', currentTitle+data[key]+'{}');", page_data.m_qa_token[3]);
+    result += &format!("                    msg_lr.setAttribute('title', currentTitle+res.token+'{}');", page_data.m_qa_token[3]);
     result += r###"
+                    // 在图片下方显示估算的token数，提问前就能看到图片的花费
+                    let token_div = document.createElement('div');
+                    token_div.setAttribute('class', 'image-token');
+"###;
+    result += &format!("                    token_div.textContent = '≈ '+res.token+'{}';", page_data.m_qa_token[3]);
+    result += r###"
+                    msg_lr.appendChild(token_div);
                     // 更新页面左侧总输入token
                     let tmp = document.getElementById("show-in-token");
-                    tmp.value = parseInt(tmp.value) + data[key];
+                    tmp.value = parseInt(tmp.value) + res.token;
                 }
                 //console.log(`Key: ${key}, Value: ${value}`);
             });
         })
         .catch(error => {
             console.error('上传文件失败:', error);
+            alert('upload failed: ' + error.message);
         });
         document.getElementById('input_query').focus();
     };
@@ -1714,6 +1861,7 @@ pub fn create_main_page(uuid: &str, v: String) -> String {
     result += r###"        const svgElement = svgDoc.documentElement; // 3. 从解析后的文档中获取根元素，即 <svg> 元素
         delicon.appendChild(svgElement);
         timeInfo.appendChild(delicon);
+        timeInfo.appendChild(create_pin_icon(current_id-1));
         let time_text = document.createTextNode(formatDate(true));
         timeInfo.appendChild(time_text);
         message.appendChild(timeInfo);
@@ -1835,11 +1983,13 @@ pub fn create_main_page(uuid: &str, v: String) -> String {
                 timeInfo.setAttribute('class', 'left-time');
                 delicon.setAttribute('title', '{}');
                 timeInfo.appendChild(time_text);
+                timeInfo.appendChild(create_pin_icon(current_id-1));
                 timeInfo.appendChild(delicon);
             }} else {{
                 timeInfo.setAttribute('class', 'right-time');
                 delicon.setAttribute('title', '{}');
                 timeInfo.appendChild(delicon);
+                timeInfo.appendChild(create_pin_icon(current_id-1));
                 timeInfo.appendChild(time_text);
             }}
 
@@ -2023,7 +2173,7 @@ print(b)
     }}
     window.handleUserChoice = handleUserChoice; // 暴露给全局
     function sendApprovalToBackend(agreed) {{
-        fetch('http://{}:{}{}/approval?approval='+agreed).catch(error => {{
+        fetch('http://{}:{}{}/approval?approval='+encodeURIComponent(agreed), {{method: 'POST'}}).catch(error => {{
             console.error('Failed send approval to server:', error);
         }});
     }}
@@ -2054,6 +2204,469 @@ print(b)
         wait_approval = false;
     }
     window.submitSkipReason = submitSkipReason;
+    // 模板变量：问题和所选prompt中需要页面提供的变量，`{{clipboard}}`读取剪贴板，其他变量弹出表单填写
+    const templateVarsModal = document.getElementById('templateVarsModal');
+    const templateVarsFields = document.getElementById('templateVarsFields');
+    let templateVarsResolve = null;
+    async function fill_template_vars() {
+        const serverVars = ['date', 'time', 'model', 'file', 'memory']; // 由服务端替换
+        let names = [];
+        const promptOption = document.getElementById("select-prompt").selectedOptions[0];
+        if (promptOption && promptOption.dataset.vars) {
+            names = promptOption.dataset.vars.split(',').filter(n => n !== '');
+        }
+        const re = /(\\?)\{\{\s*([A-Za-z_][A-Za-z0-9_\-]*)\s*(?::[^{}]*)?\}\}/g; // `\{{name}}`是转义，不是变量
+        for (const m of document.getElementById("input_query").value.matchAll(re)) {
+            if (m[1] === '' && !serverVars.includes(m[2]) && !names.includes(m[2])) {
+                names.push(m[2]);
+            }
+        }
+        let vars = {};
+        if (names.includes('clipboard')) {
+            try {
+                vars['clipboard'] = await navigator.clipboard.readText();
+            } catch (e) {
+                console.log('Failed to read clipboard:', e);
+            }
+            names = names.filter(n => n !== 'clipboard');
+        }
+        if (names.length === 0) {
+            return vars;
+        }
+        // 弹出表单，提交返回填写的值，取消返回null
+        templateVarsFields.innerHTML = '';
+        names.forEach(n => {
+            const label = document.createElement('label');
+            label.textContent = n;
+            const input = document.createElement('textarea');
+            input.className = 'skip-textarea template-var-input';
+            input.dataset.name = n;
+            input.rows = 2;
+            templateVarsFields.appendChild(label);
+            templateVarsFields.appendChild(input);
+        });
+        templateVarsModal.classList.add('active');
+        setTimeout(() => templateVarsFields.querySelector('textarea').focus(), 100);
+        const submitted = await new Promise(resolve => templateVarsResolve = resolve);
+        if (!submitted) {
+            return null;
+        }
+        templateVarsFields.querySelectorAll('textarea').forEach(t => vars[t.dataset.name] = t.value);
+        return vars;
+    }
+    function closeTemplateVars(submitted) {
+        templateVarsModal.classList.remove('active');
+        if (templateVarsResolve) {
+            templateVarsResolve(submitted);
+            templateVarsResolve = null;
+        }
+    }
+    window.closeTemplateVars = closeTemplateVars;
+"###;
+    result += &format!("    const promptApi = 'http://{}:{}{}/prompts';\n", PARAS.addr_str, PARAS.port, v);
+    result += r###"    // prompt库：下拉选项获取焦点时从服务端更新，增删改后立即生效，不需要刷新页面
+    const selectPrompt = document.getElementById('select-prompt');
+    function create_prompt_option(p) {
+        const option = document.createElement('option');
+        option.value = p.value;
+        option.textContent = p.name;
+        option.title = p.tags.join(', ');
+        option.dataset.vars = p.vars.join(',');
+        option.dataset.model = p.model_index === null ? '' : p.model_index;
+        option.dataset.tools = p.tools || '';
+        return option;
+    }
+    async function refresh_prompts() {
+        try {
+            const response = await fetch(promptApi);
+            if (!response.ok) {
+                return [];
+            }
+            const prompts = await response.json();
+            const selected = selectPrompt.value;
+            // 保留前3个选项：提示、保持当前对话、无prompt
+            while (selectPrompt.options.length > 3) {
+                selectPrompt.remove(3);
+            }
+            prompts.forEach(p => selectPrompt.appendChild(create_prompt_option(p)));
+            selectPrompt.value = selected;
+            if (selectPrompt.value !== selected) { // 选择的prompt已被删除
+                selectPrompt.value = '-1';
+            }
+            return prompts;
+        } catch (error) {
+            console.error('Failed get prompts:', error);
+            return [];
+        }
+    }
+    selectPrompt.addEventListener('focus', refresh_prompts);
+    // 选择prompt时切换到该prompt的默认模型和tools
+    selectPrompt.addEventListener('change', function() {
+        const option = selectPrompt.selectedOptions[0];
+        if (!option) {
+            return;
+        }
+        [['select-model', option.dataset.model], ['select-tool', option.dataset.tools]].forEach(([id, value]) => {
+            const select = document.getElementById(id);
+            if (value && Array.from(select.options).some(o => o.value === value)) {
+                select.value = value;
+                select.dispatchEvent(new Event('change'));
+            }
+        });
+    });
+    // prompt库管理弹窗：参数文件中的prompt只读，prompt库中的prompt可以修改和删除
+    const promptModal = document.getElementById('promptModal');
+    let editingPromptId = null; // 正在修改的prompt的id，null表示新建
+    async function open_prompt_modal() {
+        // 默认tools的选项与左侧tools下拉选项相同，第一个为空表示不切换tools
+        const promptTools = document.getElementById('promptTools');
+        promptTools.innerHTML = "<option value=''>-- no default tools --</option>" + document.getElementById('select-tool').innerHTML;
+        newPrompt();
+        await render_prompt_list();
+        promptModal.classList.add('active');
+    }
+    async function render_prompt_list() {
+        const prompts = await refresh_prompts();
+        const list = document.getElementById('promptList');
+        list.innerHTML = '';
+        prompts.forEach(p => {
+            const row = document.createElement('div');
+            row.className = 'prompt-row';
+            const name = document.createElement('span');
+            name.textContent = p.name + (p.tags.length > 0 ? ' [' + p.tags.join(', ') + ']' : '');
+            row.appendChild(name);
+            if (p.id === null) {
+                const source = document.createElement('span');
+                source.className = 'prompt-source';
+                source.textContent = 'config';
+                row.appendChild(source);
+            } else {
+                const edit = document.createElement('button');
+                edit.className = 'btn btn-skip';
+                edit.textContent = 'Edit';
+                edit.onclick = () => fill_prompt_form(p);
+                const del = document.createElement('button');
+                del.className = 'btn btn-disagree';
+                del.textContent = 'Delete';
+                del.onclick = () => delete_prompt(p);
+                row.appendChild(edit);
+                row.appendChild(del);
+            }
+            list.appendChild(row);
+        });
+    }
+    function fill_prompt_form(p) {
+        editingPromptId = p === null ? null : p.id;
+        document.getElementById('promptName').value = p === null ? '' : p.name;
+        document.getElementById('promptTags').value = p === null ? '' : p.tags.join(', ');
+        document.getElementById('promptModel').value = p === null ? '' : (p.model || '');
+        document.getElementById('promptTools').value = p === null ? '' : (p.tools || '');
+        document.getElementById('promptContent').value = p === null ? '' : p.content;
+    }
+    function newPrompt() {
+        fill_prompt_form(null);
+    }
+    async function savePrompt() {
+        let prompt = {
+            name: document.getElementById('promptName').value.trim(),
+            content: document.getElementById('promptContent').value,
+            tags: document.getElementById('promptTags').value.split(',').map(t => t.trim()).filter(t => t !== ''),
+        };
+        const model = document.getElementById('promptModel').value.trim();
+        if (model !== '') {
+            prompt.model = model;
+        }
+        const tools = document.getElementById('promptTools').value;
+        if (tools !== '') {
+            prompt.tools = tools;
+        }
+        const response = await fetch(editingPromptId === null ? promptApi : promptApi + '/' + editingPromptId, {
+            method: editingPromptId === null ? 'POST' : 'PUT',
+            headers: {'Content-Type': 'application/json'},
+            body: JSON.stringify(prompt),
+        });
+        if (!response.ok) {
+            alert(await response.text());
+            return;
+        }
+        if (editingPromptId === null) {
+            editingPromptId = (await response.json()).id;
+        }
+        await render_prompt_list();
+    }
+    async function delete_prompt(p) {
+        if (!confirm('Delete prompt "' + p.name + '"?')) {
+            return;
+        }
+        const response = await fetch(promptApi + '/' + p.id, {method: 'DELETE'});
+        if (!response.ok) {
+            alert(await response.text());
+        }
+        if (editingPromptId === p.id) {
+            newPrompt();
+        }
+        await render_prompt_list();
+    }
+    // 导出prompt库为json文件，用于分享prompt包
+    async function exportPrompts() {
+        const response = await fetch(promptApi + '/export');
+        const blob = new Blob([await response.text()], {type: 'application/json'});
+        const a = document.createElement('a');
+        a.href = URL.createObjectURL(blob);
+        a.download = 'prompts.json';
+        a.click();
+        URL.revokeObjectURL(a.href);
+    }
+    // 导入prompt包，同名的prompt会被覆盖
+    document.getElementById('importPrompts').addEventListener('change', async function(event) {
+        const file = event.target.files[0];
+        if (!file) {
+            return;
+        }
+        const response = await fetch(promptApi + '/import', {
+            method: 'POST',
+            headers: {'Content-Type': 'application/json'},
+            body: await file.text(),
+        });
+        const text = await response.text();
+        if (response.ok) {
+            const result = JSON.parse(text);
+            alert(result.added + ' added, ' + result.updated + ' updated');
+        } else {
+            alert(text);
+        }
+        event.target.value = '';
+        await render_prompt_list();
+    });
+    function closePromptModal() {
+        promptModal.classList.remove('active');
+    }
+    window.newPrompt = newPrompt;
+    window.savePrompt = savePrompt;
+    window.exportPrompts = exportPrompts;
+    window.closePromptModal = closePromptModal;
+    document.getElementById('manage-prompt').addEventListener('click', function(event) {
+        event.stopPropagation();
+        open_prompt_modal();
+    });
+"###;
+    result += &format!("    const memoryApi = 'http://{}:{}{}/memory';\n", PARAS.addr_str, PARAS.port, v);
+    result += r###"    // 记忆管理弹窗：右键左下角记忆按钮打开，查看、搜索、修改、删除、固定、合并、移动记忆，查看每次回答注入的记忆
+    const memoryModal = document.getElementById('memoryModal');
+    const memoryStore = document.getElementById('memoryStore');
+    let editingMemoryId = null; // 正在修改的记忆id，null表示没有修改
+    let memoryNotes = [];
+    async function memory_request(url, options) {
+        const response = await fetch(url, options);
+        if (!response.ok) {
+            alert(await response.text());
+            return null;
+        }
+        return response;
+    }
+    async function render_memory_list() {
+        const store = memoryStore.value;
+        const q = document.getElementById('memorySearch').value.trim();
+        const response = await memory_request(memoryApi + '/notes?store=' + store + '&q=' + encodeURIComponent(q));
+        memoryNotes = response === null ? [] : await response.json();
+        const list = document.getElementById('memoryList');
+        list.innerHTML = '';
+        if (memoryNotes.length === 0) {
+            list.textContent = 'No memory';
+        }
+        memoryNotes.forEach(n => {
+            const row = document.createElement('div');
+            row.className = 'prompt-row';
+            const check = document.createElement('input');
+            check.type = 'checkbox';
+            check.value = n.id;
+            row.appendChild(check);
+            const summary = document.createElement('span');
+            summary.textContent = (n.reserved ? '📌 ' : '') + '[' + n.namespace + '] ' + n.summary + n.tags.map(t => ' #' + t).join('') + (n.score === undefined ? '' : ' (' + n.score.toFixed(3) + ')');
+            summary.title = n.raw;
+            row.appendChild(summary);
+            const pin = document.createElement('button');
+            pin.className = 'btn btn-skip';
+            pin.textContent = n.reserved ? 'Unpin' : 'Pin';
+            pin.title = 'Pinned memories are never removed by the memory limit';
+            pin.onclick = () => update_memory(n.id, {reserved: !n.reserved});
+            const edit = document.createElement('button');
+            edit.className = 'btn btn-skip';
+            edit.textContent = 'Edit';
+            edit.onclick = () => {
+                editingMemoryId = n.id;
+                document.getElementById('memorySummary').value = n.summary;
+            };
+            const move = document.createElement('button');
+            move.className = 'btn btn-skip';
+            move.textContent = store === 'uuid' ? 'To local' : 'To chat';
+            move.onclick = () => move_memory(n.id, store === 'uuid' ? 'local' : 'uuid');
+            const del = document.createElement('button');
+            del.className = 'btn btn-disagree';
+            del.textContent = 'Delete';
+            del.onclick = () => delete_memory(n);
+            [pin, edit, move, del].forEach(b => row.appendChild(b));
+            list.appendChild(row);
+        });
+    }
+    async function update_memory(id, edit) {
+        const response = await memory_request(memoryApi + '/notes/' + id + '?store=' + memoryStore.value, {
+            method: 'PUT',
+            headers: {'Content-Type': 'application/json'},
+            body: JSON.stringify(edit),
+        });
+        if (response !== null) {
+            await render_memory_list();
+        }
+    }
+    async function saveMemory() {
+        if (editingMemoryId === null) {
+            return;
+        }
+        await update_memory(editingMemoryId, {summary: document.getElementById('memorySummary').value.trim()});
+        editingMemoryId = null;
+        document.getElementById('memorySummary').value = '';
+    }
+    async function delete_memory(n) {
+        if (!confirm('Delete memory "' + n.summary + '"?')) {
+            return;
+        }
+        await memory_request(memoryApi + '/notes/' + n.id + '?store=' + memoryStore.value, {method: 'DELETE'});
+        await render_memory_list();
+    }
+    async function move_memory(id, to) {
+        await memory_request(memoryApi + '/notes/' + id + '/move?store=' + memoryStore.value + '&to=' + to, {method: 'POST'});
+        await render_memory_list();
+    }
+    // 合并勾选的记忆，输入框为空则先填入所有勾选的记忆，修改后再次点击合并
+    async function mergeMemories() {
+        const ids = Array.from(document.querySelectorAll('#memoryList input:checked')).map(c => c.value);
+        if (ids.length < 2) {
+            alert('Check at least 2 memories to merge');
+            return;
+        }
+        const summaryInput = document.getElementById('memorySummary');
+        if (editingMemoryId !== null || summaryInput.value.trim() === '') {
+            editingMemoryId = null;
+            summaryInput.value = memoryNotes.filter(n => ids.includes(n.id)).map(n => n.summary).join('\n');
+            summaryInput.focus();
+            return;
+        }
+        const response = await memory_request(memoryApi + '/notes/merge?store=' + memoryStore.value, {
+            method: 'POST',
+            headers: {'Content-Type': 'application/json'},
+            body: JSON.stringify({ids: ids, summary: summaryInput.value.trim()}),
+        });
+        if (response !== null) {
+            summaryInput.value = '';
+            await render_memory_list();
+        }
+    }
+    // 当前对话每次回答注入的记忆，点击信息id跳转到该回答
+    async function showInjections() {
+        const div = document.getElementById('memoryInjections');
+        const response = await memory_request(memoryApi + '/injections');
+        const injections = response === null ? [] : await response.json();
+        div.innerHTML = '';
+        if (injections.length === 0) {
+            div.textContent = 'No memory was injected into this chat';
+        }
+        injections.forEach(inj => {
+            const item = document.createElement('div');
+            item.className = 'memory-injection';
+            const head = document.createElement('a');
+            head.href = '#m' + inj.msg_id;
+            head.textContent = inj.time + ' #' + inj.msg_id + ': ' + inj.query;
+            item.appendChild(head);
+            const ul = document.createElement('ul');
+            inj.notes.forEach(n => {
+                const li = document.createElement('li');
+                li.textContent = '[' + n.store + '] ' + n.summary + ' (' + n.score.toFixed(3) + ')';
+                ul.appendChild(li);
+            });
+            item.appendChild(ul);
+            div.appendChild(item);
+        });
+    }
+    // 显示整理记忆的变更，每条变更显示变更前后的记忆和原因
+    function render_memory_changes(changes, empty) {
+        const div = document.getElementById('memoryInjections');
+        div.innerHTML = '';
+        if (changes.length === 0) {
+            div.textContent = empty;
+        }
+        changes.forEach(c => {
+            const item = document.createElement('div');
+            item.className = 'memory-injection';
+            const head = document.createElement('div');
+            head.textContent = c.time + ' [' + c.namespace + '] ' + c.action + ': ' + c.reason;
+            item.appendChild(head);
+            const ul = document.createElement('ul');
+            c.before.forEach(b => {
+                const li = document.createElement('li');
+                li.textContent = '- ' + b;
+                ul.appendChild(li);
+            });
+            if (c.after !== null) {
+                const li = document.createElement('li');
+                li.textContent = '+ ' + c.after;
+                ul.appendChild(li);
+            }
+            item.appendChild(ul);
+            div.appendChild(item);
+        });
+    }
+    async function consolidateMemories() {
+        if (!confirm('Consolidate ' + memoryStore.value + ' memories with LLM?')) {
+            return;
+        }
+        document.getElementById('memoryInjections').textContent = 'Consolidating...';
+        const response = await memory_request(memoryApi + '/consolidate?store=' + memoryStore.value, {method: 'POST'});
+        render_memory_changes(response === null ? [] : await response.json(), 'Nothing changed');
+        await render_memory_list();
+    }
+    async function showChangelog() {
+        const response = await memory_request(memoryApi + '/changelog?store=' + memoryStore.value);
+        render_memory_changes(response === null ? [] : await response.json(), 'No consolidation yet');
+    }
+    function closeMemoryModal() {
+        memoryModal.classList.remove('active');
+    }
+    // 设置当前对话使用的记忆命名空间
+    async function set_memory_namespaces(names) {
+        const response = await memory_request(memoryApi + '/namespaces' + (names === null ? '' : '?names=' + encodeURIComponent(names)));
+        if (response !== null) {
+            document.getElementById('memoryNamespaces').value = (await response.json()).join(',');
+        }
+    }
+    memoryStore.addEventListener('change', render_memory_list);
+    document.getElementById('memorySearch').addEventListener('change', render_memory_list);
+    document.getElementById('memoryNamespaces').addEventListener('change', event => set_memory_namespaces(event.target.value));
+    window.saveMemory = saveMemory;
+    window.mergeMemories = mergeMemories;
+    window.showInjections = showInjections;
+    window.consolidateMemories = consolidateMemories;
+    window.showChangelog = showChangelog;
+    window.closeMemoryModal = closeMemoryModal;
+    document.getElementById('left-memory').addEventListener('contextmenu', async function(event) {
+        event.preventDefault();
+        editingMemoryId = null;
+        document.getElementById('memorySummary').value = '';
+        document.getElementById('memoryInjections').innerHTML = '';
+        await set_memory_namespaces(null);
+        await render_memory_list();
+        memoryModal.classList.add('active');
+    });
+    // 创建固定消息的图标，新插入的消息默认不固定
+    function create_pin_icon(id) {
+        let pinicon = document.createElement('span');
+        pinicon.setAttribute('id', 'p'+id);
+        pinicon.setAttribute('class', 'pin_btn');
+"###;
+    result += &format!("        pinicon.setAttribute('title', '{}');\n", page_data.pin);
+    result += r###"        pinicon.textContent = '📌';
+        return pinicon;
+    }
     // 高亮指定序号范围内的消息头像
     function highlightMessageAvatars(start, end, highlightClass = 'avatar-highlight') {
         // 确保输入是数字
@@ -2163,7 +2776,7 @@ print(b)
     result += r###"
         document.getElementById('input_query').disabled = true; // 完成回复之前禁止继续提问
         // 将参数加到问题后面
-        let req2 = q+"&model="+para_model+"&chatname="+para_chat_name+"&uuid="+para_uuid+"&stream="+para_stm+"&web="+para_web+"&num="+para_num+"&prompt="+para_prompt+"&voice="+para_voice+"&effort="+para_effort+"&temp="+para_temperature+"&topp="+para_top_p+"&tools="+para_tool+"&compress="+compress+"&plan="+para_plan+"&skills="+para_skill+"&microphone="+start_microphone;
+        let req2 = q+"&model="+para_model+"&chatname="+para_chat_name+"&uuid="+para_uuid+"&stream="+para_stm+"&web="+para_web+"&num="+para_num+"&prompt="+para_prompt+"&voice="+para_voice+"&effort="+para_effort+"&temp="+para_temperature+"&topp="+para_top_p+"&tools="+encodeURIComponent(para_tool)+"&compress="+compress+"&plan="+para_plan+"&skills="+para_skill+"&microphone="+start_microphone;
         compress = 'false';
         return [req, req2];
     }
@@ -2185,6 +2798,11 @@ print(b)
     // 提交问题并获取答案
     let controller = null;
     async function send_query_receive_answer(start_microphone) {
+        // 填写模板变量，取消则不提问
+        const template_vars = await fill_template_vars();
+        if (template_vars === null) {
+            return;
+        }
         // 从服务器获取stream内容
         no_message = true;
         already_clear_log = false;
@@ -2197,6 +2815,7 @@ print(b)
         // 由于EventSource不支持post，因此无法将问题通过body传递，只能放到url中通过url参数传递，但url有长度限制（好像大部分浏览器是2k），因此输入内容长度不能太长
         // 这里用fetch发送post，将问题字符串通过body传递，其他简单参数通过url传递
         let [req, req2] = get_url(start_microphone);
+        req2 += "&vars="+encodeURIComponent(JSON.stringify(template_vars));
         controller = new AbortController();
         const response = await fetch(address+req2, {
             method: 'POST',
@@ -2282,6 +2901,8 @@ print(b)
                         // 将窗口范围内的消息的头像border高亮
                         clearAvatarHighlight();
                         highlightMessageAvatars(jsonData.context_start, jsonData.context_end);
+                        // 固定的消息不受上下文窗口限制，也高亮
+                        jsonData.pinned.forEach(i => highlightMessageAvatars(i, i));
                         // 更新语音模式图标
                         const microphoneDiv = document.getElementById('left-microphone');
                         const microphoneImg = document.getElementById('microphone');
@@ -2490,7 +3111,7 @@ pub fn create_download_page(uuid: &str, err_str: Option<String>) -> String {
 "###;
     // 获取该uuid的chat记录，如果传递的err_str不是None，则表示无法获取chat记录
     let logs = match err_str {
-        Some(e) => vec![DisplayInfo{is_query: false, content:  e, id: 0, time: "".to_string(), is_img: false, is_voice: false, is_web: false, remembered: false, idx_qa: 1, idx_m: 1, token: 0}],
+        Some(e) => vec![DisplayInfo{is_query: false, content:  e, id: 0, time: "".to_string(), is_img: false, is_voice: false, is_web: false, remembered: false, pinned: false, idx_qa: 1, idx_m: 1, token: 0}],
         None => {
             // 在保存当前chat记录之前，先去除当前uuid的messages末尾连续的问题，这些问题没有实际调用OpenAI api
             // pop_message_before_end(uuid); // 这里不要执行这一函数，只在关闭服务时执行，这里执行完，如果再继续输入问题，会因为id与服务端不对应而报错
@@ -2585,7 +3206,7 @@ pub fn create_download_page(uuid: &str, err_str: Option<String>) -> String {
     for log in logs.iter() {
         result += &format!("            var msg = document.getElementById('m{}');
             var tmp = `{}`; // 这里将模板中的chat内容（已将“`”做了转译，“script”结束标签去掉了“<”）存入变量中
-            if (tmp.startsWith('data:image/png;base64,')) {{ // 插入图片
+            if (tmp.startsWith('data:image/png;base64,') || tmp.startsWith('data:image/jpeg;base64,')) {{ // 插入图片
                 let tmp_img = document.createElement('img');
                 tmp_img.src = tmp;
                 msg.appendChild(tmp_img);
@@ -5,7 +5,6 @@ use std::sync::Mutex;
 
 use axum_extra::extract::cookie::{Cookie, SameSite, CookieJar};
 use chrono::{Local, NaiveDateTime};
-use image::ImageReader;
 use once_cell::sync::Lazy;
 use openai_dive::v1::resources::chat::{
     ChatMessage,
@@ -16,13 +15,17 @@ use openai_dive::v1::resources::chat::{
 };
 use serde::{Serialize, Deserialize};
 use tracing::{event, Level};
+use uuid::Uuid;
 
 /// parse_paras: 解析命令行参数
 /// error: 定义的错误类型，用于错误传递
 use crate::{
     parse_paras::PARAS,
+    prompt_library::get_prompt_by_value,
+    openai::for_vision::estimate_image_token, // 根据模型的图片参数估算图片token数
     graph::graph_remove_uuid,
     html_page::create_download_page, // 生成chat记录页面html字符串
+    memory::manage::MemoryInjection, // 某次回答注入的记忆
     error::MyError,
 };
 
@@ -101,29 +104,25 @@ pub struct ChatData {
     idx_m:      usize,       // 该message属于第几条信息
     token:      u32,         // 该message的token数
     remembered: bool,        // 是否已提取过记忆
+    #[serde(default)]
+    pinned:     bool,        // 是否固定，固定的信息不受上下文窗口限制，始终包含在上下文中
 }
 
 impl ChatData {
-    fn new(uuid: &str, id: usize, message: ChatMessage, time: String, data: DataType, is_web: bool, idx_qa: usize, idx_m: usize) -> Self {
+    fn new(uuid: &str, id: usize, message: ChatMessage, time: String, data: DataType, is_web: bool, idx_qa: usize, idx_m: usize, model: &str) -> Self {
         // 计算token数
         let token = if let (DataType::Image(_), Some(name)) = (&data, get_image_name(&message)) {
-            // Qwen3-vl图片token: `width / 32 * height / 32`
+            // 按模型的图片参数计算，不设置则按Qwen3-vl: `width / 32 * height / 32`
             // Qwen2.5-vl图片token: `width / 28 * height / 28`
             // https://github.com/QwenLM/Qwen3-VL/issues/1238
             // https://github.com/QwenLM/Qwen3-VL
             let image_file = format!("{}/{}/{}", PARAS.outpath, uuid, name);
             let image_file_path = Path::new(&image_file);
             if image_file_path.exists() && image_file_path.is_file() {
-                match ImageReader::open(&image_file) {
-                    Ok(img) => match img.decode() {
-                        Ok(dec) => dec.width() * dec.height() / 1024, // Qwen3-vl tokens
-                        Err(e) => {
-                            event!(Level::ERROR, "{} decode image error: {:?}", uuid, e);
-                            0
-                        },
-                    },
+                match estimate_image_token(&image_file, &PARAS.snapshot().api.get_image_config(model)) {
+                    Ok(token) => token,
                     Err(e) => {
-                        event!(Level::ERROR, "{} read image error: {:?}", uuid, e);
+                        event!(Level::ERROR, "{} read image error: {}", uuid, e);
                         0
                     },
                 }
@@ -134,7 +133,7 @@ impl ChatData {
             0
         };
         //ChatData{message, time: if is_web {format!("🌐 {time}")} else {time}, data, idx_qa, token} // 不管用，页面不显示emoji
-        ChatData{id, message, time, data, is_web, idx_qa, idx_m, token, remembered: false}
+        ChatData{id, message, time, data, is_web, idx_qa, idx_m, token, remembered: false, pinned: false}
     }
 
     /// convert uploaded image to User
@@ -178,7 +177,18 @@ pub struct Info {
     pub save:          bool,                 // 是否需要保存该uuid的chat记录，如果只是提问，没有实际调用OpenAI的api进行回答，则最后退出程序时不需要保存该uuid的chat记录，只有本次开启服务后该uuid实际调用OpenAI的api得到回答这里才设为true
     pub pop:           usize,                // 如果只是提问而没有实际调用OpenAI api获取答案，则舍弃最后的连续的提问，这里记录要从messages最后移除的message数量，最后是答案则该值重置为0，否则累加连续的问题数
     pub is_incognito:  bool,                 // 是否无痕模式，true则关闭服务时不保存该对话，直接舍弃，如果是基于之前保存的对话继续提问，则本次新的问答不会保存；false则像常规对话那样，关闭服务时保存至本地
-    pub approved:      Option<String>,       // call tool approval
+    #[serde(default)]
+    pub knowledge:     Vec<String>,          // 该对话使用的知识库名称，每次提问检索最相关的片段注入上下文
+    #[serde(skip)]
+    pub knowledge_ctx: Option<String>,       // 当前问题从知识库检索到的片段，不保存，下次提问时重新检索
+    #[serde(default)]
+    pub memory_injections: Vec<MemoryInjection>, // 每次回答注入的记忆，用于记忆管理页面查看
+    #[serde(default)]
+    pub memory_namespaces: Vec<String>,          // 该对话使用的记忆命名空间（项目），只检索这些命名空间和全局命名空间的记忆，新记忆存入第一个命名空间
+    #[serde(skip)]
+    pub from_local:    bool,                 // 本次开启服务后最近一次提问是否来自服务端所在电脑，自动提取的记忆存入`memory.json`，否则存入uuid的记忆
+    #[serde(skip)]
+    pub unranked_code: Vec<(String, String)>, // 还没有按实际的问题选取文件的代码压缩包，(压缩包文件名, 上传时使用的问题)，随对话删除
 }
 
 /// 实现Info的方法
@@ -215,7 +225,12 @@ impl Info {
             save:          false,                          // 是否需要保存该uuid的chat记录，如果只是提问，没有实际调用OpenAI的api进行回答，则最后退出程序时不需要保存该uuid的chat记录，只有本次开启服务后该uuid实际调用OpenAI的api得到回答这里才设为true
             pop:           0,                              // 如果只是提问而没有实际调用OpenAI api获取答案，则舍弃最后的连续的提问，这里记录要从messages最后移除的message数量，最后是答案则该值重置为0，否则累加连续的问题数
             is_incognito:  false,                          // 是否无痕模式，true则关闭服务时不保存该对话，直接舍弃，如果是基于之前保存的对话继续提问，则本次新的问答不会保存；false则像常规对话那样，关闭服务时保存至本地
-            approved:      None,                           // call tool approval
+            knowledge:     vec![],                         // 该对话使用的知识库名称，每次提问检索最相关的片段注入上下文
+            knowledge_ctx: None,                           // 当前问题从知识库检索到的片段，不保存，下次提问时重新检索
+            memory_injections: vec![],                     // 每次回答注入的记忆，用于记忆管理页面查看
+            memory_namespaces: vec![],                     // 该对话使用的记忆命名空间（项目），为空则只使用全局命名空间
+            from_local:    false,                          // 本次开启服务后最近一次提问是否来自服务端所在电脑
+            unranked_code: Vec::new(),                     // 还没有按实际的问题选取文件的代码压缩包
         }
     }
 
@@ -294,6 +309,7 @@ impl Info {
 
     /// 从messages中提取所有的message，返回Vec<ChatMessage>
     /// 这里skip_pre和skip_suf不会考虑信息是否是hide，直接对总messages进行截取，截取后的信息再过滤掉hide信息
+    /// 窗口之前固定的信息按原顺序放在窗口内信息的前面
     fn get_inner_messages(&self, skip_pre: usize, skip_suf: usize) -> Vec<ChatMessage> {
         if skip_pre == 0 && skip_suf == 0 {
             //self.messages.iter().map(|m| m.message.clone()).collect()
@@ -301,22 +317,54 @@ impl Info {
         } else {
             //self.messages.iter().skip(skip_pre).map(|m| m.message.clone()).collect()
             //self.messages[skip_pre..(self.messages.len()-skip_suf)].iter().map(|m| m.message.clone()).collect()
-            self.messages[skip_pre..(self.messages.len()-skip_suf)].iter().filter(|m| !m.data.is_hide()).map(|m| m.get_real_message()).collect() // 先截取信息，然后再过滤掉截取后的信息中hide的信息
+            self.messages[..skip_pre].iter().filter(|m| m.pinned) // 窗口之前固定的信息
+                .chain(self.messages[skip_pre..(self.messages.len()-skip_suf)].iter()) // 窗口内的信息
+                .filter(|m| !m.data.is_hide()).map(|m| m.get_real_message()).collect() // 先截取信息，然后再过滤掉截取后的信息中hide的信息
+        }
+    }
+
+    /// 上下文窗口范围，返回(要忽略前几个消息数, 最后要忽略的连续回答数)，与获取上下文时相同
+    fn context_range(&self) -> (usize, usize) {
+        if self.qa_msg_p.0 > 0 && self.qa_msg_p.0 < usize::MAX { // 对问答对数量进行限制
+            let (skip_msg_num, _, skip_last_answer_num, _) = self.context_msg_num_by_qa();
+            (skip_msg_num, skip_last_answer_num)
+        } else if self.qa_msg_p.1 > 0 && self.qa_msg_p.1 < usize::MAX { // 对消息数量进行限制
+            let (skip_msg_num, _, skip_last_answer_num, _) = self.context_msg_num();
+            (skip_msg_num, skip_last_answer_num)
+        } else { // 没有对问答对或消息数进行限制
+            (0, 0)
         }
     }
 
-    // 将指定范围内 message 标注为 remembered
+    /// 上下文窗口内还未提取记忆的信息，固定的信息不受窗口限制，与手动提取当前对话的记忆时范围相同
+    fn unremembered_in_context(&self) -> Vec<&ChatData> {
+        let (skip_pre, skip_suf) = self.context_range();
+        let end = self.messages.len().saturating_sub(skip_suf);
+        self.messages.iter().enumerate().filter(|(i, m)| (m.pinned || *i >= skip_pre) && *i < end && !m.remembered && !m.data.is_hide()).map(|(_, m)| m).collect()
+    }
+
+    // 将指定范围内 message 以及窗口之前固定的 message 标注为 remembered
     fn label_remembered_by_range(&mut self, skip_pre: usize, skip_suf: usize) {
         let len = self.messages.len();
-        if let Some(slice) = self.messages.get_mut(skip_pre..(len-skip_suf)) {
-            for message in slice {
-                if !message.data.is_hide() {
-                    message.remembered = true;
-                }
+        for (i, message) in self.messages.iter_mut().enumerate() {
+            if (message.pinned || i >= skip_pre) && i < len-skip_suf && !message.data.is_hide() {
+                message.remembered = true;
             }
         }
     }
 
+    /// 切换指定索引信息的固定状态，返回切换后是否固定，索引出界或是隐藏信息则返回None
+    fn toggle_pin(&mut self, idx: usize) -> Option<bool> {
+        match self.messages.get_mut(idx) {
+            Some(m) if !m.data.is_hide() => {
+                m.pinned = !m.pinned;
+                self.save = true; // 固定状态需要保存到chat记录中
+                Some(m.pinned)
+            },
+            _ => None,
+        }
+    }
+
     /// 根据限制的问答对数量，获取(要忽略前几个消息数, 要保留的消息数, 最后要忽略的连续回答数, skip_last_answer_num)
     /// 一对问答对可以有连续多个问题，以及连续多条答案，例如下面的示例，question1和answer4之间的多个消息都属于一对问答：
     /// +----------------------+
@@ -700,9 +748,9 @@ pub fn insert_message(uuid: &str, message: ChatMessage, msg_token: Option<(u32,
     info.msg_len += 1;
     // 插入本次的message、时间、原始问题、是否网络搜索、message属于第几个Q&A对
     let mut chat_data = if qa_msg_p.is_some() { // 目前用户提出的问题都是Some，不需要加模型名称
-        ChatData::new(uuid, info.messages.len(), message, time, query, is_web, qa_num, info.msg_len)
+        ChatData::new(uuid, info.messages.len(), message, time, query, is_web, qa_num, info.msg_len, model)
     } else { // 目前模型回答的内容都是None
-        ChatData::new(uuid, info.messages.len(), message, format!("{} {}", time, model), query, is_web, qa_num, info.msg_len) // 在时间后面加上当前调用的模型名称，这样在同一对话中调用不同模型可以区分开
+        ChatData::new(uuid, info.messages.len(), message, format!("{} {}", time, model), query, is_web, qa_num, info.msg_len, model) // 在时间后面加上当前调用的模型名称，这样在同一对话中调用不同模型可以区分开
     };
     // 更新总输入、总输出、当前回复message的token数
     if let Some((prompt_tokens, completion_tokens, total_tokens)) = msg_token {
@@ -763,28 +811,6 @@ fn get_chat_name_from_user_msg(msg: &ChatMessage) -> Option<String> {
     }
 }
 
-/// update approval for call tools
-pub fn update_approval(uuid: &str, approval: Option<String>) {
-    let mut data = DATA.lock().unwrap();
-    if let Some(info) = data.get_mut(uuid) {
-        info.approved = approval;
-    }
-}
-
-/// check approved
-pub fn approved(uuid: &str) -> Option<String> {
-    let data = DATA.lock().unwrap();
-    if let Some(info) = data.get(uuid) {
-        if let Some(tf) = &info.approved {
-            Some(tf.clone())
-        } else {
-            None
-        }
-    } else {
-        None
-    }
-}
-
 /// update input total token, output total token, context token
 pub fn update_token(uuid: &str, usage: (u32, u32, u32)) {
     let mut data = DATA.lock().unwrap();
@@ -875,6 +901,180 @@ pub fn delete_msg_by_id(uuid: &str, id: &str) -> (bool, Option<String>) {
     }
 }
 
+// 固定或取消固定服务端指定uuid中指定id的信息，这里id格式是“p”+序号索引，比如“p0”表示第一条信息
+// 成功返回切换后是否固定，失败返回错误信息
+pub fn pin_msg_by_id(uuid: &str, id: &str) -> Result<bool, String> {
+    let mut data = DATA.lock().unwrap();
+    let Some(idx) = id.strip_prefix("p").and_then(|i| i.parse::<usize>().ok()) else {
+        return Err(format!("id {id} must be \"p\" + number"))
+    };
+    match data.get_mut(uuid) {
+        Some(info) => info.toggle_pin(idx).ok_or_else(|| format!("index {idx} >= total messages number or hidden")),
+        None => Err(format!("uuid {uuid} not in server")),
+    }
+}
+
+/// 替换指定uuid中最后一条记录了`raw`（上传的文件名）的用户信息的内容，例如代码压缩包按实际的问题重新选取文件，返回是否替换
+pub fn replace_raw_message(uuid: &str, raw: &str, content: String) -> bool {
+    let mut data = DATA.lock().unwrap();
+    let Some(info) = data.get_mut(uuid) else {
+        return false
+    };
+    match info.messages.iter_mut().rev().find(|m| matches!(&m.data, DataType::Raw(r) if r == raw) && matches!(m.message, ChatMessage::User{..})) {
+        Some(m) => {
+            m.message = ChatMessage::User{
+                content: ChatMessageContent::Text(content),
+                name: None,
+            };
+            true
+        },
+        None => false,
+    }
+}
+
+/// 设置指定uuid使用的知识库，空则不使用知识库
+pub fn set_knowledge(uuid: &str, names: Vec<String>) {
+    let mut data = DATA.lock().unwrap();
+    if let Some(info) = data.get_mut(uuid) {
+        if names.is_empty() {
+            info.knowledge_ctx = None;
+        }
+        info.knowledge = names; // 随chat记录保存，继续该对话时仍使用这些知识库
+    }
+}
+
+/// 获取指定uuid使用的知识库
+pub fn get_knowledge(uuid: &str) -> Vec<String> {
+    let data = DATA.lock().unwrap();
+    match data.get(uuid) {
+        Some(info) => info.knowledge.clone(),
+        None => vec![],
+    }
+}
+
+/// 更新指定uuid当前问题从知识库检索到的片段，None表示没有相关片段
+pub fn set_knowledge_context(uuid: &str, context: Option<String>) {
+    let mut data = DATA.lock().unwrap();
+    if let Some(info) = data.get_mut(uuid) {
+        info.knowledge_ctx = context;
+    }
+}
+
+/// 记录指定uuid本次回答注入的记忆，信息id是下一条信息的id，即本次回答的第一条信息
+pub fn record_memory_injection(uuid: &str, mut injection: MemoryInjection) {
+    let mut data = DATA.lock().unwrap();
+    if let Some(info) = data.get_mut(uuid) {
+        injection.msg_id = info.messages.len();
+        info.memory_injections.push(injection);
+    }
+}
+
+/// 设置指定uuid使用的记忆命名空间，空则只使用全局命名空间
+pub fn set_memory_namespaces(uuid: &str, namespaces: Vec<String>) {
+    let mut data = DATA.lock().unwrap();
+    if let Some(info) = data.get_mut(uuid) {
+        info.memory_namespaces = namespaces;
+    }
+}
+
+/// 记录指定uuid最近一次提问是否来自服务端所在电脑
+pub fn set_from_local(uuid: &str, from_local: bool) {
+    let mut data = DATA.lock().unwrap();
+    if let Some(info) = data.get_mut(uuid) {
+        info.from_local = from_local;
+    }
+}
+
+/// 记录上传后还没有按实际的问题选取文件的代码压缩包
+pub fn push_unranked_code(uuid: &str, name: &str, question: &str) {
+    let mut data = DATA.lock().unwrap();
+    if let Some(info) = data.get_mut(uuid) {
+        info.unranked_code.push((name.to_string(), question.to_string()));
+    }
+}
+
+/// 取出指定uuid还没有按实际的问题选取文件的代码压缩包
+pub fn take_unranked_code(uuid: &str) -> Vec<(String, String)> {
+    let mut data = DATA.lock().unwrap();
+    match data.get_mut(uuid) {
+        Some(info) => std::mem::take(&mut info.unranked_code),
+        None => Vec::new(),
+    }
+}
+
+/// 需要自动提取记忆的对话，返回(uuid, 最近一次提问是否来自服务端所在电脑)
+/// 最后一条信息是回答，且还未提取记忆的问答对数量达到`turns`，或最后一条信息距今超过`idle_min`分钟，0表示不使用该条件，无痕模式的对话不提取
+pub fn auto_memory_candidates(turns: usize, idle_min: u64) -> Vec<(String, bool)> {
+    let data = DATA.lock().unwrap();
+    let now = Local::now().naive_local();
+    data.values().filter(|info| !info.is_incognito && info.pop == 0).filter_map(|info| {
+        let unremembered = info.unremembered_in_context();
+        let last = unremembered.last()?;
+        if !matches!(last.message, ChatMessage::Assistant{..}) {
+            return None // 正在回答或只有提问
+        }
+        let mut qa: Vec<usize> = unremembered.iter().map(|m| m.idx_qa).collect();
+        qa.dedup();
+        let by_turns = turns > 0 && qa.len() >= turns;
+        let by_idle = idle_min > 0 && last.time.get(..19).and_then(|t| NaiveDateTime::parse_from_str(t, "%Y-%m-%d %H:%M:%S").ok()).is_some_and(|t| (now - t).num_minutes() >= idle_min as i64);
+        (by_turns || by_idle).then(|| (info.uuid.clone(), info.from_local))
+    }).collect()
+}
+
+/// 获取指定uuid上下文窗口内还未提取记忆的信息，返回(用于提取记忆的内容, 这些信息的id)，提取记忆后用`label_remembered_ids`标注
+/// 与手动提取当前对话的记忆相同，只提取页面选择的问答对或消息数以内的信息
+pub fn get_unremembered(uuid: &str) -> Option<(String, Vec<usize>)> {
+    let data = DATA.lock().unwrap();
+    let info = data.get(uuid)?;
+    let messages = info.unremembered_in_context();
+    if messages.is_empty() {
+        None
+    } else {
+        Some((
+            messages.iter().map(|m| format!("{:?}", m.message)).collect::<Vec<String>>().join("\n"),
+            messages.iter().map(|m| m.id).collect(),
+        ))
+    }
+}
+
+/// 指定 uuid 的指定id的信息标记为已提取了记忆 remembered
+pub fn label_remembered_ids(uuid: &str, ids: &[usize]) {
+    let mut data = DATA.lock().unwrap();
+    if let Some(info) = data.get_mut(uuid) {
+        for m in info.messages.iter_mut().filter(|m| ids.contains(&m.id)) {
+            m.remembered = true;
+        }
+        info.save = true; // remembered 随chat记录保存
+    }
+}
+
+/// 获取指定uuid使用的记忆命名空间
+pub fn get_memory_namespaces(uuid: &str) -> Vec<String> {
+    let data = DATA.lock().unwrap();
+    match data.get(uuid) {
+        Some(info) => info.memory_namespaces.clone(),
+        None => vec![],
+    }
+}
+
+/// 获取指定uuid每次回答注入的记忆，最新的在前
+pub fn get_memory_injections(uuid: &str) -> Vec<MemoryInjection> {
+    let data = DATA.lock().unwrap();
+    match data.get(uuid) {
+        Some(info) => info.memory_injections.iter().rev().cloned().collect(),
+        None => vec![],
+    }
+}
+
+/// 获取指定uuid所有固定信息的id，用于页面高亮
+pub fn get_pinned_ids(uuid: &str) -> Vec<usize> {
+    let data = DATA.lock().unwrap();
+    match data.get(uuid) {
+        Some(info) => info.messages.iter().enumerate().filter(|(_, m)| m.pinned && !m.data.is_hide()).map(|(i, _)| i).collect(),
+        None => vec![],
+    }
+}
+
 /// 判断指定uuid是否已存在于DATA中
 pub fn contain_uuid(uuid: &str) -> bool {
     let mut data = DATA.lock().unwrap();
@@ -906,7 +1106,7 @@ pub fn get_messages(uuid: &str) -> Vec<ChatMessage> {
     let mut data = DATA.lock().unwrap();
     match data.get_mut(uuid) {
         Some(info) => {
-            if info.qa_msg_p.0 == usize::MAX && info.qa_msg_p.1 == usize::MAX { // 没有对问答对或消息数进行限制
+            let mut messages = if info.qa_msg_p.0 == usize::MAX && info.qa_msg_p.1 == usize::MAX { // 没有对问答对或消息数进行限制
                 info.get_inner_messages(0, 0)
             } else { // 通过问答对或消息数进行了限制，需要跳过前指定数量个消息
                 // 总消息数
@@ -925,13 +1125,21 @@ pub fn get_messages(uuid: &str) -> Vec<ChatMessage> {
                 // 把prompt插入到第一位
                 if info.qa_msg_p.2 {
                     if let Some(p) = &info.prompt {
-                        if total_num != keep_msg_num { // 把prompt插入到第一位，如果相等则已经包含了prompt则不必再插入
+                        if total_num != keep_msg_num && !info.messages[0].pinned { // 把prompt插入到第一位，如果相等则已经包含了prompt则不必再插入，prompt被固定也已经包含了
                             messages.insert(0, p.clone());
                         }
                     }
                 }
                 messages
+            };
+            // 从知识库检索到的片段放在最后，只用于本次回答，不记录在messages中
+            if let Some(k) = &info.knowledge_ctx {
+                messages.push(ChatMessage::User{
+                    content: ChatMessageContent::Text(k.clone()),
+                    name: None,
+                });
             }
+            messages
         },
         None => vec![],
     }
@@ -941,20 +1149,8 @@ pub fn get_messages(uuid: &str) -> Vec<ChatMessage> {
 pub fn label_remembered(uuid: &str) {
     let mut data = DATA.lock().unwrap();
     if let Some(info) = data.get_mut(uuid) {
-        let (skip_msg_num, skip_last_answer_num) = if info.qa_msg_p.0 == usize::MAX && info.qa_msg_p.1 == usize::MAX { // 没有对问答对或消息数进行限制
-            (0, 0)
-        } else { // 通过问答对或消息数进行了限制，需要跳过前指定数量个消息
-            // 获取(要忽略前几个消息数, 要保留的消息数, 最后要忽略的连续回答数)
-            // 理论上`skip_msg_num`可能为0，但不可能等于总消息数，`keep_msg_num`肯定大于0，最大就是总消息数
-            let (skip_msg_num, _, skip_last_answer_num, _) = if info.qa_msg_p.0 > 0 && info.qa_msg_p.0 < usize::MAX { // 对问答对数量进行限制
-                info.context_msg_num_by_qa()
-            } else if info.qa_msg_p.1 > 0 && info.qa_msg_p.1 < usize::MAX { // 对消息数量进行限制
-                info.context_msg_num()
-            } else {
-                unreachable!()
-            };
-            (skip_msg_num, skip_last_answer_num)
-        };
+        // 理论上`skip_msg_num`可能为0，但不可能等于总消息数
+        let (skip_msg_num, skip_last_answer_num) = info.context_range();
         info.label_remembered_by_range(skip_msg_num, skip_last_answer_num);
     }
 }
@@ -1029,15 +1225,27 @@ pub fn is_incognito(uuid: &str) -> bool {
     }
 }
 
-/// 将DATA中指定uuid的chat记录保存至本地json文件中
-/// 文件名为：`时间戳.log`
+/// 将DATA中指定uuid的chat记录保存至本地json文件中，同时保存html文件，条件与`save_all_chat`相同
+/// 文件名为：`时间戳.log`，`chatsong chat`每次回答后调用，程序被强制结束时不会丢失之前的对话
 pub fn save_log(uuid: &str) {
     let mut data = DATA.lock().unwrap();
-    if let Some(info) = data.get_mut(uuid) {
-        // 保存chat记录
-        if let Err(e) = info.save() {
-            event!(Level::ERROR, "{} save chat log error: {}", uuid, e);
-        }
+    let log_file = match data.get_mut(uuid) {
+        Some(info) if info.save && !info.is_incognito => {
+            if let Err(e) = create_uuid_dir(uuid) {
+                event!(Level::ERROR, "{}", e);
+            }
+            // 保存chat记录
+            if let Err(e) = info.save() {
+                event!(Level::ERROR, "{} save chat log error: {}", uuid, e);
+            }
+            info.file.clone()
+        },
+        _ => return,
+    };
+    drop(data); // `create_download_page`函数内部需要进行lock
+    let file_with_path = format!("{}/{}", PARAS.outpath, log_file);
+    if let Err(e) = write(file_with_path.replace(".log", ".html"), create_download_page(uuid, None)) {
+        event!(Level::ERROR, "{} save chat log to html error: {}", uuid, e);
     }
 }
 
@@ -1082,6 +1290,11 @@ pub fn create_cookie<'a>(v: String) -> Cookie<'a> {
         .build()
 }
 
+/// cookie值是否是服务端生成的uuid（`Uuid::new_v4`的带连字符小写格式），cookie值会用作记忆的key和服务端路径，不能是`local`、`old`或含有`/`、`..`的任意字符串
+pub fn is_valid_uuid(v: &str) -> bool {
+    Uuid::try_parse(v).is_ok_and(|u| u.hyphenated().to_string() == v)
+}
+
 /// 更新cookie的max-age，用于在每次访问时都将max-age以当前时间为起始更新max-age
 pub fn update_cookie_max_age(cjar: CookieJar) -> CookieJar {
     if let Some(mut cookie) = cjar.get("srx-tzn").cloned() {
@@ -1115,14 +1328,36 @@ pub fn get_msg_token(uuid: &str, pos: isize) -> u32 {
             let idx = if pos >= 0 {
                 pos as usize
             } else {
-                info.messages.len() - (-pos) as usize
+                match info.messages.len().checked_sub(pos.unsigned_abs()) {
+                    Some(i) => i,
+                    None => return 0, // 倒数超过message总数
+                }
             };
-            info.messages[idx].token
+            info.messages.get(idx).map(|m| m.token).unwrap_or(0)
         },
         None => 0,
     }
 }
 
+/// 更新当前uuid指定位置message的token数，例如上传图片后按当前选择的模型重新估算
+/// pos>=0表示索引位置，pos<0表示倒数第几个，比如0表示第1个，1表示第2个，-1表示最后一个，-2表示倒数第个
+pub fn set_msg_token(uuid: &str, pos: isize, token: u32) {
+    let mut data = DATA.lock().unwrap();
+    if let Some(info) = data.get_mut(uuid) {
+        let idx = if pos >= 0 {
+            pos as usize
+        } else {
+            match info.messages.len().checked_sub(pos.unsigned_abs()) {
+                Some(i) => i,
+                None => return, // 倒数超过message总数
+            }
+        };
+        if let Some(m) = info.messages.get_mut(idx) {
+            m.token = token;
+        }
+    }
+}
+
 /// 获取当前uuid的问题和答案的总token数
 pub fn get_token(uuid: &str) -> [u32; 2] {
     let data = DATA.lock().unwrap();
@@ -1211,33 +1446,18 @@ pub fn get_speech_file(uuid: &str) -> String {
     }
 }
 
-/// 获取prompt名称和内容，不存在则返回空字符串
-pub fn get_prompt(p: usize) -> [String; 2] {
-    match PARAS.prompt.get(&p) {
-        Some(prompt) => prompt.clone(),
-        None => ["".to_string(), "".to_string()],
-    }
-}
-
-/// 读取服务端`指定输出路径/uuid/prompt.txt`（其中写着该uuid使用的prompt的序号），获取该uuid的prompt的序号，然后用该序号去获取prompt的名称和内容
-/// 序号从0开始，0表示无prompt
+/// 读取服务端`指定输出路径/uuid/prompt.txt`（其中写着该uuid使用的prompt下拉选项的值），然后用该值去获取prompt的名称和内容
+/// 参数文件中的prompt是序号，序号从0开始，0表示无prompt，prompt库中的prompt是`lib:id`
 fn get_prompt_from_file(uuid: &str) -> Option<[String; 2]> {
     let tmp = format!("{}/{}/prompt.txt", PARAS.outpath, uuid);
     let tmp_path = Path::new(&tmp);
     if tmp_path.exists() && tmp_path.is_file() {
         match read_to_string(&tmp_path) {
             Ok(p) => {
-                match p.parse::<usize>() {
-                    Ok(n) => {
-                        let prompt_name_str = get_prompt(n);
-                        if prompt_name_str[0].is_empty() {
-                            None
-                        } else {
-                            Some(prompt_name_str)
-                        }
-                    },
-                    Err(e) => {
-                        event!(Level::INFO, "{} warning: parse {} -> usize error: {:?}", uuid, p, e);
+                match get_prompt_by_value(p.trim()) {
+                    Some(prompt) => Some([prompt.name, prompt.content]),
+                    None => {
+                        event!(Level::INFO, "{} warning: no such prompt: {}", uuid, p);
                         None
                     },
                 }
@@ -1285,6 +1505,7 @@ pub struct DisplayInfo {
     pub is_voice:   bool,   // 是否是语音base64
     pub is_web:     bool,   // 是否网络搜索
     pub remembered: bool,   // 是否已提取记忆
+    pub pinned:     bool,   // 是否固定在上下文中
     pub idx_qa:     usize,  // 该message属于第几个Q&A对
     pub idx_m:      usize,  // 该message属于第几条信息
     pub token:      u32,    // 该message的token数
@@ -1325,6 +1546,7 @@ pub fn get_log_for_display(uuid: &str, for_template: bool) -> (usize, usize, usi
                             is_voice:   false,
                             is_web:     m.is_web,
                             remembered: m.remembered,
+                            pinned:     m.pinned,
                             idx_qa:     m.idx_qa,
                             idx_m:      m.idx_m,
                             token:      m.token,
@@ -1340,6 +1562,7 @@ pub fn get_log_for_display(uuid: &str, for_template: bool) -> (usize, usize, usi
                             is_voice:   false,
                             is_web:     m.is_web,
                             remembered: m.remembered,
+                            pinned:     m.pinned,
                             idx_qa:     m.idx_qa,
                             idx_m:      m.idx_m,
                             token:      m.token,
@@ -1369,6 +1592,7 @@ pub fn get_log_for_display(uuid: &str, for_template: bool) -> (usize, usize, usi
                             is_voice:   false,
                             is_web:     m.is_web,
                             remembered: m.remembered,
+                            pinned:     m.pinned,
                             idx_qa:     m.idx_qa,
                             idx_m:      m.idx_m,
                             token:      m.token,
@@ -1384,13 +1608,14 @@ pub fn get_log_for_display(uuid: &str, for_template: bool) -> (usize, usize, usi
                             is_voice:   false,
                             is_web:     m.is_web,
                             remembered: m.remembered,
+                            pinned:     m.pinned,
                             idx_qa:     m.idx_qa,
                             idx_m:      m.idx_m,
                             token:      m.token,
                         });
                     }
                 },
-                ChatMessageContent::None => logs.push(DisplayInfo{is_query: false, content: "".to_string(), id: i, time: tmp_time, is_img: false, is_voice: false, is_web: m.is_web, remembered: m.remembered, idx_qa: m.idx_qa, idx_m: m.idx_m, token: m.token}),
+                ChatMessageContent::None => logs.push(DisplayInfo{is_query: false, content: "".to_string(), id: i, time: tmp_time, is_img: false, is_voice: false, is_web: m.is_web, remembered: m.remembered, pinned: m.pinned, idx_qa: m.idx_qa, idx_m: m.idx_m, token: m.token}),
             },
             ChatMessage::User{content, ..} => match content {
                 ChatMessageContent::Text(t) => {
@@ -1411,6 +1636,7 @@ pub fn get_log_for_display(uuid: &str, for_template: bool) -> (usize, usize, usi
                             is_voice:   false,
                             is_web:     m.is_web,
                             remembered: m.remembered,
+                            pinned:     m.pinned,
                             idx_qa:     m.idx_qa,
                             idx_m:      m.idx_m,
                             token:      m.token,
@@ -1426,6 +1652,7 @@ pub fn get_log_for_display(uuid: &str, for_template: bool) -> (usize, usize, usi
                             is_voice:   false,
                             is_web:     m.is_web,
                             remembered: m.remembered,
+                            pinned:     m.pinned,
                             idx_qa:     m.idx_qa,
                             idx_m:      m.idx_m,
                             token:      m.token,
@@ -1463,6 +1690,7 @@ pub fn get_log_for_display(uuid: &str, for_template: bool) -> (usize, usize, usi
                             is_voice:   false,
                             is_web:     m.is_web,
                             remembered: m.remembered,
+                            pinned:     m.pinned,
                             idx_qa:     m.idx_qa,
                             idx_m:      m.idx_m,
                             token:      m.token,
@@ -1478,13 +1706,14 @@ pub fn get_log_for_display(uuid: &str, for_template: bool) -> (usize, usize, usi
                             is_voice:   false,
                             is_web:     m.is_web,
                             remembered: m.remembered,
+                            pinned:     m.pinned,
                             idx_qa:     m.idx_qa,
                             idx_m:      m.idx_m,
                             token:      m.token,
                         });
                     }
                 },
-                ChatMessageContent::None => logs.push(DisplayInfo{is_query: true, content: "".to_string(), id: i, time: tmp_time, is_img: false, is_voice: false, is_web: m.is_web, remembered: m.remembered, idx_qa: m.idx_qa, idx_m: m.idx_m, token: m.token}),
+                ChatMessageContent::None => logs.push(DisplayInfo{is_query: true, content: "".to_string(), id: i, time: tmp_time, is_img: false, is_voice: false, is_web: m.is_web, remembered: m.remembered, pinned: m.pinned, idx_qa: m.idx_qa, idx_m: m.idx_m, token: m.token}),
             },
             ChatMessage::Assistant{content, ..} => match content {
                 Some(c) => match c {
@@ -1507,6 +1736,7 @@ pub fn get_log_for_display(uuid: &str, for_template: bool) -> (usize, usize, usi
                                 is_voice,
                                 is_web:     m.is_web,
                                 remembered: m.remembered,
+                                pinned:     m.pinned,
                                 idx_qa:     m.idx_qa,
                                 idx_m:      m.idx_m,
                                 token:      m.token,
@@ -1522,6 +1752,7 @@ pub fn get_log_for_display(uuid: &str, for_template: bool) -> (usize, usize, usi
                                 is_voice,
                                 is_web:     m.is_web,
                                 remembered: m.remembered,
+                                pinned:     m.pinned,
                                 idx_qa:     m.idx_qa,
                                 idx_m:      m.idx_m,
                                 token:      m.token,
@@ -1559,6 +1790,7 @@ pub fn get_log_for_display(uuid: &str, for_template: bool) -> (usize, usize, usi
                                 is_voice:   false,
                                 is_web:     m.is_web,
                                 remembered: m.remembered,
+                                pinned:     m.pinned,
                                 idx_qa:     m.idx_qa,
                                 idx_m:      m.idx_m,
                                 token:      m.token,
@@ -1574,13 +1806,14 @@ pub fn get_log_for_display(uuid: &str, for_template: bool) -> (usize, usize, usi
                                 is_voice:   false,
                                 is_web:     m.is_web,
                                 remembered: m.remembered,
+                                pinned:     m.pinned,
                                 idx_qa:     m.idx_qa,
                                 idx_m:      m.idx_m,
                                 token:      m.token,
                             });
                         }
                     },
-                    ChatMessageContent::None => logs.push(DisplayInfo{is_query: false, content: "".to_string(), id: i, time: tmp_time, is_img: false, is_voice: false, is_web: m.is_web, remembered: m.remembered, idx_qa: m.idx_qa, idx_m: m.idx_m, token: m.token}),
+                    ChatMessageContent::None => logs.push(DisplayInfo{is_query: false, content: "".to_string(), id: i, time: tmp_time, is_img: false, is_voice: false, is_web: m.is_web, remembered: m.remembered, pinned: m.pinned, idx_qa: m.idx_qa, idx_m: m.idx_m, token: m.token}),
                 },
                 None => (),
             },
@@ -1597,6 +1830,7 @@ pub fn get_log_for_display(uuid: &str, for_template: bool) -> (usize, usize, usi
                             is_voice:   false,
                             is_web:     m.is_web,
                             remembered: m.remembered,
+                            pinned:     m.pinned,
                             idx_qa:     m.idx_qa,
                             idx_m:      m.idx_m,
                             token:      m.token,
@@ -1612,6 +1846,7 @@ pub fn get_log_for_display(uuid: &str, for_template: bool) -> (usize, usize, usi
                             is_voice:   false,
                             is_web:     m.is_web,
                             remembered: m.remembered,
+                            pinned:     m.pinned,
                             idx_qa:     m.idx_qa,
                             idx_m:      m.idx_m,
                             token:      m.token,
@@ -1641,6 +1876,7 @@ pub fn get_log_for_display(uuid: &str, for_template: bool) -> (usize, usize, usi
                             is_voice:   false,
                             is_web:     m.is_web,
                             remembered: m.remembered,
+                            pinned:     m.pinned,
                             idx_qa:     m.idx_qa,
                             idx_m:      m.idx_m,
                             token:      m.token,
@@ -1656,15 +1892,16 @@ pub fn get_log_for_display(uuid: &str, for_template: bool) -> (usize, usize, usi
                             is_voice:   false,
                             is_web:     m.is_web,
                             remembered: m.remembered,
+                            pinned:     m.pinned,
                             idx_qa:     m.idx_qa,
                             idx_m:      m.idx_m,
                             token:      m.token,
                         });
                     }
                 },
-                ChatMessageContent::None => logs.push(DisplayInfo{is_query: false, content: "".to_string(), id: i, time: tmp_time, is_img: false, is_voice: false, is_web: m.is_web, remembered: m.remembered, idx_qa: m.idx_qa, idx_m: m.idx_m, token: m.token}),
+                ChatMessageContent::None => logs.push(DisplayInfo{is_query: false, content: "".to_string(), id: i, time: tmp_time, is_img: false, is_voice: false, is_web: m.is_web, remembered: m.remembered, pinned: m.pinned, idx_qa: m.idx_qa, idx_m: m.idx_m, token: m.token}),
             },
-            ChatMessage::Tool{content: ChatMessageContent::Text(content_str), ..} => logs.push(DisplayInfo{is_query: false, content: content_str.clone(), id: i, time: tmp_time, is_img: false, is_voice: false, is_web: m.is_web, remembered: m.remembered, idx_qa: m.idx_qa, idx_m: m.idx_m, token: m.token}),
+            ChatMessage::Tool{content: ChatMessageContent::Text(content_str), ..} => logs.push(DisplayInfo{is_query: false, content: content_str.clone(), id: i, time: tmp_time, is_img: false, is_voice: false, is_web: m.is_web, remembered: m.remembered, pinned: m.pinned, idx_qa: m.idx_qa, idx_m: m.idx_m, token: m.token}),
             _ => unreachable!(),
         }
     }
@@ -1683,6 +1920,7 @@ pub fn get_log_for_display(uuid: &str, for_template: bool) -> (usize, usize, usi
                         is_voice:   false,
                         is_web:     false,
                         remembered: false,
+                        pinned:     false,
                         idx_qa:     1,
                         idx_m:      1,
                         token:      0,
@@ -1698,6 +1936,7 @@ pub fn get_log_for_display(uuid: &str, for_template: bool) -> (usize, usize, usi
                         is_voice:   false,
                         is_web:     false,
                         remembered: false,
+                        pinned:     false,
                         idx_qa:     1,
                         idx_m:      1,
                         token:      0,
@@ -1725,6 +1964,7 @@ pub fn get_log_for_display(uuid: &str, for_template: bool) -> (usize, usize, usi
             is_voice:   false,
             is_web:     false,
             remembered: false,
+            pinned:     false,
             idx_qa:     0,
             idx_m:      0,
             token:      0,
@@ -1739,6 +1979,7 @@ pub fn get_log_for_display(uuid: &str, for_template: bool) -> (usize, usize, usi
             is_voice:   false,
             is_web:     false,
             remembered: false,
+            pinned:     false,
             idx_qa:     0,
             idx_m:      0,
             token:      0,
@@ -1753,6 +1994,7 @@ pub fn get_log_for_display(uuid: &str, for_template: bool) -> (usize, usize, usi
             is_voice:   false,
             is_web:     false,
             remembered: false,
+            pinned:     false,
             idx_qa:     0,
             idx_m:      0,
             token:      0,
@@ -1767,6 +2009,7 @@ pub fn get_log_for_display(uuid: &str, for_template: bool) -> (usize, usize, usi
             is_voice:   false,
             is_web:     false,
             remembered: false,
+            pinned:     false,
             idx_qa:     0,
             idx_m:      0,
             token:      0,
@@ -1916,6 +2159,49 @@ pub fn get_latest_query(uuid: &str) -> Option<String> {
     last_q
 }
 
+/// 获取最后一个message，且必须是模型回答的文本
+pub fn get_latest_answer(uuid: &str) -> Option<String> {
+    let data = DATA.lock().unwrap();
+    let info = data.get(uuid)?;
+    match &info.messages.iter().rev().find(|m| !m.data.is_hide())?.message {
+        ChatMessage::Assistant{content: Some(ChatMessageContent::Text(c)), ..} => Some(c.clone()),
+        _ => None,
+    }
+}
+
+/// 根据对话名称查找uuid，先查找服务端data，再查找输出路径下每个uuid最近的chat记录，有多个同名对话则返回最近的
+pub fn find_uuid_by_chat_name(name: &str) -> Option<String> {
+    {
+        let data = DATA.lock().unwrap();
+        if let Some(info) = data.values().find(|info| info.chat_name == name) {
+            return Some(info.uuid.clone())
+        }
+    }
+    let mut found: Option<(String, String)> = None; // (chat记录文件, uuid)
+    for entry in Path::new(&PARAS.outpath).read_dir().ok()?.flatten() {
+        if !entry.path().is_dir() {
+            continue
+        }
+        let uuid = entry.file_name().to_string_lossy().to_string();
+        let log_file = get_latest_log_file(&uuid);
+        if log_file.is_empty() {
+            continue
+        }
+        // 文件名是时间戳，直接比较字符串即可找到最近的
+        if found.as_ref().is_some_and(|f| f.0.rsplit('/').next() >= log_file.rsplit('/').next()) {
+            continue
+        }
+        if let Ok(s) = read_to_string(&log_file) {
+            if let Ok(v) = serde_json::from_str::<serde_json::Value>(&s) {
+                if v["chat_name"].as_str() == Some(name) {
+                    found = Some((log_file, uuid));
+                }
+            }
+        }
+    }
+    found.map(|f| f.1)
+}
+
 /// 获取最后一个上传的图片
 pub fn get_latest_image(uuid: &str) -> Option<String> {
     let data = DATA.lock().unwrap();
@@ -0,0 +1,133 @@
+use std::fs::read_to_string;
+use std::path::Path;
+
+/// error: 定义的错误类型，用于错误传递
+use crate::{
+    code::budget::SYMBOL_DEF, // 匹配常见语言中函数、类、结构体等定义的名称
+    error::MyError,
+    office::{
+        is_office_file, // 是否是支持提取内容的office文档或表格
+        office_to_markdown, // 读取office文档或表格，转为markdown
+    },
+    pdf::extract_pdf_pages, // 按页读取pdf文件的文本内容
+};
+
+/// 每个片段的最大字符数，超过则按段落、行拆分
+const MAX_CHUNK_CHARS: usize = 1500;
+
+/// 按markdown标题拆分的格式
+const MARKDOWN_FORMATS: [&str; 2] = ["md", "markdown"];
+
+/// 按代码符号拆分的格式
+const CODE_FORMATS: [&str; 26] = ["rs", "py", "js", "jsx", "ts", "tsx", "go", "java", "kt", "scala", "c", "h", "cc", "cpp", "hpp", "cs", "rb", "php", "swift", "lua", "r", "jl", "zig", "sh", "vue", "svelte"];
+
+/// 按段落拆分的文本格式
+const TEXT_FORMATS: [&str; 12] = ["txt", "rst", "adoc", "org", "tex", "json", "yaml", "yml", "toml", "ron", "sql", "html"];
+
+/// 文件中的一个片段
+pub struct Section {
+    pub location: String, // 片段在文件中的位置：标题路径、页码或符号名称
+    pub text:     String, // 片段内容
+}
+
+/// 是否是知识库支持的文件格式
+pub fn is_supported(path: &Path) -> bool {
+    let ext = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    ext == "pdf" || MARKDOWN_FORMATS.contains(&ext.as_str()) || CODE_FORMATS.contains(&ext.as_str()) || TEXT_FORMATS.contains(&ext.as_str()) || is_office_file(&path.to_string_lossy())
+}
+
+/// 按文件结构拆分：markdown按标题，pdf按页，代码按顶层符号，office文档转为markdown后按标题，其他文本按段落
+pub fn chunk_file(path: &Path) -> Result<Vec<Section>, MyError> {
+    let file = path.to_string_lossy().to_string();
+    let ext = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let sections = if ext == "pdf" {
+        extract_pdf_pages(&file)?.into_iter().enumerate().map(|(i, text)| Section{location: format!("page {}", i+1), text}).collect()
+    } else if is_office_file(&file) {
+        chunk_markdown(&office_to_markdown(&file)?)
+    } else {
+        let content = read_to_string(path).map_err(|e| MyError::ReadFileError{file: file.clone(), error: e})?;
+        if MARKDOWN_FORMATS.contains(&ext.as_str()) {
+            chunk_markdown(&content)
+        } else if CODE_FORMATS.contains(&ext.as_str()) {
+            chunk_code(&content)
+        } else {
+            vec![Section{location: String::new(), text: content}]
+        }
+    };
+    Ok(sections.into_iter().filter(|s| !s.text.trim().is_empty()).flat_map(split_long).collect())
+}
+
+/// 按标题拆分markdown，位置是各级标题，例如`Install > Linux`，代码块中的`#`不视为标题
+fn chunk_markdown(content: &str) -> Vec<Section> {
+    let mut sections: Vec<Section> = vec![];
+    let mut headings: Vec<(usize, String)> = vec![]; // (标题级别, 标题)
+    let mut text = String::new();
+    let mut in_code = false;
+    for line in content.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+        }
+        let level = line.chars().take_while(|c| *c == '#').count();
+        if !in_code && (1..=6).contains(&level) && line[level..].starts_with(' ') {
+            sections.push(Section{location: headings.iter().map(|h| h.1.as_str()).collect::<Vec<_>>().join(" > "), text: std::mem::take(&mut text)});
+            headings.retain(|h| h.0 < level);
+            headings.push((level, line[level..].trim().to_string()));
+        }
+        text += line;
+        text.push('\n');
+    }
+    sections.push(Section{location: headings.iter().map(|h| h.1.as_str()).collect::<Vec<_>>().join(" > "), text});
+    sections
+}
+
+/// 按顶层的函数、类、结构体等定义拆分代码，位置是符号名称，第一个定义之前的内容（导入、注释等）单独作为一个片段
+fn chunk_code(content: &str) -> Vec<Section> {
+    let mut sections: Vec<Section> = vec![];
+    let mut location = String::new();
+    let mut text = String::new();
+    for line in content.lines() {
+        if !line.starts_with([' ', '\t']) {
+            if let Some(c) = SYMBOL_DEF.captures(line) {
+                sections.push(Section{location: std::mem::take(&mut location), text: std::mem::take(&mut text)});
+                location = c[1].to_string();
+            }
+        }
+        text += line;
+        text.push('\n');
+    }
+    sections.push(Section{location, text});
+    sections
+}
+
+/// 超过最大字符数的片段按段落拆分，段落太长则按行拆分，位置加上序号
+fn split_long(section: Section) -> Vec<Section> {
+    if section.text.chars().count() <= MAX_CHUNK_CHARS {
+        return vec![section]
+    }
+    let mut parts: Vec<String> = vec![];
+    let mut current = String::new();
+    for para in section.text.split("\n\n") {
+        // (内容, 与前面内容的间隔)，段落太长则按行拆分，行太长则按字符数拆分
+        let pieces: Vec<(String, &str)> = if para.chars().count() > MAX_CHUNK_CHARS {
+            para.lines().flat_map(|l| l.chars().collect::<Vec<_>>().chunks(MAX_CHUNK_CHARS).map(|c| (c.iter().collect::<String>(), "\n")).collect::<Vec<_>>()).collect()
+        } else {
+            vec![(para.to_string(), "\n\n")]
+        };
+        for (piece, sep) in pieces {
+            if !current.is_empty() && current.chars().count() + piece.chars().count() > MAX_CHUNK_CHARS {
+                parts.push(std::mem::take(&mut current));
+            }
+            if !current.is_empty() {
+                current += sep;
+            }
+            current += &piece;
+        }
+    }
+    if !current.trim().is_empty() {
+        parts.push(current);
+    }
+    parts.into_iter().enumerate().map(|(i, text)| Section{
+        location: if section.location.is_empty() { format!("part {}", i+1) } else { format!("{} ({})", section.location, i+1) },
+        text,
+    }).collect()
+}
@@ -0,0 +1,369 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, create_dir_all, read_to_string, remove_dir_all, write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use ignore::WalkBuilder;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tracing::{event, Level};
+
+use crate::{
+    parse_paras::{PARAS, Api},
+    error::MyError,
+    info::{
+        get_knowledge, // 获取指定uuid使用的知识库
+        set_knowledge_context, // 更新指定uuid当前问题从知识库检索到的片段
+    },
+    memory::{
+        bm25_scores,
+        cosine_similarity,
+        fuse_scores,
+        tokenize,
+        serialize_embedding_raw,
+        deserialize_embedding_raw,
+    },
+    api::handlers::memory::get_embedding, // 调用 embedding 模型，计算指定字符串的 embedding 向量
+    tools::built_in_tools::filesystem::utils::validate_path,
+};
+
+pub mod chunk;
+
+// 本地知识库
+// 1. 每个知识库包含若干目录或文件，以及通过页面上传到知识库的文件，按结构拆分为片段（markdown标题、pdf页、代码符号），计算embedding
+// 2. 知识库保存在`输出路径/knowledge/知识库名称/index.json`，上传的文件保存在同路径的`files`文件夹
+// 3. 重新索引时只对修改过的文件（大小或修改时间变化）重新拆分并计算embedding，没有修改的文件只补算缺少的embedding
+// 4. 对话可以使用多个知识库，每次提问检索最相关的片段并注明来源，注入上下文
+
+/// 每次提问注入的最相关片段数
+const TOP_K: usize = 5;
+
+/// 已加载的知识库，key是知识库名称
+static BASES: Lazy<Mutex<HashMap<String, KnowledgeBase>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 文件中的一个片段
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Chunk {
+    pub location:  String,           // 片段在文件中的位置：标题路径、页码或符号名称
+    pub text:      String,           // 片段内容
+    #[serde(default, serialize_with = "serialize_embedding_raw", deserialize_with = "deserialize_embedding_raw")]
+    pub embedding: Option<Vec<f64>>, // 片段的embedding，没有设置embedding模型则为None，检索时使用关键词匹配
+}
+
+/// 已索引的文件，大小或修改时间变化则重新索引
+#[derive(Clone, Serialize, Deserialize)]
+struct IndexedFile {
+    size:     u64,        // 文件大小
+    modified: u64,        // 修改时间，unix秒
+    chunks:   Vec<Chunk>, // 拆分的片段
+}
+
+/// 知识库
+#[derive(Clone, Serialize, Deserialize)]
+pub struct KnowledgeBase {
+    pub name:    String,                       // 知识库名称
+    pub sources: Vec<String>,                  // 目录或文件的绝对路径，目录遵循`.gitignore`和`.ignore`
+    files:       BTreeMap<String, IndexedFile>, // 已索引的文件，key是文件路径
+}
+
+/// 页面和api获取的知识库概要
+#[derive(Serialize)]
+pub struct KnowledgeSummary {
+    pub name:    String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<String>, // 不是服务端所在电脑或局域网的请求则为空，不返回服务端路径
+    pub files:   usize, // 已索引的文件数
+    pub chunks:  usize, // 片段数
+}
+
+/// 重新索引的结果
+#[derive(Default, Serialize)]
+pub struct IndexStats {
+    pub added:     usize,       // 新增的文件数
+    pub updated:   usize,       // 修改后重新索引的文件数
+    pub removed:   usize,       // 已删除的文件数
+    pub unchanged: usize,       // 没有修改的文件数，不重新计算embedding
+    pub chunks:    usize,       // 重新计算embedding的片段数
+    pub failed:    Vec<String>, // 读取或拆分出错的文件
+}
+
+/// 知识库所在路径
+fn knowledge_dir() -> String {
+    format!("{}/knowledge", PARAS.outpath)
+}
+
+/// 指定知识库的索引文件
+fn index_file(name: &str) -> String {
+    format!("{}/{}/index.json", knowledge_dir(), name)
+}
+
+/// 指定知识库上传文件的保存路径
+pub fn upload_dir(name: &str) -> String {
+    format!("{}/{}/files", knowledge_dir(), name)
+}
+
+/// 知识库名称不能为空，不能含有路径分隔符、逗号（多个知识库用逗号间隔）、引号和控制字符，不能以`.`起始
+pub fn check_name(name: &str) -> Result<(), MyError> {
+    if name.trim().is_empty() || name != name.trim() || name.starts_with('.') || name.chars().any(|c| c.is_control() || ['/', '\\', ',', ':', '*', '?', '"', '\'', '<', '>', '|', '&'].contains(&c)) {
+        return Err(MyError::ParaError{para: format!("invalid knowledge base name: {:?}", name)})
+    }
+    Ok(())
+}
+
+/// 读取知识库到已加载的知识库中，已加载则跳过，返回该知识库是否存在，名称不合法（例如`../x`）则返回false
+fn ensure_loaded(bases: &mut HashMap<String, KnowledgeBase>, name: &str) -> bool {
+    if check_name(name).is_err() {
+        return false
+    }
+    if bases.contains_key(name) {
+        return true
+    }
+    let file = index_file(name);
+    if !Path::new(&file).is_file() {
+        return false
+    }
+    match read_to_string(&file).map_err(|e| MyError::ReadFileError{file: file.clone(), error: e}).and_then(|s| serde_json::from_str::<KnowledgeBase>(&s).map_err(|e| MyError::SerdeJsonFromStrError{error: e})) {
+        Ok(base) => {
+            bases.insert(name.to_string(), base);
+            true
+        },
+        Err(e) => {
+            event!(Level::WARN, "load knowledge base {} error: {}", file, e);
+            false
+        },
+    }
+}
+
+/// 获取知识库的副本，用于修改后保存，名称不合法则返回None
+fn load_base(name: &str) -> Option<KnowledgeBase> {
+    let mut bases = BASES.lock().unwrap();
+    if ensure_loaded(&mut bases, name) {
+        bases.get(name).cloned()
+    } else {
+        None
+    }
+}
+
+/// 保存知识库，并更新已加载的知识库
+fn save_base(base: KnowledgeBase) -> Result<(), MyError> {
+    let file = index_file(&base.name);
+    let content = serde_json::to_string_pretty(&base).map_err(|e| MyError::JsonToStringError{error: e.into()})?;
+    write(&file, content).map_err(|e| MyError::WriteFileError{file, error: e})?;
+    BASES.lock().unwrap().insert(base.name.clone(), base);
+    Ok(())
+}
+
+/// 获取所有知识库的概要，按名称排序
+pub fn list_bases() -> Vec<KnowledgeSummary> {
+    let mut names: Vec<String> = match fs::read_dir(knowledge_dir()) {
+        Ok(entries) => entries.flatten().filter(|e| e.path().join("index.json").is_file()).map(|e| e.file_name().to_string_lossy().to_string()).collect(),
+        Err(_) => vec![],
+    };
+    names.sort();
+    names.iter().filter_map(|n| load_base(n)).map(|b| KnowledgeSummary{
+        name:    b.name,
+        sources: b.sources,
+        files:   b.files.len(),
+        chunks:  b.files.values().map(|f| f.chunks.len()).sum(),
+    }).collect()
+}
+
+/// 是否存在指定知识库，名称不合法则返回false
+pub fn contain_base(name: &str) -> bool {
+    check_name(name).is_ok() && Path::new(&index_file(name)).is_file()
+}
+
+/// 创建知识库，已存在则添加目录或文件，需要调用`reindex`索引
+/// 目录或文件必须在参数文件的`allowed_path`内（解析符号链接之后），与模板的`{{file:路径}}`相同，避免通过知识库读取任意文件
+pub fn add_sources(name: &str, sources: &[String]) -> Result<(), MyError> {
+    check_name(name)?;
+    let mut base = load_base(name).unwrap_or(KnowledgeBase{name: name.to_string(), sources: vec![], files: BTreeMap::new()});
+    for s in sources {
+        let path = Path::new(s).canonicalize().map_err(|e| MyError::ParaError{para: format!("knowledge source {} error: {}", s, e)})?;
+        let path = validate_path(&PARAS.allowed_path, &path, true).map_err(|e| MyError::ParaError{para: format!("knowledge source {} error: {}", s, e)})?;
+        let path = path.to_string_lossy().to_string();
+        if !base.sources.contains(&path) {
+            base.sources.push(path);
+        }
+    }
+    let dir = upload_dir(name);
+    create_dir_all(&dir).map_err(|e| MyError::CreateDirAllError{dir_name: dir, error: e})?;
+    save_base(base)
+}
+
+/// 从知识库移除目录或文件，需要调用`reindex`移除已索引的片段
+pub fn remove_sources(name: &str, sources: &[String]) -> Result<(), MyError> {
+    let mut base = load_base(name).ok_or_else(|| MyError::ParaError{para: format!("no such knowledge base: {}", name)})?;
+    for s in sources {
+        let path = Path::new(s).canonicalize().map(|p| p.to_string_lossy().to_string()).unwrap_or_else(|_| s.clone()); // 已删除的路径无法获取绝对路径，直接比较
+        base.sources.retain(|x| *x != path);
+    }
+    save_base(base)
+}
+
+/// 删除知识库，包括上传的文件
+pub fn delete_base(name: &str) -> Result<(), MyError> {
+    check_name(name)?;
+    if !contain_base(name) {
+        return Err(MyError::ParaError{para: format!("no such knowledge base: {}", name)})
+    }
+    BASES.lock().unwrap().remove(name);
+    let dir = format!("{}/{}", knowledge_dir(), name);
+    remove_dir_all(&dir).map_err(|e| MyError::ParaError{para: format!("delete {} error: {}", dir, e)})
+}
+
+/// 获取知识库所有目录、文件以及上传文件中支持的文件
+fn collect_files(base: &KnowledgeBase) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = vec![];
+    for source in base.sources.iter().cloned().chain([upload_dir(&base.name)]) {
+        let path = Path::new(&source);
+        if path.is_file() {
+            files.push(path.to_path_buf());
+        } else if path.is_dir() {
+            for entry in WalkBuilder::new(path).require_git(false).build().flatten() {
+                if entry.file_type().is_some_and(|t| t.is_file()) && chunk::is_supported(entry.path()) { // 不跟随符号链接，避免读取`allowed_path`之外的文件
+                    files.push(entry.into_path());
+                }
+            }
+        } else {
+            event!(Level::WARN, "knowledge base {} source not exists: {}", base.name, source);
+        }
+    }
+    files.sort();
+    files.dedup();
+    files
+}
+
+/// 重新索引知识库，只对新增和修改过（大小或修改时间变化）的文件拆分并计算embedding，移除已删除文件的片段
+/// 没有修改的文件补算缺少的embedding（之前出错或之后才设置embedding模型）
+pub async fn reindex(name: &str) -> Result<IndexStats, MyError> {
+    check_name(name)?;
+    let mut base = load_base(name).ok_or_else(|| MyError::ParaError{para: format!("no such knowledge base: {}", name)})?;
+    let mut stats = IndexStats::default();
+    let snapshot = PARAS.snapshot(); // 整个索引过程使用同一个embedding模型
+    let has_embedding_model = snapshot.api.get_embedding_modle(None).is_some();
+    let mut files: BTreeMap<String, IndexedFile> = BTreeMap::new();
+    for path in collect_files(&base) {
+        let key = path.to_string_lossy().to_string();
+        let (size, modified) = match path.metadata() {
+            Ok(m) => (m.len(), m.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs()).unwrap_or(0)),
+            Err(e) => {
+                event!(Level::WARN, "knowledge base {} read {} error: {}", name, key, e);
+                stats.failed.push(key);
+                continue
+            },
+        };
+        let updated = match base.files.remove(&key) {
+            Some(mut f) if f.size == size && f.modified == modified => { // 没有修改，保留之前的片段和embedding
+                if has_embedding_model {
+                    for c in f.chunks.iter_mut().filter(|c| c.embedding.is_none()) {
+                        c.embedding = chunk_embedding(&snapshot.api, name, &key, c).await;
+                        stats.chunks += 1;
+                    }
+                }
+                stats.unchanged += 1;
+                files.insert(key, f);
+                continue
+            },
+            Some(_) => true,
+            None => false,
+        };
+        let sections = match chunk::chunk_file(&path) {
+            Ok(s) => s,
+            Err(e) => {
+                event!(Level::WARN, "knowledge base {} chunk {} error: {}", name, key, e);
+                stats.failed.push(key);
+                continue
+            },
+        };
+        let mut chunks: Vec<Chunk> = vec![];
+        for s in sections {
+            let mut c = Chunk{location: s.location, text: s.text, embedding: None};
+            c.embedding = chunk_embedding(&snapshot.api, name, &key, &c).await;
+            chunks.push(c);
+        }
+        event!(Level::INFO, "knowledge base {} index {}: {} chunks", name, key, chunks.len());
+        stats.chunks += chunks.len();
+        if updated {
+            stats.updated += 1;
+        } else {
+            stats.added += 1;
+        }
+        files.insert(key, IndexedFile{size, modified, chunks});
+    }
+    stats.removed = base.files.len(); // 剩余的是已删除的文件
+    base.files = files;
+    save_base(base)?;
+    Ok(stats)
+}
+
+/// 计算片段的embedding，没有embedding模型或出错则为None，检索时只使用关键词匹配
+async fn chunk_embedding(api: &Api, name: &str, file: &str, c: &Chunk) -> Option<Vec<f64>> {
+    match get_embedding(api, "knowledge", format!("{}\n{}", c.location, c.text)).await {
+        Ok(e) => e,
+        Err(e) => {
+            event!(Level::WARN, "knowledge base {} embedding {} error: {}", name, file, e);
+            None
+        },
+    }
+}
+
+/// 显示的来源：知识库名称、文件路径（上传的文件只显示文件名）和位置
+fn source_label(base: &str, file: &str, location: &str) -> String {
+    let upload = upload_dir(base);
+    let file = file.strip_prefix(&upload).map(|f| f.trim_start_matches(['/', '\\'])).unwrap_or(file);
+    if location.is_empty() {
+        format!("{}: {}", base, file)
+    } else {
+        format!("{}: {}#{}", base, file, location)
+    }
+}
+
+/// 在指定知识库中检索与问题最相关的片段，返回(分数, 来源, 内容)
+/// 与检索记忆相同：关键词（BM25）和余弦相似度分别排名，再用倒数排序融合（RRF）合并，问题或片段没有embedding则只有关键词排名
+fn search(names: &[String], query: &str, embedding: &Option<Vec<f64>>, limit: usize) -> Vec<(f64, String, String)> {
+    let mut bases = BASES.lock().unwrap();
+    let names: Vec<&String> = names.iter().filter(|name| {
+        let loaded = ensure_loaded(&mut bases, name);
+        if !loaded {
+            event!(Level::WARN, "no such knowledge base: {}", name);
+        }
+        loaded
+    }).collect();
+    let candidates: Vec<(&String, &String, &Chunk)> = names.iter().flat_map(|name| bases[*name].files.iter().flat_map(move |(file, f)| f.chunks.iter().map(move |c| (*name, file, c)))).collect();
+    let docs = candidates.iter().map(|(_, _, c)| tokenize(&format!("{} {}", c.location, c.text))).collect::<Vec<_>>(); // 位置（标题、符号名称）也参与匹配
+    let bm25 = bm25_scores(&tokenize(query), &docs);
+    let cosine = candidates.iter().map(|(_, _, c)| match (embedding, &c.embedding) {
+        (Some(q), Some(e)) => Some(cosine_similarity(q, e)),
+        _ => None,
+    }).collect::<Vec<_>>();
+    let mut hits: Vec<(f64, String, String)> = candidates.into_iter().zip(fuse_scores(&bm25, &cosine)).filter(|(_, score)| *score > 0.0).map(|((name, file, c), score)| (score, source_label(name, file, &c.location), c.text.clone())).collect();
+    hits.sort_by(|a, b| b.0.total_cmp(&a.0));
+    hits.truncate(limit);
+    hits
+}
+
+/// 根据当前问题检索该对话使用的知识库，将最相关的片段和来源记录为本次回答的上下文，没有使用知识库或没有相关片段则清空
+pub async fn retrieve_knowledge(api: &Api, uuid: &str, query: &str) {
+    let names = get_knowledge(uuid);
+    if names.is_empty() || query.trim().is_empty() {
+        return
+    }
+    let embedding = get_embedding(api, uuid, query.to_string()).await.unwrap_or_else(|e| {
+        event!(Level::WARN, "{} knowledge embedding error: {}", uuid, e);
+        None
+    });
+    let hits = search(&names, query, &embedding, TOP_K);
+    event!(Level::INFO, "{} retrieve {} chunks from knowledge base: {}", uuid, hits.len(), names.join(", "));
+    let context = if hits.is_empty() {
+        None
+    } else {
+        Some(format!(
+            "The following excerpts were retrieved from the knowledge base ({}) for the question above. Use them if they are relevant, and cite the source number like [1] when you use them.\n\n{}",
+            names.join(", "),
+            hits.into_iter().enumerate().map(|(i, h)| format!("[{}] {}\n{}", i+1, h.1, h.2.trim())).collect::<Vec<_>>().join("\n\n"),
+        ))
+    };
+    set_knowledge_context(uuid, context);
+}
@@ -86,6 +86,7 @@ pub struct ToolInfo {
     pub description: Option<String>,
     pub schema:      Value,
     pub server_name: String,
+    pub read_only:   bool, // 只读，可以与其他只读工具调用并发运行
 }
 
 /// https://docs.rs/rust-mcp-sdk/0.7.4/rust_mcp_sdk/struct.StdioTransport.html
@@ -182,8 +183,10 @@ impl McpServers {
                 },
                 SelectedTools::AllBuiltIn => Vec::new(), // all built-in tools
                 SelectedTools::AllExternal => Vec::new(), // all external tools
+                SelectedTools::AllHttp => Vec::new(), // all HTTP tools
+                SelectedTools::HttpGroup(_) => Vec::new(), // HTTP tool group, select all tools of one OpenAPI spec
                 SelectedTools::Group(_) => Vec::new(), // built-in group, select all tools of one group
-                SelectedTools::Single(_) => Vec::new(), // single built-in or external tool id
+                SelectedTools::Single(_) => Vec::new(), // single built-in, external or HTTP tool id
                 SelectedTools::McpServer(server_id) => self.stdio.select_tools_by_server_id(server_id), // single mcp server id, start with `mcp_server_`, select all tools of one server
                 SelectedTools::McpTool(name_id) => self.stdio.select_tool_by_name_and_id(name_id), // single mcp tool `name__id`, select by tool name and server id
             },
@@ -208,6 +211,26 @@ impl McpServers {
         Ok(tools)
     }
 
+    /// number of mcp tools
+    pub fn tool_num(&self) -> usize {
+        self.stdio.tools.len()
+    }
+
+    /// get server id by server name
+    pub fn get_server_id_by_name(&self, name: &str) -> Option<String> {
+        self.stdio.tools.iter().find(|t| t.server_name == name).map(|t| t.id.clone())
+    }
+
+    /// get `name__id` by tool name, return the first matched tool
+    pub fn get_name_id_by_name(&self, name: &str) -> Option<String> {
+        self.stdio.tools.iter().find(|t| t.name == name).map(|t| t.name_id.clone())
+    }
+
+    /// check mcp tool `name__id` is read-only
+    pub fn read_only(&self, name_id: &[&str]) -> bool {
+        self.stdio.tools.iter().any(|t| t.id == name_id[1] && t.name == name_id[0] && t.read_only)
+    }
+
     /// check contain server id
     pub fn contain_server_id(&self, id: &str) -> bool {
         if self.stdio.id_map.contains_key(id) {
@@ -30,6 +30,7 @@ use uuid::Uuid;
 use crate::{
     error::MyError,
     mcp::{
+        McpServers,
         MyMcp,
         ProtocolVersion,
         ToolInfo,
@@ -57,7 +58,8 @@ impl StdIoTransport {
         cmd
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
+            .stderr(Stdio::piped())
+            .kill_on_drop(true); // 重载参数文件后，旧配置不再被使用时自动结束旧的mcp server
 
         let mut child = cmd.spawn()?;
         // stdin
@@ -98,12 +100,13 @@ impl StdIoTransport {
         });
         // Send request via stdin
         let request_line = serde_json::to_string(&request_body).map_err(|e| MyError::JsonToStringError{error: e.into()})? + "\n";
+        // 发送请求前先锁住stdout，直到读取回答，并发调用同一个server的工具时不会读到其他请求的回答
+        let mut stdout_reader = self.stdout_reader.lock().await;
         let mut stdin = self.stdin.lock().await;
         stdin.write_all(request_line.as_bytes()).await?;
         stdin.flush().await?;
         drop(stdin);
         // Read response from stdout
-        let mut stdout_reader = self.stdout_reader.lock().await;
         let mut response_line = String::new();
         stdout_reader.read_line(&mut response_line).await?;
         drop(stdout_reader);
@@ -172,7 +175,7 @@ impl StdIoTransport {
     }
 
     /// list all tools in this MCP server
-    async fn list_tools(&self, command: &str) -> Result<Vec<(String, Option<String>, Value)>, MyError> {
+    async fn list_tools(&self, command: &str) -> Result<Vec<(String, Option<String>, Value, bool)>, MyError> {
         // send request
         let result = self.send_request("tools/list", Value::Null).await?;
         // Parse the MCP tool result
@@ -199,7 +202,14 @@ impl StdIoTransport {
                 .cloned()
                 .unwrap_or(Value::Object(serde_json::Map::new()));
 
-            tool_infos.push((name, description, input_schema));
+            // https://modelcontextprotocol.io/specification/2025-11-25/schema#toolannotations
+            let read_only = tool
+                .get("annotations")
+                .and_then(|a| a.get("readOnlyHint"))
+                .and_then(|r| r.as_bool())
+                .unwrap_or(false);
+
+            tool_infos.push((name, description, input_schema, read_only));
         }
         // sort tool by name
         tool_infos.sort_by(|a, b| a.0.cmp(&b.0));
@@ -231,6 +241,10 @@ pub struct StdIoServer {
     #[serde(default, skip_serializing_if = "None::is_none")]
     pub env: Option<HashMap<String, String>>,
 
+    /// read-only tools of this server, for servers without `readOnlyHint`, read-only calls can run concurrently
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub read_only_tools: Vec<String>,
+
     /// name from server initialization
     #[serde(skip)]
     pub name: String,
@@ -246,8 +260,20 @@ pub struct StdIoServers {
     pub tools:  Vec<ToolInfo>, // all tools
 }
 
+impl StdIoServer {
+    /// 用于重载时判断是否是同一个server
+    fn key(&self) -> String {
+        format!("{} {}", self.command, self.args.join(" "))
+    }
+}
+
 impl StdIoServers {
-    pub async fn new(mut servers: Vec<StdIoServer>) -> Result<Self, MyError> {
+    /// old: mcp servers before reloading config file, server with the same command and args keep the old id
+    pub async fn new(mut servers: Vec<StdIoServer>, old: Option<&McpServers>) -> Result<Self, MyError> {
+        let old_ids: HashMap<String, String> = match old {
+            Some(o) => o.stdio.id_map.iter().map(|(k, v)| (v.1.key(), k.clone())).collect(),
+            None => HashMap::new(),
+        };
         let mut id_map: HashMap<String, (StdIoTransport, StdIoServer)> = HashMap::new();
         let mut tools: Vec<ToolInfo> = Vec::new();
         for server in servers.iter_mut() {
@@ -255,10 +281,13 @@ impl StdIoServers {
             let (server_version, server_name) = transport.initialize(&server.command).await?;
             server.name = server_name.clone(); // name from server initialization
             server.protocol_version = server_version; // protocol version from server initialization
-            let tmp_uuid = Uuid::new_v4().to_string();
-            let id = tmp_uuid[0..8].to_string();
+            let id = match old_ids.get(&server.key()) {
+                Some(id) if !id_map.contains_key(id) => id.clone(),
+                _ => Uuid::new_v4().to_string()[0..8].to_string(),
+            };
             tools.extend(transport.list_tools(&server.command).await?.into_iter().map(|t| ToolInfo {
                 name_id:     format!("{}__{}", t.0, id), // name__id
+                read_only:   t.3 || server.read_only_tools.contains(&t.0), // server声明的readOnlyHint或参数文件中指定的只读工具
                 name:        t.0, // tool name
                 id:          id.clone(), // server id
                 description: t.1,
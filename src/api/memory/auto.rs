@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use tracing::{event, Level};
+
+use crate::{
+    parse_paras::{PARAS, Api},
+    error::MyError,
+    info::{
+        auto_memory_candidates, // 需要自动提取记忆的对话
+        get_unremembered, // 获取还未提取记忆的信息
+        label_remembered_ids, // 标注已提取记忆的信息
+    },
+    memory::{
+        MEMORY,
+        consolidate::consolidate,
+    },
+    api::handlers::memory::remember, // 提取记忆并存储
+};
+
+// 自动提取和整理记忆，参数文件中`memory`的设置默认都为0，不开启
+// 每分钟检查一次：对话新增的问答对达到`auto_turns`，或超过`auto_idle_min`分钟没有新信息（视为对话结束），则提取还未提取记忆的内容
+// 每`consolidate_hours`小时整理一次本地记忆和已加载的uuid记忆
+
+/// 自动提取和整理记忆使用的模型，参数文件没有指定则使用默认模型，返回(api_key, endpoint, 模型名称, 是否支持深度思考)
+pub fn memory_model(api: &Api) -> Result<(String, String, String, bool), MyError> {
+    match &PARAS.memory.model {
+        Some(m) => api.get_model_by_name(m),
+        None => api.get_default_model(),
+    }
+}
+
+/// 开启自动提取和整理记忆的后台任务
+pub fn start_memory_jobs() {
+    let config = PARAS.memory.clone();
+    if config.auto_turns == 0 && config.auto_idle_min == 0 && config.consolidate_hours == 0 {
+        return
+    }
+    tokio::spawn(async move {
+        let mut failed: HashMap<String, Vec<usize>> = HashMap::new(); // 提取失败的对话及其信息id，没有新信息则不再重试
+        let mut last_consolidate = Instant::now();
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            if config.auto_turns > 0 || config.auto_idle_min > 0 {
+                for (uuid, is_local) in auto_memory_candidates(config.auto_turns, config.auto_idle_min) {
+                    let Some((for_memory, ids)) = get_unremembered(&uuid) else {
+                        continue
+                    };
+                    if failed.get(&uuid) == Some(&ids) {
+                        continue
+                    }
+                    event!(Level::INFO, "{} auto extract memory from {} messages", uuid, ids.len());
+                    match auto_remember(&uuid, for_memory, is_local).await {
+                        Ok(_) => {
+                            label_remembered_ids(&uuid, &ids);
+                            failed.remove(&uuid);
+                        },
+                        Err(e) => {
+                            event!(Level::ERROR, "{} auto extract memory error: {}", uuid, e);
+                            failed.insert(uuid, ids);
+                        },
+                    }
+                }
+            }
+            if config.consolidate_hours > 0 && last_consolidate.elapsed() >= Duration::from_secs(config.consolidate_hours * 3600) {
+                last_consolidate = Instant::now();
+                consolidate_all().await;
+            }
+        }
+    });
+}
+
+/// 使用参数文件指定的模型提取记忆
+async fn auto_remember(uuid: &str, for_memory: String, is_local: bool) -> Result<(), MyError> {
+    let snapshot = PARAS.snapshot();
+    remember(&snapshot.api, uuid, for_memory, memory_model(&snapshot.api)?, is_local).await
+}
+
+/// 整理本地记忆和已加载的uuid记忆，上次整理后没有变化的跳过
+async fn consolidate_all() {
+    let snapshot = PARAS.snapshot();
+    let model = match memory_model(&snapshot.api) {
+        Ok(m) => m,
+        Err(e) => {
+            event!(Level::ERROR, "get model for memory consolidation error: {}", e);
+            return
+        },
+    };
+    let mut keys: Vec<String> = MEMORY.lock().unwrap().keys().filter(|k| *k != "old" && *k != "local").cloned().collect();
+    keys.insert(0, "local".to_string());
+    for key in keys {
+        if let Err(e) = consolidate(&snapshot.api, &key, model.clone(), false).await {
+            event!(Level::ERROR, "{} memory consolidation error: {}", key, e);
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{OpenOptions, read_to_string};
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::sync::Mutex;
+
+use chrono::Local;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tracing::{event, Level};
+
+use crate::{
+    parse_paras::Api,
+    error::MyError,
+    memory::{
+        MemoryNote,
+        memory_file,
+        manage::{
+            self,
+            NoteEdit,
+        },
+    },
+    api::handlers::memory::get_embedding, // 计算指定字符串的embedding
+    openai::for_chat::run_single_llm,
+};
+
+// 用LLM整理记忆：合并重叠的记忆、解决矛盾（新的记忆优先）、改写记忆
+// 每个命名空间单独整理，固定的记忆不参与整理，每次变更追加到changelog，可在记忆管理页面查看
+// 本地记忆中被合并或删除的记忆移到`memory_old.json`，与超出容量的旧记忆相同
+
+/// 每个位置上次整理后记忆的指纹，没有变化则不再整理
+static CONSOLIDATED: Lazy<Mutex<HashMap<String, u64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 整理记忆的prompt
+const CONSOLIDATE_PROMPT: &str = r###"You are a memory consolidation assistant.
+
+Below are the long-term memories of one project, one per line as `[id] memory`, ordered from oldest to newest.
+
+Clean them up with these operations:
+- merge: several memories overlap or describe the same thing. Write one concise memory that keeps all useful details.
+- rewrite: a memory is vague, verbose or badly worded. Rewrite it concisely without changing its meaning.
+- delete: a memory is fully covered by another memory, or is outdated by a newer one.
+
+When memories contradict each other, the newer memory wins: merge them into one memory that states the newer preference, or delete the older one.
+
+Each id may appear in at most one operation. Only use the given ids. Leave good memories untouched. Never add information that is not in the memories.
+
+Output only JSON, without Markdown or explanations:
+{"operations": [{"action": "merge", "ids": ["id1", "id2"], "summary": "merged memory", "reason": "why"}, {"action": "rewrite", "ids": ["id3"], "summary": "rewritten memory", "reason": "why"}, {"action": "delete", "ids": ["id4"], "reason": "why"}]}
+
+If nothing needs to change, output {"operations": []}.
+
+Memories:
+"###;
+
+/// 模型返回的一个整理操作
+#[derive(Deserialize)]
+struct Operation {
+    action:  String,      // merge、rewrite、delete
+    ids:     Vec<String>, // 涉及的记忆id
+    #[serde(default)]
+    summary: String,      // merge和rewrite后的记忆
+    #[serde(default)]
+    reason:  String,      // 原因
+}
+
+/// 模型返回的所有整理操作
+#[derive(Deserialize)]
+struct Operations {
+    operations: Vec<Operation>,
+}
+
+/// 整理记忆的一条变更记录，追加到记忆文件同路径下的`*_changelog.jsonl`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemoryChange {
+    pub time:      String,         // 整理的时间
+    pub namespace: String,         // 所属的命名空间
+    pub action:    String,         // merge、rewrite、delete
+    pub ids:       Vec<String>,    // 涉及的记忆id，merge后保留第一个id
+    pub before:    Vec<String>,    // 变更前的记忆
+    pub after:     Option<String>, // 变更后的记忆，delete为None
+    pub reason:    String,         // 模型给出的原因
+}
+
+/// 记忆变更记录的文件路径
+fn changelog_file(key: &str) -> String {
+    memory_file(key, key == "local").replace(".json", "_changelog.jsonl")
+}
+
+/// 获取指定位置的记忆变更记录，最新的在前
+pub fn read_changelog(key: &str) -> Vec<MemoryChange> {
+    match read_to_string(changelog_file(key)) {
+        Ok(s) => s.lines().rev().filter_map(|l| serde_json::from_str(l).ok()).collect(),
+        Err(_) => vec![],
+    }
+}
+
+/// 追加记忆变更记录
+fn append_changelog(key: &str, changes: &[MemoryChange]) -> Result<(), MyError> {
+    let file = changelog_file(key);
+    let mut f = OpenOptions::new().create(true).append(true).open(&file).map_err(|e| MyError::WriteFileError{file: file.clone(), error: e})?;
+    for c in changes {
+        let line = serde_json::to_string(c).map_err(|e| MyError::ToJsonStirngError{uuid: "memory".to_string(), error: e})?;
+        writeln!(f, "{}", line).map_err(|e| MyError::WriteFileError{file: file.clone(), error: e})?;
+    }
+    Ok(())
+}
+
+/// 记忆的指纹，用于判断上次整理后是否有变化
+fn fingerprint(notes: &[MemoryNote]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for n in notes {
+        n.id.hash(&mut hasher);
+        n.summary.hash(&mut hasher);
+        n.namespace.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// 用LLM整理指定位置的记忆，返回本次的变更，上次整理后没有变化且`force`为false则跳过
+/// model: (api_key, endpoint, 模型名称, 是否支持深度思考)
+pub async fn consolidate(api: &Api, key: &str, model: (String, String, String, bool), force: bool) -> Result<Vec<MemoryChange>, MyError> {
+    let notes = manage::all_notes(key);
+    if !force && CONSOLIDATED.lock().unwrap().get(key) == Some(&fingerprint(&notes)) {
+        return Ok(vec![])
+    }
+    // 按命名空间分组，固定的记忆不参与整理
+    let mut groups: Vec<(String, Vec<&MemoryNote>)> = vec![];
+    for note in notes.iter().filter(|n| !n.reserved) {
+        match groups.iter_mut().find(|(ns, _)| *ns == note.namespace) {
+            Some((_, g)) => g.push(note),
+            None => groups.push((note.namespace.clone(), vec![note])),
+        }
+    }
+    let mut changes: Vec<MemoryChange> = vec![];
+    for (namespace, group) in groups.into_iter().filter(|(_, g)| g.len() >= 2) {
+        let content = format!("{}{}", CONSOLIDATE_PROMPT, group.iter().map(|n| format!("[{}] {}", n.id, n.summary)).collect::<Vec<_>>().join("\n"));
+        let result = run_single_llm("memory", content, model.0.clone(), model.1.clone(), model.2.clone()).await?;
+        let result = result.trim();
+        let json_str = match (result.starts_with("```json"), result.ends_with("```")) {
+            (true, true) => result.strip_prefix("```json").unwrap().strip_suffix("```").unwrap(),
+            (true, false) => result.strip_prefix("```json").unwrap(),
+            (false, true) => result.strip_suffix("```").unwrap(),
+            (false, false) => result,
+        };
+        let operations: Operations = match serde_json::from_str(json_str.trim()) {
+            Ok(o) => o,
+            Err(e) => {
+                event!(Level::ERROR, "{} memory consolidation result is not valid json: {}\n{}", key, e, json_str);
+                continue
+            },
+        };
+        let mut used: HashSet<String> = HashSet::new();
+        for op in operations.operations {
+            // 只处理该命名空间中存在且未被其他操作使用的id
+            let mut members: Vec<&MemoryNote> = op.ids.iter().filter_map(|id| group.iter().find(|n| &n.id == id).copied()).collect();
+            if members.len() != op.ids.len() || members.iter().any(|n| used.contains(&n.id)) {
+                event!(Level::WARN, "{} skip memory consolidation {} with unknown or reused ids: {:?}", key, op.action, op.ids);
+                continue
+            }
+            let summary = op.summary.trim().to_string();
+            let result = match (op.action.as_str(), members.len()) {
+                ("merge", 2..) if !summary.is_empty() => {
+                    // 合并后保留最新记忆的位置和id
+                    members.sort_by_key(|n| std::cmp::Reverse(notes.iter().position(|x| x.id == n.id)));
+                    let ids: Vec<String> = members.iter().map(|n| n.id.clone()).collect();
+                    let merged: Vec<MemoryNote> = members[1..].iter().map(|n| (*n).clone()).collect(); // 合并后被移除的记忆
+                    let embedding = get_embedding(api, key, summary.clone()).await.unwrap_or(None);
+                    manage::merge_notes(key, &ids, summary.clone(), embedding).map(|_| {
+                        if key == "local" {
+                            manage::append_old_notes(merged);
+                        }
+                        Some(summary)
+                    })
+                },
+                ("rewrite", 1) if !summary.is_empty() => {
+                    let embedding = get_embedding(api, key, summary.clone()).await.unwrap_or(None);
+                    let edit = NoteEdit{summary: Some(summary.clone()), raw: None, reserved: None, namespace: None, tags: None};
+                    manage::update_note(key, &members[0].id, edit, embedding).map(|_| Some(summary))
+                },
+                ("delete", 1..) => {
+                    let ids: Vec<String> = members.iter().map(|n| n.id.clone()).collect();
+                    manage::delete_notes(key, &ids);
+                    if key == "local" {
+                        manage::append_old_notes(members.iter().map(|n| (*n).clone()).collect());
+                    }
+                    Ok(None)
+                },
+                _ => {
+                    event!(Level::WARN, "{} skip invalid memory consolidation {} of {} memories", key, op.action, members.len());
+                    continue
+                },
+            };
+            match result {
+                Ok(after) => {
+                    used.extend(members.iter().map(|n| n.id.clone()));
+                    changes.push(MemoryChange{
+                        time:      Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+                        namespace: namespace.clone(),
+                        action:    op.action,
+                        ids:       members.iter().map(|n| n.id.clone()).collect(),
+                        before:    members.iter().map(|n| n.summary.clone()).collect(),
+                        after,
+                        reason:    op.reason,
+                    });
+                },
+                Err(e) => event!(Level::ERROR, "{} memory consolidation {} error: {}", key, op.action, e),
+            }
+        }
+    }
+    if !changes.is_empty() {
+        append_changelog(key, &changes)?;
+        event!(Level::INFO, "{} memory consolidation: {} changes", key, changes.len());
+    }
+    CONSOLIDATED.lock().unwrap().insert(key.to_string(), fingerprint(&manage::all_notes(key)));
+    Ok(changes)
+}
@@ -0,0 +1,190 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::MyError,
+    memory::{
+        MEMORY,
+        MemoryNote,
+        SimpleMemory,
+        load_memory,
+        memory_file,
+    },
+};
+
+// 记忆管理：页面和api查看、搜索、修改、删除、固定、合并记忆，以及在本地记忆和uuid记忆之间移动
+// 记忆的存储位置用key表示：`local`是`memory.json`，`old`是`memory_old.json`，其他是uuid
+
+/// 页面和api显示的一条记忆
+#[derive(Serialize)]
+pub struct NoteView {
+    pub id:        String,
+    pub summary:   String,
+    pub raw:       String,
+    pub reserved:  bool,
+    pub namespace: String,      // 所属的命名空间
+    pub tags:      Vec<String>, // 标签
+    pub embedding: bool,        // 是否有embedding
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score:     Option<f64>, // 搜索时与搜索内容的相关性分数
+}
+
+/// 修改记忆，不指定的字段保持不变
+#[derive(Deserialize)]
+pub struct NoteEdit {
+    pub summary:   Option<String>,
+    pub raw:       Option<String>,
+    pub reserved:  Option<bool>,
+    pub namespace: Option<String>,
+    pub tags:      Option<Vec<String>>,
+}
+
+/// 注入到某次回答的一条记忆
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InjectedNote {
+    pub store:   String, // `local`、`old`或`uuid`
+    pub id:      String,
+    pub summary: String,
+    pub score:   f64,
+}
+
+/// 某次回答注入的记忆，记录在chat记录中
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemoryInjection {
+    pub msg_id: usize,             // 注入记忆后第一条回答的信息id
+    pub time:   String,            // 注入的时间
+    pub query:  String,            // 检索记忆的问题，过长则截断
+    pub notes:  Vec<InjectedNote>, // 注入的记忆
+}
+
+/// 对指定位置的记忆执行操作，不在服务端则先从记忆文件加载，`create`为true时文件不存在则创建空记忆
+fn with_store<T>(key: &str, create: bool, f: impl FnOnce(&mut SimpleMemory) -> T) -> Option<T> {
+    let mut data = MEMORY.lock().unwrap();
+    if !data.contains_key(key) {
+        let memory = match load_memory(key, key == "local") {
+            Some(m) => m,
+            None if create => SimpleMemory::new(if key == "old" { usize::MAX } else { 100 }, memory_file(key, key == "local")),
+            None => return None,
+        };
+        data.insert(key.to_string(), memory);
+    }
+    data.get_mut(key).map(f)
+}
+
+/// 获取指定位置的所有记忆，指定搜索内容则只返回相关的记忆，按相关性排序，有embedding时使用余弦相似度，指定命名空间则只返回该命名空间的记忆
+pub fn list_notes(key: &str, query: Option<&str>, embedding: Option<Vec<f64>>, namespace: Option<&str>) -> Vec<NoteView> {
+    with_store(key, false, |memory| {
+        let view = |note: &MemoryNote, score: Option<f64>| NoteView{
+            id:        note.id.clone(),
+            summary:   note.summary.clone(),
+            raw:       note.raw.clone(),
+            reserved:  note.reserved,
+            namespace: note.namespace.clone(),
+            tags:      note.tags.clone(),
+            embedding: note.embedding.is_some(),
+            score,
+        };
+        let in_namespace = |note: &MemoryNote| namespace.is_none_or(|ns| note.namespace == ns);
+        match query.filter(|q| !q.trim().is_empty()) {
+            Some(q) => memory.search_relevant(q, embedding, usize::MAX, None).iter().filter(|hit| in_namespace(&hit.note)).map(|hit| view(&hit.note, Some(hit.score))).collect(),
+            None => memory.notes.iter().rev().filter(|n| in_namespace(n)).map(|n| view(n, None)).collect(), // 最新的记忆在前
+        }
+    }).unwrap_or_default()
+}
+
+/// 获取指定位置的所有记忆，按添加顺序，最旧的在前
+pub fn all_notes(key: &str) -> Vec<MemoryNote> {
+    with_store(key, false, |memory| memory.notes.clone()).unwrap_or_default()
+}
+
+/// 获取指定id的记忆
+pub fn get_note(key: &str, id: &str) -> Option<MemoryNote> {
+    with_store(key, false, |memory| memory.notes.iter().find(|n| n.id == id).cloned()).flatten()
+}
+
+/// 修改指定id的记忆，修改了summary则同时更新embedding
+pub fn update_note(key: &str, id: &str, edit: NoteEdit, embedding: Option<Vec<f64>>) -> Result<(), MyError> {
+    with_store(key, false, |memory| {
+        let note = memory.notes.iter_mut().find(|n| n.id == id)?;
+        if let Some(summary) = edit.summary {
+            if summary != note.summary {
+                note.summary = summary;
+                note.embedding = embedding;
+            }
+        }
+        if let Some(raw) = edit.raw {
+            note.raw = raw;
+        }
+        if let Some(reserved) = edit.reserved {
+            note.reserved = reserved;
+        }
+        if let Some(namespace) = edit.namespace.map(|ns| ns.trim().to_string()).filter(|ns| !ns.is_empty()) {
+            note.namespace = namespace;
+        }
+        if let Some(tags) = edit.tags {
+            note.tags = tags.iter().map(|t| t.trim().to_lowercase()).filter(|t| !t.is_empty()).collect();
+        }
+        memory.save = true;
+        Some(())
+    }).flatten().ok_or_else(|| MyError::ParaError{para: format!("no memory {} in {}", id, key)})
+}
+
+/// 删除指定id的记忆，返回删除的数量
+pub fn delete_notes(key: &str, ids: &[String]) -> usize {
+    with_store(key, false, |memory| {
+        let before = memory.notes.len();
+        memory.notes.retain(|n| !ids.contains(&n.id));
+        let removed = before - memory.notes.len();
+        if removed > 0 {
+            memory.save = true;
+        }
+        removed
+    }).unwrap_or(0)
+}
+
+/// 将记忆追加到`memory_old.json`，例如整理本地记忆时被合并或删除的记忆，文件不存在则创建
+pub fn append_old_notes(notes: Vec<MemoryNote>) {
+    if !notes.is_empty() {
+        with_store("old", true, |memory| memory.append_memory(notes));
+    }
+}
+
+/// 合并多条重复的记忆：保留第一条的位置、id和命名空间，使用新的summary，原始内容依次拼接，标签取并集，任意一条固定则合并后固定，删除其他记忆
+pub fn merge_notes(key: &str, ids: &[String], summary: String, embedding: Option<Vec<f64>>) -> Result<String, MyError> {
+    if ids.len() < 2 {
+        return Err(MyError::ParaError{para: "select at least 2 memories to merge".to_string()})
+    }
+    with_store(key, false, |memory| {
+        let merged: Vec<MemoryNote> = ids.iter().filter_map(|id| memory.notes.iter().find(|n| &n.id == id).cloned()).collect();
+        if merged.len() != ids.len() {
+            return None
+        }
+        let keep = memory.notes.iter_mut().find(|n| n.id == ids[0])?;
+        keep.summary = summary;
+        keep.raw = merged.iter().map(|n| n.raw.as_str()).collect::<Vec<_>>().join("\n\n");
+        keep.reserved = merged.iter().any(|n| n.reserved);
+        keep.tags = merged.iter().flat_map(|n| n.tags.iter()).fold(vec![], |mut tags, t| {
+            if !tags.contains(t) {
+                tags.push(t.clone());
+            }
+            tags
+        });
+        keep.embedding = embedding;
+        memory.notes.retain(|n| !ids[1..].contains(&n.id));
+        memory.save = true;
+        Some(ids[0].clone())
+    }).flatten().ok_or_else(|| MyError::ParaError{para: format!("some memories not found in {}", key)})
+}
+
+/// 将指定id的记忆从一个位置移动到另一个位置，目标位置没有记忆文件则创建，id保持不变
+pub fn move_note(from: &str, to: &str, id: &str) -> Result<(), MyError> {
+    if from == to {
+        return Err(MyError::ParaError{para: "the source and target of moving memory are the same".to_string()})
+    }
+    let note = with_store(from, false, |memory| {
+        let i = memory.notes.iter().position(|n| n.id == id)?;
+        memory.save = true;
+        Some(memory.notes.remove(i))
+    }).flatten().ok_or_else(|| MyError::ParaError{para: format!("no memory {} in {}", id, from)})?;
+    with_store(to, true, |memory| memory.append_memory(vec![note]));
+    Ok(())
+}
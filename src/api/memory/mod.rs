@@ -13,6 +13,10 @@ use crate::{
     error::MyError,
 };
 
+pub mod manage;
+pub mod consolidate;
+pub mod auto;
+
 // 极简版记忆体
 // 1. 每轮任务开始前，根据“当前用户问题”检索相关记忆，注入模型上下文
 // 2. 每轮任务结束后，由用户决定是否提取对话记忆进行存储
@@ -20,18 +24,47 @@ use crate::{
 
 pub static MEMORY: Lazy<Mutex<HashMap<String, SimpleMemory>>> = Lazy::new(|| Mutex::new(HashMap::new()));
 
+/// 全局命名空间，所有对话都会检索，没有命名空间的旧记忆属于全局命名空间
+pub const GLOBAL_NAMESPACE: &str = "global";
+
+// 检索记忆的参数
+const BM25_K1: f64 = 1.2;         // BM25 词频饱和参数
+const BM25_B: f64 = 0.75;         // BM25 文档长度归一化参数
+const RRF_K: f64 = 60.0;          // 倒数排序融合的常数，越大则排名靠后的记忆与靠前的差距越小
+const MIN_BM25: f64 = 0.2;        // BM25 分数低于该值不参与排名
+const MIN_COSINE: f64 = 0.35;     // 余弦相似度低于该值不参与排名
+const RECENCY_WEIGHT: f64 = 0.1;  // 最新的记忆融合分数乘以 1 + RECENCY_WEIGHT，最旧的乘以 1
+
 /// 从对话中提取的一条记忆
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MemoryNote {
+    #[serde(default)]
+    pub id:        String,           // 记忆的id，用于页面和api修改、删除、合并、移动，旧的记忆文件没有id则加载时生成
     pub raw:       String,           // 原始内容
     pub summary:   String,           // 提取的记忆
-    pub reserved:  bool,             // 是否一直保留该记忆，不被移除，程序不会修改这个值，只能自己手动编辑
+    pub reserved:  bool,             // 是否一直保留该记忆，不被移除，程序不会修改这个值，通过记忆管理页面或api固定
+    #[serde(default = "default_namespace")]
+    pub namespace: String,           // 所属的命名空间（项目），对话只检索其使用的命名空间和全局命名空间
+    #[serde(default)]
+    pub tags:      Vec<String>,      // 提取记忆时模型指定的标签，关键词检索时参与匹配
     #[serde(serialize_with = "serialize_embedding_raw", deserialize_with = "deserialize_embedding_raw")]
     pub embedding: Option<Vec<f64>>, // summary 对应的 embedding
 }
 
+/// 没有命名空间的记忆属于全局命名空间
+fn default_namespace() -> String {
+    GLOBAL_NAMESPACE.to_string()
+}
+
+impl MemoryNote {
+    /// 是否属于指定的命名空间，全局命名空间的记忆始终属于
+    fn in_namespaces(&self, namespaces: &[String]) -> bool {
+        self.namespace == GLOBAL_NAMESPACE || namespaces.contains(&self.namespace)
+    }
+}
+
 /// 对 MemoryNote 的 embedding 进行自定义序列化，使数值在一行显示
-fn serialize_embedding_raw<S>(emb: &Option<Vec<f64>>, s: S) -> Result<S::Ok, S::Error>
+pub fn serialize_embedding_raw<S>(emb: &Option<Vec<f64>>, s: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
@@ -49,7 +82,7 @@ where
 }
 
 /// 对 MemoryNote 的 embedding 进行自定义反序列化
-fn deserialize_embedding_raw<'de, D>(d: D) -> Result<Option<Vec<f64>>, D::Error>
+pub fn deserialize_embedding_raw<'de, D>(d: D) -> Result<Option<Vec<f64>>, D::Error>
 where
     D: Deserializer<'de>,
 {
@@ -61,8 +94,10 @@ where
 /// 一条和当前问题相关的记忆命中结果
 #[derive(Debug, Clone)]
 pub struct RelevantMemory {
-    pub note:  MemoryNote, // 被命中的原始记忆
-    pub score: f64, // 简单相关性分数。分数越高，越应该优先注入模型
+    pub note:   MemoryNote,  // 被命中的原始记忆
+    pub score:  f64,         // BM25 和 embedding 排名融合后的相关性分数，分数越高，越应该优先注入模型，0表示未达到阈值
+    pub bm25:   f64,         // 关键词 BM25 分数
+    pub cosine: Option<f64>, // 与问题 embedding 的余弦相似度，问题或记忆没有 embedding 则为None
 }
 
 /// 极简记忆体
@@ -97,6 +132,10 @@ impl SimpleMemory {
         let json = fs::read_to_string(file)?;
         let mut memory = serde_json::from_str::<Self>(&json).map_err(|e| MyError::ResponseTextToJsonError{error: e})?;
         memory.max_notes = memory.max_notes.max(10); // 至少10条记忆
+        for note in memory.notes.iter_mut().filter(|n| n.id.is_empty()) {
+            note.id = new_note_id();
+            memory.save = true; // 生成的id需要保存，保证之后id不变
+        }
         if !file.contains("memory_old.json") {
             memory.trim_old_notes(is_local);
         }
@@ -167,20 +206,22 @@ impl SimpleMemory {
 
     /// 判断一条候选记忆是否已经存在
     /// 这里既检查规范化后的完全相同，也检查轻量 token 相似度
-    fn is_duplicate_memory(&self, summary: &str) -> bool {
-        self.notes.iter().any(|note| is_duplicate_note(&note.summary, summary))
+    fn is_duplicate_memory(&self, summary: &str, namespace: &str) -> bool {
+        self.notes.iter().filter(|note| note.namespace == namespace).any(|note| is_duplicate_note(&note.summary, summary))
     }
 
     /// 调用 LLM 抽提总结指定字符串作为记忆，返回超出容量的旧记忆
     /// text: 要提取记忆的原始内容
     /// model_for_memory: (api_key, endpoint, 模型名称, 是否支持深度思考)
-    pub fn remember(&mut self, raw: String, summary: String, summary_embedding: Option<Vec<f64>>, is_local: bool) -> Option<Vec<MemoryNote>> {
+    /// namespace: 记忆所属的命名空间，只与同一命名空间的记忆判断是否重复
+    /// tags: 提取记忆时模型指定的标签
+    pub fn remember(&mut self, raw: String, summary: String, namespace: String, tags: Vec<String>, summary_embedding: Option<Vec<f64>>, is_local: bool) -> Option<Vec<MemoryNote>> {
         if !summary.trim().is_empty() {
             // 尝试通过 embedding 模型计算 summary 的 embedding 向量
             let (add, embedding) = match summary_embedding {
                 Some(embedding) => (
                     // 计算 embedding 相似度，判断当前 summary 是否与记忆体中的记忆重复
-                    !self.notes.iter().any(|note| {
+                    !self.notes.iter().filter(|note| note.namespace == namespace).any(|note| {
                         note.embedding.as_ref().map_or(false, |emb| {
                             let simi = cosine_similarity(&embedding, &emb);
                             if simi > 0.8 {
@@ -196,13 +237,13 @@ impl SimpleMemory {
                 None => (true, None),
             };
             if add {
-                if self.is_duplicate_memory(&summary) {
+                if self.is_duplicate_memory(&summary, &namespace) {
                     // 重复的记忆，不添加
                     event!(Level::WARN, "Memory similarity >= 80%, do not add this memory.\nnew memory: {}\nall memory:\n{}", summary, self.notes.iter().map(|m| format!("- {}", m.summary)).collect::<Vec<_>>().join("\n"));
                     None
                 } else {
                     // 添加新记忆
-                    self.notes.push(MemoryNote { raw, summary, reserved: false, embedding });
+                    self.notes.push(MemoryNote { id: new_note_id(), raw, summary, reserved: false, namespace, tags, embedding });
                     self.save = true;
                     event!(Level::INFO, "push new memory successful, current memory number: {}", self.notes.len());
                     self.trim_old_notes(is_local)
@@ -216,15 +257,16 @@ impl SimpleMemory {
         }
     }
 
-    /// 获取所有记忆
-    fn get_all_memory(&self) -> Option<String> {
-        if self.notes.is_empty() {
+    /// 获取指定命名空间和全局命名空间的所有记忆
+    fn get_all_memory(&self, namespaces: &[String]) -> Option<String> {
+        let notes: Vec<&MemoryNote> = self.notes.iter().filter(|n| n.in_namespaces(namespaces)).collect();
+        if notes.is_empty() {
             None
         } else {
             let mut prompt = "## Memory\nThe following content is from previous tasks. Use it only when relevant to the current issue; if there is a conflict with the current user message, the current user message takes precedence.\n".to_string();
             prompt.push_str("\n### all memories:\n");
-            event!(Level::INFO, "got all memory:\n{}", self.notes.iter().map(|m| format!("- {}", m.summary)).collect::<Vec<_>>().join("\n"));
-            for note in &self.notes {
+            event!(Level::INFO, "got all memory:\n{}", notes.iter().map(|m| format!("- {}", m.summary)).collect::<Vec<_>>().join("\n"));
+            for note in notes {
                 prompt.push_str("- ");
                 prompt.push_str(&note.summary);
                 prompt.push('\n');
@@ -233,31 +275,48 @@ impl SimpleMemory {
         }
     }
 
-    /// 根据当前问题检索最相关的 notes 记忆
-    ///
-    /// 这个实现没有依赖向量库或分词库，只做一个很小的相关性打分：
-    ///
-    /// - 完整 query 出现在 note 中：高分
-    /// - 英文/数字词重合：加分
-    /// - 中文连续片段的 bigram 或单字重合：加分
-    /// - 分数相同：越新的 note 越靠前
-    ///
-    /// 复杂项目里可以把这里替换成 BM25、tantivy、SQLite FTS、embedding 检索或混合检索
-    fn search_relevant(&self, query: &str, embedding: Option<Vec<f64>>, limit: usize) -> Vec<RelevantMemory> {
+    /// 根据当前问题检索最相关的 notes 记忆，只返回达到阈值的前 `limit` 条
+    /// `namespaces`为None表示检索所有命名空间（记忆管理页面搜索），否则只检索指定的命名空间和全局命名空间
+    fn search_relevant(&self, query: &str, embedding: Option<Vec<f64>>, limit: usize, namespaces: Option<&[String]>) -> Vec<RelevantMemory> {
         if limit == 0 || query.trim().is_empty() {
             return Vec::new();
         }
+        let mut hits = self.score_all(query, embedding.as_deref(), namespaces);
+        hits.retain(|hit| hit.score > 0.0);
+        hits.truncate(limit);
+        hits
+    }
 
-        let mut scored = self
+    /// 对候选记忆打分，按融合分数从高到低排序
+    ///
+    /// - 关键词：对 `tokenize` 分词后的 summary 和标签计算 BM25
+    /// - 语义：有 embedding 时计算与问题的余弦相似度
+    /// - 两种分数分别排名，低于 `MIN_BM25`、`MIN_COSINE` 的不参与排名，再用倒数排序融合（RRF）合并排名
+    /// - 越新的记忆融合分数越高，最多提高 `RECENCY_WEIGHT`，分数相同则越新的记忆越靠前
+    ///
+    /// 两种分数都未达到阈值的记忆融合分数为0，不会被注入
+    fn score_all(&self, query: &str, embedding: Option<&[f64]>, namespaces: Option<&[String]>) -> Vec<RelevantMemory> {
+        let candidates = self
             .notes
             .iter()
             .enumerate()
-            .filter_map(|(index, note)| {
-                let score = match (&embedding, &note.embedding) {
-                    (Some(vec_a), Some(vec_b)) => cosine_similarity(&vec_a, &vec_b), // 有 embedding 模型，使用 embedding 计算相似度
-                    _ => score_note(query, &note.summary), // 没有 embedding 模型，使用关键词占比计算相似度
-                };
-                (score > 0.0).then_some((index, RelevantMemory { note: note.clone(), score }))
+            .filter(|(_, note)| namespaces.is_none_or(|ns| note.in_namespaces(ns)))
+            .collect::<Vec<_>>();
+        let docs = candidates.iter().map(|(_, note)| tokenize(&format!("{} {}", note.summary, note.tags.join(" ")))).collect::<Vec<_>>(); // 标签也参与匹配
+        let bm25 = bm25_scores(&tokenize(query), &docs);
+        let cosine = candidates.iter().map(|(_, note)| match (embedding, &note.embedding) {
+            (Some(vec_a), Some(vec_b)) => Some(cosine_similarity(vec_a, vec_b)),
+            _ => None,
+        }).collect::<Vec<_>>();
+        let fused = fuse_scores(&bm25, &cosine);
+        let newest = self.notes.len().saturating_sub(1).max(1) as f64;
+
+        let mut scored = candidates
+            .into_iter()
+            .enumerate()
+            .map(|(i, (index, note))| {
+                let recency = 1.0 + RECENCY_WEIGHT * index as f64 / newest;
+                (index, RelevantMemory { note: note.clone(), score: fused[i] * recency, bm25: bm25[i], cosine: cosine[i] })
             })
             .collect::<Vec<_>>();
 
@@ -268,26 +327,26 @@ impl SimpleMemory {
                 .then_with(|| right_index.cmp(left_index)) // score 相同则最新的记忆排前面
         });
 
-        scored.into_iter().take(limit).map(|(_, hit)| hit).collect()
+        scored.into_iter().map(|(_, hit)| hit).collect()
     }
 
-    /// 根据当前问题生成要注入模型的记忆 prompt
+    /// 根据当前问题生成要注入模型的记忆 prompt，同时返回注入的记忆
     /// 这是推荐在 agent loop 开始时调用的方法
     /// 它会先根据 `current_query` 检索相关 notes，然后只注入前 `max_hits` 条记忆
-    fn relevant_memory_prompt(&self, current_query: &str, embedding: Option<Vec<f64>>, max_hits: usize) -> Option<String> {
-        let hits = self.search_relevant(current_query, embedding, max_hits);
+    fn relevant_memory_prompt(&self, current_query: &str, embedding: Option<Vec<f64>>, max_hits: usize, namespaces: &[String]) -> Option<(String, Vec<RelevantMemory>)> {
+        let hits = self.search_relevant(current_query, embedding, max_hits, Some(namespaces));
         if hits.is_empty() {
             None
         } else {
             let mut prompt = "## Memory\nThe following content is from previous tasks. Use it only when relevant to the current issue; if there is a conflict with the current user message, the current user message takes precedence.\n".to_string();
             prompt.push_str("\n### Memories related to the current issue:\n");
-            event!(Level::INFO, "got memory:\n{}", hits.iter().map(|m| format!("- score: {:.4}, memory: {}", m.score, m.note.summary)).collect::<Vec<_>>().join("\n"));
-            for hit in hits {
+            event!(Level::INFO, "got memory:\n{}", hits.iter().map(|m| format!("- score: {:.4}, bm25: {:.3}, cosine: {}, memory: {}", m.score, m.bm25, m.cosine.map_or("-".to_string(), |c| format!("{:.3}", c)), m.note.summary)).collect::<Vec<_>>().join("\n"));
+            for hit in &hits {
                 prompt.push_str("- ");
                 prompt.push_str(&hit.note.summary);
                 prompt.push('\n');
             }
-            Some(prompt)
+            Some((prompt, hits))
         }
     }
 }
@@ -366,42 +425,52 @@ fn is_ignored_punctuation(ch: char) -> bool {
         )
 }
 
-/// 计算问题与记忆的相关性分数
-fn score_note(query: &str, note: &str) -> f64 {
-    let query = query.trim().to_lowercase();
-    let note = note.to_lowercase();
-    if query.is_empty() || note.is_empty() {
-        return 0.0;
+/// 计算问题对每个文档的 BM25 分数，文档是分词后的 token 列表
+pub fn bm25_scores(query_terms: &[String], docs: &[Vec<String>]) -> Vec<f64> {
+    if docs.is_empty() {
+        return Vec::new();
     }
+    let n = docs.len() as f64;
+    let avgdl = (docs.iter().map(|d| d.len()).sum::<usize>() as f64 / n).max(1.0);
+    let query_terms = query_terms.iter().collect::<HashSet<_>>();
+    // 每个词出现在多少个文档中
+    let df = query_terms.iter().map(|t| (*t, docs.iter().filter(|d| d.contains(*t)).count())).collect::<HashMap<_, _>>();
+
+    docs.iter().map(|doc| {
+        let dl = doc.len() as f64;
+        query_terms.iter().map(|t| {
+            let tf = doc.iter().filter(|x| *x == *t).count() as f64;
+            if tf == 0.0 {
+                return 0.0;
+            }
+            let df = df[t] as f64;
+            let idf = ((n - df + 0.5) / (df + 0.5) + 1.0).ln();
+            idf * tf * (BM25_K1 + 1.0) / (tf + BM25_K1 * (1.0 - BM25_B + BM25_B * dl / avgdl))
+        }).sum()
+    }).collect()
+}
 
-    let mut score = 0;
-    if note.contains(&query) { // 问题完全包含在记忆中
-        score += 100;
+/// 按分数从高到低计算排名（从1开始），None 不参与排名
+fn rank_scores(scores: &[Option<f64>]) -> Vec<Option<usize>> {
+    let mut order = scores.iter().enumerate().filter_map(|(i, s)| s.map(|s| (i, s))).collect::<Vec<_>>();
+    order.sort_by(|(_, left), (_, right)| right.total_cmp(left));
+    let mut ranks = vec![None; scores.len()];
+    for (rank, (i, _)) in order.into_iter().enumerate() {
+        ranks[i] = Some(rank + 1);
     }
-
-    let note_terms = tokenize(&note).into_iter().collect::<HashSet<_>>();
-    let query_terms = tokenize(&query).into_iter().collect::<HashSet<_>>();
-
-    for term in query_terms {
-        if note_terms.contains(&term) || note.contains(&term) {
-            score += term_weight(&term);
-        }
-    }
-
-    score as f64
+    ranks
 }
 
-/// 单字符1分，多字符4分
-fn term_weight(term: &str) -> usize {
-    if term.chars().count() >= 2 {
-        4
-    } else {
-        1
-    }
+/// 关键词和语义分数分别排名，低于 `MIN_BM25`、`MIN_COSINE` 的不参与排名，再用倒数排序融合（RRF）合并排名，两种分数都未达到阈值则为0
+/// 记忆和知识库检索共用
+pub fn fuse_scores(bm25: &[f64], cosine: &[Option<f64>]) -> Vec<f64> {
+    let bm25_rank = rank_scores(&bm25.iter().map(|s| (*s >= MIN_BM25).then_some(*s)).collect::<Vec<_>>());
+    let cosine_rank = rank_scores(&cosine.iter().map(|c| c.filter(|c| *c >= MIN_COSINE)).collect::<Vec<_>>());
+    bm25_rank.iter().zip(&cosine_rank).map(|(b, c)| [b, c].into_iter().flatten().map(|rank| 1.0 / (RRF_K + *rank as f64)).sum()).collect()
 }
 
 /// 对字符串进行分词，支持中英文
-fn tokenize(input: &str) -> Vec<String> {
+pub fn tokenize(input: &str) -> Vec<String> {
     let mut tokens = Vec::new();
     let mut latin = String::new();
     let mut cjk_run = Vec::new();
@@ -465,8 +534,13 @@ fn is_cjk(ch: char) -> bool {
     )
 }
 
-/// 获取相关记忆
-pub fn get_relevant_memory(uuid: &str, query: &str, embedding: Option<Vec<f64>>, max_hits: usize, is_local: bool) -> Option<String> {
+/// 生成记忆的id
+fn new_note_id() -> String {
+    uuid::Uuid::new_v4().simple().to_string()[..12].to_string()
+}
+
+/// 获取相关记忆，只检索对话使用的命名空间和全局命名空间，返回要注入的prompt和命中的记忆
+pub fn get_relevant_memory(uuid: &str, query: &str, embedding: Option<Vec<f64>>, max_hits: usize, is_local: bool, namespaces: &[String]) -> Option<(String, Vec<RelevantMemory>)> {
     let mut data = MEMORY.lock().unwrap();
     let key = if is_local {
         "local"
@@ -474,41 +548,75 @@ pub fn get_relevant_memory(uuid: &str, query: &str, embedding: Option<Vec<f64>>,
         uuid
     };
     match data.get_mut(key) {
-        Some(memory) => memory.relevant_memory_prompt(query, embedding, max_hits),
+        Some(memory) => memory.relevant_memory_prompt(query, embedding, max_hits, namespaces),
         None => {
-            let memory_file = if is_local {
-                format!("{}/memory.json", PARAS.memory_dir)
-            } else if key == "old" {
-                format!("{}/memory_old.json", PARAS.memory_dir)
-            } else {
-                format!("{}/{}/{}_memory.json", PARAS.outpath, key, key)
-            };
-            let memory_path = Path::new(&memory_file);
-            if memory_path.exists() && memory_path.is_file() {
-                match SimpleMemory::load_from_file(&memory_file, is_local) {
-                    Ok(memory) => {
-                        let result = memory.relevant_memory_prompt(query, embedding, max_hits);
-                        data.insert(key.to_string(), memory);
-                        result
-                    },
-                    Err(e) => {
-                        event!(Level::ERROR, "load memory file ({}) error: {}", memory_file, e);
-                        None
-                    },
-                }
-            } else {
-                event!(Level::WARN, "no memory in {}", key);
-                None
-            }
+            let memory = load_memory(key, is_local)?;
+            let result = memory.relevant_memory_prompt(query, embedding, max_hits, namespaces);
+            data.insert(key.to_string(), memory);
+            result
         },
     }
 }
 
-/// 获取所有记忆
-pub fn get_all_memory(key: &str) -> String {
+/// 获取对话使用的命名空间和全局命名空间的所有记忆，不在服务端则先从记忆文件加载，用于模板变量`{{memory}}`
+pub fn get_all_memory_or_load(uuid: &str, is_local: bool, namespaces: &[String]) -> Option<String> {
+    let mut data = MEMORY.lock().unwrap();
+    let key = if is_local {
+        "local"
+    } else {
+        uuid
+    };
+    if !data.contains_key(key) {
+        let memory = load_memory(key, is_local)?;
+        data.insert(key.to_string(), memory);
+    }
+    data.get(key).and_then(|m| m.get_all_memory(namespaces))
+}
+
+/// 评估记忆检索，返回指定位置所有候选记忆的打分，按融合分数排序，分数为0表示未达到阈值不会被注入，用于`chatsong memory`
+pub fn evaluate_memory(key: &str, query: &str, embedding: Option<Vec<f64>>, namespaces: &[String]) -> Option<Vec<RelevantMemory>> {
+    let mut data = MEMORY.lock().unwrap();
+    if !data.contains_key(key) {
+        let memory = load_memory(key, key == "local")?;
+        data.insert(key.to_string(), memory);
+    }
+    data.get(key).map(|m| m.score_all(query, embedding.as_deref(), Some(namespaces)))
+}
+
+/// 记忆文件路径：`local`是`memory.json`，`old`是`memory_old.json`，其他是uuid路径下的`uuid_memory.json`
+fn memory_file(key: &str, is_local: bool) -> String {
+    if is_local {
+        format!("{}/memory.json", PARAS.memory_dir)
+    } else if key == "old" {
+        format!("{}/memory_old.json", PARAS.memory_dir)
+    } else {
+        format!("{}/{}/{}_memory.json", PARAS.outpath, key, key)
+    }
+}
+
+/// 从记忆文件加载记忆，文件不存在或出错则返回None
+fn load_memory(key: &str, is_local: bool) -> Option<SimpleMemory> {
+    let memory_file = memory_file(key, is_local);
+    let memory_path = Path::new(&memory_file);
+    if memory_path.exists() && memory_path.is_file() {
+        match SimpleMemory::load_from_file(&memory_file, is_local) {
+            Ok(memory) => Some(memory),
+            Err(e) => {
+                event!(Level::ERROR, "load memory file ({}) error: {}", memory_file, e);
+                None
+            },
+        }
+    } else {
+        event!(Level::WARN, "no memory in {}", key);
+        None
+    }
+}
+
+/// 获取指定命名空间和全局命名空间的所有记忆
+pub fn get_all_memory(key: &str, namespaces: &[String]) -> String {
     let data = MEMORY.lock().unwrap();
     if let Some(memory) = data.get(key) {
-        if let Some(m) = memory.get_all_memory() {
+        if let Some(m) = memory.get_all_memory(namespaces) {
             return m
         }
     }
@@ -518,7 +626,7 @@ pub fn get_all_memory(key: &str) -> String {
 /// Computes the cosine similarity between two embedding vector
 /// https://en.wikipedia.org/wiki/Cosine_similarity
 /// https://github.com/gaspiman/cosine_similarity/blob/master/cosine.go
-fn cosine_similarity(vec_a: &[f64], vec_b: &[f64]) -> f64 {
+pub fn cosine_similarity(vec_a: &[f64], vec_b: &[f64]) -> f64 {
     let mut ab: f64 = 0.0;
     let mut sum_a: f64 = 0.0;
     let mut sum_b: f64 = 0.0;
@@ -529,3 +637,67 @@ fn cosine_similarity(vec_a: &[f64], vec_b: &[f64]) -> f64 {
     }
     ab / (sum_a.sqrt() * sum_b.sqrt())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(summary: &str, embedding: Option<Vec<f64>>) -> MemoryNote {
+        MemoryNote { id: new_note_id(), raw: String::new(), summary: summary.to_string(), reserved: false, namespace: GLOBAL_NAMESPACE.to_string(), tags: vec![], embedding }
+    }
+
+    fn memory(notes: Vec<MemoryNote>) -> SimpleMemory {
+        SimpleMemory { notes, max_notes: 10, path: String::new(), save: false }
+    }
+
+    #[test]
+    fn test_bm25_ordering() {
+        let docs = ["rust async runtime tokio", "rust borrow checker", "python async", "java spring"].iter().map(|d| tokenize(d)).collect::<Vec<_>>();
+        let scores = bm25_scores(&tokenize("rust async"), &docs);
+        assert!(scores[0] > scores[2]); // 两个词都匹配
+        assert!(scores[2] > scores[1]); // 都只匹配一个词，文档越短分数越高
+        assert!(scores[1] > 0.0);
+        assert_eq!(scores[3], 0.0);
+        assert!(bm25_scores(&tokenize("rust"), &[]).is_empty());
+    }
+
+    #[test]
+    fn test_rrf_single_signal() {
+        assert_eq!(rank_scores(&[Some(0.5), None, Some(0.9)]), vec![Some(2), None, Some(1)]);
+        // 只有关键词或只有语义分数时，按该分数的排名融合
+        assert_eq!(fuse_scores(&[1.0, 0.5, 0.0], &[None, None, None]), vec![1.0 / (RRF_K + 1.0), 1.0 / (RRF_K + 2.0), 0.0]);
+        assert_eq!(fuse_scores(&[0.0, 0.0], &[Some(0.4), Some(0.9)]), vec![1.0 / (RRF_K + 2.0), 1.0 / (RRF_K + 1.0)]);
+        // 两种分数都排第一
+        assert_eq!(fuse_scores(&[1.0], &[Some(0.9)]), vec![2.0 / (RRF_K + 1.0)]);
+    }
+
+    #[test]
+    fn test_recency_tie_break() {
+        let m = memory(vec![note("deploy with docker", None), note("deploy with docker", None), note("unrelated cooking note", None)]);
+        let hits = m.score_all("docker deploy", None, None);
+        // BM25 相同，越新的记忆融合分数越高
+        assert_eq!(hits[0].note.id, m.notes[1].id);
+        assert_eq!(hits[1].note.id, m.notes[0].id);
+        assert!(hits[0].score > hits[1].score);
+        assert_eq!(hits[2].score, 0.0);
+        // 分数都为0时越新的记忆越靠前
+        let hits = m.score_all("kubernetes", None, None);
+        assert_eq!(hits.iter().map(|h| h.note.id.clone()).collect::<Vec<_>>(), m.notes.iter().rev().map(|n| n.id.clone()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_below_thresholds_not_injected() {
+        let m = memory(vec![note("golang channels", Some(vec![0.0, 1.0])), note("prefers dark mode", Some(vec![1.0, 0.0]))]);
+        let query_embedding = vec![1.0, 0.1];
+        let hits = m.score_all("rust", Some(query_embedding.as_slice()), None);
+        let below = hits.iter().find(|h| h.note.summary == "golang channels").unwrap();
+        assert!(below.bm25 < MIN_BM25 && below.cosine.unwrap() < MIN_COSINE);
+        assert_eq!(below.score, 0.0);
+        let (prompt, injected) = m.relevant_memory_prompt("rust", Some(query_embedding), 5, &[]).unwrap();
+        assert_eq!(injected.len(), 1);
+        assert!(prompt.contains("prefers dark mode"));
+        assert!(!prompt.contains("golang channels"));
+        // 没有embedding时两条记忆都未达到阈值，不注入
+        assert!(m.relevant_memory_prompt("rust", None, 5, &[]).is_none());
+    }
+}
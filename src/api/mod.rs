@@ -4,10 +4,13 @@ pub mod parse_paras;
 pub mod info;
 pub mod error;
 pub mod prompt;
+pub mod prompt_library;
+pub mod template;
 pub mod web;
 pub mod openai;
 pub mod code;
 pub mod pdf;
+pub mod office;
 pub mod html_page;
 pub mod graph;
 pub mod ctrlc;
@@ -16,6 +19,9 @@ pub mod mcp;
 pub mod channel;
 pub mod skills;
 pub mod memory;
+pub mod knowledge;
+pub mod check;
+pub mod cli;
 #[cfg(feature = "code-completion")]
 pub mod code_completion;
 #[cfg(any(feature = "asr", feature = "asr-cuda", feature = "asr-metal"))]
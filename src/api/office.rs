@@ -0,0 +1,534 @@
+use std::fs::{File, write};
+use std::io::{self, Read};
+use std::path::Path;
+
+use calamine::{open_workbook_auto, Data, DataType as _, Reader};
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader as XmlReader,
+};
+use zip::ZipArchive;
+
+/// error: 定义的错误类型，用于错误传递
+use crate::error::MyError;
+
+/// 支持提取内容的office文档和表格格式
+pub const OFFICE_FORMATS: [&str; 9] = [".docx", ".pptx", ".odt", ".xlsx", ".xlsm", ".xls", ".ods", ".csv", ".tsv"];
+
+/// 每个表格最多显示的数据行数，超出的只在末尾说明省略的行数
+const MAX_SHEET_ROWS: usize = 200;
+
+/// docx、pptx、odt中单个xml，以及xlsx、xlsm、ods中所有文件解压后的最大字节数，避免压缩炸弹占满内存
+const MAX_XML_SIZE: u64 = 100 * 1024 * 1024;
+
+/// 是否是支持提取内容的office文档或表格
+pub fn is_office_file(name: &str) -> bool {
+    let lowercase_name = name.to_lowercase();
+    OFFICE_FORMATS.iter().any(|x| lowercase_name.ends_with(x))
+}
+
+/// 读取docx、pptx、odt文档，提取文本内容，标题和表格转为markdown
+/// 读取xlsx、xls、ods、csv、tsv表格，每个sheet转为markdown表格，并添加行列数、数值列范围等概要
+/// 提取的内容保存至同名的md文件
+pub fn extract_office_content(uuid: &str, outpath: &str, office_file: &str) -> Result<String, MyError> {
+    let office_file = format!("{}/{}/{}", outpath, uuid, office_file);
+    // 检查文件是否在服务端
+    let tmp_path = Path::new(&office_file);
+    if !(tmp_path.exists() && tmp_path.is_file()) {
+        return Err(MyError::ParaError{para: format!("no such file in server: {}", office_file)})
+    }
+
+    let content = office_to_markdown(&office_file)?;
+
+    // 保存提取的内容，文件名同原文件，只是格式后缀改为md
+    let outfile = tmp_path.with_extension("md").to_string_lossy().to_string();
+    if let Err(e) = write(&outfile, &content) {
+        return Err(MyError::WriteFileError{file: outfile, error: e})
+    }
+
+    Ok(content)
+}
+
+/// 根据后缀读取指定路径的office文档或表格，转为markdown，不保存
+pub fn office_to_markdown(office_file: &str) -> Result<String, MyError> {
+    let lowercase_name = office_file.to_lowercase();
+    if lowercase_name.ends_with(".docx") {
+        let xml = read_zip_entry(office_file, "word/document.xml", MAX_XML_SIZE)?;
+        docx_to_markdown(&xml).map_err(|e| MyError::ParseXmlError{file: office_file.to_string(), error: e})
+    } else if lowercase_name.ends_with(".pptx") {
+        pptx_to_markdown(office_file)
+    } else if lowercase_name.ends_with(".odt") {
+        let xml = read_zip_entry(office_file, "content.xml", MAX_XML_SIZE)?;
+        odt_to_markdown(&xml).map_err(|e| MyError::ParseXmlError{file: office_file.to_string(), error: e})
+    } else if lowercase_name.ends_with(".csv") || lowercase_name.ends_with(".tsv") {
+        csv_to_markdown(office_file, lowercase_name.ends_with(".tsv"))
+    } else {
+        spreadsheet_to_markdown(office_file)
+    }
+}
+
+/// 读取zip压缩包（docx、pptx、odt）中指定文件的内容，解压后超过limit字节则返回错误
+fn read_zip_entry(file: &str, entry: &str, limit: u64) -> Result<String, MyError> {
+    let f = File::open(file).map_err(|e| MyError::OpenFileError{file: file.to_string(), error: e})?;
+    let mut archive = ZipArchive::new(f).map_err(|e| MyError::ZipArchiveError{file: file.to_string(), error: e})?;
+    let zip_file = archive.by_name(entry).map_err(|e| MyError::ZipArchiveError{file: format!("{}/{}", file, entry), error: e})?;
+    let mut content: Vec<u8> = Vec::new();
+    zip_file.take(limit + 1).read_to_end(&mut content).map_err(|e| MyError::ReadFileError{file: format!("{}/{}", file, entry), error: e})?; // 不使用zip中记录的大小，可能被伪造
+    if content.len() as u64 > limit {
+        return Err(MyError::ReadFileError{file: format!("{}/{}", file, entry), error: io::Error::other(format!("decompressed size exceeds {} MB", limit / 1024 / 1024))})
+    }
+    String::from_utf8(content).map_err(|e| MyError::ReadFileError{file: format!("{}/{}", file, entry), error: io::Error::new(io::ErrorKind::InvalidData, e)})
+}
+
+/// 检查zip压缩包（xlsx、xlsm、ods）中所有文件解压后的总大小，超过limit字节则返回错误
+/// calamine会把sheet整个读入内存，打开之前先检查
+fn check_zip_size(file: &str, limit: u64) -> Result<(), MyError> {
+    let f = File::open(file).map_err(|e| MyError::OpenFileError{file: file.to_string(), error: e})?;
+    let mut archive = ZipArchive::new(f).map_err(|e| MyError::ZipArchiveError{file: file.to_string(), error: e})?;
+    let mut total: u64 = 0;
+    for i in 0..archive.len() {
+        let entry = archive.by_index_raw(i).map_err(|e| MyError::ZipArchiveError{file: file.to_string(), error: e})?;
+        total = total.saturating_add(entry.size());
+        if total > limit {
+            return Err(MyError::ReadFileError{file: file.to_string(), error: io::Error::other(format!("decompressed size exceeds {} MB", limit / 1024 / 1024))})
+        }
+    }
+    Ok(())
+}
+
+/// 获取xml元素指定属性的值
+fn attr(e: &BytesStart, key: &[u8]) -> Option<String> {
+    e.attributes().flatten().find(|a| a.key.as_ref() == key).map(|a| String::from_utf8_lossy(&a.value).to_string())
+}
+
+/// 将文档中的段落和表格拼接为markdown，docx、pptx、odt共用
+#[derive(Default)]
+struct MarkdownBuilder {
+    md:      String,                // 已生成的markdown
+    para:    String,                // 当前段落的文本
+    heading: usize,                 // 当前段落的标题级别，0表示不是标题
+    list:    usize,                 // 当前段落的列表层级，0表示不是列表
+    tables:  Vec<Vec<Vec<String>>>, // 表格栈，支持嵌套表格，每个表格是行，每行是单元格
+}
+
+impl MarkdownBuilder {
+    /// 开始新段落
+    fn start_para(&mut self) {
+        self.para.clear();
+        self.heading = 0;
+        self.list = 0;
+    }
+
+    /// 结束段落，在表格内则添加到当前单元格，否则按标题、列表或普通段落添加到markdown
+    fn end_para(&mut self) {
+        let text = self.para.trim().to_string();
+        self.para.clear();
+        if text.is_empty() {
+            return
+        }
+        if let Some(cell) = self.tables.last_mut().and_then(|t| t.last_mut()).and_then(|r| r.last_mut()) {
+            if !cell.is_empty() {
+                cell.push('\n');
+            }
+            cell.push_str(&text);
+        } else if self.heading > 0 {
+            self.close_list();
+            self.md += &format!("{} {}\n\n", "#".repeat(self.heading.min(6)), text);
+        } else if self.list > 0 {
+            self.md += &format!("{}- {}\n", "  ".repeat(self.list-1), text);
+        } else {
+            self.close_list();
+            self.md += &format!("{}\n\n", text);
+        }
+    }
+
+    /// 列表项之间只换行，列表结束后需要空一行再接段落或表格
+    fn close_list(&mut self) {
+        if self.md.ends_with('\n') && !self.md.ends_with("\n\n") {
+            self.md.push('\n');
+        }
+    }
+
+    fn start_table(&mut self) {
+        self.tables.push(vec![]);
+    }
+
+    fn start_row(&mut self) {
+        if let Some(t) = self.tables.last_mut() {
+            t.push(vec![]);
+        }
+    }
+
+    fn start_cell(&mut self) {
+        if let Some(r) = self.tables.last_mut().and_then(|t| t.last_mut()) {
+            r.push(String::new());
+        }
+    }
+
+    /// 结束表格，最外层表格转为markdown表格，嵌套的表格合并为文本放到外层单元格中
+    fn end_table(&mut self) {
+        if let Some(table) = self.tables.pop() {
+            if let Some(cell) = self.tables.last_mut().and_then(|t| t.last_mut()).and_then(|r| r.last_mut()) {
+                let text = table.iter().map(|r| r.join(" / ")).collect::<Vec<_>>().join("; ");
+                if !cell.is_empty() {
+                    cell.push('\n');
+                }
+                cell.push_str(&text);
+            } else {
+                self.close_list();
+                self.md += &markdown_table(&table, usize::MAX);
+                self.md.push('\n');
+            }
+        }
+    }
+
+    fn finish(mut self) -> String {
+        while !self.tables.is_empty() { // 文档不完整时，未结束的表格也输出
+            self.end_table();
+        }
+        self.md.trim().to_string()
+    }
+}
+
+/// docx标题样式的级别，例如`Title`、`Heading1`，中文版word标题样式id为数字，例如`1`
+fn docx_heading_level(style: &str) -> usize {
+    match style {
+        "Title" => 1,
+        "Subtitle" => 2,
+        s => s.strip_prefix("Heading").unwrap_or(s).parse::<usize>().unwrap_or(0),
+    }
+}
+
+/// docx的`word/document.xml`转为markdown
+fn docx_to_markdown(xml: &str) -> Result<String, quick_xml::Error> {
+    let mut reader = XmlReader::from_str(xml);
+    let mut builder = MarkdownBuilder::default();
+    let mut in_text = false; // 是否在`w:t`中
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => match e.name().as_ref() {
+                b"w:p" => builder.start_para(),
+                b"w:t" => in_text = true,
+                b"w:tbl" => builder.start_table(),
+                b"w:tr" => builder.start_row(),
+                b"w:tc" => builder.start_cell(),
+                b"w:numPr" => builder.list = 1,
+                _ => (),
+            },
+            Event::Empty(e) => match e.name().as_ref() {
+                b"w:pStyle" => builder.heading = attr(&e, b"w:val").map(|s| docx_heading_level(&s)).unwrap_or(0),
+                b"w:ilvl" => builder.list = attr(&e, b"w:val").and_then(|l| l.parse::<usize>().ok()).unwrap_or(0) + 1,
+                b"w:numPr" => builder.list = 1,
+                b"w:tab" => builder.para.push('\t'),
+                b"w:br" | b"w:cr" => builder.para.push('\n'),
+                _ => (),
+            },
+            Event::Text(t) if in_text => builder.para += &t.unescape().map(|s| s.to_string()).unwrap_or_else(|_| String::from_utf8_lossy(&t).to_string()),
+            Event::End(e) => match e.name().as_ref() {
+                b"w:p" => builder.end_para(),
+                b"w:t" => in_text = false,
+                b"w:tbl" => builder.end_table(),
+                _ => (),
+            },
+            Event::Eof => break,
+            _ => (),
+        }
+    }
+    Ok(builder.finish())
+}
+
+/// pptx的每个`ppt/slides/slideN.xml`按页码顺序转为markdown，每页一个二级标题，标题占位符转为三级标题
+fn pptx_to_markdown(file: &str) -> Result<String, MyError> {
+    let f = File::open(file).map_err(|e| MyError::OpenFileError{file: file.to_string(), error: e})?;
+    let archive = ZipArchive::new(f).map_err(|e| MyError::ZipArchiveError{file: file.to_string(), error: e})?;
+    let mut slides: Vec<(usize, String)> = archive.file_names()
+        .filter_map(|n| n.strip_prefix("ppt/slides/slide").and_then(|s| s.strip_suffix(".xml")).and_then(|s| s.parse::<usize>().ok()).map(|i| (i, n.to_string())))
+        .collect();
+    slides.sort_by_key(|(i, _)| *i);
+    let mut md = String::new();
+    for (i, entry) in slides {
+        let xml = read_zip_entry(file, &entry, MAX_XML_SIZE)?;
+        let slide = slide_to_markdown(&xml).map_err(|e| MyError::ParseXmlError{file: format!("{}/{}", file, entry), error: e})?;
+        md += &format!("## Slide {}\n\n{}\n\n", i, slide);
+    }
+    Ok(md.trim().to_string())
+}
+
+/// pptx单页的xml转为markdown
+fn slide_to_markdown(xml: &str) -> Result<String, quick_xml::Error> {
+    let mut reader = XmlReader::from_str(xml);
+    let mut builder = MarkdownBuilder::default();
+    let mut in_text = false; // 是否在`a:t`中
+    let mut is_title = false; // 当前形状是否是标题占位符
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => match e.name().as_ref() {
+                b"p:sp" => is_title = false,
+                b"a:p" => {
+                    builder.start_para();
+                    if is_title {
+                        builder.heading = 3;
+                    }
+                },
+                b"a:t" => in_text = true,
+                b"a:tbl" => builder.start_table(),
+                b"a:tr" => builder.start_row(),
+                b"a:tc" => builder.start_cell(),
+                _ => (),
+            },
+            Event::Empty(e) => match e.name().as_ref() {
+                b"p:ph" => is_title = matches!(attr(&e, b"type").as_deref(), Some("title") | Some("ctrTitle")),
+                b"a:br" => builder.para.push('\n'),
+                _ => (),
+            },
+            Event::Text(t) if in_text => builder.para += &t.unescape().map(|s| s.to_string()).unwrap_or_else(|_| String::from_utf8_lossy(&t).to_string()),
+            Event::End(e) => match e.name().as_ref() {
+                b"a:p" => builder.end_para(),
+                b"a:t" => in_text = false,
+                b"a:tbl" => builder.end_table(),
+                _ => (),
+            },
+            Event::Eof => break,
+            _ => (),
+        }
+    }
+    Ok(builder.finish())
+}
+
+/// odt的`content.xml`转为markdown
+fn odt_to_markdown(xml: &str) -> Result<String, quick_xml::Error> {
+    let mut reader = XmlReader::from_str(xml);
+    let mut builder = MarkdownBuilder::default();
+    let mut in_para = 0; // 是否在`text:p`或`text:h`中，可能嵌套
+    let mut list = 0; // 当前列表层级
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => match e.name().as_ref() {
+                b"text:h" => {
+                    if in_para == 0 {
+                        builder.start_para();
+                        builder.heading = attr(&e, b"text:outline-level").and_then(|l| l.parse::<usize>().ok()).unwrap_or(1);
+                    }
+                    in_para += 1;
+                },
+                b"text:p" => {
+                    if in_para == 0 {
+                        builder.start_para();
+                        builder.list = list;
+                    }
+                    in_para += 1;
+                },
+                b"text:list" => list += 1,
+                b"table:table" => builder.start_table(),
+                b"table:table-row" => builder.start_row(),
+                b"table:table-cell" | b"table:covered-table-cell" => builder.start_cell(),
+                _ => (),
+            },
+            Event::Empty(e) => match e.name().as_ref() {
+                b"text:s" => builder.para += &" ".repeat(attr(&e, b"text:c").and_then(|c| c.parse::<usize>().ok()).unwrap_or(1)),
+                b"text:tab" => builder.para.push('\t'),
+                b"text:line-break" => builder.para.push('\n'),
+                b"table:table-cell" | b"table:covered-table-cell" => builder.start_cell(), // 空单元格，重复的空单元格只记录一个
+                _ => (),
+            },
+            Event::Text(t) if in_para > 0 => builder.para += &t.unescape().map(|s| s.to_string()).unwrap_or_else(|_| String::from_utf8_lossy(&t).to_string()),
+            Event::End(e) => match e.name().as_ref() {
+                b"text:h" | b"text:p" => {
+                    in_para -= 1;
+                    if in_para == 0 {
+                        builder.end_para();
+                    }
+                },
+                b"text:list" => list -= 1,
+                b"table:table" => builder.end_table(),
+                _ => (),
+            },
+            Event::Eof => break,
+            _ => (),
+        }
+    }
+    Ok(builder.finish())
+}
+
+/// xlsx、xls、ods的每个sheet转为markdown表格，每个sheet一个二级标题
+fn spreadsheet_to_markdown(file: &str) -> Result<String, MyError> {
+    let lowercase_name = file.to_lowercase();
+    if [".xlsx", ".xlsm", ".ods"].iter().any(|x| lowercase_name.ends_with(x)) {
+        check_zip_size(file, MAX_XML_SIZE)?;
+    }
+    let mut workbook = open_workbook_auto(file).map_err(|e| MyError::ReadSpreadsheetError{file: file.to_string(), error: e})?;
+    let mut md = String::new();
+    for name in workbook.sheet_names() {
+        let range = workbook.worksheet_range(&name).map_err(|e| MyError::ReadSpreadsheetError{file: format!("{}/{}", file, name), error: e})?;
+        let rows: Vec<Vec<String>> = range.rows().map(|r| r.iter().map(cell_to_string).collect()).collect();
+        md += &format!("## {}\n\n{}\n\n", name, sheet_to_markdown(rows));
+    }
+    Ok(md.trim().to_string())
+}
+
+/// 单元格转为字符串，日期转为`年-月-日 时:分:秒`
+fn cell_to_string(cell: &Data) -> String {
+    match cell {
+        Data::DateTime(_) | Data::DateTimeIso(_) => cell.as_datetime().map(|d| d.to_string()).unwrap_or_else(|| cell.to_string()),
+        _ => cell.to_string(),
+    }
+}
+
+/// csv、tsv转为markdown表格，不是utf8的内容会有乱码
+fn csv_to_markdown(file: &str, is_tsv: bool) -> Result<String, MyError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .delimiter(if is_tsv {b'\t'} else {b','})
+        .from_path(file)
+        .map_err(|e| MyError::ReadCsvError{file: file.to_string(), error: e})?;
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    for record in reader.byte_records() {
+        let record = record.map_err(|e| MyError::ReadCsvError{file: file.to_string(), error: e})?;
+        rows.push(record.iter().map(|f| String::from_utf8_lossy(f).to_string()).collect());
+    }
+    Ok(sheet_to_markdown(rows))
+}
+
+/// 表格转为markdown，第一行作为表头，最多显示`MAX_SHEET_ROWS`行数据，前面添加概要：行列数、列名、数值列的最小值、最大值、平均值
+fn sheet_to_markdown(rows: Vec<Vec<String>>) -> String {
+    let rows = trim_table(&rows);
+    if rows.is_empty() {
+        return "(empty sheet)".to_string()
+    }
+    let header = &rows[0];
+    let data = &rows[1..];
+    let mut summary = format!("{} rows x {} columns (excluding header)\n\ncolumns: {}\n", data.len(), header.len(), header.join(", "));
+    for (i, name) in header.iter().enumerate() {
+        let values: Vec<&str> = data.iter().filter_map(|r| r.get(i)).map(|v| v.trim()).filter(|v| !v.is_empty()).collect();
+        let numbers: Vec<f64> = values.iter().filter_map(|v| v.parse::<f64>().ok()).collect();
+        if !numbers.is_empty() && numbers.len() == values.len() { // 该列非空单元格都是数值
+            let min = numbers.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = numbers.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            let mean = numbers.iter().sum::<f64>() / numbers.len() as f64;
+            summary += &format!("- {}: min {}, max {}, mean {:.4}\n", if name.is_empty() {format!("column {}", i+1)} else {name.clone()}, min, max, mean);
+        }
+    }
+    format!("{}\n{}", summary, markdown_table(&rows, MAX_SHEET_ROWS))
+}
+
+/// 去除全部为空的行，以及每行末尾的空单元格
+fn trim_table(rows: &[Vec<String>]) -> Vec<Vec<String>> {
+    rows.iter().filter_map(|r| {
+        let end = r.iter().rposition(|c| !c.trim().is_empty())?;
+        Some(r[..=end].to_vec())
+    }).collect()
+}
+
+/// 转为markdown表格，第一行作为表头，最多显示max_rows行数据
+fn markdown_table(rows: &[Vec<String>], max_rows: usize) -> String {
+    let rows = trim_table(rows);
+    let cols = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+    if cols == 0 {
+        return "".to_string()
+    }
+    let format_row = |r: &Vec<String>| -> String {
+        let cells: Vec<String> = (0..cols).map(|i| r.get(i).map(|c| c.trim().replace('|', "\\|").replace("\r\n", "<br>").replace('\n', "<br>")).unwrap_or_default()).collect();
+        format!("| {} |\n", cells.join(" | "))
+    };
+    let mut table = format_row(&rows[0]);
+    table += &format!("|{}\n", " --- |".repeat(cols));
+    for r in rows.iter().skip(1).take(max_rows) {
+        table += &format_row(r);
+    }
+    if rows.len() - 1 > max_rows {
+        table += &format!("\n... {} more rows omitted\n", rows.len() - 1 - max_rows);
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+    #[test]
+    fn test_docx_to_markdown() {
+        let xml = concat!(
+            r#"<w:document><w:body>"#,
+            r#"<w:p><w:pPr><w:pStyle w:val="Heading1"/></w:pPr><w:r><w:t>Intro</w:t></w:r></w:p>"#,
+            r#"<w:p><w:pPr><w:numPr><w:ilvl w:val="0"/><w:numId w:val="1"/></w:numPr></w:pPr><w:r><w:t>first</w:t></w:r></w:p>"#,
+            r#"<w:p><w:pPr><w:numPr><w:ilvl w:val="1"/><w:numId w:val="1"/></w:numPr></w:pPr><w:r><w:t>nested</w:t></w:r></w:p>"#,
+            r#"<w:p><w:r><w:t>Body </w:t></w:r><w:r><w:t>text</w:t></w:r></w:p>"#,
+            r#"<w:tbl>"#,
+            r#"<w:tr><w:tc><w:p><w:r><w:t>a</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>b|c</w:t></w:r></w:p></w:tc></w:tr>"#,
+            r#"<w:tr><w:tc><w:p><w:r><w:t>1</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>2</w:t></w:r></w:p></w:tc></w:tr>"#,
+            r#"</w:tbl>"#,
+            r#"</w:body></w:document>"#,
+        );
+        assert_eq!(docx_to_markdown(xml).unwrap(), "# Intro\n\n- first\n  - nested\n\nBody text\n\n| a | b\\|c |\n| --- | --- |\n| 1 | 2 |");
+    }
+
+    #[test]
+    fn test_odt_to_markdown() {
+        let xml = concat!(
+            r#"<office:document-content><office:body><office:text>"#,
+            r#"<text:h text:outline-level="2">Plan</text:h>"#,
+            r#"<text:list><text:list-item><text:p>one</text:p><text:list><text:list-item><text:p>two</text:p></text:list-item></text:list></text:list-item></text:list>"#,
+            r#"<text:p>Done<text:s text:c="2"/>now</text:p>"#,
+            r#"<table:table>"#,
+            r#"<table:table-row><table:table-cell><text:p>k</text:p></table:table-cell><table:table-cell><text:p>v</text:p></table:table-cell></table:table-row>"#,
+            r#"<table:table-row><table:table-cell><text:p>x</text:p></table:table-cell><table:table-cell/></table:table-row>"#,
+            r#"</table:table>"#,
+            r#"</office:text></office:body></office:document-content>"#,
+        );
+        assert_eq!(odt_to_markdown(xml).unwrap(), "## Plan\n\n- one\n  - two\n\nDone  now\n\n| k | v |\n| --- | --- |\n| x |  |");
+    }
+
+    #[test]
+    fn test_csv_summary() {
+        let file = std::env::temp_dir().join(format!("chatsong_office_{}.csv", std::process::id()));
+        write(&file, b"name,score,note\na,1,x\nb,3,\n,,\n").unwrap();
+        let md = csv_to_markdown(&file.to_string_lossy(), false).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert!(md.starts_with("2 rows x 3 columns (excluding header)\n\ncolumns: name, score, note\n- score: min 1, max 3, mean 2.0000\n\n"));
+        assert!(!md.contains("- note:")); // 非数值列没有范围
+        assert!(md.ends_with("| name | score | note |\n| --- | --- | --- |\n| a | 1 | x |\n| b | 3 |  |\n"));
+    }
+
+    #[test]
+    fn test_sheet_row_limit() {
+        let mut rows = vec![vec!["n".to_string()]];
+        rows.extend((1..=MAX_SHEET_ROWS+5).map(|i| vec![i.to_string()]));
+        let md = sheet_to_markdown(rows);
+        assert!(md.starts_with(&format!("{} rows x 1 columns", MAX_SHEET_ROWS+5)));
+        assert!(md.contains(&format!("| {} |\n", MAX_SHEET_ROWS)));
+        assert!(!md.contains(&format!("| {} |\n", MAX_SHEET_ROWS+1)));
+        assert!(md.ends_with("\n... 5 more rows omitted\n"));
+        assert_eq!(sheet_to_markdown(vec![vec!["".to_string()]]), "(empty sheet)");
+    }
+
+    #[test]
+    fn test_read_zip_entry_limit() {
+        let file = std::env::temp_dir().join(format!("chatsong_office_{}.docx", std::process::id()));
+        let mut zip = ZipWriter::new(File::create(&file).unwrap());
+        zip.start_file("word/document.xml", SimpleFileOptions::default().compression_method(CompressionMethod::Stored)).unwrap();
+        zip.write_all(&[b'a'; 2048]).unwrap();
+        zip.finish().unwrap();
+        let name = file.to_string_lossy().to_string();
+        assert_eq!(read_zip_entry(&name, "word/document.xml", 2048).unwrap().len(), 2048);
+        assert!(read_zip_entry(&name, "word/document.xml", 2047).is_err());
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_check_zip_size() {
+        let file = std::env::temp_dir().join(format!("chatsong_office_{}.xlsx", std::process::id()));
+        let mut zip = ZipWriter::new(File::create(&file).unwrap());
+        for entry in ["xl/workbook.xml", "xl/worksheets/sheet1.xml"] {
+            zip.start_file(entry, SimpleFileOptions::default()).unwrap();
+            zip.write_all(&[b'a'; 1024]).unwrap();
+        }
+        zip.finish().unwrap();
+        let name = file.to_string_lossy().to_string();
+        assert!(check_zip_size(&name, 2048).is_ok());
+        assert!(check_zip_size(&name, 2047).is_err()); // 单个文件没有超过，但总大小超过
+        std::fs::remove_file(&file).unwrap();
+    }
+}
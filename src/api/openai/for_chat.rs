@@ -11,10 +11,12 @@ use openai_dive::v1::{
             ChatMessage,
             ChatMessageContent,
             DeltaChatMessage,
+            ChatCompletionResponseFormat,
         },
         shared::Usage,
     },
 };
+use serde_json::json;
 use tokio::sync::mpsc::{Sender, UnboundedReceiver};
 use tokio::sync::Notify;
 //use tokio::time::{sleep, Duration};
@@ -32,6 +34,7 @@ use crate::{
     },
     api::handlers::chat::MainData,
     error::MyError,
+    tools::call_llm,
 };
 
 #[cfg(any(feature = "asr", feature = "asr-cuda", feature = "asr-metal"))]
@@ -621,3 +624,43 @@ pub fn get_print_token(usage: Usage, uuid: &str) -> Option<(u32, u32, u32)> {
         },
     }
 }
+
+/// 单次调用LLM，关闭思考，返回完整答案
+pub async fn run_single_llm(uuid: &str, content: String, api_key: String, endpoint: String, model: String) -> Result<String, MyError> {
+    // 使用api key初始化
+    let mut client = Client::new(api_key.clone());
+    client.set_base_url(&endpoint); // 从0.7.0开始舍弃了new_with_base
+    let mut para_builder = ChatCompletionParametersBuilder::default();
+    para_builder.model(&model); // 指定模型，例如：Gpt4Engine::Gpt4O.to_string()
+    para_builder.response_format(ChatCompletionResponseFormat::Text);
+    //para_builder.stream(stream); // 这里不需要设置，调用`create_stream`时会设置
+    let lowercase_model = model.to_lowercase();
+    // 关闭思考，不同模型思考的设置不同
+    if lowercase_model.starts_with("deepseek") {
+        // deepseek: https://api-docs.deepseek.com/
+        para_builder.extra_body(json!({"thinking": {"type": "disabled"}}));
+    } else if lowercase_model.starts_with("qwen") {
+        if endpoint.starts_with("http://") { // local model
+            // https://modelscope.cn/models/Qwen/Qwen3.5-397B-A17B
+            // https://modelscope.cn/models/Qwen/Qwen3.6-35B-A3B#instruct-or-non-thinking-mode
+            para_builder.extra_body(json!({"chat_template_kwargs": {"enable_thinking": false}}));
+        } else {
+            // Qwen: https://help.aliyun.com/zh/model-studio/qwen-api-via-openai-chat-completions#05cfceb898csa
+            para_builder.extra_body(json!({"enable_thinking": false}));
+        }
+    } else if lowercase_model.starts_with("kimi") {
+        // kimi: https://platform.kimi.com/docs/api/models-overview
+        para_builder.extra_body(json!({"thinking": {"type": "disabled"}}));
+    } else if lowercase_model.starts_with("glm") {
+        // glm: https://docs.bigmodel.cn/cn/guide/develop/openai/introduction
+        para_builder.extra_body(json!({"thinking": {"type": "disabled"}}));
+    } else if lowercase_model.starts_with("minimax") {
+        // minimax，目前不支持关闭thinking：https://github.com/MiniMax-AI/MiniMax-M2/issues/68
+        //para_builder.extra_body(json!({"reasoning_split": false}));
+    }
+    let summary_prompt = ChatMessage::User{
+        content: ChatMessageContent::Text(content),
+        name: None,
+    };
+    call_llm(vec![summary_prompt], uuid.to_string(), client, para_builder, &model).await
+}
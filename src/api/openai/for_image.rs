@@ -210,10 +210,12 @@ pub async fn create_edit_image(uuid: &str, uploaded_image: Option<String>, q: St
     }
 }
 
-/// 文件转base64
+/// 文件转base64，jpg格式的mime是`image/jpeg`，其他都按png
 pub fn image_to_base64_helper(file: impl AsRef<Path>) -> Result<String, MyError> {
+    let lowercase_name = file.as_ref().to_string_lossy().to_lowercase();
+    let mime = if lowercase_name.ends_with(".jpg") || lowercase_name.ends_with(".jpeg") {"image/jpeg"} else {"image/png"};
     let data: Vec<u8> = read(file)?; // 相当于`File::open`+`read_to_end`，返回`Result<Vec<u8>>`
-    Ok(format!("data:image/png;base64,{}", general_purpose::STANDARD.encode(data)))
+    Ok(format!("data:{};base64,{}", mime, general_purpose::STANDARD.encode(data)))
 }
 
 /// 图片转base64，返回base64编码的字符串
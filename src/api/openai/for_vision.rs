@@ -0,0 +1,138 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use image::{
+    codecs::jpeg::JpegEncoder,
+    imageops::FilterType,
+    DynamicImage,
+    ImageDecoder,
+    ImageFormat,
+    ImageReader,
+    metadata::Orientation,
+};
+use serde::Deserialize;
+
+/// error: 定义的错误类型，用于错误传递
+use crate::error::MyError;
+
+/// 上传后需要处理的图片格式，png和jpg保持原格式，其他格式转为jpg
+pub const IMAGE_FORMATS: [&str; 10] = [".png", ".jpg", ".jpeg", ".webp", ".gif", ".bmp", ".tif", ".tiff", ".heic", ".heif"];
+
+/// 转为jpg时的质量
+const JPEG_QUALITY: u8 = 85;
+
+/// 参数文件中每个模型的图片参数，不设置则使用默认值：最大2048x2048，按Qwen3-VL计算token
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ImageConfig {
+    pub max_width:  u32,        // 图片最大宽度，超过则等比例缩小
+    pub max_height: u32,        // 图片最大高度，超过则等比例缩小
+    pub token:      ImageToken, // 图片token数的计算方式
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        ImageConfig{max_width: 2048, max_height: 2048, token: ImageToken::Patch(32)}
+    }
+}
+
+/// 不同模型图片token数的计算方式
+#[derive(Clone, Debug, Deserialize)]
+pub enum ImageToken {
+    Patch(u32),                                // `(宽 / n) * (高 / n)`，Qwen3-VL是32，Qwen2.5-VL是28
+    Pixels(u32),                               // `宽 * 高 / n`，Claude是750
+    Tile{base: u32, per_tile: u32, size: u32}, // `base + per_tile * 切块数`，每块`size x size`，GPT-4o是`base: 85, per_tile: 170, size: 512`
+    Fixed(u32),                                // 每张图片固定token数，例如Gemini是258
+}
+
+impl ImageToken {
+    /// 根据图片宽高估算token数
+    pub fn estimate(&self, width: u32, height: u32) -> u32 {
+        match self {
+            ImageToken::Patch(n) => (width / (*n).max(1)) * (height / (*n).max(1)),
+            ImageToken::Pixels(n) => width * height / (*n).max(1),
+            ImageToken::Tile{base, per_tile, size} => base + per_tile * width.div_ceil((*size).max(1)) * height.div_ceil((*size).max(1)),
+            ImageToken::Fixed(n) => *n,
+        }
+    }
+}
+
+/// 是否是需要处理的图片格式
+pub fn is_image_file(name: &str) -> bool {
+    let lowercase_name = name.to_lowercase();
+    IMAGE_FORMATS.iter().any(|x| lowercase_name.ends_with(x))
+}
+
+/// 处理上传的图片：按EXIF方向旋转，超过最大宽高则等比例缩小，png和jpg以外的格式转为jpg
+/// 不需要处理则保留原文件，否则png覆盖原文件，其他格式保存为`原文件名.jpg`，返回(处理后的文件名, 宽, 高)
+/// 解码和缩放比较耗时，在async中需要通过`spawn_blocking`调用
+pub fn normalize_image(dir: &str, name: &str, config: &ImageConfig) -> Result<(String, u32, u32), MyError> {
+    let file = format!("{}/{}", dir, name);
+    let lowercase_name = name.to_lowercase();
+    let (mut img, format, orientation) = if lowercase_name.ends_with(".heic") || lowercase_name.ends_with(".heif") {
+        (decode_heif(&file, name)?, None, Orientation::NoTransforms) // libheif解码时已按图片中的旋转信息处理
+    } else {
+        let reader = ImageReader::open(&file).map_err(|e| MyError::OpenFileError{file: file.clone(), error: e})?
+            .with_guessed_format().map_err(|e| MyError::ReadFileError{file: file.clone(), error: e})?;
+        let format = reader.format();
+        let mut decoder = reader.into_decoder().map_err(|e| MyError::ParaError{para: format!("decode image {} error: {}", file, e)})?;
+        let orientation = decoder.orientation().map_err(|e| MyError::ParaError{para: format!("read EXIF of {} error: {}", file, e)})?;
+        (DynamicImage::from_decoder(decoder).map_err(|e| MyError::ParaError{para: format!("decode image {} error: {}", file, e)})?, format, orientation)
+    };
+    let rotated = orientation != Orientation::NoTransforms;
+    img.apply_orientation(orientation);
+    let resized = img.width() > config.max_width || img.height() > config.max_height;
+    if resized {
+        img = img.resize(config.max_width, config.max_height, FilterType::Lanczos3); // 等比例缩小到最大宽高以内
+    }
+    let keep_format = matches!(format, Some(ImageFormat::Png) | Some(ImageFormat::Jpeg));
+    if keep_format && !rotated && !resized { // 不需要处理
+        return Ok((name.to_string(), img.width(), img.height()))
+    }
+    let new_name = if format == Some(ImageFormat::Png) {
+        img.save_with_format(&file, ImageFormat::Png).map_err(|e| MyError::ParaError{para: format!("save image {} error: {}", file, e)})?;
+        name.to_string()
+    } else {
+        let new_name = if keep_format {name.to_string()} else {format!("{}.jpg", name)};
+        let new_file = format!("{}/{}", dir, new_name);
+        let writer = BufWriter::new(File::create(&new_file).map_err(|e| MyError::CreateFileError{file: new_file.clone(), error: e})?);
+        DynamicImage::ImageRgb8(img.to_rgb8()).write_with_encoder(JpegEncoder::new_with_quality(writer, JPEG_QUALITY)).map_err(|e| MyError::ParaError{para: format!("save image {} error: {}", new_file, e)})?;
+        new_name
+    };
+    Ok((new_name, img.width(), img.height()))
+}
+
+/// 使用libheif解码HEIC/HEIF图片，编译时需要指定`--features heic`，并安装libheif
+#[cfg(feature = "heic")]
+fn decode_heif(file: &str, _name: &str) -> Result<DynamicImage, MyError> {
+    use image::RgbImage;
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let error = |e: libheif_rs::HeifError| MyError::ParaError{para: format!("decode image {} error: {}", file, e)};
+    let context = HeifContext::read_from_file(file).map_err(error)?;
+    let handle = context.primary_image_handle().map_err(error)?;
+    let heif_image = LibHeif::new().decode(&handle, ColorSpace::Rgb(RgbChroma::Rgb), None).map_err(error)?;
+    let Some(plane) = heif_image.planes().interleaved else {
+        return Err(MyError::ParaError{para: format!("decode image {} error: no interleaved RGB plane", file)})
+    };
+    // 每行末尾可能有填充，按stride逐行复制
+    let row_len = plane.width as usize * 3;
+    let mut pixels = Vec::with_capacity(row_len * plane.height as usize);
+    for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        pixels.extend_from_slice(&row[..row_len]);
+    }
+    RgbImage::from_raw(plane.width, plane.height, pixels).map(DynamicImage::ImageRgb8).ok_or_else(|| MyError::ParaError{para: format!("decode image {} error: invalid size", file)})
+}
+
+/// 没有开启`heic` feature时不支持HEIC/HEIF
+#[cfg(not(feature = "heic"))]
+fn decode_heif(_file: &str, name: &str) -> Result<DynamicImage, MyError> {
+    Err(MyError::ParaError{para: format!("{}: HEIC/HEIF needs chatsong compiled with `--features heic`, or convert it to JPEG or PNG before uploading", name)})
+}
+
+/// 读取图片宽高，根据模型的图片参数估算token数
+pub fn estimate_image_token(file: impl AsRef<Path>, config: &ImageConfig) -> Result<u32, MyError> {
+    let (width, height) = image::image_dimensions(&file).map_err(|e| MyError::ParaError{para: format!("read image {} error: {}", file.as_ref().display(), e)})?;
+    Ok(config.token.estimate(width, height))
+}
@@ -4,3 +4,4 @@ pub mod for_speech;
 pub mod for_tool;
 pub mod for_transcription;
 pub mod for_translation;
+pub mod for_vision;
@@ -3,13 +3,14 @@ use std::env::{current_dir, current_exe};
 use std::fs::{write, read_to_string, create_dir_all, remove_dir_all}; // remove_dir只删除空文件夹
 use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::sync::{Arc, RwLock};
 use std::thread;
 
 use argh::FromArgs;
 use chrono::NaiveDateTime;
 use once_cell::sync::Lazy;
 use ron::de::from_str;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use time::Duration;
 
 /// token: 编码类型，返回CoreBPE对象
@@ -17,6 +18,8 @@ use time::Duration;
 /// error: 定义的错误类型，用于错误传递
 use crate::{
     prompt::create_prompt,
+    prompt_library::prompt_option,
+    openai::for_vision::ImageConfig,
     error::MyError,
     tools::{
         Tools,
@@ -28,6 +31,12 @@ use crate::{
             SingleExternalTool,
             ExternalTools,
         },
+        http_tools::{
+            SingleHttpTool,
+            HttpTools,
+        },
+        openapi_tools::OpenApiSpec,
+        command_policy::CommandPolicy,
     },
     mcp::{
         McpServers,
@@ -38,6 +47,7 @@ use crate::{
     },
     skills::{Skills, SkillManager},
     channel::Channel,
+    check::check_config,
 };
 
 /// 全局变量，可以修改，存储解析的命令行参数，在解析命令行参数时初始化
@@ -153,12 +163,176 @@ struct Paras {
     /// output path, default: ./chat-log
     #[argh(option, short = 'o')]
     outpath: Option<String>,
+
+    #[argh(subcommand)]
+    command: Option<SubCommand>,
+}
+
+/// 子命令，不指定则开启服务
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+pub enum SubCommand {
+    Check(CheckCmd),
+    Chat(ChatCmd),
+    Ask(AskCmd),
+    Kb(KbCmd),
+    Memory(MemoryCmd),
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "check")]
+/// check config file, external tools, MCP servers and skills, exit non-zero on problems
+pub struct CheckCmd {}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "chat")]
+/// chat in terminal, use the same config, tools, skills, MCP servers and chat logs as the web page
+pub struct ChatCmd {
+    /// model name, default: the default model in config file
+    #[argh(option, short = 'm')]
+    pub model: Option<String>,
+
+    /// tools, same as the value of the tools pulldown: select_all_tools, select_all_built_in, select_all_external, select_all_http, select_all_mcp, built_in_group_xxx, http_group_xxx, mcp_server_xxx, or a single tool name
+    #[argh(option, short = 't')]
+    pub tools: Option<String>,
+
+    /// skills, same as the value of the skills pulldown: select_all_available_skills, skill_group_xxx, available-skill-xxx
+    #[argh(option, short = 's')]
+    pub skills: Option<String>,
+
+    /// prompt name or index used to start a new chat, see the prompt pulldown
+    #[argh(option, short = 'p')]
+    pub prompt: Option<String>,
+
+    /// continue the chat of the specified uuid in the output path
+    #[argh(option, short = 'u')]
+    pub uuid: Option<String>,
+
+    /// use plan mode when tools are selected
+    #[argh(switch)]
+    pub plan: bool,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "ask")]
+/// ask once without interaction, the question is the arguments and/or stdin, print the answer to stdout
+/// exit code: 0 ok, 1 invalid arguments, 2 api error, 3 tool error, 4 tool call denied, 5 config error, 6 file read/write error, 7 internal error
+pub struct AskCmd {
+    /// question, appended before the content of stdin
+    #[argh(positional)]
+    pub question: Vec<String>,
+
+    /// model name, default: the default model in config file
+    #[argh(option, short = 'm')]
+    pub model: Option<String>,
+
+    /// tools, same as `chat -t`, also support built-in group name, e.g. "file system"
+    #[argh(option, short = 't')]
+    pub tools: Option<String>,
+
+    /// skills, same as `chat -s`
+    #[argh(option, short = 's')]
+    pub skills: Option<String>,
+
+    /// prompt name or index, see the prompt pulldown
+    #[argh(option, short = 'p')]
+    pub prompt: Option<String>,
+
+    /// attach file, can be used multiple times, same as uploading files on the web page
+    #[argh(option, short = 'a')]
+    pub attach: Vec<String>,
+
+    /// template variable key=value used by {{key}} in the prompt and question, can be used multiple times
+    #[argh(option)]
+    pub var: Vec<String>,
+
+    /// decision when a tool asks for approval: allow, deny, skip, default: deny
+    #[argh(option, default = "String::from(\"deny\")")]
+    pub approval: String,
+
+    /// approval policy file (ron) with allow, deny, skip tool names and default, overrides --approval
+    #[argh(option)]
+    pub policy: Option<String>,
+
+    /// print json with answer, usage and tool approvals instead of plain text
+    #[argh(switch)]
+    pub json: bool,
+
+    /// save into the conversation of this name, continue it if exists, default: not saved
+    #[argh(option, short = 'n')]
+    pub name: Option<String>,
+
+    /// knowledge bases used to answer, multiple names separated by commas, see `kb`
+    #[argh(option, short = 'k')]
+    pub knowledge: Option<String>,
+
+    /// use plan mode when tools are selected
+    #[argh(switch)]
+    pub plan: bool,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "kb")]
+/// manage local knowledge bases: list all bases without name, create a base or add/remove directories and files, then reindex changed files
+pub struct KbCmd {
+    /// knowledge base name
+    #[argh(positional)]
+    pub name: Option<String>,
+
+    /// add a directory or file (respect .gitignore) to the knowledge base, create it if not exists, can be used multiple times
+    #[argh(option, short = 'a')]
+    pub add: Vec<String>,
+
+    /// remove a directory or file from the knowledge base, can be used multiple times
+    #[argh(option, short = 'r')]
+    pub remove: Vec<String>,
+
+    /// delete the knowledge base and its uploaded files
+    #[argh(switch)]
+    pub delete: bool,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "memory")]
+/// evaluate memory retrieval: print which memories would be injected for the query, with BM25, embedding similarity and fused scores
+pub struct MemoryCmd {
+    /// query used to retrieve memories
+    #[argh(positional)]
+    pub query: Vec<String>,
+
+    /// memory store: local (memory.json), old (memory_old.json) or a chat uuid, default: local
+    #[argh(option, default = "String::from(\"local\")")]
+    pub store: String,
+
+    /// memory namespaces of the chat, multiple names separated by commas, default: only global
+    #[argh(option, short = 'n')]
+    pub namespace: Option<String>,
+
+    /// max number of injected memories, default: 10
+    #[argh(option, short = 'l', default = "10")]
+    pub limit: usize,
+
+    /// also print memories below the threshold
+    #[argh(switch)]
+    pub all: bool,
+}
+
+/// config.txt中可以热重载的部分：Api、prompts、external_tools、http_tools、skills、mcp_servers、command_policy
+/// 重载时整体替换，正在进行的请求持有旧的Arc，不受影响
+pub struct ConfigSnapshot {
+    pub api:         Api,                         // 各api的信息
+    pub prompt:      HashMap<usize, Prompt>,      // 存储prompt
+    pub tools:       Tools,                       // all tools
+    pub mcp_servers: McpServers,                  // mcp servers
+    pub skills:      Skills,                      // skills
+    pub command_policy: Arc<CommandPolicy>,       // 命令策略，没有指定策略文件则为空，与tools共用
 }
 
 /// 存储解析后的命令行参数
 ///#[derive(Debug, Default)]
 pub struct ParsedParas {
-    pub api:          Api,                         // 各api的信息
+    pub config_file:  PathBuf,                     // 参数文件路径，重载时重新读取
+    pub snapshot:     RwLock<Arc<ConfigSnapshot>>, // 可热重载的配置，通过`PARAS.snapshot()`获取当前配置
     pub addr:         [u8; 4],                     // 要监听的地址，默认127.0.0.1，解析为[127, 0, 0, 1]
     pub addr_str:     String,                      // 要监听的地址，默认127.0.0.1
     pub port:         u16,                         // 要监听的端口，默认8080
@@ -166,7 +340,6 @@ pub struct ParsedParas {
     pub search_key:   String,                      // 搜索api的key，去google开启并免费获取，每天免费100次搜索，使用google api进行搜索时要用，可以输入密码使用srx的search engine key
     pub channels:     Vec<Channel>,                // 要连接的机器人，当前只支持discord，需要bot的token和guild-id，多个channel`::`间隔，示例：`-C discord:token:guild_id`
     pub allowed_path: Vec<(PathBuf, PathBuf)>,     // allowed path (absolute path (may be not exist), normalized path) for tools, multiple paths separated by commas, default: ./
-    pub prompt:       HashMap<usize, [String; 2]>, // 存储prompt
     pub graph:        String,                      // 图文件，默认在指定输出路径下搜索最新的“时间戳.graph”，指定的文件不存在或没有搜索到则创建空图结构，每次使用`ctrl-c`停止服务时程序会自动保存图文件
     pub maxage:       Duration,                    // cookie过期时间，默认1DAY，支持的单位：SECOND、MINUTE、HOUR、DAY、WEEK
     pub share:        bool,                        // 用户A将自己的uuid-a分享给用户B，用户B将自己的uuid-b与uuid-a建立间接关系（用户B在uuid-b页面左侧“uuid”中输入uuid-a），如果使用该参数，此时用户A可以看到用户B的uuid-b，如果不使用该参数，则用户A看不到用户B的uuid-b，即使用该参数则间接关系是双向的（互相可以看到建立间接关系的uuid-a和uuid-b），不使用该参数则间接关系是单向的（用户B可以看到uuid-a但用户A看不到uuid-b）
@@ -187,17 +360,114 @@ pub struct ParsedParas {
     #[cfg(any(feature = "asr", feature = "asr-cuda", feature = "asr-metal"))]
     pub stop_words:       Vec<String>,                 // 语音asr结束词
     pub outpath:      String,                      // 输出结果路径，不存在则创建，已存在则删除其中的空uuid文件夹，默认./chat-log，不需要加上`/`或`\`后缀（加上了会自动去除），保存chat记录、生成的图片、音频等
-    pub tools:        Tools,                       // all tools
-    pub mcp_servers:  McpServers,                  // mcp servers
     pub bgc:          String,                      // 页面背景色
-    pub skills:       Skills,                      // skills
+    pub skills_path:  Option<String>,              // 命令行`-S`指定的skills路径，优先于参数文件中的skills_path，重载时使用
     pub memory_dir:   String,                      // memory.json 存储路径，默认存储在-o指定的输出路径下
+    pub upload_limit: UploadLimit,                 // 上传文件的大小限制
+    pub memory:       MemoryConfig,                // 自动提取和整理记忆
+    pub command_limit: CommandLimit,               // 运行命令的工具的超时和输出限制
+    pub sandbox:      SandboxConfig,               // 在Linux沙箱中运行命令的工具
+    pub approval_timeout: ApprovalTimeout,         // 等待确认的超时和超时后的默认操作
+    pub parallel_tools: ParallelTools,             // 并发运行只读的工具调用
+    pub command:      Option<SubCommand>,          // 子命令，None表示开启服务
+}
+
+impl ParsedParas {
+    /// 获取当前配置，返回Arc，调用者在整个请求期间持有，重载不会影响正在进行的请求
+    pub fn snapshot(&self) -> Arc<ConfigSnapshot> {
+        self.snapshot.read().unwrap().clone()
+    }
+
+    /// 重新读取参数文件，重建Api、prompts、external_tools、http_tools、skills、mcp_servers，重新读取命令策略文件
+    /// 全部解析成功后才替换，任何一步出错都保留旧配置并返回错误
+    /// 未改变的工具和mcp server沿用旧id，已打开的页面和定时任务仍然可用
+    /// 其他参数（地址、端口、输出路径等）需要重启服务才能生效
+    pub async fn reload(&self) -> Result<String, MyError> {
+        let (api, other_para) = Api::new(&self.config_file)?;
+        let old = self.snapshot();
+        let stdio = StdIoServers::new(other_para.mcp_servers, Some(&old.mcp_servers)).await?;
+        let skills_path = match &self.skills_path {
+            Some(s) => Some(s.clone()),
+            None => other_para.skills_path,
+        };
+        let new = build_snapshot(api, other_para.prompt, other_para.external_tools, other_para.http_tools, stdio, skills_path, other_para.command_policy, self.english, Some(&old))?;
+        let summary = format!(
+            "reload {} done: {} models, {} prompts, {} external tools, {} HTTP tools, {} MCP tools, {} skills",
+            self.config_file.display(),
+            new.api.models.len(),
+            new.prompt.len(),
+            new.tools.external_num(),
+            new.tools.http_num(),
+            new.mcp_servers.tool_num(),
+            new.skills.available.len(),
+        );
+        *self.snapshot.write().unwrap() = Arc::new(new);
+        Ok(summary)
+    }
+}
+
+/// 创建可热重载的配置，old是重载前的配置，用于沿用未改变的工具id
+fn build_snapshot(api: Api, prompt: HashMap<usize, Prompt>, external_tools: ExternalTools, http_tools: HttpTools, stdio: StdIoServers, skills_path: Option<String>, command_policy: Option<String>, english: bool, old: Option<&ConfigSnapshot>) -> Result<ConfigSnapshot, MyError> {
+    let command_policy = Arc::new(match command_policy { // 命令策略
+        Some(f) => CommandPolicy::from_file(&f)?,
+        None => CommandPolicy::default(),
+    });
+    Ok(ConfigSnapshot {
+        api,
+        prompt: if prompt.len() == 0 {
+            create_prompt().into_iter().map(|(i, [name, content])| (i, Prompt::new(name, content))).collect() // 参数文件没指定prompt，则使用默认prompt，使用索引获取
+        } else {
+            prompt
+        },
+        tools: Tools::new(external_tools, http_tools, command_policy.clone(), english, old.map(|o| &o.tools))?, // all tools
+        mcp_servers: McpServers::new(stdio, english), // mcp servers
+        skills: get_skills(skills_path, english), // skills
+        command_policy,
+    })
+}
+
+/// `chatsong check`逐项检查后，按启动服务的流程解析参数文件并创建配置，确保检查通过的参数文件可以启动服务
+/// mcp server已逐个检查过，这里不启动
+pub fn try_load_config(config_file: &Path, skills_path: Option<String>) -> Result<(), MyError> {
+    let (api, other_para) = Api::new(config_file)?;
+    let skills_path = skills_path.or(other_para.skills_path);
+    let stdio = StdIoServers{id_map: HashMap::new(), tools: Vec::new()};
+    build_snapshot(api, other_para.prompt, other_para.external_tools, other_para.http_tools, stdio, skills_path, other_para.command_policy, false, None)?;
+    Ok(())
+}
+
+/// 根据skills路径获取所有skills
+fn get_skills(skills_path: Option<String>, english: bool) -> Skills {
+    if let Some(s) = skills_path {
+        let p = PathBuf::from(&s);
+        let skill_manager = SkillManager::from_skills_dir(p.clone());
+        let (available, unavailable, html) = if p.exists() && p.is_dir() {
+            let (available, unavailable) = skill_manager.discover_skills();
+            let html = skill_manager.html_dropdown(&available, &unavailable, english);
+            (available, unavailable, html)
+        } else {
+            (Vec::new(), Vec::new(), String::new())
+        };
+        Skills{skill_manager, available, unavailable, html}
+    } else {
+        Skills{
+            skill_manager: SkillManager::from_skills_dir(PathBuf::from("./skills")),
+            available: Vec::new(),
+            unavailable: Vec::new(),
+            html: String::new(),
+        }
+    }
 }
 
 /// 解析参数
 pub fn parse_para() -> Result<ParsedParas, MyError> {
     let para: Paras = argh::from_env();
-    let (api, other_para) = Api::new(para.config)?;
+    // `chatsong check`只检查参数文件，不开启服务，参数文件有错误时也要能逐项报告，因此在解析参数文件之前执行
+    if let Some(SubCommand::Check(_)) = para.command {
+        exit(check_config(para.config, para.skills));
+    }
+    let config_file = get_config_file(para.config)?;
+    let (api, other_para) = Api::new(&config_file)?;
     let english = if para.english { // 是否展示英文界面，不指定则展示中文界面
         true
     } else {
@@ -217,8 +487,25 @@ pub fn parse_para() -> Result<ParsedParas, MyError> {
             }
         },
     };
+    // get mcp servers
+    let stdio = {
+        let servers = other_para.mcp_servers;
+        let handle = thread::spawn(|| {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            rt.block_on(async {
+                StdIoServers::new(servers, None).await
+            })
+        });
+        handle.join().unwrap()
+    }?;
+    let skills_path = match &para.skills {
+        Some(s) => Some(s.clone()),
+        None => other_para.skills_path,
+    };
+    let snapshot = build_snapshot(api, other_para.prompt, other_para.external_tools, other_para.http_tools, stdio, skills_path, other_para.command_policy, english, None)?;
     let out: ParsedParas = ParsedParas{
-        api: api,
+        config_file,
+        snapshot: RwLock::new(Arc::new(snapshot)),
         addr: match &para.addr { // 要监听的地址，默认127.0.0.1，解析为[127, 0, 0, 1]
             Some(a) => get_addr(a)?,
             None => {
@@ -283,11 +570,6 @@ pub fn parse_para() -> Result<ParsedParas, MyError> {
                 }
             },
         },
-        prompt: if other_para.prompt.len() == 0 {
-            create_prompt() // 参数文件没指定prompt，则使用默认prompt，使用索引获取
-        } else {
-            other_para.prompt
-        },
         graph: match para.graph { // 图文件，默认在指定输出路径下搜索最新的“时间戳.graph”，指定的文件不存在或没有搜索到则创建空图结构，每次使用`ctrl-c`停止服务时程序会自动保存图文件
             Some(g) => g,
             None => "".to_string(),
@@ -350,36 +632,6 @@ pub fn parse_para() -> Result<ParsedParas, MyError> {
             None => vec!["结束".to_string(), "stop".to_string()],
         },
         outpath: outpath.clone(), // 输出结果路径，不存在则创建，已存在则删除其中的空uuid文件夹，默认./chat-log，不需要加上`/`或`\`后缀（加上了会自动去除），保存chat记录、生成的图片、音频等
-        tools: Tools::new(other_para.external_tools, english)?, // all tools
-        mcp_servers: McpServers::new(other_para.mcp_servers, english), // mcp servers
-        skills: {
-            let skills_path = match para.skills {
-                Some(s) => Some(PathBuf::from(&s)),
-                None => if let Some(s) = other_para.skills_path {
-                    Some(PathBuf::from(&s))
-                } else {
-                    None
-                },
-            };
-            if let Some(p) = skills_path {
-                let skill_manager = SkillManager::from_skills_dir(p.clone());
-                let (available, unavailable, html) = if p.exists() && p.is_dir() {
-                    let (available, unavailable) = skill_manager.discover_skills();
-                    let html = skill_manager.html_dropdown(&available, &unavailable, english);
-                    (available, unavailable, html)
-                } else {
-                    (Vec::new(), Vec::new(), String::new())
-                };
-                Skills{skill_manager, available, unavailable, html}
-            } else {
-                Skills{
-                    skill_manager: SkillManager::from_skills_dir(PathBuf::from("./skills")),
-                    available: Vec::new(),
-                    unavailable: Vec::new(),
-                    html: String::new(),
-                }
-            }
-        },
         bgc: match para.bgc { // 页面背景色
             Some(b) => get_bg_color(&b),
             None => if other_para.bgc.is_empty() {
@@ -388,6 +640,7 @@ pub fn parse_para() -> Result<ParsedParas, MyError> {
                 get_bg_color(&other_para.bgc)
             },
         },
+        skills_path: para.skills, // 命令行`-S`指定的skills路径
         memory_dir: match para.memory_dir { // memory.json 存储路径，默认存储在-o指定的输出路径下
             Some(m) => get_outpath(&m),
             None => if let Some(m) = other_para.memory_path {
@@ -396,6 +649,13 @@ pub fn parse_para() -> Result<ParsedParas, MyError> {
                 outpath
             },
         },
+        upload_limit: other_para.upload_limit, // 上传文件的大小限制
+        memory: other_para.memory, // 自动提取和整理记忆
+        command_limit: other_para.command_limit, // 运行命令的工具的超时和输出限制
+        sandbox: other_para.sandbox, // 在Linux沙箱中运行命令的工具
+        approval_timeout: other_para.approval_timeout, // 等待确认的超时和超时后的默认操作
+        parallel_tools: other_para.parallel_tools, // 并发运行只读的工具调用
+        command: para.command, // 子命令，None表示开启服务
     };
     // 输出路径不存在则创建，已存在则删除其中的空uuid文件夹
     let tmp_outpath = Path::new(&out.outpath);
@@ -623,6 +883,40 @@ fn file_name_timestamp_txt_html(name: &str) -> bool {
     }
 }
 
+/// 获取参数文件，先在当前路径下检查是否有config.txt，如果没有，再去程序所在路径下检查是否有config.txt，还没有则在当前路径下生成一个模板config.txt，供用户修改
+pub fn get_config_file(config_file: Option<String>) -> Result<PathBuf, MyError> {
+    Ok(match config_file {
+        Some(c) => PathBuf::from(c),
+        None => { // 没有指定参数文件
+            let tmp_config = Path::new("config.txt");
+            if !(tmp_config.exists() && tmp_config.is_file()) { // 当前路径下没有config.txt
+                let cfg = match current_exe() { // 检查程序所在路径下是否有config.txt
+                    Ok(exe_path) => {
+                        let cfg_path = exe_path.with_file_name("config.txt"); // 将路径的程序名替换为config.txt
+                        if cfg_path.exists() && cfg_path.is_file() {
+                            Some(cfg_path)
+                        } else {
+                            None
+                        }
+                    }
+                    Err(_) => None,
+                };
+                match cfg {
+                    Some(c) => c,
+                    None => { // 在当前路径下生成一个示例模板，用户基于此进行修改
+                        if let Err(e) = write("config.txt", include_str!("../../config_template.txt")) {
+                            return Err(MyError::WriteFileError{file: "./config.txt".to_string(), error: e})
+                        }
+                        return Err(MyError::ParaError{para: "The config.txt is missing, please revise the generated config.txt".to_string()})
+                    },
+                }
+            } else { // 当前路径下有config.txt，则使用该参数文件
+                PathBuf::from("config.txt")
+            }
+        },
+    })
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct Model {
     pub name:        String, // 模型名称，例如："deepseek"
@@ -631,6 +925,8 @@ pub struct Model {
     pub group:       String, // 模型分组，例如："DeepSeek"，将相同组的模型相邻放置，下拉时会按照组分开
     pub is_default:  bool,   // 是否将该模型作为默认模型
     pub is_cot:      bool,   // 是否是思维链模型
+    #[serde(default)]
+    pub image:       Option<ImageConfig>, // 图片最大宽高和token计算方式，不设置则使用默认值
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -641,34 +937,150 @@ pub struct Config {
     pub models:   Vec<Model>, // 所有模型
 }
 
-#[derive(Debug, Deserialize)]
-struct Prompt {
-    name:    String, // prompt名称
-    content: String, // prompt内容
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Prompt {
+    pub name:    String,         // prompt名称
+    pub content: String,         // prompt内容
+    #[serde(default)]
+    pub tags:    Vec<String>,    // 标签，用于分类、筛选和导出
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model:   Option<String>, // 默认模型名称，页面选择该prompt时切换到该模型
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools:   Option<String>, // 默认tools，与tools下拉选项的值相同，页面选择该prompt时切换到该tools
+}
+
+impl Prompt {
+    /// 只有名称和内容的prompt
+    pub fn new(name: String, content: String) -> Self {
+        Prompt{name, content, tags: vec![], model: None, tools: None}
+    }
+}
+
+/// 上传文件的大小限制，单位MB
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct UploadLimit {
+    pub file_mb: u64, // 单个文件最大MB，默认50
+    pub uuid_mb: u64, // 每个对话（uuid文件夹）最多MB，默认500
+}
+
+impl Default for UploadLimit {
+    fn default() -> Self {
+        UploadLimit{file_mb: 50, uuid_mb: 500}
+    }
+}
+
+/// 自动提取和整理记忆，默认都不开启
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct MemoryConfig {
+    pub auto_turns:        usize,          // 对话每新增N轮问答自动提取一次记忆，0不开启
+    pub auto_idle_min:     u64,            // 对话超过N分钟没有新信息视为结束，自动提取还未提取记忆的内容，0不开启
+    pub consolidate_hours: u64,            // 每N小时用LLM整理一次记忆：合并重叠的记忆、解决矛盾（新的偏好优先）、改写记忆，0不开启
+    pub model:             Option<String>, // 自动提取和整理记忆使用的模型名称，不指定则使用默认模型
+}
+
+/// 等待确认工具调用的超时，超时没人回答则按默认操作处理：deny（拒绝，回答终止）、skip（跳过，告知模型未执行）、allow（允许）
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ApprovalTimeout {
+    pub secs:           u64,    // 超时秒数，0表示一直等待，默认600
+    pub default_action: String, // 超时后的默认操作，默认deny
+}
+
+impl Default for ApprovalTimeout {
+    fn default() -> Self {
+        ApprovalTimeout{secs: 600, default_action: "deny".to_string()}
+    }
+}
+
+/// 模型一次返回多个工具调用时，连续的只读且不需要确认的调用并发运行
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ParallelTools {
+    pub max_concurrent: usize, // 最多同时运行的只读工具调用数，0或1表示依次运行，默认4
+}
+
+impl Default for ParallelTools {
+    fn default() -> Self {
+        ParallelTools{max_concurrent: 4}
+    }
+}
+
+/// 运行命令的工具（run_command、run_script、外部工具）的超时和输出限制
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct CommandLimit {
+    pub timeout_secs:  u64,                  // 超时秒数，超时则结束整个进程组，0不限制，默认300
+    pub max_output_kb: usize,                // stdout和stderr各自最多保留的KB，超出则保留开头和结尾各一半，0不限制，默认32
+    pub timeouts:      HashMap<String, u64>, // 指定工具的超时秒数，key为工具名称，例如：{"run_script": 600}
+}
+
+impl Default for CommandLimit {
+    fn default() -> Self {
+        CommandLimit{timeout_secs: 300, max_output_kb: 32, timeouts: HashMap::new()}
+    }
+}
+
+/// 在Linux沙箱中运行命令的工具（run_command、run_script、外部工具）：根目录只读，只能写入allowed_path，默认断网，限制资源
+/// `tools`和`users`都为空则不使用沙箱
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct SandboxConfig {
+    pub tools:     Vec<String>, // 总在沙箱中运行的工具名称，例如：["run_command", "run_script"]
+    pub users:     Vec<String>, // 这些用户调用的命令都在沙箱中运行：`*`所有用户、`remote`非本机和局域网、`local`本机和局域网、`cli`命令行，或客户端ip
+    pub network:   bool,        // 沙箱中是否可以联网，默认false
+    pub cpu_secs:  u64,         // CPU时间上限（秒），0不限制，默认300
+    pub memory_mb: u64,         // 数据段内存上限（MB，RLIMIT_DATA，堆和匿名映射），0不限制，默认4096
+    pub max_procs: u64,         // 进程数上限，统计的是运行chatsong的系统用户的所有进程，0不限制，默认0
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        SandboxConfig{tools: vec![], users: vec![], network: false, cpu_secs: 300, memory_mb: 4096, max_procs: 0}
+    }
 }
 
 #[derive(Deserialize)]
-struct Para {
-    ip_address:        String,                  // 要监听的地址，默认127.0.0.1
-    port:              u16,                     // 要监听的端口，默认8080
-    google_engine_key: String,                  // 搜索引擎的key，去google开启并免费获取，使用google api进行搜索时要用，可以输入密码使用srx的search engine key
-    google_search_key: String,                  // 搜索api的key，去google开启并免费获取，每天免费100次搜索，使用google api进行搜索时要用，可以输入密码使用srx的search engine key
-    allowed_path:      String,                  // allowed path for tools, multiple paths separated by commas, default: ./
-    maxage:            String,                  // cookie过期时间，默认1DAY，支持的单位：SECOND、MINUTE、HOUR、DAY、WEEK
-    show_english:      bool,                    // true展示英文界面，false展示中文界面
+pub struct Para {
+    pub ip_address:        String,                  // 要监听的地址，默认127.0.0.1
+    pub port:              u16,                     // 要监听的端口，默认8080
+    pub google_engine_key: String,                  // 搜索引擎的key，去google开启并免费获取，使用google api进行搜索时要用，可以输入密码使用srx的search engine key
+    pub google_search_key: String,                  // 搜索api的key，去google开启并免费获取，每天免费100次搜索，使用google api进行搜索时要用，可以输入密码使用srx的search engine key
+    pub allowed_path:      String,                  // allowed path for tools, multiple paths separated by commas, default: ./
+    pub maxage:            String,                  // cookie过期时间，默认1DAY，支持的单位：SECOND、MINUTE、HOUR、DAY、WEEK
+    pub show_english:      bool,                    // true展示英文界面，false展示中文界面
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    skills_path:       Option<String>,          // skills路径
+    pub skills_path:       Option<String>,          // skills路径
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    memory_path:       Option<String>,          // memory路径
-    bgc:               String,                  // 页面背景色
-    outpath:           String,                  // 问答结果输出路径
-    model_config:      Vec<Config>,             // 模型参数
+    pub memory_path:       Option<String>,          // memory路径
+    #[serde(default)]
+    pub upload_limit:      UploadLimit,             // 上传文件的大小限制
+    #[serde(default)]
+    pub memory:            MemoryConfig,            // 自动提取和整理记忆
+    #[serde(default)]
+    pub command_limit:     CommandLimit,            // 运行命令的工具的超时和输出限制
+    #[serde(default)]
+    pub sandbox:           SandboxConfig,           // 在Linux沙箱中运行命令的工具
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command_policy:    Option<String>,          // 命令策略文件，自动允许、总是拒绝、总是确认的命令和MCP工具
+    #[serde(default)]
+    pub approval_timeout:  ApprovalTimeout,         // 等待确认的超时和超时后的默认操作
+    #[serde(default)]
+    pub parallel_tools:    ParallelTools,           // 并发运行只读的工具调用
+    pub bgc:               String,                  // 页面背景色
+    pub outpath:           String,                  // 问答结果输出路径
+    pub model_config:      Vec<Config>,             // 模型参数
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub prompts:           Vec<Prompt>,             // prompt
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
-    prompts:           Vec<Prompt>,             // prompt
+    pub external_tools:    Vec<SingleExternalTool>, // external tools
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
-    external_tools:    Vec<SingleExternalTool>, // external tools
+    pub http_tools:        Vec<SingleHttpTool>,     // HTTP tools
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
-    mcp_servers:       Vec<StdIoServer>,        // mcp servers, currently only support stdio, not http
+    pub openapi_tools:     Vec<OpenApiSpec>,        // OpenAPI specs, each operation is a HTTP tool
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mcp_servers:       Vec<StdIoServer>,        // mcp servers, currently only support stdio, not http
 }
 
 //#[derive(Deserialize)]
@@ -682,11 +1094,19 @@ struct OptherPara {
     show_english:      bool,                        // true展示英文界面，false展示中文界面
     skills_path:       Option<String>,              // skills路径
     memory_path:       Option<String>,              // memory路径
+    upload_limit:      UploadLimit,                 // 上传文件的大小限制
+    memory:            MemoryConfig,                // 自动提取和整理记忆
+    command_limit:     CommandLimit,                // 运行命令的工具的超时和输出限制
+    sandbox:           SandboxConfig,               // 在Linux沙箱中运行命令的工具
+    command_policy:    Option<String>,              // 命令策略文件，自动允许、总是拒绝、总是确认的命令和MCP工具
+    approval_timeout:  ApprovalTimeout,             // 等待确认的超时和超时后的默认操作
+    parallel_tools:    ParallelTools,               // 并发运行只读的工具调用
     bgc:               String,                      // 页面背景色
     outpath:           String,                      // 问答结果输出路径
-    prompt:            HashMap<usize, [String; 2]>, // key: 序号，value: [prompt名称, prompt内容]
+    prompt:            HashMap<usize, Prompt>,      // key: 序号，value: prompt
     external_tools:    ExternalTools,               // external tools
-    mcp_servers:       StdIoServers,                // mcp servers, currently only support stdio, not http
+    http_tools:        HttpTools,                   // HTTP tools
+    mcp_servers:       Vec<StdIoServer>,            // mcp servers, currently only support stdio, not http, 在parse_para和reload中启动
 }
 
 /// 支持的所有api
@@ -702,41 +1122,10 @@ pub struct Api {
 /// 实现Api的方法
 impl Api {
     /// 初始化模型参数
-    fn new(config_file: Option<String>) -> Result<(Self, OptherPara), MyError> {
-        // 获取参数文件，先在当前路径下检查是否有config.txt，如果没有，再去程序所在路径下检查是否有config.txt，还没有则在当前路径下生成一个模板config.txt，供用户修改
-        let config_file = match config_file {
-            Some(c) => PathBuf::from(c),
-            None => { // 没有指定参数文件
-                let tmp_config = Path::new("config.txt");
-                if !(tmp_config.exists() && tmp_config.is_file()) { // 当前路径下没有config.txt
-                    let cfg = match current_exe() { // 检查程序所在路径下是否有config.txt
-                        Ok(exe_path) => {
-                            let cfg_path = exe_path.with_file_name("config.txt"); // 将路径的程序名替换为config.txt
-                            if cfg_path.exists() && cfg_path.is_file() {
-                                Some(cfg_path)
-                            } else {
-                                None
-                            }
-                        }
-                        Err(_) => None,
-                    };
-                    match cfg {
-                        Some(c) => c,
-                        None => { // 在当前路径下生成一个示例模板，用户基于此进行修改
-                            if let Err(e) = write("config.txt", include_str!("../../config_template.txt")) {
-                                return Err(MyError::WriteFileError{file: "./config.txt".to_string(), error: e})
-                            }
-                            return Err(MyError::ParaError{para: "The config.txt is missing, please revise the generated config.txt".to_string()})
-                        },
-                    }
-                } else { // 当前路径下有config.txt，则使用该参数文件
-                    PathBuf::from("config.txt")
-                }
-            },
-        };
+    fn new(config_file: &Path) -> Result<(Self, OptherPara), MyError> {
         let all_para: Para = match from_str(&read_to_string(config_file)?) {
             Ok(p) => p,
-            Err(e) => return Err(MyError::ParaError{para: format!("parse config file error, {:?}", e)}),
+            Err(e) => return Err(MyError::ParaError{para: format!("parse config file {} error, {:?}", config_file.display(), e)}),
         };
         let mut config: HashMap<String, Config> = HashMap::new();
         let mut models: HashMap<usize, (String, String, bool)> = HashMap::new();
@@ -791,18 +1180,26 @@ impl Api {
             default = 1; // 使用参数文件中第一个模型作为默认模型
         }
         // 这里i要加1，即参数文件第一个prompt是1，因为内置“保持当前对话”是-1，“无prompt”是0
-        let pulldown_prompt = all_para.prompts.iter().enumerate().fold("".to_string(), |acc, (i, p)| format!("{}                <option value='{}'>{}</option>\n", acc, i+1, p.name));
-        let prompt: HashMap<usize, [String; 2]> = all_para.prompts.into_iter().enumerate().map(|(i, p)| (i+1, [p.name, p.content])).collect();
-        // get mcp servers
-        let mcp_servers = {
-            let handle = thread::spawn(|| {
-                let rt = tokio::runtime::Runtime::new().unwrap();
-                rt.block_on(async {
-                    StdIoServers::new(all_para.mcp_servers).await
-                })
-            });
-            handle.join().unwrap()
-        }?;
+        // prompt含有需要用户填写的模板变量，则记录在`data-vars`中，页面开启新对话前弹出表单填写
+        // prompt的默认模型和tools记录在`data-model`和`data-tools`中，页面选择该prompt时切换
+        let pulldown_prompt = all_para.prompts.iter().enumerate().fold("".to_string(), |acc, (i, p)| {
+            let model_index = p.model.as_ref().and_then(|m| models.iter().find(|(_, v)| &v.1 == m).map(|(k, _)| *k));
+            acc + &prompt_option(&(i+1).to_string(), p, model_index)
+        });
+        let prompt: HashMap<usize, Prompt> = all_para.prompts.into_iter().enumerate().map(|(i, p)| (i+1, p)).collect();
+        // OpenAPI spec的每个operation生成一个HTTP工具，与http_tools一起管理
+        // 分组名称不能重复，否则页面和`http_group_名称`选择分组时会把不同spec的工具合并在一起
+        let mut http_tools = all_para.http_tools;
+        let mut groups: HashMap<String, String> = HashMap::new(); // key: 分组名称，value: spec文件
+        for spec in &all_para.openapi_tools {
+            let tools = spec.to_http_tools()?;
+            if let Some(t) = tools.first() {
+                if let Some(other) = groups.insert(t.group.clone(), spec.spec.clone()) {
+                    return Err(MyError::ParaError{para: format!("openapi spec {} and {} have the same tool group \"{}\", set a different `name`", other, spec.spec, t.group)})
+                }
+            }
+            http_tools.extend(tools);
+        }
         Ok(
             (
                 Api {
@@ -820,13 +1217,21 @@ impl Api {
                     allowed_path:      all_para.allowed_path,                       // allowed path for tools, multiple paths separated by commas, default: ./
                     skills_path:       all_para.skills_path,                        // skills路径
                     memory_path:       all_para.memory_path,                        // memory路径
+                    upload_limit:      all_para.upload_limit,                       // 上传文件的大小限制
+                    memory:            all_para.memory,                             // 自动提取和整理记忆
+                    command_limit:     all_para.command_limit,                      // 运行命令的工具的超时和输出限制
+                    sandbox:           all_para.sandbox,                            // 在Linux沙箱中运行命令的工具
+                    command_policy:    all_para.command_policy,                     // 命令策略文件
+                    approval_timeout:  all_para.approval_timeout,                   // 等待确认的超时和超时后的默认操作
+                    parallel_tools:    all_para.parallel_tools,                     // 并发运行只读的工具调用
                     bgc:               all_para.bgc,                                // 页面背景色
                     outpath:           all_para.outpath,                            // 问答结果输出路径
                     maxage:            all_para.maxage,                             // cookie过期时间，默认1DAY，支持的单位：SECOND、MINUTE、HOUR、DAY、WEEK
                     show_english:      all_para.show_english,                       // true展示英文界面，false展示中文界面
-                    prompt,                                                         // key: 序号，value: (prompt名称, prompt内容)
+                    prompt,                                                         // key: 序号，value: prompt
                     external_tools:    ExternalTools::new(all_para.external_tools), // external tools
-                    mcp_servers,                                                    // mcp servers, currently only support stdio, not http
+                    http_tools:        HttpTools::new(http_tools),                  // HTTP tools and tools generated from OpenAPI specs
+                    mcp_servers:       all_para.mcp_servers,                        // mcp servers, currently only support stdio, not http
                 },
             )
         )
@@ -863,6 +1268,21 @@ impl Api {
         }
     }
 
+    /// 获取指定名称的模型在模型下拉选项中的序号
+    pub fn get_model_index(&self, name: &str) -> Option<usize> {
+        self.models.iter().find(|(_, v)| v.1 == name).map(|(k, _)| *k)
+    }
+
+    /// 获取指定名称模型的图片参数，没有设置则使用默认值
+    pub fn get_image_config(&self, name: &str) -> ImageConfig {
+        self.config.values().flat_map(|c| c.models.iter()).find(|m| m.name == name).and_then(|m| m.image.clone()).unwrap_or_default()
+    }
+
+    /// 是否有指定名称的模型
+    pub fn contain_model(&self, name: &str) -> bool {
+        self.models.values().any(|v| v.1 == name)
+    }
+
     /// 获取 embedding 模型
     pub fn get_embedding_modle(&self, modle_name: Option<String>) -> Option<(String, String, String)> {
         let name = modle_name.unwrap_or("embedding".to_string());
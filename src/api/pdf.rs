@@ -1,7 +1,7 @@
 use std::fs::write;
 use std::path::Path;
 
-use pdf_extract::extract_text;
+use pdf_extract::{extract_text, extract_text_by_pages};
 //use pdf_extract::extract_text_from_mem;
 
 /// error: 定义的错误类型，用于错误传递
@@ -39,3 +39,9 @@ pub fn extract_pdf_content(uuid: &str, outpath: &str, pdf_file: &str) -> Result<
 
     Ok(content)
 }
+
+/// 按页读取pdf文件的文本内容，不保存，用于知识库按页拆分
+pub fn extract_pdf_pages(pdf_file: &str) -> Result<Vec<String>, MyError> {
+    let pages = extract_text_by_pages(pdf_file).map_err(|e| MyError::ExtractPdfError{file: pdf_file.to_string(), error: e})?;
+    Ok(pages.into_iter().map(|p| p.replace("\u{0000}", "").replace("\u{2009}", "")).collect())
+}
@@ -0,0 +1,199 @@
+use std::fs::{read_to_string, write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use html_escape::{encode_single_quoted_attribute, encode_text};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tracing::{event, Level};
+use uuid::Uuid;
+
+use crate::{
+    parse_paras::{PARAS, Prompt},
+    error::MyError,
+    template::user_variables,
+};
+
+/// prompt库中的prompt，id不随增删改变化，页面下拉选项的值为`lib:id`
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LibraryPrompt {
+    pub id:     String,
+    #[serde(flatten)]
+    pub prompt: Prompt,
+}
+
+/// 页面和api获取的prompt，包括参数文件中的prompt和prompt库中的prompt
+#[derive(Serialize)]
+pub struct PromptItem {
+    pub value:       String,         // 下拉选项的值，参数文件中的prompt是序号，prompt库中的prompt是`lib:id`
+    pub id:          Option<String>, // prompt库中的id，参数文件中的prompt为None，不能通过api修改
+    pub source:      String,         // config或library
+    pub vars:        Vec<String>,    // 需要页面填写的模板变量
+    pub model_index: Option<usize>,  // 默认模型在模型下拉选项中的序号，模型不存在则为None
+    #[serde(flatten)]
+    pub prompt:      Prompt,
+}
+
+/// prompt库，页面和api增删改的prompt，保存在`输出路径/prompts.json`，与参数文件分开，修改后立即生效，不需要重启
+static LIBRARY: Lazy<Mutex<Vec<LibraryPrompt>>> = Lazy::new(|| Mutex::new(load_library()));
+
+/// prompt库文件
+fn library_file() -> String {
+    format!("{}/prompts.json", PARAS.outpath)
+}
+
+/// 读取prompt库文件，不存在或出错则为空
+fn load_library() -> Vec<LibraryPrompt> {
+    let file = library_file();
+    if !Path::new(&file).is_file() {
+        return vec![]
+    }
+    match read_to_string(&file).map_err(|e| MyError::ReadFileError{file: file.clone(), error: e}).and_then(|s| serde_json::from_str(&s).map_err(|e| MyError::SerdeJsonFromStrError{error: e})) {
+        Ok(library) => library,
+        Err(e) => {
+            event!(Level::WARN, "load prompt library {} error: {}", file, e);
+            vec![]
+        },
+    }
+}
+
+/// 保存prompt库文件
+fn save_library(library: &[LibraryPrompt]) -> Result<(), MyError> {
+    let file = library_file();
+    let content = serde_json::to_string_pretty(library).map_err(|e| MyError::JsonToStringError{error: e.into()})?;
+    write(&file, content).map_err(|e| MyError::WriteFileError{file, error: e})
+}
+
+/// 检查prompt，名称和内容不能为空，默认模型必须存在
+fn check_prompt(prompt: &Prompt) -> Result<(), MyError> {
+    if prompt.name.trim().is_empty() || prompt.content.trim().is_empty() {
+        return Err(MyError::ParaError{para: "prompt name and content must not be empty".to_string()})
+    }
+    if let Some(m) = &prompt.model {
+        if !PARAS.snapshot().api.contain_model(m) {
+            return Err(MyError::ParaError{para: format!("no such model: {}", m)})
+        }
+    }
+    Ok(())
+}
+
+/// 获取所有prompt，参数文件中的在前，prompt库中的在后，指定tag则只返回含有该tag的prompt
+pub fn list_prompts(tag: Option<&str>) -> Vec<PromptItem> {
+    let snapshot = PARAS.snapshot();
+    let mut config: Vec<(&usize, &Prompt)> = snapshot.prompt.iter().collect();
+    config.sort_by_key(|(i, _)| **i);
+    let mut items: Vec<PromptItem> = config.into_iter().map(|(i, p)| PromptItem{
+        value:       i.to_string(),
+        id:          None,
+        source:      "config".to_string(),
+        vars:        user_variables(&p.content),
+        model_index: p.model.as_ref().and_then(|m| snapshot.api.get_model_index(m)),
+        prompt:      p.clone(),
+    }).collect();
+    for p in LIBRARY.lock().unwrap().iter() {
+        items.push(PromptItem{
+            value:       format!("lib:{}", p.id),
+            id:          Some(p.id.clone()),
+            source:      "library".to_string(),
+            vars:        user_variables(&p.prompt.content),
+            model_index: p.prompt.model.as_ref().and_then(|m| snapshot.api.get_model_index(m)),
+            prompt:      p.prompt.clone(),
+        });
+    }
+    match tag {
+        Some(t) => items.into_iter().filter(|i| i.prompt.tags.iter().any(|pt| pt == t)).collect(),
+        None => items,
+    }
+}
+
+/// 根据下拉选项的值获取prompt，序号是参数文件中的prompt，`lib:id`是prompt库中的prompt
+pub fn get_prompt_by_value(value: &str) -> Option<Prompt> {
+    match value.strip_prefix("lib:") {
+        Some(id) => LIBRARY.lock().unwrap().iter().find(|p| p.id == id).map(|p| p.prompt.clone()),
+        None => value.parse::<usize>().ok().and_then(|n| PARAS.snapshot().prompt.get(&n).cloned()),
+    }
+}
+
+/// 根据名称（不区分大小写）获取prompt库中prompt的下拉选项的值
+pub fn find_library_prompt(name: &str) -> Option<String> {
+    LIBRARY.lock().unwrap().iter().find(|p| p.prompt.name.to_lowercase() == name.to_lowercase()).map(|p| format!("lib:{}", p.id))
+}
+
+/// 添加prompt到prompt库，返回id
+pub fn add_prompt(prompt: Prompt) -> Result<String, MyError> {
+    check_prompt(&prompt)?;
+    let mut library = LIBRARY.lock().unwrap();
+    let id = Uuid::new_v4().to_string();
+    library.push(LibraryPrompt{id: id.clone(), prompt});
+    save_library(&library)?;
+    Ok(id)
+}
+
+/// 修改prompt库中指定id的prompt
+pub fn update_prompt(id: &str, prompt: Prompt) -> Result<(), MyError> {
+    check_prompt(&prompt)?;
+    let mut library = LIBRARY.lock().unwrap();
+    match library.iter_mut().find(|p| p.id == id) {
+        Some(p) => p.prompt = prompt,
+        None => return Err(MyError::ParaError{para: format!("no such prompt: {}", id)}),
+    }
+    save_library(&library)
+}
+
+/// 删除prompt库中指定id的prompt
+pub fn delete_prompt(id: &str) -> Result<(), MyError> {
+    let mut library = LIBRARY.lock().unwrap();
+    let len = library.len();
+    library.retain(|p| p.id != id);
+    if library.len() == len {
+        return Err(MyError::ParaError{para: format!("no such prompt: {}", id)})
+    }
+    save_library(&library)
+}
+
+/// 导入prompt包，与prompt库中同名的prompt会被覆盖，其余添加，返回(添加数量, 覆盖数量)
+pub fn import_prompts(prompts: Vec<Prompt>) -> Result<(usize, usize), MyError> {
+    for p in &prompts {
+        check_prompt(p)?;
+    }
+    let mut library = LIBRARY.lock().unwrap();
+    let (mut added, mut updated) = (0, 0);
+    for prompt in prompts {
+        match library.iter_mut().find(|p| p.prompt.name == prompt.name) {
+            Some(p) => {
+                p.prompt = prompt;
+                updated += 1;
+            },
+            None => {
+                library.push(LibraryPrompt{id: Uuid::new_v4().to_string(), prompt});
+                added += 1;
+            },
+        }
+    }
+    save_library(&library)?;
+    Ok((added, updated))
+}
+
+/// 导出prompt包，默认只导出prompt库，all为true则包括参数文件中的prompt，指定tag则只导出含有该tag的prompt
+pub fn export_prompts(tag: Option<&str>, all: bool) -> Vec<Prompt> {
+    list_prompts(tag).into_iter().filter(|i| all || i.id.is_some()).map(|i| i.prompt).collect()
+}
+
+/// 创建prompt下拉选项，含有需要填写的模板变量、默认模型序号、默认tools
+pub fn prompt_option(value: &str, prompt: &Prompt, model_index: Option<usize>) -> String {
+    format!(
+        "                <option value='{}' data-vars='{}' data-model='{}' data-tools='{}' title='{}'>{}</option>\n",
+        encode_single_quoted_attribute(value),
+        encode_single_quoted_attribute(&user_variables(&prompt.content).join(",")),
+        model_index.map(|i| i.to_string()).unwrap_or_default(),
+        encode_single_quoted_attribute(prompt.tools.as_deref().unwrap_or_default()),
+        encode_single_quoted_attribute(&prompt.tags.join(", ")),
+        encode_text(&prompt.name),
+    )
+}
+
+/// 给html使用的prompt库下拉选项字符串，接在参数文件的prompt下拉选项后面
+pub fn pulldown_library() -> String {
+    let snapshot = PARAS.snapshot();
+    LIBRARY.lock().unwrap().iter().fold("".to_string(), |acc, p| acc + &prompt_option(&format!("lib:{}", p.id), &p.prompt, p.prompt.model.as_ref().and_then(|m| snapshot.api.get_model_index(m))))
+}
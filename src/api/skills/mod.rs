@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
 use serde::Deserialize;
@@ -76,6 +77,7 @@ impl Skills {
 }
 
 /// 下拉选择的skills
+#[derive(Clone)]
 pub enum SelectedSkills {
     All,
     Group(String),
@@ -222,63 +224,26 @@ impl SkillManager {
     /// 这样其实就是把skill做链分组，方便直接按照分组选择
     fn discover_skill_statuses(&self) -> Vec<SkillAvailability> {
         let mut statuses = Vec::new();
-        let entries = match std::fs::read_dir(&self.skills_dir) {
-            Ok(e) => e,
-            Err(_) => return statuses,
-        };
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if !path.is_dir() {
-                continue
-            }
-            let (skill_mds, path_vec, group) = {
-                let md_path = path.join("SKILL.md");
-                if md_path.exists() {
-                    (vec![md_path], vec![path], "Other-skills".to_string())
-                } else { // 该路径下没有SKILL.md，则从该路径中寻找含有SKILL.md的文件夹，即该路径内包含链一组skills
-                    let group = path.file_name().unwrap().to_str().unwrap().to_string();
-                    let entries2 = match std::fs::read_dir(&path) {
-                        Ok(e) => e,
-                        Err(_) => return statuses,
+        for (md, path, group) in self.find_skill_mds() {
+            if let Ok(content) = std::fs::read_to_string(&md) {
+                if let Some((meta, body)) = parse_skill_md(&content, &path) {
+                    match self.skill_is_available(&meta) {
+                        Ok(()) => statuses.push(SkillAvailability {
+                            meta,
+                            body,
+                            available: true,
+                            reason: None,
+                            group,
+                        }),
+                        Err(MyError::OtherError{info: reason}) => statuses.push(SkillAvailability {
+                            meta,
+                            body,
+                            available: false,
+                            reason: Some(reason),
+                            group,
+                        }),
+                        _ => unreachable!(),
                     };
-                    let mut mds = Vec::new();
-                    let mut path2_vec = Vec::new();
-                    for entry2 in entries2.flatten() {
-                        let path2 = entry2.path();
-                        if !path2.is_dir() {
-                            continue
-                        }
-                        let skill_md = path2.join("SKILL.md");
-                        if !skill_md.exists() {
-                            continue
-                        }
-                        mds.push(skill_md);
-                        path2_vec.push(path2);
-                    }
-                    (mds, path2_vec, group)
-                }
-            };
-            for (md, path) in skill_mds.into_iter().zip(path_vec) {
-                if let Ok(content) = std::fs::read_to_string(&md) {
-                    if let Some((meta, body)) = parse_skill_md(&content, &path) {
-                        match self.skill_is_available(&meta) {
-                            Ok(()) => statuses.push(SkillAvailability {
-                                meta,
-                                body,
-                                available: true,
-                                reason: None,
-                                group: group.clone(),
-                            }),
-                            Err(MyError::OtherError{info: reason}) => statuses.push(SkillAvailability {
-                                meta,
-                                body,
-                                available: false,
-                                reason: Some(reason),
-                                group: group.clone(),
-                            }),
-                            _ => unreachable!(),
-                        };
-                    }
                 }
             }
         }
@@ -296,6 +261,76 @@ impl SkillManager {
         statuses
     }
 
+    /// 获取skills路径下所有SKILL.md，返回(SKILL.md路径, skill路径, 分组)
+    /// 如果`skills_dir`路径下一级文件夹含有`SKILL.md`，则归为`Other`分组
+    /// 如果`skills_dir`路径下一级文件夹不含有`SKILL.md`，继续判断该一级文件夹下的文件夹（二级文件夹）中是否含有`SKILL.md`，含有则该skill的分组设为一级文件夹的名称
+    fn find_skill_mds(&self) -> Vec<(PathBuf, PathBuf, String)> {
+        let mut skill_mds = Vec::new();
+        let entries = match std::fs::read_dir(&self.skills_dir) {
+            Ok(e) => e,
+            Err(_) => return skill_mds,
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if !path.is_dir() {
+                continue
+            }
+            let md_path = path.join("SKILL.md");
+            if md_path.exists() {
+                skill_mds.push((md_path, path, "Other-skills".to_string()));
+            } else { // 该路径下没有SKILL.md，则从该路径中寻找含有SKILL.md的文件夹，即该路径内包含链一组skills
+                let group = path.file_name().unwrap().to_str().unwrap().to_string();
+                let entries2 = match std::fs::read_dir(&path) {
+                    Ok(e) => e,
+                    Err(_) => return skill_mds,
+                };
+                for entry2 in entries2.flatten() {
+                    let path2 = entry2.path();
+                    if !path2.is_dir() {
+                        continue
+                    }
+                    let skill_md = path2.join("SKILL.md");
+                    if !skill_md.exists() {
+                        continue
+                    }
+                    skill_mds.push((skill_md, path2, group.clone()));
+                }
+            }
+        }
+        skill_mds
+    }
+
+    /// 检查所有SKILL.md的frontmatter，返回(SKILL.md路径, 行号, 问题, 是否是错误)，用于`chatsong check`
+    /// 当前系统不支持或缺少依赖只作为警告，不算错误
+    pub fn check_skills(&self) -> Vec<(PathBuf, usize, String, bool)> {
+        let mut problems = Vec::new();
+        let mut names: HashMap<String, PathBuf> = HashMap::new(); // 检查重名的skill
+        for (md, path, _) in self.find_skill_mds() {
+            let content = match std::fs::read_to_string(&md) {
+                Ok(c) => c,
+                Err(e) => {
+                    problems.push((md, 0, format!("read file error: {}", e), true));
+                    continue
+                },
+            };
+            let md_problems = check_skill_md(&content);
+            if md_problems.is_empty() {
+                if let Some((meta, _)) = parse_skill_md(&content, &path) {
+                    if let Some(first) = names.get(&meta.name) {
+                        problems.push((md.clone(), 2, format!("duplicate skill name \"{}\", already used by {}", meta.name, first.display()), true));
+                    } else {
+                        names.insert(meta.name.clone(), md.clone());
+                    }
+                    if let Err(MyError::OtherError{info}) = self.skill_is_available(&meta) {
+                        problems.push((md.clone(), 0, info, false));
+                    }
+                }
+            }
+            problems.extend(md_problems.into_iter().map(|(line, info)| (md.clone(), line, info, true)));
+        }
+        problems
+    }
+
     /// 获取所有可用和不可用的skills
     pub fn discover_skills(&self) -> (Vec<SkillAvailability>, Vec<SkillAvailability>) {
         let statuses = self.discover_skill_statuses();
@@ -535,6 +570,49 @@ fn normalize_single_line_frontmatter(content: &str) -> Option<String> {
     Some(format!("---\n{yaml}\n---\n{body}"))
 }
 
+/// 检查SKILL.md的frontmatter格式，返回(行号, 问题)，没有问题则返回空
+fn check_skill_md(content: &str) -> Vec<(usize, String)> {
+    let trimmed = content.trim_start_matches('\u{feff}');
+    // 单行frontmatter无法定位具体行号，都报告为第1行
+    if !trimmed.starts_with("---\n") && !trimmed.starts_with("---\r\n") {
+        return match normalize_single_line_frontmatter(trimmed) {
+            Some(normalized) => match parse_skill_md(&normalized, Path::new("")) {
+                Some(_) => Vec::new(),
+                None => vec![(1, "invalid single line frontmatter, need `--- name: x description: y ---`".to_string())],
+            },
+            None => vec![(1, "missing YAML frontmatter, SKILL.md must start with `---`".to_string())],
+        }
+    }
+    let mut yaml_block = String::new();
+    let mut closed = false;
+    for line in trimmed.lines().skip(1) {
+        if line.trim() == "---" || line.trim() == "..." {
+            closed = true;
+            break
+        }
+        yaml_block.push_str(line);
+        yaml_block.push('\n');
+    }
+    if !closed {
+        return vec![(1, "frontmatter is not closed by `---`".to_string())]
+    }
+    // yaml从文件第2行开始
+    let fm: SkillFrontmatter = match serde_yaml::from_str(&yaml_block) {
+        Ok(fm) => fm,
+        Err(e) => return vec![(e.location().map(|l| l.line() + 1).unwrap_or(1), format!("invalid YAML frontmatter: {}", e))],
+    };
+    let mut problems = Vec::new();
+    let line_of = |key: &str| yaml_block.lines().position(|l| l.trim_start().starts_with(key)).map(|i| i + 2).unwrap_or(1);
+    match fm.name {
+        Some(name) if !name.trim().is_empty() => (),
+        _ => problems.push((1, "missing `name` in frontmatter".to_string())),
+    }
+    if fm.description.trim().is_empty() {
+        problems.push((line_of("description:"), "missing `description` in frontmatter, the model uses it to decide when to activate this skill".to_string()));
+    }
+    problems
+}
+
 /// 解析SKILL.md
 fn parse_skill_md(content: &str, dir_path: &Path) -> Option<(SkillMetadata, String)> {
     let trimmed = content.trim_start_matches('\u{feff}'); // 去除起始的连续字符`\u{feff}`，这个字符是`ZERO WIDTH NO-BREAK SPACE`，称为`Byte order mark`或`BOM`
@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::Path;
+
+use chrono::Local;
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use tracing::{event, Level};
+
+use crate::{
+    parse_paras::PARAS,
+    error::MyError,
+    memory::get_all_memory_or_load,
+    info::get_memory_namespaces, // 获取对话使用的记忆命名空间
+    tools::built_in_tools::filesystem::utils::validate_path,
+};
+
+/// 模板变量`{{name}}`或`{{name:参数}}`，前面加`\`（`\{{name}}`）则不替换，去掉`\`后原样保留，用于问题中粘贴的含有`{{...}}`的文本
+static TEMPLATE_VAR: Lazy<Regex> = Lazy::new(|| Regex::new(r"(\\?)\{\{\s*([A-Za-z_][A-Za-z0-9_\-]*)\s*(?::([^{}]*))?\}\}").unwrap());
+
+/// 由服务端替换的内置变量，其他变量由页面提供：`{{clipboard}}`由浏览器读取，其余由用户在表单中填写
+const SERVER_VARS: [&str; 5] = ["date", "time", "model", "file", "memory"];
+
+/// 是否含有模板变量
+pub fn has_template(text: &str) -> bool {
+    TEMPLATE_VAR.is_match(text)
+}
+
+/// 获取需要页面提供的变量名称，去重并保持出现顺序
+pub fn user_variables(text: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for caps in TEMPLATE_VAR.captures_iter(text).filter(|c| c[1].is_empty()) {
+        let name = caps[2].to_string();
+        if !SERVER_VARS.contains(&name.as_str()) && !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// 替换prompt或用户提问中的模板变量
+/// `{{date}}`、`{{time}}`：当前日期和时间
+/// `{{model}}`：当前模型
+/// `{{file:路径}}`：文件内容，路径必须在`allowed_path`内
+/// `{{clipboard}}`：剪贴板内容，页面提问时由浏览器读取，随其他变量一起传入，只有命令行（is_cli）才读取服务端所在电脑的剪贴板
+/// `{{memory}}`：所有记忆
+/// 其他变量从vars中获取，没有则保持原样
+/// `\{{name}}`去掉`\`后原样保留，替换后的内容不会再次替换
+pub fn render_template(text: &str, uuid: &str, model: &str, vars: &HashMap<String, String>, is_local: bool, is_cli: bool) -> String {
+    if !has_template(text) {
+        return text.to_string()
+    }
+    TEMPLATE_VAR.replace_all(text, |caps: &Captures| {
+        if !caps[1].is_empty() {
+            return caps[0][1..].to_string()
+        }
+        let arg = caps.get(3).map(|a| a.as_str().trim());
+        match (&caps[2], arg) {
+            ("date", None) => Local::now().format("%Y-%m-%d").to_string(),
+            ("time", None) => Local::now().format("%H:%M:%S").to_string(),
+            ("model", None) => model.to_string(),
+            ("file", Some(f)) => read_template_file(uuid, f),
+            ("clipboard", None) => match vars.get("clipboard") {
+                Some(c) => c.clone(),
+                None if is_cli => read_clipboard().unwrap_or_else(|| caps[0].to_string()),
+                None => caps[0].to_string(),
+            },
+            ("memory", None) => get_all_memory_or_load(uuid, is_local, &get_memory_namespaces(uuid)).unwrap_or_default(),
+            (name, None) => match vars.get(name) {
+                Some(v) => v.clone(),
+                None => caps[0].to_string(),
+            },
+            _ => caps[0].to_string(),
+        }
+    }).to_string()
+}
+
+/// 读取`{{file:路径}}`指定的文件，不在`allowed_path`内或读取出错则返回错误信息
+fn read_template_file(uuid: &str, file: &str) -> String {
+    let result = validate_path(&PARAS.allowed_path, Path::new(&file.replace("\\", "/")), true)
+        .and_then(|p| read_to_string(&p).map_err(|e| MyError::ReadFileError{file: file.to_string(), error: e}));
+    match result {
+        Ok(content) => content,
+        Err(e) => {
+            event!(Level::WARN, "{} template file {} error: {}", uuid, file, e);
+            format!("[{{{{file:{}}}}} error: {}]", file, e)
+        },
+    }
+}
+
+/// 命令行使用时读取本机剪贴板，需要`code-completion` feature
+#[cfg(feature = "code-completion")]
+fn read_clipboard() -> Option<String> {
+    arboard::Clipboard::new().and_then(|mut c| c.get_text()).ok()
+}
+
+#[cfg(not(feature = "code-completion"))]
+fn read_clipboard() -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        let vars = HashMap::from([("topic".to_string(), "rust".to_string())]);
+        assert_eq!(render_template(r"\{{ time }} and \{{topic}} on {{model}}", "", "m", &vars, false, false), "{{ time }} and {{topic}} on m");
+        assert_eq!(user_variables(r"\{{topic}} {{lang}}"), vec!["lang".to_string()]);
+    }
+
+    #[test]
+    fn test_values_not_rendered_again() {
+        let vars = HashMap::from([("clipboard".to_string(), "{{model}} {{memory}}".to_string())]);
+        assert_eq!(render_template("{{clipboard}}", "", "m", &vars, false, false), "{{model}} {{memory}}");
+    }
+
+    #[test]
+    fn test_no_server_clipboard_for_web() {
+        assert_eq!(render_template("{{clipboard}}", "", "m", &HashMap::new(), true, false), "{{clipboard}}");
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use ron::de::from_str;
+use serde::Deserialize;
+
+use crate::error::MyError;
+
+/// 非交互调用tool时的确认规则，例如命令行`chatsong ask`
+/// 规则文件为ron格式，tool名称支持以`*`结尾的前缀匹配，优先级：deny > skip > allow > default
+/// ```ron
+/// (
+///     default: "deny",
+///     allow: ["read_file", "list_directory", "search_*"],
+///     deny: ["run_command"],
+///     skip: [],
+/// )
+/// ```
+#[derive(Deserialize)]
+pub struct ApprovalPolicy {
+    #[serde(default)]
+    pub allow:   Vec<String>, // 允许调用的tool
+    #[serde(default)]
+    pub deny:    Vec<String>, // 拒绝调用的tool，回答终止
+    #[serde(default)]
+    pub skip:    Vec<String>, // 跳过的tool，告知模型未执行，继续回答
+    #[serde(default = "default_action")]
+    pub default: String,      // 其他tool: allow, deny, skip
+}
+
+fn default_action() -> String {
+    "deny".to_string()
+}
+
+impl ApprovalPolicy {
+    /// 所有tool使用同一个决定：allow, deny, skip
+    pub fn all(action: &str) -> Result<Self, MyError> {
+        let policy = ApprovalPolicy{allow: vec![], deny: vec![], skip: vec![], default: action.to_string()};
+        policy.check()?;
+        Ok(policy)
+    }
+
+    /// 读取规则文件
+    pub fn from_file(file: &str) -> Result<Self, MyError> {
+        let content = read_to_string(file).map_err(|e| MyError::ReadFileError{file: file.to_string(), error: e})?;
+        let policy: ApprovalPolicy = from_str(&content).map_err(|e| MyError::ParaError{para: format!("parse approval policy file {} error: {}", file, e)})?;
+        policy.check()?;
+        Ok(policy)
+    }
+
+    /// default只能是allow, deny, skip
+    fn check(&self) -> Result<(), MyError> {
+        if ["allow", "deny", "skip"].contains(&self.default.as_str()) {
+            Ok(())
+        } else {
+            Err(MyError::ParaError{para: format!("approval must be allow, deny or skip, not {}", self.default)})
+        }
+    }
+
+    /// 返回与页面确认相同的结果：`true`（允许）、`false`（拒绝）、`skip`（跳过）
+    pub fn decide(&self, tool: &str) -> String {
+        let action = if match_any(&self.deny, tool) {
+            "deny"
+        } else if match_any(&self.skip, tool) {
+            "skip"
+        } else if match_any(&self.allow, tool) {
+            "allow"
+        } else {
+            &self.default
+        };
+        match action {
+            "allow" => "true".to_string(),
+            "skip" => "skip".to_string(),
+            _ => "false".to_string(),
+        }
+    }
+}
+
+/// tool名称是否匹配其中一个规则，`*`结尾表示前缀匹配
+fn match_any(patterns: &[String], tool: &str) -> bool {
+    patterns.iter().any(|p| match p.strip_suffix('*') {
+        Some(prefix) => tool.starts_with(prefix),
+        None => p == tool,
+    })
+}
+
+/// 设置了确认规则的uuid，以及该uuid每次确认的(tool名称, 结果)
+static POLICIES: Lazy<Mutex<HashMap<String, (ApprovalPolicy, Vec<(String, String)>)>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 为指定uuid设置确认规则，之后该uuid调用tool时不再等待页面确认
+pub fn set_policy(uuid: &str, policy: ApprovalPolicy) {
+    let mut policies = POLICIES.lock().unwrap();
+    policies.insert(uuid.to_string(), (policy, vec![]));
+}
+
+/// 移除指定uuid的确认规则，返回该uuid每次确认的(tool名称, 结果)
+pub fn remove_policy(uuid: &str) -> Vec<(String, String)> {
+    let mut policies = POLICIES.lock().unwrap();
+    policies.remove(uuid).map(|p| p.1).unwrap_or_default()
+}
+
+/// 指定uuid设置了确认规则则返回决定并记录，否则返回None，由页面确认
+pub fn decide_by_policy(uuid: &str, tool: &str) -> Option<String> {
+    let mut policies = POLICIES.lock().unwrap();
+    policies.get_mut(uuid).map(|(policy, decisions)| {
+        let decision = policy.decide(tool);
+        decisions.push((tool.to_string(), decision.clone()));
+        decision
+    })
+}
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::fs::{OpenOptions, read_to_string};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use chrono::Local;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tracing::{event, Level};
+
+use crate::{
+    parse_paras::PARAS,
+    error::MyError,
+};
+
+// 工具调用审计日志：没有标记为只读的工具（内置工具、外部工具、MCP工具、HTTP工具），每次调用追加一行json到`输出路径/audit.jsonl`
+// 只追加不修改，可通过`/v1/audit`按日期、对话、工具、结果筛选，回答“agent昨天在这台电脑上改了什么”
+
+/// 审计日志中工具输出保留的最大字符数
+const MAX_OUTPUT_CHARS: usize = 2000;
+
+/// 各uuid最近一次提问的用户，页面提问为客户端ip，命令行为`cli`
+static USERS: Lazy<Mutex<HashMap<String, String>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 追加写入审计日志时加锁，避免并发调用时行交错
+static WRITE_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+/// 一次工具调用的审计记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub time:        String, // 调用开始的时间，例如：2024-10-21 16:35:47
+    pub uuid:        String, // 对话uuid
+    pub user:        String, // 提问的用户，页面为客户端ip，命令行为`cli`
+    pub tool:        String, // 工具名称
+    pub tool_id:     String, // 内置工具id或MCP服务id
+    pub args:        String, // 模型给出的参数
+    pub approval:    String, // 确认结果：true、false、skip、新指示，不需要确认为`not_required`，参数`approval_all`为`approval_all`
+    pub approver:    String, // 确认者：页面客户端ip、`cli`（命令行）、`discord:用户名`、`timeout`（超时的默认操作）、`policy`（命令行确认规则）、`command_policy`（命令策略文件）、`config`（参数`approval_all`），不需要确认为空
+    pub duration_ms: u64,    // 执行耗时（毫秒），不含等待确认的时间
+    pub status:      String, // 执行结果：ok、error、denied、skipped
+    pub output:      String, // 输出或错误信息，超过2000个字符则截断
+}
+
+/// 审计日志文件路径
+fn audit_file() -> String {
+    format!("{}/audit.jsonl", PARAS.outpath)
+}
+
+/// 指定工具是否需要审计，只读工具不审计，其他工具全部审计
+pub fn is_audited(read_only: bool) -> bool {
+    !read_only
+}
+
+/// 记录提问的用户
+pub fn set_user(uuid: &str, user: String) {
+    let mut users = USERS.lock().unwrap();
+    users.insert(uuid.to_string(), user);
+}
+
+/// 获取提问的用户，没有记录则根据是否本机请求返回`local`或`remote`
+pub fn get_user(uuid: &str, is_local: bool) -> String {
+    USERS.lock().unwrap().get(uuid).cloned().unwrap_or(if is_local { "local".to_string() } else { "remote".to_string() })
+}
+
+/// 一次工具调用的确认结果，由确认的地方返回给调用者，随调用结果一起写入审计日志
+/// 不按uuid保存，sub_agent等工具内部的工具调用与外层调用共用uuid，各自的确认不会混在一起
+pub struct Approval {
+    pub decision: String,  // 确认结果：true、false、skip、新指示
+    pub approver: String,  // 确认者
+    pub at:       Instant, // 确认时间，执行耗时从此时开始计算
+}
+
+impl Approval {
+    /// 以当前时间作为确认时间
+    pub fn new(decision: &str, approver: &str) -> Self {
+        Approval{decision: decision.to_string(), approver: approver.to_string(), at: Instant::now()}
+    }
+}
+
+/// 调用开始的(时间, Instant)
+pub fn start() -> (String, Instant) {
+    (Local::now().format("%Y-%m-%d %H:%M:%S").to_string(), Instant::now())
+}
+
+/// 调用结果的(status, output)，调用工具时出错为error，拒绝为denied，跳过为skipped
+pub fn outcome<T>(result: &Result<Result<T, MyError>, MyError>, output: fn(&T) -> &str) -> (&'static str, String) {
+    match result {
+        Ok(Ok(r)) => ("ok", output(r).to_string()),
+        Ok(Err(e @ MyError::PolicyDeniedError{..})) => ("denied", e.to_string()),
+        Ok(Err(e)) => ("error", e.to_string()),
+        Err(MyError::ApprovalDeniedError{..}) => ("denied", String::new()),
+        Err(MyError::PlanModeError{info}) if info.starts_with("skip, this tool has not been executed: ") => ("skipped", info.clone()),
+        Err(e) => ("error", e.to_string()),
+    }
+}
+
+/// 追加一条工具调用的审计记录，写入失败只打印错误，不影响工具调用
+/// name_id: [工具名称, 工具id]，started: 调用开始的(时间, Instant)，approval: 该调用的确认结果，确认过则从确认时开始计算耗时，result: `outcome`返回的(status, output)
+pub fn record(uuid: &str, is_local: bool, name_id: &[&str], args: &str, started: (String, Instant), approval: Option<Approval>, result: (&str, String)) {
+    let (status, output) = result;
+    let (time, started) = started;
+    let (approval, approver, started) = match approval {
+        Some(a) => (a.decision, a.approver, a.at),
+        None if PARAS.approval_all => ("approval_all".to_string(), "config".to_string(), started),
+        None => ("not_required".to_string(), String::new(), started),
+    };
+    let entry = AuditEntry {
+        time,
+        uuid: uuid.to_string(),
+        user: get_user(uuid, is_local),
+        tool: name_id[0].to_string(),
+        tool_id: name_id.get(1).unwrap_or(&"").to_string(),
+        args: args.to_string(),
+        approval,
+        approver,
+        duration_ms: started.elapsed().as_millis() as u64,
+        status: status.to_string(),
+        output: if output.chars().count() > MAX_OUTPUT_CHARS {
+            format!("{}...(truncated)", output.chars().take(MAX_OUTPUT_CHARS).collect::<String>())
+        } else {
+            output
+        },
+    };
+    if let Err(e) = append_audit(&entry) {
+        event!(Level::ERROR, "{} write audit log error: {}", uuid, e);
+    }
+}
+
+/// 追加审计记录
+fn append_audit(entry: &AuditEntry) -> Result<(), MyError> {
+    let file = audit_file();
+    let line = serde_json::to_string(entry).map_err(|e| MyError::ToJsonStirngError{uuid: entry.uuid.clone(), error: e})?;
+    let _lock = WRITE_LOCK.lock().unwrap();
+    let mut f = OpenOptions::new().create(true).append(true).open(&file).map_err(|e| MyError::WriteFileError{file: file.clone(), error: e})?;
+    writeln!(f, "{}", line).map_err(|e| MyError::WriteFileError{file, error: e})
+}
+
+/// 筛选审计记录的条件，为None则不筛选
+#[derive(Debug, Default)]
+pub struct AuditFilter {
+    pub date:   Option<String>, // 日期前缀，例如：2024-10-21，或2024-10-21 16
+    pub since:  Option<String>, // 不早于该时间，例如：2024-10-21 08:00:00
+    pub until:  Option<String>, // 早于该时间
+    pub uuid:   Option<String>,
+    pub tool:   Option<String>,
+    pub status: Option<String>,
+    pub query:  Option<String>, // 参数或输出包含的内容，不区分大小写
+    pub limit:  usize,          // 最多返回的记录数，0表示不限制
+}
+
+impl AuditFilter {
+    /// 是否符合筛选条件
+    fn matches(&self, e: &AuditEntry) -> bool {
+        self.date.as_ref().map_or(true, |d| e.time.starts_with(d.as_str()))
+            && self.since.as_ref().map_or(true, |s| e.time.as_str() >= s.as_str())
+            && self.until.as_ref().map_or(true, |u| e.time.as_str() < u.as_str())
+            && self.uuid.as_ref().map_or(true, |u| &e.uuid == u)
+            && self.tool.as_ref().map_or(true, |t| &e.tool == t)
+            && self.status.as_ref().map_or(true, |s| &e.status == s)
+            && self.query.as_ref().map_or(true, |q| {
+                let q = q.to_lowercase();
+                e.args.to_lowercase().contains(&q) || e.output.to_lowercase().contains(&q)
+            })
+    }
+}
+
+/// 获取符合条件的审计记录，最新的在前
+pub fn read_audit(filter: &AuditFilter) -> Vec<AuditEntry> {
+    match read_to_string(audit_file()) {
+        Ok(s) => {
+            let entries = s.lines().rev().filter_map(|l| serde_json::from_str::<AuditEntry>(l).ok()).filter(|e| filter.matches(e));
+            if filter.limit == 0 {
+                entries.collect()
+            } else {
+                entries.take(filter.limit).collect()
+            }
+        },
+        Err(_) => vec![],
+    }
+}
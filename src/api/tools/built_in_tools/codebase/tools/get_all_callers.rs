@@ -88,4 +88,9 @@ impl BuiltIn for GetAllCallers {
     fn get_approval(&self, _args: &str, _info: Option<String>, _is_en: bool) -> Result<Option<String>, MyError> {
         Ok(None)
     }
+
+    /// read-only
+    fn read_only(&self) -> bool {
+        true
+    }
 }
@@ -99,4 +99,9 @@ impl BuiltIn for GetCodeSignature {
     fn get_approval(&self, _args: &str, _info: Option<String>, _is_en: bool) -> Result<Option<String>, MyError> {
         Ok(None)
     }
+
+    /// read-only
+    fn read_only(&self) -> bool {
+        true
+    }
 }
@@ -82,4 +82,9 @@ impl BuiltIn for GetMethods {
     fn get_approval(&self, _args: &str, _info: Option<String>, _is_en: bool) -> Result<Option<String>, MyError> {
         Ok(None)
     }
+
+    /// read-only
+    fn read_only(&self) -> bool {
+        true
+    }
 }
@@ -22,7 +22,7 @@ use crate::{
         ArgFixSpec,
         built_in_tools::BuiltIn,
     },
-    parse_paras::PARAS,
+    parse_paras::{PARAS, ConfigSnapshot},
 };
 
 /// params for image generation
@@ -87,9 +87,9 @@ impl BuiltIn for ImageGeneration {
 
 /// 调用模型绘图
 /// https://developers.openai.com/api/reference/resources/images/methods/generate
-pub async fn image_generation(uuid: &str, prompt: String, m: &str) -> Result<String, MyError> {
+pub async fn image_generation(snapshot: &ConfigSnapshot, uuid: &str, prompt: String, m: &str) -> Result<String, MyError> {
     // 根据模型名称获取(api_key, endpoint, 模型名称, 是否支持深度思考)
-    let (api_key, endpoint, model, _) = PARAS.api.get_model_by_name(m)?;
+    let (api_key, endpoint, model, _) = snapshot.api.get_model_by_name(m)?;
     // 使用api key初始化
     let mut client = Client::new(api_key);
     client.set_base_url(&endpoint); // 从0.7.0开始舍弃了new_with_base
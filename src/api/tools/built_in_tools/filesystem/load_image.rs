@@ -75,6 +75,11 @@ impl BuiltIn for LoadImage {
     fn get_approval(&self, _args: &str, _info: Option<String>, _is_en: bool) -> Result<Option<String>, MyError> {
         Ok(None)
     }
+
+    /// read-only
+    fn read_only(&self) -> bool {
+        true
+    }
 }
 
 /// 检查给定路径是否以图片格式后缀（png、jpg、jpeg）结尾，不区分大小写
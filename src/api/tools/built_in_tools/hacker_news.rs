@@ -7,25 +7,16 @@ use html_escape::decode_html_entities;
 use tokio::time::{sleep, Duration};
 use serde::Deserialize; // Serialize
 use serde_json::{json, Value}; // https://docs.rs/serde_json/latest/serde_json/enum.Value.html
-use openai_dive::v1::{
-    api::Client,
-    resources::chat::{
-        ChatCompletionParametersBuilder,
-        ChatMessage,
-        ChatMessageContent,
-        ChatCompletionResponseFormat,
-    },
-};
 use tracing::{event, Level};
 
 use crate::{
-    parse_paras::PARAS,
+    parse_paras::{PARAS, ConfigSnapshot},
     error::MyError,
+    openai::for_chat::run_single_llm,
     tools::{
         parse_tool_args,
         ArgFixSpec,
         built_in_tools::BuiltIn,
-        call_llm,
     },
 };
 
@@ -95,7 +86,7 @@ Here are the comments:
 "###;
 
 /// 爬取 hacker news 的文章标题、链接、comment总结
-pub async fn hacker_news_summaries(uuid: &str, save_html: bool, model: &str) -> Result<String, MyError> {
+pub async fn hacker_news_summaries(snapshot: &ConfigSnapshot, uuid: &str, save_html: bool, model: &str) -> Result<String, MyError> {
     let base_url = Url::parse("https://news.ycombinator.com").map_err(|e| MyError::UrlParseError{error: e})?;
 
     let client = reqwest::Client::builder()
@@ -276,7 +267,7 @@ pub async fn hacker_news_summaries(uuid: &str, save_html: bool, model: &str) ->
             let summary = match summary_opt {
                 Some(s) => {
                     // 根据模型名称获取(api_key, endpoint, 模型名称, 是否支持深度思考)
-                    let (api_key, endpoint, m, _) = PARAS.api.get_model_by_name(model)?;
+                    let (api_key, endpoint, m, _) = snapshot.api.get_model_by_name(model)?;
                     let content = format!("{}{}{}", SUMMARY_PROMPT, s, if PARAS.english { "" } else { "\n使用中文总结" });
                     run_single_llm(uuid, content, api_key, endpoint, m).await?
                 },
@@ -304,7 +295,7 @@ pub async fn hacker_news_summaries(uuid: &str, save_html: bool, model: &str) ->
             let summary = match summary_opt {
                 Some(s) => {
                     // 根据模型名称获取(api_key, endpoint, 模型名称, 是否支持深度思考)
-                    let (api_key, endpoint, m, _) = PARAS.api.get_model_by_name(model)?;
+                    let (api_key, endpoint, m, _) = snapshot.api.get_model_by_name(model)?;
                     let content = format!("{}{}{}", SUMMARY_PROMPT, s, if PARAS.english { "" } else { "\n使用中文总结" });
                     run_single_llm(uuid, content, api_key, endpoint, m).await?
                 },
@@ -325,43 +316,3 @@ pub async fn hacker_news_summaries(uuid: &str, save_html: bool, model: &str) ->
         //Ok("get hacker news successfully".to_string())
     }
 }
-
-/// 单词调用LLM
-pub async fn run_single_llm(uuid: &str, content: String, api_key: String, endpoint: String, model: String) -> Result<String, MyError> {
-    // 使用api key初始化
-    let mut client = Client::new(api_key.clone());
-    client.set_base_url(&endpoint); // 从0.7.0开始舍弃了new_with_base
-    let mut para_builder = ChatCompletionParametersBuilder::default();
-    para_builder.model(&model); // 指定模型，例如：Gpt4Engine::Gpt4O.to_string()
-    para_builder.response_format(ChatCompletionResponseFormat::Text);
-    //para_builder.stream(stream); // 这里不需要设置，调用`create_stream`时会设置
-    let lowercase_model = model.to_lowercase();
-    // 关闭思考，不同模型思考的设置不同
-    if lowercase_model.starts_with("deepseek") {
-        // deepseek: https://api-docs.deepseek.com/
-        para_builder.extra_body(json!({"thinking": {"type": "disabled"}}));
-    } else if lowercase_model.starts_with("qwen") {
-        if endpoint.starts_with("http://") { // local model
-            // https://modelscope.cn/models/Qwen/Qwen3.5-397B-A17B
-            // https://modelscope.cn/models/Qwen/Qwen3.6-35B-A3B#instruct-or-non-thinking-mode
-            para_builder.extra_body(json!({"chat_template_kwargs": {"enable_thinking": false}}));
-        } else {
-            // Qwen: https://help.aliyun.com/zh/model-studio/qwen-api-via-openai-chat-completions#05cfceb898csa
-            para_builder.extra_body(json!({"enable_thinking": false}));
-        }
-    } else if lowercase_model.starts_with("kimi") {
-        // kimi: https://platform.kimi.com/docs/api/models-overview
-        para_builder.extra_body(json!({"thinking": {"type": "disabled"}}));
-    } else if lowercase_model.starts_with("glm") {
-        // glm: https://docs.bigmodel.cn/cn/guide/develop/openai/introduction
-        para_builder.extra_body(json!({"thinking": {"type": "disabled"}}));
-    } else if lowercase_model.starts_with("minimax") {
-        // minimax，目前不支持关闭thinking：https://github.com/MiniMax-AI/MiniMax-M2/issues/68
-        //para_builder.extra_body(json!({"reasoning_split": false}));
-    }
-    let summary_prompt = ChatMessage::User{
-        content: ChatMessageContent::Text(content),
-        name: None,
-    };
-    call_llm(vec![summary_prompt], uuid.to_string(), client, para_builder, &model).await
-}
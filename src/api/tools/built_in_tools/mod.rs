@@ -83,6 +83,16 @@ pub trait BuiltIn: Send + Sync {
 
     /// get approval message
     fn get_approval(&self, args: &str, info: Option<String>, is_en: bool) -> Result<Option<String>, MyError>;
+
+    /// get argv of the command to run, only tools running commands return Some, used by command policy
+    fn argv(&self, _args: &str) -> Option<Vec<String>> {
+        None
+    }
+
+    /// whether the tool only reads (files, code, web pages) and changes nothing, read-only calls can run concurrently
+    fn read_only(&self) -> bool {
+        false
+    }
 }
 
 /// tool group
@@ -134,12 +144,14 @@ impl Group {
 }
 
 /// single built-in tool
+#[derive(Clone)]
 pub struct SingleBuiltInTool {
     pub tool: Arc<dyn BuiltIn>, // struct impl BuiltIn
     pub group: Group,
 }
 
 /// all built-in tools
+#[derive(Clone)]
 pub struct BuiltInTools {
     pub id_map: HashMap<String, SingleBuiltInTool>, // key: tool id, value: SingleBuiltInTool
     pub groups: HashSet<Group>, // tool groups
@@ -229,6 +241,16 @@ impl BuiltInTools {
         }
         Ok(selected_tools)
     }
+
+    /// get argv of the command to run
+    pub fn argv(&self, id: &str, args: &str) -> Option<Vec<String>> {
+        self.id_map.get(id).and_then(|t| t.tool.argv(args))
+    }
+
+    /// check tool is read-only
+    pub fn read_only(&self, id: &str) -> bool {
+        self.id_map.get(id).map(|t| t.tool.read_only()).unwrap_or(false)
+    }
 }
 
 impl MyTools for BuiltInTools {
@@ -10,6 +10,8 @@ use crate::{
         parse_tool_args,
         ArgFixSpec,
         built_in_tools::BuiltIn,
+        process::run_limited,
+        sandbox::approval_note,
     },
 };
 
@@ -31,6 +33,13 @@ impl RunCommand {
     }
 }
 
+/// 命令的argv，有些模型会把命令和参数都写在`command`里
+fn command_argv(params: &Params) -> Vec<String> {
+    let mut argv: Vec<String> = params.command.split(" ").map(|s| s.to_string()).collect();
+    argv.extend(params.args.clone().unwrap_or_default());
+    argv
+}
+
 impl BuiltIn for RunCommand {
     /// get tool name
     fn name(&self) -> String {
@@ -98,11 +107,8 @@ impl BuiltIn for RunCommand {
         };
 
         // 执行并捕获输出
-        // 注意：使用 .output() 比 spawn -> wait -> read 更安全，可避免管道缓冲区死锁
-        let output = tool_cmd
-            .output()
-            //.await
-            .map_err(|e| MyError::CommandError{info: format!("failed to execute `{}{}`: {:?}", &params.command, args_string, e)})?;
+        // 在单独的线程读取 stdout 和 stderr，避免管道缓冲区死锁，超时则结束进程组，输出超过限制则截断
+        let output = run_limited(tool_cmd, "run_command", None, &format!("{}{}", &params.command, args_string))?;
 
         // 检查状态
         if !output.status.success() {
@@ -110,8 +116,8 @@ impl BuiltIn for RunCommand {
         }
 
         // 格式化结果
-        let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
-        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        let stdout = output.stdout.trim().to_string();
+        let stderr = output.stderr.trim().to_string();
 
         Ok(match (stderr.is_empty(), stdout.is_empty()) {
             (false, false) => (format!("stdout:\n{}stderr:\n{}", stderr, stdout), None),
@@ -126,9 +132,15 @@ impl BuiltIn for RunCommand {
         //let params: Params = serde_json::from_str(args).map_err(|e| MyError::SerdeJsonFromStrError{error: e})?;
         let params: Params = parse_tool_args(args, ArgFixSpec{ array_fields: Some(vec!["args".to_string()]), object_fields: None })?;
         if is_en {
-            Ok(Some(format!("Do you allow running this command: {} {}?{}", params.command, params.args.unwrap_or_default().join(" "), info.unwrap_or_default())))
+            Ok(Some(format!("Do you allow running this command: {} {}?{}{}", params.command, params.args.unwrap_or_default().join(" "), info.unwrap_or_default(), approval_note("run_command", is_en))))
         } else {
-            Ok(Some(format!("是否允许调用该命令：{} {}？{}", params.command, params.args.unwrap_or_default().join(" "), info.unwrap_or_default())))
+            Ok(Some(format!("是否允许调用该命令：{} {}？{}{}", params.command, params.args.unwrap_or_default().join(" "), info.unwrap_or_default(), approval_note("run_command", is_en))))
         }
     }
+
+    /// get argv of the command to run
+    fn argv(&self, args: &str) -> Option<Vec<String>> {
+        let params: Params = parse_tool_args(args, ArgFixSpec{ array_fields: Some(vec!["args".to_string()]), object_fields: None }).ok()?;
+        Some(command_argv(&params))
+    }
 }
@@ -11,6 +11,8 @@ use crate::{
         parse_tool_args,
         ArgFixSpec,
         built_in_tools::BuiltIn,
+        process::run_limited,
+        sandbox::approval_note,
     },
     skills::command_exists,
 };
@@ -135,11 +137,8 @@ impl BuiltIn for RunScript {
         */
 
         // 执行并捕获输出
-        // 注意：使用 .output() 比 spawn -> wait -> read 更安全，可避免管道缓冲区死锁
-        let output = tool_cmd
-            .output() // 捕获 stdout 和 stderr
-            //.await
-            .map_err(|e| MyError::CommandError{info: format!("failed to execute `{} {}{}`: {:?}", interpreter, &params.script, args_string, e)})?;
+        // 在单独的线程读取 stdout 和 stderr，避免管道缓冲区死锁，超时则结束进程组，输出超过限制则截断
+        let output = run_limited(tool_cmd, "run_script", None, &format!("{} {}{}", interpreter, &params.script, args_string))?;
 
         // 检查状态
         if !output.status.success() {
@@ -147,8 +146,8 @@ impl BuiltIn for RunScript {
         }
 
         // 格式化结果
-        let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
-        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        let stdout = output.stdout.trim().to_string();
+        let stderr = output.stderr.trim().to_string();
 
         Ok(match (stderr.is_empty(), stdout.is_empty()) {
             (false, false) => (format!("stdout:\n{}stderr:\n{}", stderr, stdout), None),
@@ -164,11 +163,25 @@ impl BuiltIn for RunScript {
         let params: Params = parse_tool_args(args, ArgFixSpec{ array_fields: Some(vec!["args".to_string()]), object_fields: None })?;
         let interpreter = get_interpreter(&params.script)?;
         if is_en {
-            Ok(Some(format!("Do you allow running this script: {} {} {}?{}", interpreter, params.script, params.args.unwrap_or_default().join(" "), info.unwrap_or_default())))
+            Ok(Some(format!("Do you allow running this script: {} {} {}?{}{}", interpreter, params.script, params.args.unwrap_or_default().join(" "), info.unwrap_or_default(), approval_note("run_script", is_en))))
         } else {
-            Ok(Some(format!("是否允许调用该脚本：{} {} {}？{}", interpreter, params.script, params.args.unwrap_or_default().join(" "), info.unwrap_or_default())))
+            Ok(Some(format!("是否允许调用该脚本：{} {} {}？{}{}", interpreter, params.script, params.args.unwrap_or_default().join(" "), info.unwrap_or_default(), approval_note("run_script", is_en))))
         }
     }
+
+    /// get argv of the command to run
+    fn argv(&self, args: &str) -> Option<Vec<String>> {
+        let params: Params = parse_tool_args(args, ArgFixSpec{ array_fields: Some(vec!["args".to_string()]), object_fields: None }).ok()?;
+        let interpreter = get_interpreter(&params.script).ok()?;
+        Some(script_argv(&interpreter, &params))
+    }
+}
+
+/// 脚本的argv：[解释器, 脚本, 参数...]
+fn script_argv(interpreter: &str, params: &Params) -> Vec<String> {
+    let mut argv = vec![interpreter.to_string(), params.script.clone()];
+    argv.extend(params.args.clone().unwrap_or_default());
+    argv
 }
 
 /// 获取调用脚本的程序
@@ -5,7 +5,7 @@ use tokio::sync::oneshot;
 use tracing::{event, Level};
 
 use crate::{
-    parse_paras::PARAS,
+    parse_paras::{PARAS, ConfigSnapshot},
     error::MyError,
     tools::{
         parse_tool_args,
@@ -167,7 +167,7 @@ impl BuiltIn for ScheduleTask {
 }
 
 /// 向后台任务调度器发送任务，并等待结果
-pub async fn run_schedule_task(args: &str) -> Result<(String, Option<String>), MyError> {
+pub async fn run_schedule_task(snapshot: &ConfigSnapshot, args: &str) -> Result<(String, Option<String>), MyError> {
     let params: Params = serde_json::from_str(args).map_err(|e| MyError::SerdeJsonFromStrError{error: e})?;
     let cmd_tx = get_cmd_tx().unwrap();
 
@@ -201,7 +201,7 @@ pub async fn run_schedule_task(args: &str) -> Result<(String, Option<String>), M
 
             let (tool_name, tool_id) = match tool_name.split_once("__") {
                 Some((t_name, t_id)) => (t_name.to_string(), t_id.to_string()), // 获取工具 id，`工具名__后缀`
-                None => match PARAS.tools.get_tool_id_by_name(&tool_name) {
+                None => match snapshot.tools.get_tool_id_by_name(&tool_name) {
                     Some(t_id) => (tool_name, t_id),
                     None => return Err(MyError::OtherError{info: format!("schedule can not find tool '{tool_name}', not exist")}), // 没有后缀，仅含有工具名，则通过工具名获取 id
                 },
@@ -166,7 +166,7 @@ impl SimpleScheduler {
 
                 // 🔥 在这里调用你的 agent 工具系统
                 //    例如: agent.call_tool(&job.tool_name, &job.tool_args).await
-                match PARAS.tools.run(&job.tool_id, &job.tool_args) {
+                match PARAS.snapshot().tools.run(&job.tool_id, &job.tool_args) {
                     Ok(r) => event!(Level::INFO, "Job '{}' call tool '{}' successfull: {}", job.name, job.tool_name, r.0),
                     Err(e) => event!(Level::ERROR, "Job '{}' call tool '{}' failed: {}", job.name, job.tool_name, e),
                 }
@@ -74,4 +74,9 @@ impl BuiltIn for GetWebPageContent {
             Ok(Some(format!("是否允许调用 get_web_page_content 工具访问 {} ？{}", params.url, info.unwrap_or_default())))
         }
     }
+
+    /// read-only
+    fn read_only(&self) -> bool {
+        true
+    }
 }
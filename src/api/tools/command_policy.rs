@@ -0,0 +1,422 @@
+use std::fs::read_to_string;
+use std::path::Path;
+
+use ron::de::from_str;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::error::MyError;
+
+// 命令策略：不弹窗直接决定run_command、run_script和MCP工具的调用，参数文件`command_policy`指定策略文件
+// 优先级：deny（总是拒绝） > ask（总是确认，即使指定了`approval_all`） > allow（自动允许） > 没有匹配则按原来的流程确认
+
+/// 命令策略文件，ron格式
+/// 命令规则按空格分词后与命令的argv比较，`*`单独作为一个词表示任意多个词，词中的`*`匹配任意字符，程序只比较文件名（`/usr/bin/sudo`为`sudo`），`env`、`nohup`、`xargs`等包装程序之后的程序也只比较文件名
+/// deny和ask出现在命令任意位置即匹配（包括`sh -c "..."`中的命令和shell脚本中的每一行），allow需要管道和`;`、`&&`分隔的每一段命令都以规则开头
+/// MCP规则为`工具名称`或`工具名称 参数`，参数与去掉空白的json参数比较，`*`匹配任意字符
+/// ```ron
+/// (
+///     allow: ["ls", "git status", "git diff", "cargo check", "cat *"],
+///     deny: ["rm -rf /", "curl * | sh", "wget * | sh", "sudo"],
+///     ask: ["git push", "cargo publish"],
+///     mcp_allow: ["read_*", "list_*"],
+///     mcp_deny: ["delete_*"],
+///     mcp_ask: ["write_file *\"/etc/*"],
+/// )
+/// ```
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CommandPolicy {
+    pub allow:     Vec<String>, // 自动允许的命令
+    pub deny:      Vec<String>, // 总是拒绝的命令
+    pub ask:       Vec<String>, // 总是需要确认的命令
+    pub mcp_allow: Vec<String>, // 自动允许的MCP工具
+    pub mcp_deny:  Vec<String>, // 总是拒绝的MCP工具
+    pub mcp_ask:   Vec<String>, // 总是需要确认的MCP工具
+}
+
+/// 策略的决定
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PolicyAction {
+    Allow,
+    Deny,
+    Ask,
+}
+
+/// 策略的决定以及匹配的规则
+#[derive(Clone, Debug)]
+pub struct PolicyDecision {
+    pub action: PolicyAction,
+    pub rule:   String,
+}
+
+impl PolicyDecision {
+    /// 说明决定的原因，用于日志、确认弹窗和返回给模型的错误信息
+    pub fn explain(&self, is_en: bool) -> String {
+        match (self.action, is_en) {
+            (PolicyAction::Allow, true) => format!("auto-approved by command policy rule `{}`", self.rule),
+            (PolicyAction::Deny, true) => format!("denied by command policy rule `{}`", self.rule),
+            (PolicyAction::Ask, true) => format!("command policy rule `{}` always asks for approval", self.rule),
+            (PolicyAction::Allow, false) => format!("命令策略规则`{}`自动允许", self.rule),
+            (PolicyAction::Deny, false) => format!("命令策略规则`{}`拒绝", self.rule),
+            (PolicyAction::Ask, false) => format!("命令策略规则`{}`总是需要确认", self.rule),
+        }
+    }
+}
+
+impl CommandPolicy {
+    /// 读取策略文件
+    pub fn from_file(file: &str) -> Result<Self, MyError> {
+        let content = read_to_string(file).map_err(|e| MyError::ReadFileError{file: file.to_string(), error: e})?;
+        from_str(&content).map_err(|e| MyError::ParaError{para: format!("parse command policy file {} error: {}", file, e)})
+    }
+
+    /// 是否没有任何规则
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty() && self.ask.is_empty() && self.mcp_allow.is_empty() && self.mcp_deny.is_empty() && self.mcp_ask.is_empty()
+    }
+
+    /// 根据命令的argv决定，没有匹配的规则返回None
+    pub fn decide_command(&self, argv: &[String]) -> Option<PolicyDecision> {
+        let words = command_words(argv);
+        // shell脚本的每一行也检查deny和ask
+        let script_lines: Vec<Vec<String>> = script_file(&words).and_then(|f| read_to_string(f).ok()).map(|s| {
+            s.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with('#')).map(|l| command_words(&[l.to_string()])).collect()
+        }).unwrap_or_default();
+        for (rules, action) in [(&self.deny, PolicyAction::Deny), (&self.ask, PolicyAction::Ask)] {
+            if let Some(rule) = rules.iter().find(|r| {
+                let pattern = split_words(r);
+                contains_words(&pattern, &words) || script_lines.iter().any(|l| contains_words(&pattern, l))
+            }) {
+                return Some(PolicyDecision{action, rule: rule.clone()})
+            }
+        }
+        // 命令替换和重定向（`>`、`>>`、`<`、`&>`、`2>`等，`>`和`<`可能在词中任意位置）不自动允许
+        if words.is_empty() || words.iter().any(|w| w.contains("$(") || w.contains('`') || w.contains('>') || w.contains('<')) {
+            return None
+        }
+        let mut matched = vec![];
+        for segment in words.split(|w| is_operator(w)).filter(|s| !s.is_empty()) {
+            let segment = strip_shell(segment);
+            match self.allow.iter().find(|r| starts_with_words(&split_words(r), segment)) {
+                Some(rule) => matched.push(rule.clone()),
+                None => return None,
+            }
+        }
+        matched.dedup();
+        Some(PolicyDecision{action: PolicyAction::Allow, rule: matched.join("`, `")})
+    }
+
+    /// 根据MCP工具名称和参数决定，没有匹配的规则返回None
+    pub fn decide_mcp(&self, tool: &str, args: &str) -> Option<PolicyDecision> {
+        // 去掉json参数中的空白，方便书写规则
+        let args = serde_json::from_str::<Value>(args).map(|v| v.to_string()).unwrap_or(args.to_string());
+        for (rules, action) in [(&self.mcp_deny, PolicyAction::Deny), (&self.mcp_ask, PolicyAction::Ask), (&self.mcp_allow, PolicyAction::Allow)] {
+            if let Some(rule) = rules.iter().find(|r| {
+                let (name, pattern) = r.trim().split_once(char::is_whitespace).unwrap_or((r.trim(), "*"));
+                glob(name, tool) && glob(pattern.trim(), &args)
+            }) {
+                return Some(PolicyDecision{action, rule: rule.clone()})
+            }
+        }
+        None
+    }
+}
+
+/// 执行命令前按本次请求的配置中的策略检查，被拒绝则返回错误，确认流程之外（例如`approval_all`）也不会执行被拒绝的命令
+pub fn check_command(policy: &CommandPolicy, tool: &str, argv: &[String]) -> Result<(), MyError> {
+    match policy.decide_command(argv) {
+        Some(d) if d.action == PolicyAction::Deny => Err(MyError::PolicyDeniedError{tool: tool.to_string(), info: d.explain(true)}),
+        _ => Ok(()),
+    }
+}
+
+/// 确认弹窗中说明策略的决定，没有指定策略文件则为空
+pub fn policy_note(policy: &CommandPolicy, decision: Option<&PolicyDecision>, is_en: bool) -> String {
+    if policy.is_empty() {
+        return String::new()
+    }
+    match (decision, is_en) {
+        (Some(d), _) => format!("\n📋 {}", d.explain(is_en)),
+        (None, true) => "\n📋 no command policy rule matched".to_string(),
+        (None, false) => "\n📋 没有匹配的命令策略规则".to_string(),
+    }
+}
+
+/// 执行其后命令的包装程序，其后的程序也只保留文件名，例如`env /bin/rm`、`nohup /usr/bin/curl`
+const WRAPPERS: [&str; 9] = ["env", "nice", "nohup", "command", "exec", "xargs", "time", "timeout", "sudo"];
+
+/// 命令argv中词的位置
+#[derive(Clone, Copy, PartialEq)]
+enum WordState {
+    Program,             // 程序
+    Wrapper(usize),      // 包装程序的参数，值是包装程序在WRAPPERS中的索引
+    WrapperValue(usize), // 包装程序选项的值，例如`nice -n 10`中的`10`
+    Shell,               // `sh`、`bash`之后，`-c`之后是命令
+    Args,                // 程序的参数
+}
+
+/// 包装程序中需要值的选项，选项之后的词不是程序
+fn wrapper_value_option(wrapper: usize, option: &str) -> bool {
+    let options: &[&str] = match WRAPPERS[wrapper] {
+        "env"     => &["-u", "-C", "-S", "--unset", "--chdir", "--split-string"],
+        "nice"    => &["-n", "--adjustment"],
+        "exec"    => &["-a"],
+        "xargs"   => &["-I", "-n", "-L", "-P", "-d", "-s", "-E", "-a", "--max-args", "--max-procs", "--delimiter", "--arg-file"],
+        "time"    => &["-f", "-o", "--format", "--output"],
+        "timeout" => &["-s", "-k", "--signal", "--kill-after"],
+        "sudo"    => &["-u", "-g", "-C", "-h", "-p", "-r", "-t", "-U", "-D", "--user", "--group", "--host", "--prompt", "--chdir"],
+        _         => &[],
+    };
+    options.contains(&option)
+}
+
+/// 命令argv分词，每个参数再按shell规则分词（`sh -c "..."`中的命令也能匹配），程序只保留文件名
+/// 跳过包装程序的选项、环境变量赋值和`timeout`的时长，其后的词作为程序
+fn command_words(argv: &[String]) -> Vec<String> {
+    let mut words: Vec<String> = argv.iter().flat_map(|a| split_words(a)).collect();
+    let mut state = WordState::Program;
+    for w in words.iter_mut() {
+        if is_operator(w) {
+            state = WordState::Program;
+            continue
+        }
+        state = match state {
+            WordState::WrapperValue(i) => WordState::Wrapper(i),
+            WordState::Wrapper(i) if w.starts_with('-') => if wrapper_value_option(i, w) { WordState::WrapperValue(i) } else { WordState::Wrapper(i) },
+            WordState::Wrapper(i) if w.contains('=') || w.starts_with(|c: char| c.is_ascii_digit()) => WordState::Wrapper(i),
+            WordState::Wrapper(_) | WordState::Program => {
+                *w = program_name(w);
+                match WRAPPERS.iter().position(|x| x == w) {
+                    Some(i) => WordState::Wrapper(i),
+                    None if ["sh", "bash", "zsh", "dash"].contains(&w.as_str()) => WordState::Shell,
+                    None => WordState::Args,
+                }
+            },
+            WordState::Shell if is_command_option(w) => WordState::Program,
+            WordState::Shell => WordState::Shell,
+            WordState::Args => WordState::Args,
+        };
+    }
+    words
+}
+
+/// 按空白分词，支持单引号和双引号，`|`、`;`、`&`作为单独的词
+/// 与POSIX sh相同处理`\`：引号外转义任意字符（`c\url`即`curl`），双引号内只转义`$`、`` ` ``、`"`、`\`，单引号内不转义，`\`加换行是续行
+fn split_words(s: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some('"') if c == '\\' => match chars.peek() {
+                Some('$' | '`' | '"' | '\\') => current.push(chars.next().unwrap()),
+                Some('\n') => {
+                    chars.next();
+                },
+                _ => current.push(c),
+            },
+            Some(_) => current.push(c),
+            None => match c {
+                '\'' | '"' => quote = Some(c),
+                '\\' => match chars.next() {
+                    Some('\n') => (),
+                    Some(n) => current.push(n),
+                    None => current.push(c),
+                },
+                '|' | ';' | '&' | '\n' => {
+                    if !current.is_empty() {
+                        words.push(std::mem::take(&mut current));
+                    }
+                    let mut op = if c == '\n' { ';'.to_string() } else { c.to_string() };
+                    if (c == '|' || c == '&') && chars.peek() == Some(&c) {
+                        op.push(chars.next().unwrap());
+                    }
+                    words.push(op);
+                },
+                c if c.is_whitespace() => if !current.is_empty() {
+                    words.push(std::mem::take(&mut current));
+                },
+                c => current.push(c),
+            },
+        }
+    }
+    if !current.is_empty() {
+        words.push(current);
+    }
+    words
+}
+
+/// 是否为分隔命令的符号
+fn is_operator(w: &str) -> bool {
+    matches!(w, "|" | "||" | "&" | "&&" | ";")
+}
+
+/// 程序的文件名，去掉路径和windows的`.exe`后缀
+fn program_name(w: &str) -> String {
+    let name = Path::new(w).file_name().and_then(|n| n.to_str()).unwrap_or(w);
+    name.strip_suffix(".exe").unwrap_or(name).to_string()
+}
+
+/// shell执行命令的选项：`-c`，或包含`c`的短选项组合，例如`-lc`、`-ic`、`-ec`
+fn is_command_option(w: &str) -> bool {
+    w.starts_with('-') && !w.starts_with("--") && w.contains('c')
+}
+
+/// 去掉开头的`sh -c`、`bash -lc`、`bash -l -c`等，比较实际执行的命令
+fn strip_shell(segment: &[String]) -> &[String] {
+    if segment.len() > 2 && ["sh", "bash", "zsh", "dash"].contains(&segment[0].as_str()) {
+        for (i, w) in segment.iter().enumerate().skip(1).take_while(|(_, w)| w.starts_with('-')) {
+            if is_command_option(w) && i + 1 < segment.len() {
+                return &segment[i+1..]
+            }
+        }
+    }
+    segment
+}
+
+/// `bash script.sh`这类命令的脚本文件
+fn script_file(words: &[String]) -> Option<&str> {
+    match words {
+        [shell, script, ..] if ["sh", "bash", "zsh", "dash"].contains(&shell.as_str()) && (script.ends_with(".sh") || script.ends_with(".bash")) => Some(script),
+        _ => None,
+    }
+}
+
+/// 命令是否以规则开头
+fn starts_with_words(pattern: &[String], words: &[String]) -> bool {
+    match pattern.split_first() {
+        None => true,
+        Some((p, rest)) if p == "*" => (0..=words.len()).any(|i| starts_with_words(rest, &words[i..])),
+        Some((p, rest)) => match words.split_first() {
+            Some((w, words_rest)) => glob(p, w) && starts_with_words(rest, words_rest),
+            None => false,
+        },
+    }
+}
+
+/// 命令任意位置是否包含规则
+fn contains_words(pattern: &[String], words: &[String]) -> bool {
+    !pattern.is_empty() && (0..words.len()).any(|i| starts_with_words(pattern, &words[i..]))
+}
+
+/// 通配符匹配，`*`匹配任意字符
+fn glob(pattern: &str, text: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == text,
+        Some((head, rest)) => {
+            if !text.starts_with(head) {
+                return false
+            }
+            let text = &text[head.len()..];
+            if rest.is_empty() {
+                return true
+            }
+            text.char_indices().map(|(i, _)| i).chain([text.len()]).any(|i| glob(rest, &text[i..]))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> CommandPolicy {
+        CommandPolicy {
+            allow: vec!["ls".to_string(), "git status".to_string(), "cat *".to_string(), "echo *".to_string()],
+            deny:  vec!["rm -rf /".to_string(), "curl".to_string(), "sudo".to_string()],
+            ask:   vec!["git push".to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn action(command: &str) -> Option<PolicyAction> {
+        policy().decide_command(&[command.to_string()]).map(|d| d.action)
+    }
+
+    #[test]
+    fn test_deny_through_wrappers() {
+        for command in [
+            "curl http://x",
+            "/usr/bin/curl http://x",
+            "env /usr/bin/curl http://x",
+            "env -i FOO=1 /usr/bin/curl http://x",
+            "env -u HOME /usr/bin/curl http://x",
+            "nice -n 10 /usr/bin/curl http://x",
+            "nohup /usr/bin/curl http://x",
+            "command /usr/bin/curl http://x",
+            "exec -a name /usr/bin/curl http://x",
+            "xargs -I {} /usr/bin/curl {}",
+            "time -p /usr/bin/curl http://x",
+            "timeout -s KILL 5s /usr/bin/curl http://x",
+            "nohup nice /usr/bin/curl http://x",
+            "ls && /usr/bin/curl http://x",
+            "/bin/rm -rf /",
+            "env /bin/rm -rf /",
+            "/usr/bin/sudo ls",
+            r"c\url http://x",
+            r"/usr/bin/cu\rl http://x",
+            r"env \curl http://x",
+        ] {
+            assert_eq!(action(command), Some(PolicyAction::Deny), "{}", command);
+        }
+        // `sh -c "..."`的命令是单独的argv
+        for argv in [["sh", "-c", "/usr/bin/curl http://x"], ["bash", "-lc", "env /usr/bin/curl http://x"], ["/bin/bash", "-c", "nohup /usr/bin/sudo ls"], ["sh", "-ec", r"c\url http://x"]] {
+            let argv: Vec<String> = argv.iter().map(|a| a.to_string()).collect();
+            assert_eq!(policy().decide_command(&argv).map(|d| d.action), Some(PolicyAction::Deny), "{:?}", argv);
+        }
+    }
+
+    #[test]
+    fn test_redirect_not_allowed() {
+        for command in [
+            "echo hi > out.txt",
+            "echo hi >out.txt",
+            "echo hi >> out.txt",
+            "echo hi 2>err.txt",
+            "echo hi &> out.txt",
+            "echo hi 1>&2",
+            "cat < /etc/passwd",
+            "cat 0</etc/passwd",
+            "ls $(whoami)",
+            "ls `whoami`",
+        ] {
+            assert_eq!(action(command), None, "{}", command);
+        }
+    }
+
+    #[test]
+    fn test_allow_and_ask() {
+        for (command, expected) in [
+            ("ls -la", Some(PolicyAction::Allow)),
+            ("/bin/ls -la", Some(PolicyAction::Allow)),
+            ("git status | cat", Some(PolicyAction::Allow)),
+            ("ls; git push", Some(PolicyAction::Ask)),
+            ("env git push", Some(PolicyAction::Ask)),
+            ("ls && rm a", None),
+            ("env ls", None),
+        ] {
+            assert_eq!(action(command), expected, "{}", command);
+        }
+        for (argv, expected) in [
+            (vec!["bash", "-lc", "ls -la"], Some(PolicyAction::Allow)),
+            (vec!["sh", "-ic", "git status | cat"], Some(PolicyAction::Allow)),
+            (vec!["bash", "-l", "-c", "ls"], Some(PolicyAction::Allow)),
+            (vec!["bash", "--norc", "-c", "ls"], Some(PolicyAction::Allow)),
+            (vec!["bash", "-lc", "rm a"], None),
+        ] {
+            let argv: Vec<String> = argv.iter().map(|a| a.to_string()).collect();
+            assert_eq!(policy().decide_command(&argv).map(|d| d.action), expected, "{:?}", argv);
+        }
+    }
+
+    #[test]
+    fn test_split_words_escape() {
+        assert_eq!(split_words(r#"c\url 'a\b' "x\"y\z" a\ b"#), vec!["curl", r"a\b", r#"x"y\z"#, "a b"]);
+        assert_eq!(split_words("ls \\\n-la"), vec!["ls", "-la"]); // 续行
+        assert_eq!(split_words(r"echo a\|b"), vec!["echo", "a|b"]);
+        assert_eq!(split_words(r"ls\"), vec![r"ls\"]);
+    }
+}
@@ -1,6 +1,5 @@
 use std::collections::HashMap;
-use std::io::Read;
-use std::process::{Command, Stdio};
+use std::process::Command;
 
 use serde::Deserialize;
 use serde_json::Value;
@@ -8,7 +7,13 @@ use uuid::Uuid;
 
 use crate::{
     error::MyError,
-    tools::MyTools,
+    tools::{
+        MyTools,
+        ArgFixSpec,
+        parse_tool_args,
+        process::{run_limited, run_limited_with_stdin, limit_output},
+        sandbox::approval_note,
+    },
 };
 
 /// single external tool
@@ -22,6 +27,32 @@ pub struct SingleExternalTool {
     pub schema:      Value, // for LLM api function calling
     #[serde(default)]
     pub approval:    bool, // ask for approval
+    #[serde(default)]
+    pub timeout:     Option<u64>, // 超时秒数，优先于参数文件中的`command_limit`
+    #[serde(default)]
+    pub read_only:   bool, // 只读，可以与其他只读工具调用并发运行
+    #[serde(default)]
+    pub protocol:    String, // 参数传递方式：空或`args`将参数转为`--key value`，`json`将参数作为json写入stdin，从stdout读取json结果
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env:         Option<HashMap<String, String>>, // 额外的环境变量
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<String>, // 工作目录，默认为chatsong的工作目录
+}
+
+/// `protocol: "json"`的外部工具stdout的最大字节数，完整读取后解析json，超过则视为出错，解析后的`text`再按`command_limit.max_output_kb`截断
+const MAX_JSON_STDOUT: usize = 16 * 1024 * 1024;
+
+/// `protocol: "json"`的外部工具输出到stdout的结果，所有字段都是可选的
+/// ```json
+/// {"text": "summary of the result", "files": ["./out/report.csv"], "images": ["./out/plot.png"], "error": null}
+/// ```
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct JsonToolResult {
+    text:   String,         // 返回给模型的结果
+    files:  Vec<String>,    // 生成或修改的文件
+    images: Vec<String>,    // 生成的图片，第一张随结果发送给模型，其他的模型可以通过load_image查看
+    error:  Option<String>, // 出错信息，不为空则作为调用失败告知模型
 }
 
 impl SingleExternalTool {
@@ -33,6 +64,15 @@ impl SingleExternalTool {
         if !self.args.is_empty() {
             tool_cmd.args(&self.args);
         }
+        if let Some(envs) = &self.env {
+            tool_cmd.envs(envs);
+        }
+        if let Some(dir) = &self.working_dir {
+            tool_cmd.current_dir(dir);
+        }
+        if self.protocol == "json" {
+            return self.run_json(tool_cmd, args)
+        }
         if !args.is_empty() {
             let mut args_vec: Vec<String> = Vec::new();
             let mut value: String;
@@ -51,8 +91,8 @@ impl SingleExternalTool {
                         },
                         Value::Number(n) => value = n.to_string(),
                         Value::String(s) => value = s,
-                        Value::Array(_) => return Err(MyError::OtherError{info: format!("external tool args value only support string, number, boolean, not Array: {}", args)}),
-                        Value::Object(_) => return Err(MyError::OtherError{info: format!("external tool args value only support string, number, boolean, not Object: {}", args)}),
+                        Value::Array(_) => return Err(MyError::OtherError{info: format!("external tool args value only support string, number, boolean, not Array, set `protocol: \"json\"` to pass arrays: {}", args)}),
+                        Value::Object(_) => return Err(MyError::OtherError{info: format!("external tool args value only support string, number, boolean, not Object, set `protocol: \"json\"` to pass objects: {}", args)}),
                     }
                     // push to vec
                     args_vec.push(format!("--{k}"));
@@ -66,40 +106,23 @@ impl SingleExternalTool {
             //println!("\n\n{:?}\n\n", args_vec);
             tool_cmd.args(&args_vec);
         }
-        tool_cmd
-            .stdout(Stdio::piped()) // pipe stdout
-            .stderr(Stdio::piped()); // pipe stderr
-        let mut tool_cmd = tool_cmd
-            .spawn()
-            .map_err(|e| MyError::CommandError{info: format!("failed to execute {} ({}): {:?}", &self.name, &self.command, e)})?;
-        // wait and check status
-        let status = tool_cmd.wait()?;
-        if !status.success() {
-            return Err(MyError::CommandError{info: format!("execute {} ({}) failed with status: {}", &self.name, &self.command, status)})
+        // 执行并捕获输出，超时则结束进程组，输出超过限制则截断
+        let output = run_limited(tool_cmd, &self.name, self.timeout, &format!("{} ({})", self.name, self.command))?;
+        // check status
+        if !output.status.success() {
+            return Err(MyError::CommandError{info: format!("execute {} ({}) failed with status: {}", &self.name, &self.command, output.status)})
         }
         // stderr
-        let stderr: Option<String> = if let Some(mut stderr) = tool_cmd.stderr {
-            let mut buffer = String::new();
-            stderr.read_to_string(&mut buffer)?;
-            if buffer.is_empty() {
-                None
-            } else {
-                Some(format!("stderr:\n{:?}", buffer.trim()))
-            }
-        } else {
+        let stderr: Option<String> = if output.stderr.is_empty() {
             None
+        } else {
+            Some(format!("stderr:\n{:?}", output.stderr.trim()))
         };
         // stdout
-        let stdout: Option<String> = if let Some(mut stdout) = tool_cmd.stdout {
-            let mut buffer = String::new();
-            stdout.read_to_string(&mut buffer)?;
-            if buffer.is_empty() {
-                None
-            } else {
-                Some(format!("stdout:\n{:?}", buffer.trim()))
-            }
-        } else {
+        let stdout: Option<String> = if output.stdout.is_empty() {
             None
+        } else {
+            Some(format!("stdout:\n{:?}", output.stdout.trim()))
         };
         // result
         Ok(match (stderr, stdout) {
@@ -109,6 +132,46 @@ impl SingleExternalTool {
             (None, None)       => ("".to_string(), None),
         })
     }
+
+    /// `protocol: "json"`：参数作为json写入stdin（支持数组和对象），从stdout读取json结果
+    /// 返回(结果, 第一张图片的路径)，图片读取为base64随结果一起发送给模型
+    fn run_json(&self, tool_cmd: Command, args: &str) -> Result<(String, Option<String>), MyError> {
+        // 按schema修复模型把数组或对象多包了一层字符串的参数
+        let input: Value = if args.trim().is_empty() {
+            Value::Object(serde_json::Map::new())
+        } else {
+            parse_tool_args(args, ArgFixSpec::from_schema(&self.schema))?
+        };
+        let label = format!("{} ({})", self.name, self.command);
+        let output = run_limited_with_stdin(tool_cmd, &self.name, self.timeout, &label, Some(input.to_string().into_bytes()), Some(MAX_JSON_STDOUT))?;
+        if output.stdout.len() > MAX_JSON_STDOUT {
+            return Err(MyError::CommandError{info: format!("{} printed more than {} MB to stdout, the json result is too large", label, MAX_JSON_STDOUT / 1024 / 1024)})
+        }
+        let stderr = if output.stderr.trim().is_empty() {
+            String::new()
+        } else {
+            format!("\nstderr:\n{}", output.stderr.trim())
+        };
+        let result: JsonToolResult = match serde_json::from_str(output.stdout.trim()) {
+            Ok(r) => r,
+            Err(e) if output.status.success() => return Err(MyError::CommandError{info: format!("{} should print a json result to stdout, parse error: {}\nstdout:\n{}{}", label, e, limit_output(output.stdout.trim()), stderr)}),
+            Err(_) => return Err(MyError::CommandError{info: format!("execute {} failed with status: {}{}", label, output.status, stderr)}),
+        };
+        if let Some(error) = result.error.filter(|e| !e.trim().is_empty()) {
+            return Err(MyError::CommandError{info: format!("{} returned error: {}", label, error)})
+        }
+        if !output.status.success() {
+            return Err(MyError::CommandError{info: format!("execute {} failed with status: {}{}", label, output.status, stderr)})
+        }
+        let mut content = limit_output(&result.text);
+        if !result.files.is_empty() {
+            content += &format!("\nfiles:\n{}", result.files.iter().map(|f| format!("- {}", f)).collect::<Vec<String>>().join("\n"));
+        }
+        if !result.images.is_empty() {
+            content += &format!("\nimages (the first one is attached, view the others with load_image):\n{}", result.images.iter().map(|f| format!("- {}", f)).collect::<Vec<String>>().join("\n"));
+        }
+        Ok((content.trim().to_string(), result.images.into_iter().next()))
+    }
 }
 
 /// all external tools
@@ -118,8 +181,19 @@ pub struct ExternalTools {
 
 impl ExternalTools {
     pub fn new(external_tools: Vec<SingleExternalTool>) -> Self {
-        let tmp_uuid = Uuid::new_v4().to_string();
-        Self {id_map: external_tools.into_iter().map(|t| (tmp_uuid[0..8].to_string(), t)).collect::<HashMap<String, SingleExternalTool>>()}
+        Self {id_map: external_tools.into_iter().map(|t| {
+            let tmp_uuid = Uuid::new_v4().to_string();
+            (tmp_uuid[0..8].to_string(), t)
+        }).collect::<HashMap<String, SingleExternalTool>>()}
+    }
+
+    /// 重载参数文件后，与旧工具同名的工具沿用旧id，已打开页面选择的工具仍然可用
+    pub fn keep_old_id(self, old: &ExternalTools) -> Self {
+        let old_ids: HashMap<&str, &str> = old.id_map.iter().map(|(k, v)| (v.name.as_str(), k.as_str())).collect();
+        Self {id_map: self.id_map.into_iter().map(|(k, v)| match old_ids.get(v.name.as_str()) {
+            Some(id) => (id.to_string(), v),
+            None => (k, v),
+        }).collect::<HashMap<String, SingleExternalTool>>()}
     }
 }
 
@@ -151,9 +225,9 @@ impl MyTools for ExternalTools {
         match self.id_map.get(id) {
             Some(tool) => if tool.approval {
                 if is_en {
-                   Ok(Some(format!("Do you allow calling the {} ({}) tool?{}", tool.name, args, info.unwrap_or_default())))
+                   Ok(Some(format!("Do you allow calling the {} ({}) tool?{}{}", tool.name, args, info.unwrap_or_default(), approval_note(&tool.name, is_en))))
                 } else {
-                   Ok(Some(format!("是否允许调用 {} ({}) 工具？{}", tool.name, args, info.unwrap_or_default())))
+                   Ok(Some(format!("是否允许调用 {} ({}) 工具？{}{}", tool.name, args, info.unwrap_or_default(), approval_note(&tool.name, is_en))))
                 }
             } else {
                 Ok(None)
@@ -0,0 +1,461 @@
+use std::collections::HashMap;
+use std::env;
+use std::io::Read;
+
+use reqwest::{
+    Method,
+    blocking::Client,
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tokio::runtime::{Handle, RuntimeFlavor};
+use url::Url;
+use uuid::Uuid;
+
+use crate::{
+    parse_paras::PARAS,
+    error::MyError,
+    tools::{
+        MyTools,
+        ArgFixSpec,
+        parse_tool_args,
+        process::tool_timeout,
+    },
+};
+
+// HTTP工具：参数文件`http_tools`中定义的HTTP接口，不需要为每个接口写命令行包装就能作为工具调用
+// url、headers、body都是模板：`{{参数名}}`替换为模型给出的参数，`${环境变量}`替换为环境变量（用于密钥，不写在参数文件中）
+// 与外部工具一样经过确认、超时（`command_limit`）和审计日志
+
+/// 响应的最大字节数，json_path提取之前需要完整的响应，因此这里不按`command_limit`截断，只拒绝过大的响应
+const MAX_RESPONSE_SIZE: u64 = 10 * 1024 * 1024;
+
+/// single HTTP tool
+#[derive(Clone, Deserialize)]
+pub struct SingleHttpTool {
+    pub name:        String,
+    pub description: String,
+    pub schema:      Value, // for LLM api function calling
+    #[serde(default)]
+    pub method:      String, // GET、POST、PUT、PATCH、DELETE等，默认GET
+    pub url:         String, // url模板，`{{参数名}}`替换为url编码后的参数值，例如：`http://127.0.0.1:9000/users/{{id}}`
+    #[serde(default)]
+    pub headers:     HashMap<String, String>, // 请求头，值中的`${环境变量}`替换为环境变量，例如：`{"Authorization": "Bearer ${MY_TOKEN}"}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body:        Option<String>, // 请求体模板，`{{参数名}}`替换为参数的json值（字符串带引号），不指定则GET、DELETE的参数加到url的query中，其他方法的参数作为json请求体
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub query:       Vec<String>, // 总是加到url的query中的参数，不论请求方法和是否指定了body模板
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json_path:   Option<String>, // 从json响应中提取的内容，例如：`$.data.items[*].name`，不指定则返回整个响应
+    #[serde(default)]
+    pub approval:    bool, // ask for approval
+    #[serde(default)]
+    pub timeout:     Option<u64>, // 超时秒数，优先于参数文件中的`command_limit`
+    #[serde(default)]
+    pub read_only:   Option<bool>, // 只读，可以与其他只读工具调用并发运行，不指定则GET和HEAD为只读
+    #[serde(skip)]
+    pub group:       String, // 下拉选项中的分组，`openapi_tools`生成的工具为spec的名称，`http_tools`中的工具为空
+}
+
+impl SingleHttpTool {
+    /// 请求方法，默认GET
+    pub fn method(&self) -> Result<Method, MyError> {
+        let method = if self.method.trim().is_empty() { "GET".to_string() } else { self.method.trim().to_uppercase() };
+        Method::from_bytes(method.as_bytes()).map_err(|e| MyError::ParaError{para: format!("http tool {} method `{}` error: {}", self.name, self.method, e)})
+    }
+
+    /// 是否只读，不指定则GET和HEAD为只读
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.unwrap_or_else(|| self.method().map(|m| m == Method::GET || m == Method::HEAD).unwrap_or(false))
+    }
+
+    /// 按schema修复模型把数组或对象多包了一层字符串的参数
+    fn parse_args(&self, args: &str) -> Result<Map<String, Value>, MyError> {
+        if args.trim().is_empty() {
+            return Ok(Map::new())
+        }
+        match parse_tool_args::<Value>(args, ArgFixSpec::from_schema(&self.schema))? {
+            Value::Object(map) => Ok(map),
+            _ => Err(MyError::OtherError{info: format!("http tool args `{}` must be object", args)}),
+        }
+    }
+
+    /// 替换url模板中的环境变量和参数，没有用到的参数在没有body模板时加到query中（GET、HEAD、DELETE），返回url和没有用到的参数
+    /// 先替换模板中的环境变量再填入参数，参数中的`${...}`原样发送，模型不能借此读取本机的环境变量
+    fn build_url(&self, args: &Map<String, Value>) -> Result<(Url, Map<String, Value>), MyError> {
+        let mut unused = args.clone();
+        let url = render(&interpolate_env(&self.url)?, args, &mut unused, url_encode);
+        let url = Url::parse(&url).map_err(|e| MyError::OtherError{info: format!("http tool {} url `{}` error: {}", self.name, self.url, e)})?;
+        Ok((url, unused))
+    }
+
+    /// 由模型给出的参数生成请求方法、url和请求体，环境变量只在模板中替换
+    fn prepare(&self, args: &str) -> Result<(Method, Url, Option<String>), MyError> {
+        let args = self.parse_args(args)?;
+        let method = self.method()?;
+        let (mut url, mut unused) = self.build_url(&args)?;
+        let query: Map<String, Value> = self.query.iter().filter_map(|k| unused.remove(k).map(|v| (k.clone(), v))).collect();
+        append_query(&mut url, &query);
+        let body = match &self.body {
+            Some(template) => Some(render(&interpolate_env(template)?, &args, &mut Map::new(), |v| v.map_or("null".to_string(), |v| v.to_string()))),
+            None if [Method::GET, Method::HEAD, Method::DELETE].contains(&method) => {
+                append_query(&mut url, &unused);
+                None
+            },
+            None => Some(Value::Object(unused).to_string()),
+        };
+        Ok((method, url, body))
+    }
+
+    /// 发送请求，错误信息中只使用未替换环境变量的url模板，避免密钥出现在返回给模型的结果、页面和审计日志中
+    fn run(&self, args: &str) -> Result<(String, Option<String>), MyError> {
+        let (method, url, body) = self.prepare(args)?;
+        let label = format!("{} {} {}", self.name, method, self.url);
+        // 多线程runtime中移交给阻塞线程执行，其他任务转移到别的worker上继续运行
+        match Handle::try_current() {
+            Ok(h) if h.runtime_flavor() == RuntimeFlavor::MultiThread => tokio::task::block_in_place(|| self.send(method, url, body, &label)),
+            _ => self.send(method, url, body, &label),
+        }
+    }
+
+    /// 发送请求并读取响应，超时设置同运行命令的工具，非2xx状态码作为调用失败
+    fn send(&self, method: Method, url: Url, body: Option<String>, label: &str) -> Result<(String, Option<String>), MyError> {
+        let client = Client::builder().timeout(tool_timeout(&self.name, self.timeout)).build().map_err(|e| MyError::SendRequestError{url: self.url.clone(), error: e.without_url()})?;
+        let mut request = client.request(method, url);
+        let mut has_content_type = false;
+        for (k, v) in &self.headers {
+            has_content_type |= k.eq_ignore_ascii_case("content-type");
+            request = request.header(k, interpolate_env(v)?);
+        }
+        if let Some(b) = body {
+            if !has_content_type {
+                request = request.header("Content-Type", "application/json");
+            }
+            request = request.body(b);
+        }
+        let response = request.send().map_err(|e| if e.is_timeout() {
+            MyError::CommandError{info: format!("`{}` timed out", label)}
+        } else {
+            MyError::SendRequestError{url: self.url.clone(), error: e.without_url()}
+        })?;
+        let status = response.status();
+        let too_large = || MyError::CommandError{info: format!("`{}` response exceeds {} MB", label, MAX_RESPONSE_SIZE / 1024 / 1024)};
+        if response.content_length().is_some_and(|n| n > MAX_RESPONSE_SIZE) {
+            return Err(too_large())
+        }
+        // 没有Content-Length或与实际不符时，最多读取MAX_RESPONSE_SIZE+1个字节
+        let mut body: Vec<u8> = Vec::new();
+        response.take(MAX_RESPONSE_SIZE + 1).read_to_end(&mut body).map_err(|e| MyError::CommandError{info: format!("`{}` read response error: {}", label, e)})?;
+        if body.len() as u64 > MAX_RESPONSE_SIZE {
+            return Err(too_large())
+        }
+        let text = String::from_utf8_lossy(&body).to_string();
+        if !status.is_success() {
+            return Err(MyError::CommandError{info: format!("`{}` returned status {}:\n{}", label, status, truncate(text.trim()))})
+        }
+        let content = match &self.json_path {
+            Some(path) => {
+                let json: Value = serde_json::from_str(&text).map_err(|e| MyError::CommandError{info: format!("`{}` response is not json, can not extract `{}`: {}", label, path, e)})?;
+                match extract_json_path(&json, path)? {
+                    Value::String(s) => s,
+                    v => serde_json::to_string_pretty(&v).unwrap_or(v.to_string()),
+                }
+            },
+            None => text,
+        };
+        Ok((truncate(content.trim()), None))
+    }
+}
+
+/// 替换模板中的`{{参数名}}`，encode决定参数值的写法（参数不存在时为None），用到的参数从unused中移除
+pub fn render(template: &str, args: &Map<String, Value>, unused: &mut Map<String, Value>, encode: impl Fn(Option<&Value>) -> String) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start+2..].find("}}") else {
+            break
+        };
+        let key = rest[start+2..start+2+end].trim();
+        result.push_str(&rest[..start]);
+        result.push_str(&encode(args.get(key)));
+        unused.remove(key);
+        rest = &rest[start+2+end+2..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// url中的参数值，字符串不带引号，保留`A-Za-z0-9-_.~`，其他字符按utf-8编码为`%XX`
+fn url_encode(value: Option<&Value>) -> String {
+    let s = match value {
+        None | Some(Value::Null) => return String::new(),
+        Some(Value::String(s)) => s.clone(),
+        Some(v) => v.to_string(),
+    };
+    s.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+        _ => format!("%{:02X}", b),
+    }).collect()
+}
+
+/// 参数加到url的query中，数组的每个元素作为同名参数，null忽略
+fn append_query(url: &mut Url, args: &Map<String, Value>) {
+    if args.is_empty() {
+        return
+    }
+    let mut pairs = url.query_pairs_mut();
+    for (k, v) in args {
+        let values = match v {
+            Value::Array(a) => a.iter().collect(),
+            v => vec![v],
+        };
+        for v in values {
+            match v {
+                Value::Null => (),
+                Value::String(s) => { pairs.append_pair(k, s); },
+                v => { pairs.append_pair(k, &v.to_string()); },
+            }
+        }
+    }
+}
+
+/// 替换`${环境变量}`，环境变量不存在则返回错误，避免把空的密钥发出去
+pub fn interpolate_env(s: &str) -> Result<String, MyError> {
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        let Some(end) = rest[start+2..].find('}') else {
+            break
+        };
+        let name = &rest[start+2..start+2+end];
+        let value = env::var(name).map_err(|_| MyError::ParaError{para: format!("environment variable `{}` used by http tool is not set", name)})?;
+        result.push_str(&rest[..start]);
+        result.push_str(&value);
+        rest = &rest[start+2+end+1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// 响应最多保留`command_limit.max_output_kb`，超出则保留开头和结尾各一半
+fn truncate(text: &str) -> String {
+    let max_bytes = PARAS.command_limit.max_output_kb * 1024;
+    if max_bytes == 0 || text.len() <= max_bytes {
+        return text.to_string()
+    }
+    let mut head = max_bytes / 2;
+    while !text.is_char_boundary(head) {
+        head -= 1;
+    }
+    let mut tail = text.len() - max_bytes / 2;
+    while !text.is_char_boundary(tail) {
+        tail += 1;
+    }
+    format!("{}\n... [truncated {} bytes, output limit {} KB] ...\n{}", &text[..head], tail - head, max_bytes / 1024, &text[tail..])
+}
+
+/// JSONPath的一段
+#[derive(Debug, PartialEq)]
+pub enum PathSegment {
+    Key(String), // `.key`或`['key']`
+    Index(usize), // `[0]`
+    Wildcard, // `[*]`或`.*`，数组的所有元素或对象的所有值
+}
+
+/// 解析JSONPath，支持`$.a.b`、`$.a[0]`、`$.a[*].b`、`$['a b']`，不支持递归（`..`）和过滤表达式
+pub fn parse_json_path(path: &str) -> Result<Vec<PathSegment>, MyError> {
+    let error = |info: &str| MyError::ParaError{para: format!("json_path `{}` {}, only `$.a.b`, `$.a[0]`, `$.a[*].b` and `$['a']` are supported", path, info)};
+    let chars: Vec<char> = path.trim().strip_prefix('$').unwrap_or(path.trim()).chars().collect();
+    let mut segments = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '.' if chars.get(i+1) == Some(&'.') => return Err(error("uses recursive descent")),
+            '.' if chars.get(i+1) == Some(&'*') => {
+                segments.push(PathSegment::Wildcard);
+                i += 2;
+            },
+            '[' => {
+                let end = chars[i..].iter().position(|c| *c == ']').map(|p| i + p).ok_or_else(|| error("misses `]`"))?;
+                let inner: String = chars[i+1..end].iter().collect::<String>().trim().to_string();
+                if inner == "*" {
+                    segments.push(PathSegment::Wildcard);
+                } else if let Ok(n) = inner.parse::<usize>() {
+                    segments.push(PathSegment::Index(n));
+                } else if inner.len() >= 2 && ((inner.starts_with('\'') && inner.ends_with('\'')) || (inner.starts_with('"') && inner.ends_with('"'))) {
+                    segments.push(PathSegment::Key(inner[1..inner.len()-1].to_string()));
+                } else {
+                    return Err(error(&format!("has unsupported `[{}]`", inner)))
+                }
+                i = end + 1;
+            },
+            c => {
+                let start = if c == '.' { i + 1 } else { i };
+                let end = chars[start..].iter().position(|c| *c == '.' || *c == '[').map_or(chars.len(), |p| start + p);
+                if end == start {
+                    return Err(error("has an empty key"))
+                }
+                segments.push(PathSegment::Key(chars[start..end].iter().collect()));
+                i = end;
+            },
+        }
+    }
+    Ok(segments)
+}
+
+/// 从json中提取JSONPath指定的内容，使用了通配符则返回所有匹配的数组，否则返回唯一的匹配
+fn extract_json_path(value: &Value, path: &str) -> Result<Value, MyError> {
+    let segments = parse_json_path(path)?;
+    let mut current: Vec<&Value> = vec![value];
+    for seg in &segments {
+        current = current.into_iter().flat_map(|v| match (seg, v) {
+            (PathSegment::Key(k), Value::Object(m)) => m.get(k).into_iter().collect::<Vec<&Value>>(),
+            (PathSegment::Index(n), Value::Array(a)) => a.get(*n).into_iter().collect(),
+            (PathSegment::Wildcard, Value::Array(a)) => a.iter().collect(),
+            (PathSegment::Wildcard, Value::Object(m)) => m.values().collect(),
+            _ => Vec::new(),
+        }).collect();
+    }
+    if segments.contains(&PathSegment::Wildcard) {
+        Ok(Value::Array(current.into_iter().cloned().collect()))
+    } else {
+        current.first().map(|v| (*v).clone()).ok_or_else(|| MyError::CommandError{info: format!("json_path `{}` matches nothing in the response", path)})
+    }
+}
+
+/// all HTTP tools
+pub struct HttpTools {
+    pub id_map: HashMap<String, SingleHttpTool>, // key: tool id, value: SingleHttpTool
+}
+
+impl HttpTools {
+    pub fn new(http_tools: Vec<SingleHttpTool>) -> Self {
+        Self {id_map: http_tools.into_iter().map(|t| {
+            let tmp_uuid = Uuid::new_v4().to_string();
+            (tmp_uuid[0..8].to_string(), t)
+        }).collect::<HashMap<String, SingleHttpTool>>()}
+    }
+
+    /// 重载参数文件后，与旧工具同组同名的工具沿用旧id，已打开页面选择的工具仍然可用
+    pub fn keep_old_id(self, old: &HttpTools) -> Self {
+        let old_ids: HashMap<(&str, &str), &str> = old.id_map.iter().map(|(k, v)| ((v.group.as_str(), v.name.as_str()), k.as_str())).collect();
+        Self {id_map: self.id_map.into_iter().map(|(k, v)| match old_ids.get(&(v.group.as_str(), v.name.as_str())) {
+            Some(id) => (id.to_string(), v),
+            None => (k, v),
+        }).collect::<HashMap<String, SingleHttpTool>>()}
+    }
+
+    /// 选择一组的所有工具，例如一个OpenAPI spec生成的所有工具
+    pub fn select_tools_by_group(&self, group: &str) -> Result<Vec<String>, MyError> {
+        let selected: Vec<String> = self.id_map.iter().filter(|(_, v)| !v.group.is_empty() && v.group == group).map(|(k, _)| k.clone()).collect();
+        if selected.is_empty() {
+            Err(MyError::ToolNotExistError{id: group.to_string(), info: "HttpTools::select_tools_by_group()".to_string()})
+        } else {
+            Ok(selected)
+        }
+    }
+
+    /// check contain HTTP tool group
+    pub fn contain_group(&self, group: &str) -> bool {
+        !group.is_empty() && self.id_map.values().any(|v| v.group == group)
+    }
+}
+
+impl MyTools for HttpTools {
+    /// send request
+    fn run(&self, id: &str, args: &str) -> Result<(String, Option<String>), MyError> {
+        match self.id_map.get(id) {
+            Some(tool) => tool.run(args),
+            None => Err(MyError::ToolNotExistError{id: id.to_string(), info: "HttpTools::run()".to_string()}),
+        }
+    }
+
+    /// get all selected tools name (name format: `name__id`, max name length is 26), description and schema
+    fn get_desc_and_schema(&self, selected_tools: Vec<String>) -> Vec<(String, String, Value)> {
+        self.id_map.iter().filter(|(k, _)| selected_tools.contains(&k)).map(|(k, v)| (format!("{}__{}", v.name, k), v.description.clone(), v.schema.clone())).collect()
+    }
+
+    /// select all tools, return uuid vector
+    fn select_all_tools(&self) -> Vec<String> {
+        self.id_map.keys().cloned().collect()
+    }
+
+    /// get approval message, show the request url
+    fn get_approval(&self, id: &str, args: &str, info: Option<String>, is_en: bool) -> Result<Option<String>, MyError> {
+        match self.id_map.get(id) {
+            Some(tool) => if tool.approval {
+                // 显示未替换环境变量的url，确认弹窗中不出现密钥
+                let request = match tool.parse_args(args) {
+                    Ok(a) => format!("{} {}", tool.method().map(|m| m.to_string()).unwrap_or(tool.method.clone()), render(&tool.url, &a, &mut Map::new(), url_encode)),
+                    Err(_) => format!("{} {}", tool.method, tool.url),
+                };
+                if is_en {
+                    Ok(Some(format!("Do you allow calling the {} ({}) tool?\n{}{}", tool.name, args, request, info.unwrap_or_default())))
+                } else {
+                    Ok(Some(format!("是否允许调用 {} ({}) 工具？\n{}{}", tool.name, args, request, info.unwrap_or_default())))
+                }
+            } else {
+                Ok(None)
+            },
+            None => Err(MyError::ToolNotExistError{id: id.to_string(), info: "HttpTools::get_approval()".to_string()}),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tool(url: &str, body: Option<&str>) -> SingleHttpTool {
+        SingleHttpTool {
+            name: "test_tool".to_string(),
+            description: String::new(),
+            schema: Value::Null,
+            method: "POST".to_string(),
+            url: url.to_string(),
+            headers: HashMap::new(),
+            body: body.map(|b| b.to_string()),
+            query: Vec::new(),
+            json_path: None,
+            approval: false,
+            timeout: None,
+            read_only: None,
+            group: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_env_in_args_sent_literally() {
+        let t = tool("http://127.0.0.1:9000/items/{{id}}", Some(r#"{"note": {{note}}}"#));
+        let (_, url, body) = t.prepare(r#"{"id": "${HOME}", "note": "${HOME}"}"#).unwrap();
+        assert_eq!(url.as_str(), "http://127.0.0.1:9000/items/%24%7BHOME%7D");
+        assert_eq!(body.unwrap(), r#"{"note": "${HOME}"}"#);
+    }
+
+    #[test]
+    fn test_env_in_args_without_body_template() {
+        let t = tool("http://127.0.0.1:9000/items", None);
+        let (_, _, body) = t.prepare(r#"{"note": "${HOME}"}"#).unwrap();
+        assert_eq!(body.unwrap(), r#"{"note":"${HOME}"}"#);
+    }
+
+    #[test]
+    fn test_env_in_template_expanded() {
+        let Ok(home) = env::var("HOME") else {
+            return
+        };
+        let t = tool("http://127.0.0.1:9000/items?token=${HOME}", Some(r#"{"home": "${HOME}", "note": {{note}}}"#));
+        let (_, url, body) = t.prepare(r#"{"note": "x"}"#).unwrap();
+        assert_eq!(url.query(), Some(format!("token={}", home).as_str()));
+        assert_eq!(body.unwrap(), format!(r#"{{"home": "{}", "note": "x"}}"#, home));
+    }
+
+    #[test]
+    fn test_json_path() {
+        let json: Value = serde_json::from_str(r#"{"data": {"items": [{"name": "a"}, {"name": "b"}]}}"#).unwrap();
+        assert_eq!(extract_json_path(&json, "$.data.items[*].name").unwrap(), serde_json::json!(["a", "b"]));
+        assert_eq!(extract_json_path(&json, "$.data.items[1].name").unwrap(), serde_json::json!("b"));
+        assert_eq!(extract_json_path(&json, "$['data']['items'][0]").unwrap(), serde_json::json!({"name": "a"}));
+        assert!(parse_json_path("$..name").is_err());
+    }
+}
@@ -1,9 +1,12 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 //use std::io::Read;
 use std::path::Path;
+use std::sync::Arc;
 //use std::process::{Command, Stdio};
 
 use chrono::Local;
+use futures::{stream, StreamExt};
+use html_escape::{encode_double_quoted_attribute, encode_single_quoted_attribute, encode_text};
 use openai_dive::v1::{
     api::Client,
     resources::chat::{
@@ -15,6 +18,7 @@ use openai_dive::v1::{
         ChatMessageContent,
         ChatMessageContentPart,
         ChatMessageImageContentPart,
+        ChatMessageTextContentPart,
         ImageUrlType,
     },
 };
@@ -23,6 +27,7 @@ use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use serde_json::{json, Value};
 use tokio::{
     sync::mpsc::Sender,
+    task::JoinHandle,
     time::{
         sleep,
         Duration,
@@ -37,9 +42,9 @@ use crate::{
         get_messages,
         get_messages_num,
         DataType,
-        update_approval,
-        approved,
         get_tool_calling_count,
+        record_memory_injection, // 记录本次回答注入的记忆
+        get_memory_namespaces, // 获取对话使用的记忆命名空间
     },
     openai::{
         for_tool::{
@@ -47,9 +52,12 @@ use crate::{
             CallToolResult,
         },
         for_chat::not_use_stream,
-        for_image::image_to_base64, // 图片转base64，返回base64编码的字符串
+        for_image::{
+            image_to_base64, // 图片转base64，返回base64编码的字符串
+            image_to_base64_helper, // 文件转base64
+        },
     },
-    parse_paras::PARAS,
+    parse_paras::{PARAS, ConfigSnapshot},
     error::MyError,
     api::handlers::{
         chat::{
@@ -67,11 +75,23 @@ use crate::{
     memory::{
         get_relevant_memory,
         get_all_memory,
+        manage::{
+            InjectedNote,
+            MemoryInjection,
+        },
     },
 };
 
 pub mod built_in_tools;
 pub mod external_tools;
+pub mod http_tools;
+pub mod openapi_tools;
+pub mod approval_policy;
+pub mod command_policy;
+pub mod audit;
+pub mod process;
+pub mod sandbox;
+pub mod pending_approval;
 
 use built_in_tools::{
     BuiltInTools,
@@ -91,20 +111,27 @@ use built_in_tools::{
     },
 };
 use external_tools::ExternalTools;
+use http_tools::HttpTools;
+use approval_policy::decide_by_policy;
+use command_policy::{CommandPolicy, PolicyAction, PolicyDecision, check_command, policy_note};
+use audit::Approval;
 
 /// html pulldown option selected tools
+#[derive(Clone)]
 pub enum SelectedTools {
     All,               // all tools
     AllBuiltIn,        // all built-in tools
     AllExternal,       // all external tools
+    AllHttp,           // all HTTP tools
+    HttpGroup(String), // HTTP tool group, start with `http_group_`, select all tools generated from one OpenAPI spec
     Group(String),     // built-in group, start with `built_in_group_`, select all tools of one group
-    Single(String),    // single built-in or external tool id
+    Single(String),    // single built-in, external or HTTP tool id
     AllMcp,            // all mcp tools
     McpServer(String), // single mcp server id, start with `mcp_server_`, select all tools of one server
     McpTool(String),   // single mcp tool `name__id`, select by tool name and server id
 }
 
-/// trait for built-in tools & external tools
+/// trait for built-in tools, external tools & HTTP tools
 pub trait MyTools {
     /// run tool
     fn run(&self, id: &str, args: &str) -> Result<(String, Option<String>), MyError>;
@@ -119,18 +146,24 @@ pub trait MyTools {
     fn get_approval(&self, id: &str, args: &str, info: Option<String>, is_en: bool) -> Result<Option<String>, MyError>;
 }
 
-/// all tools: built-in tools & external tools
+/// all tools: built-in tools, external tools & HTTP tools
 pub struct Tools {
     built_in: BuiltInTools,
     external: ExternalTools,
+    http:     HttpTools,
+    command_policy: Arc<CommandPolicy>, // command policy of the same config snapshot, checked before running commands
     pub html: String, // html pulldown options
 }
 
 impl Tools {
-    /// create new Tools, ExternalTools from config file
-    pub fn new(external: ExternalTools, english: bool) -> Result<Self, MyError> {
-        let built_in = BuiltInTools::new()?;
-        let mut options: Vec<String> = Vec::with_capacity(3 + built_in.id_map.len() + built_in.groups.len() * 2 + 2 + external.id_map.len());
+    /// create new Tools, ExternalTools and HttpTools from config file
+    /// old: tools before reloading config file, built-in tools, external tools and HTTP tools with the same name keep the old id
+    pub fn new(external: ExternalTools, http: HttpTools, command_policy: Arc<CommandPolicy>, english: bool, old: Option<&Tools>) -> Result<Self, MyError> {
+        let (built_in, external, http) = match old {
+            Some(o) => (o.built_in.clone(), external.keep_old_id(&o.external), http.keep_old_id(&o.http)),
+            None => (BuiltInTools::new()?, external, http),
+        };
+        let mut options: Vec<String> = Vec::with_capacity(3 + built_in.id_map.len() + built_in.groups.len() * 2 + 2 + external.id_map.len() + 2 + http.id_map.len());
         // built-in pulldown options
         let mut groups: Vec<(Group, String)> = built_in.groups.iter().map(|g| (g.clone(), g.to_string())).collect();
         groups.sort_by(|a, b| a.1.cmp(&b.1)); // sort by group name
@@ -177,16 +210,50 @@ impl Tools {
             }
             options.push("                </optgroup>".to_string());
         }
+        // HTTP pulldown options
+        if !http.id_map.is_empty() {
+            if english {
+                options.push("                <optgroup label='HTTP tools'>".to_string());
+                options.push("                    <option value='select_all_http'>🟠 select all HTTP tools</option>".to_string());
+            } else {
+                options.push("                <optgroup label='HTTP工具'>".to_string());
+                options.push("                    <option value='select_all_http'>🟠 选择所有HTTP工具</option>".to_string());
+            }
+            let mut tools: Vec<(&String, &http_tools::SingleHttpTool)> = http.id_map.iter().collect();
+            tools.sort_by(|a, b| (&a.1.group, &a.1.name).cmp(&(&b.1.group, &b.1.name))); // sort by group and tool name, tools of `http_tools` have no group and come first
+            let mut group = "";
+            for (k, v) in tools {
+                if v.group != group {
+                    group = v.group.as_str();
+                    // 分组名称来自spec的`info.title`或参数文件，需要转义
+                    let (value, label) = (encode_single_quoted_attribute(group), encode_text(group));
+                    options.push(format!("                    <option disabled>--{}--</option>", label));
+                    if english {
+                        options.push(format!("                    <option value='http_group_{}'>🟠 select all {}</option>", value, label));
+                    } else {
+                        options.push(format!("                    <option value='http_group_{}'>🟠 选择所有{}</option>", value, label));
+                    }
+                }
+                options.push(format!("                    <option value='{}' title=\"{}\">{}</option>", k, encode_double_quoted_attribute(&v.description), encode_text(&v.name)));
+            }
+            options.push("                </optgroup>".to_string());
+        }
         // return
-        Ok(Self {built_in, external, html: options.join("\n")})
+        Ok(Self {built_in, external, http, command_policy, html: options.join("\n")})
     }
 
     /// run tool
+    /// commands denied by the command policy are not run, even without approval (e.g. `approval_all`)
     pub fn run(&self, id: &str, args: &str) -> Result<(String, Option<String>), MyError> {
-        if self.built_in.id_map.contains_key(id) {
+        if let Some(tool) = self.built_in.id_map.get(id) {
+            if let Some(argv) = tool.tool.argv(args) {
+                check_command(&self.command_policy, &tool.tool.name(), &argv)?;
+            }
             self.built_in.run(id, args)
         } else if self.external.id_map.contains_key(id) {
             self.external.run(id, args)
+        } else if self.http.id_map.contains_key(id) {
+            self.http.run(id, args)
         } else {
             Err(MyError::ToolNotExistError{id: id.to_string(), info: "Tools::run()".to_string()})
         }
@@ -196,32 +263,37 @@ impl Tools {
     /// tool name format: `name__id`, max name length is 26
     pub fn get_desc_and_schema(&self, selected_tools: &Option<SelectedTools>) -> Result<Vec<ChatCompletionTool>, MyError> {
         // get selected tools
-        let (selected_builtin_tools, selected_external_tools) = match selected_tools {
+        let (selected_builtin_tools, selected_external_tools, selected_http_tools) = match selected_tools {
             Some(s) => match s {
-                SelectedTools::All => (self.built_in.select_all_tools(), self.external.select_all_tools()), // all tools
-                SelectedTools::AllBuiltIn => (self.built_in.select_all_tools(), Vec::new()), // all built-in tools
-                SelectedTools::AllExternal => (Vec::new(), self.external.select_all_tools()), // all external tools
-                SelectedTools::Group(group) => (self.built_in.select_tools_by_group(&group)?, Vec::new()), // built-in group
-                SelectedTools::Single(id) => { // single built-in or external tool id
+                SelectedTools::All => (self.built_in.select_all_tools(), self.external.select_all_tools(), self.http.select_all_tools()), // all tools
+                SelectedTools::AllBuiltIn => (self.built_in.select_all_tools(), Vec::new(), Vec::new()), // all built-in tools
+                SelectedTools::AllExternal => (Vec::new(), self.external.select_all_tools(), Vec::new()), // all external tools
+                SelectedTools::AllHttp => (Vec::new(), Vec::new(), self.http.select_all_tools()), // all HTTP tools
+                SelectedTools::HttpGroup(group) => (Vec::new(), Vec::new(), self.http.select_tools_by_group(&group)?), // HTTP tool group
+                SelectedTools::Group(group) => (self.built_in.select_tools_by_group(&group)?, Vec::new(), Vec::new()), // built-in group
+                SelectedTools::Single(id) => { // single built-in, external or HTTP tool id
                     if self.built_in.id_map.contains_key(id) {
-                        (vec![id.to_string()], Vec::new())
+                        (vec![id.to_string()], Vec::new(), Vec::new())
                     } else if self.external.id_map.contains_key(id) {
-                        (Vec::new(), vec![id.to_string()])
+                        (Vec::new(), vec![id.to_string()], Vec::new())
+                    } else if self.http.id_map.contains_key(id) {
+                        (Vec::new(), Vec::new(), vec![id.to_string()])
                     } else {
                         return Err(MyError::ToolNotExistError{id: id.to_string(), info: "Tools::select_tools()".to_string()})
                     }
                 },
-                SelectedTools::AllMcp => (Vec::new(), Vec::new()), // all mcp tools
-                SelectedTools::McpServer(_) => (Vec::new(), Vec::new()), // single mcp server id, start with `mcp_server_`, select all tools of one server
-                SelectedTools::McpTool(_) => (Vec::new(), Vec::new()), // single mcp tool `name__id`, select by tool name and server id
+                SelectedTools::AllMcp => (Vec::new(), Vec::new(), Vec::new()), // all mcp tools
+                SelectedTools::McpServer(_) => (Vec::new(), Vec::new(), Vec::new()), // single mcp server id, start with `mcp_server_`, select all tools of one server
+                SelectedTools::McpTool(_) => (Vec::new(), Vec::new(), Vec::new()), // single mcp tool `name__id`, select by tool name and server id
             },
-            None => (Vec::new(), Vec::new()), // not select any tool
+            None => (Vec::new(), Vec::new(), Vec::new()), // not select any tool
         };
         // get name, description and schema
         let mut tools: Vec<ChatCompletionTool> = Vec::new();
         let desc_and_schema_builtin = self.built_in.get_desc_and_schema(selected_builtin_tools);
         let desc_and_schema_external = self.external.get_desc_and_schema(selected_external_tools);
-        for tool in desc_and_schema_builtin.into_iter().chain(desc_and_schema_external) {
+        let desc_and_schema_http = self.http.get_desc_and_schema(selected_http_tools);
+        for tool in desc_and_schema_builtin.into_iter().chain(desc_and_schema_external).chain(desc_and_schema_http) {
             tools.push(
                 ChatCompletionTool {
                     r#type: ChatCompletionToolType::Function,
@@ -242,22 +314,73 @@ impl Tools {
             true
         } else if self.external.id_map.contains_key(id) {
             true
+        } else if self.http.id_map.contains_key(id) {
+            true
         } else {
             false
         }
     }
 
-    /// get approval message
+    /// check contain built-in group, e.g. `file system`
+    pub fn contain_group(&self, group: &str) -> bool {
+        self.built_in.groups.iter().any(|g| g.to_string() == group)
+    }
+
+    /// check contain HTTP tool group, e.g. the name of an OpenAPI spec
+    pub fn contain_http_group(&self, group: &str) -> bool {
+        self.http.contain_group(group)
+    }
+
+    /// get approval message, tools running commands append the decision of the command policy
     pub fn get_approval(&self, id: &str, args: &str, info: Option<String>, is_en: bool) -> Result<Option<String>, MyError> {
         if self.built_in.id_map.contains_key(id) {
-            self.built_in.get_approval(id, args, info, is_en)
+            let msg = self.built_in.get_approval(id, args, info, is_en)?;
+            Ok(match self.built_in.argv(id, args) {
+                Some(argv) => msg.map(|m| m + &policy_note(&self.command_policy, self.command_policy.decide_command(&argv).as_ref(), is_en)),
+                None => msg,
+            })
         } else if self.external.id_map.contains_key(id) {
             self.external.get_approval(id, args, info, is_en)
+        } else if self.http.id_map.contains_key(id) {
+            self.http.get_approval(id, args, info, is_en)
         } else {
             Err(MyError::ToolNotExistError{id: id.to_string(), info: "Tools::get_approval()".to_string()})
         }
     }
 
+    /// get argv of the command to run, only `run_command` and `run_script` return Some
+    pub fn argv(&self, id: &str, args: &str) -> Option<Vec<String>> {
+        self.built_in.argv(id, args)
+    }
+
+    /// check tool is read-only, read-only calls can run concurrently
+    pub fn read_only(&self, id: &str) -> bool {
+        if self.built_in.id_map.contains_key(id) {
+            self.built_in.read_only(id)
+        } else if let Some(tool) = self.external.id_map.get(id) {
+            tool.read_only
+        } else if let Some(tool) = self.http.id_map.get(id) {
+            tool.is_read_only()
+        } else {
+            false
+        }
+    }
+
+    /// check tool is external tool
+    pub fn is_external(&self, id: &str) -> bool {
+        self.external.id_map.contains_key(id)
+    }
+
+    /// number of external tools
+    pub fn external_num(&self) -> usize {
+        self.external.id_map.len()
+    }
+
+    /// number of HTTP tools
+    pub fn http_num(&self) -> usize {
+        self.http.id_map.len()
+    }
+
     /// get tool id by name
     pub fn get_tool_id_by_name(&self, name: &str) -> Option<String> {
         // 从 built-in 工具中查找
@@ -272,6 +395,12 @@ impl Tools {
                 return Some(k.clone())
             }
         }
+        // 从 HTTP 工具中查找
+        for (k, v) in &self.http.id_map {
+            if v.name == name {
+                return Some(k.clone())
+            }
+        }
         None
     }
 }
@@ -324,6 +453,7 @@ static SCHEDULE_LIST_REMOVE: &[&str; 21] = &[
 /// https://docs.bigmodel.cn/cn/guide/capabilities/function-calling
 /// https://platform.moonshot.cn/docs/api/tool-use
 pub async fn run_tools(
+    snapshot: Arc<ConfigSnapshot>,
     selected_tools: Option<SelectedTools>,
     selected_skills: Option<SelectedSkills>,
     uuid: String,
@@ -341,9 +471,9 @@ pub async fn run_tools(
         (None, false)
     };
     // get built-in and external tools schame
-    let mut tool_schema = PARAS.tools.get_desc_and_schema(&selected_tools)?;
+    let mut tool_schema = snapshot.tools.get_desc_and_schema(&selected_tools)?;
     // get mcp tools schema
-    let mcp_schema = PARAS.mcp_servers.get_desc_and_schema(&selected_tools).await?;
+    let mcp_schema = snapshot.mcp_servers.get_desc_and_schema(&selected_tools).await?;
     // 如果指定了skills，则加入一个激活指定skill的tool
     if selected_skills.is_some() {
         tool_schema.push(
@@ -367,7 +497,7 @@ pub async fn run_tools(
         );
         // 如果没有选择`run_command`，则自动添加这个工具
         if tool_schema.iter().any(|t| !t.function.name.starts_with("run_command")) {
-            let run_command_schema: Vec<_> = PARAS
+            let run_command_schema: Vec<_> = snapshot
                 .tools
                 .built_in
                 .id_map
@@ -388,7 +518,7 @@ pub async fn run_tools(
         }
         // 如果没有选择`run_script`，则自动添加这个工具
         if tool_schema.iter().any(|t| !t.function.name.starts_with("run_script")) {
-            let run_command_schema: Vec<_> = PARAS
+            let run_command_schema: Vec<_> = snapshot
                 .tools
                 .built_in
                 .id_map
@@ -410,7 +540,7 @@ pub async fn run_tools(
     }
     // 加上 sub_agent 工具
     {
-        let sub_agent_schema: Vec<_> = PARAS
+        let sub_agent_schema: Vec<_> = snapshot
             .tools
             .built_in
             .id_map
@@ -431,7 +561,7 @@ pub async fn run_tools(
     }
     // goal 模式加上 update_goal_status
     if goal_mode {
-        let update_goal_status: Vec<_> = PARAS
+        let update_goal_status: Vec<_> = snapshot
             .tools
             .built_in
             .id_map
@@ -454,7 +584,7 @@ pub async fn run_tools(
     tool_schema.extend(mcp_schema);
     // 如果只选择了 schedule_task 这一个 tool，则从问题中提取是否有指定要调用的其他 tool，如果有，则获取这些 tool 的 schema，作为单独一条信息发送给模型，如果没有指定，则把所有其他 tool 单独作为一条信息发送给模型
     let other_tools = if tool_schema.len() == 1 && tool_schema[0].function.name.starts_with("schedule_task__") {
-        let mut all_tools = PARAS.tools.get_desc_and_schema(&Some(SelectedTools::All))?;
+        let mut all_tools = snapshot.tools.get_desc_and_schema(&Some(SelectedTools::All))?;
         all_tools.retain(|t| !t.function.name.starts_with("schedule_task__")); // 排除 schedule_task
         Some(all_tools)
     } else {
@@ -479,23 +609,31 @@ pub async fn run_tools(
         if !user_msg.is_empty() {
             let query = user_msg.join("\n");
             // 计算 embedding
-            let embedding = if let Ok(embedding) = get_embedding(&uuid, query.clone()).await {
+            let embedding = if let Ok(embedding) = get_embedding(&snapshot.api, &uuid, query.clone()).await {
                 embedding
             } else {
                 None
             };
+            let namespaces = get_memory_namespaces(&uuid); // 只检索对话使用的命名空间和全局命名空间
             let memory = {
-                match get_relevant_memory(&uuid, &query, embedding.clone(), 10, is_local) {
-                    Some(memory) => Some(memory),
+                match get_relevant_memory(&uuid, &query, embedding.clone(), 10, is_local, &namespaces) {
+                    Some(memory) => Some((memory, if is_local { "local" } else { "uuid" })),
                     None => if is_local {
                         // 再尝试从 memory_old.json 中获取
-                        get_relevant_memory("local", &query, embedding, 10, false)
+                        get_relevant_memory("old", &query, embedding, 10, false, &namespaces).map(|m| (m, "old"))
                     } else {
                         None
                     }
                 }
             };
-            if let Some(m) = memory {
+            if let Some(((m, hits), store)) = memory {
+                // 记录本次回答注入了哪些记忆，用于记忆管理页面查看
+                record_memory_injection(&uuid, MemoryInjection{
+                    msg_id: 0, // 记录时更新为本次回答的信息id
+                    time:   Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+                    query:  query.chars().take(200).collect(),
+                    notes:  hits.into_iter().map(|h| InjectedNote{store: store.to_string(), id: h.note.id, summary: h.note.summary, score: h.score}).collect(),
+                });
                 //event!(Level::INFO, "{} insert memory prompt: {}\n", uuid, m);
                 history_messages.push(
                     ChatMessage::User{
@@ -559,9 +697,9 @@ pub async fn run_tools(
     }
     if let Some(sele_skills) = selected_skills {
         let skill_prompt = match sele_skills {
-            SelectedSkills::All => PARAS.skills.get_all_available_skills_prompt(),
-            SelectedSkills::Group(group) => PARAS.skills.get_group_available_skills_prompt(group),
-            SelectedSkills::Single(idx) => PARAS.skills.get_single_available_skill_prompt(idx),
+            SelectedSkills::All => snapshot.skills.get_all_available_skills_prompt(),
+            SelectedSkills::Group(group) => snapshot.skills.get_group_available_skills_prompt(group),
+            SelectedSkills::Single(idx) => snapshot.skills.get_single_available_skill_prompt(idx),
         };
         history_messages.push(
             ChatMessage::User{
@@ -574,7 +712,6 @@ pub async fn run_tools(
     let call_tool_limit = call_tool_count+100; // 调用工具的次数限制
     let mut try_count = 0;
     let mut is_first: bool;
-    let mut real_name: String; // 要调用的工具的真实名称，含有`_uuid第一部分`后缀
 
     // 进入循环前，如果当前 uuid 的新指令不为 None，则设为 None
     reset_new_instruction(&uuid);
@@ -607,39 +744,56 @@ pub async fn run_tools(
         match answer {
             CallToolResult::CallTool((raw_message, call_tool_result)) => { // (ChatMessage, Vec<(tool name, tool args, call tool id, content)>)
                 is_first = true;
-                for j in call_tool_result {
+                let real_names: Vec<String> = call_tool_result.iter().map(|j| get_real_tool_name(&uuid, &j.0, &tool_map, "")).collect(); // 要调用的工具的真实名称，含有`_uuid第一部分`后缀
+                let mut concurrent_results: VecDeque<Result<Result<(String, Option<String>), MyError>, MyError>> = VecDeque::new(); // 并发运行的调用结果，按调用顺序依次处理
+                for (idx, j) in call_tool_result.iter().enumerate() {
                     // call tool
-                    let mut name_id: Vec<&str> = j.0.split("__").collect();
-                    if name_id.len() < 2 && name_id[0] != "activate_skill" {
-                        if let Some(tools_vec) = tool_map.get(&j.0) {
-                            if tools_vec.len() > 1 {
-                                event!(Level::WARN, "{} call tool error, llm only return tool name prefix `{}`, but this prefix have multiple tools: {}", uuid, j.0, tools_vec.join(", "));
-                            } else {
-                                real_name = tools_vec[0].clone();
-                                name_id = real_name.split("__").collect();
-                                event!(Level::WARN, "{} call real tool `{}` by model returned `{}`", uuid, real_name, j.0);
-                            }
-                        } else {
-                            event!(Level::WARN, "{} can't find real tool by model returned `{}`", uuid, j.0);
+                    let name_id: Vec<&str> = real_names[idx].split("__").collect();
+                    // 从当前调用开始，连续的只读且不需要确认的调用并发运行
+                    if concurrent_results.is_empty() {
+                        let num = concurrent_call_num(&snapshot, &real_names[idx..], &call_tool_result[idx..]);
+                        if num > 1 {
+                            event!(Level::INFO, "{} run {} read-only tool calls concurrently", uuid, num);
+                            concurrent_results = run_concurrently(
+                                &snapshot,
+                                &uuid,
+                                &real_names[idx..idx+num],
+                                &call_tool_result[idx..idx+num],
+                                sender.clone(),
+                                model,
+                                Some(tool_schema.clone()),
+                                Some(client.clone()),
+                                Some(para_builder_for_sub_agent.clone()),
+                                true, // is main agent
+                                is_local,
+                            ).await.into();
                         }
                     }
-                    let (mut result, language, is_image) = match try_call_tool(
-                        &uuid,
-                        &name_id,
-                        &j.1,
-                        j.3.clone(),
-                        sender.clone(),
-                        model,
-                        Some(tool_schema.clone()),
-                        Some(client.clone()),
-                        Some(para_builder_for_sub_agent.clone()),
-                        true, // is main agent
-                        is_local,
-                    ).await {
+                    let call_result = match concurrent_results.pop_front() {
+                        Some(r) => r,
+                        None => try_call_tool(
+                            &snapshot,
+                            &uuid,
+                            &name_id,
+                            &j.1,
+                            j.3.clone(),
+                            sender.clone(),
+                            model,
+                            Some(tool_schema.clone()),
+                            Some(client.clone()),
+                            Some(para_builder_for_sub_agent.clone()),
+                            true, // is main agent
+                            is_local,
+                        ).await,
+                    };
+                    let mut attached_image: Option<String> = None; // 外部工具返回的图片，随文本结果发送给模型
+                    let (mut result, language, is_image) = match call_result {
                         Ok(inner_result) => {
                             match inner_result {
                                 Ok((result, file_option)) => {
                                     try_count = 0;
+                                    attached_image = external_tool_image(&snapshot, &name_id, &file_option);
+                                    let file_option = if attached_image.is_some() { None } else { file_option };
                                     if name_id[0].starts_with("load_image") {
                                         (result, get_file_language(file_option), true)
                                     } else {
@@ -780,10 +934,27 @@ pub async fn run_tools(
                                     },
                                 },
                             )]),
-                            tool_call_id: j.2,
+                            tool_call_id: j.2.clone(),
+                        });
+                    } else if let Some(image) = attached_image {
+                        history_messages.push(ChatMessage::Tool{
+                            content: ChatMessageContent::ContentPart(vec![
+                                ChatMessageContentPart::Text(ChatMessageTextContentPart {
+                                    r#type: "text".to_string(),
+                                    text: result,
+                                }),
+                                ChatMessageContentPart::Image(ChatMessageImageContentPart {
+                                    r#type: "image_url".to_string(),
+                                    image_url: ImageUrlType {
+                                        url: image,
+                                        detail: None,
+                                    },
+                                }),
+                            ]),
+                            tool_call_id: j.2.clone(),
                         });
                     } else {
-                        history_messages.push(ChatMessage::Tool{content: ChatMessageContent::Text(result), tool_call_id: j.2});
+                        history_messages.push(ChatMessage::Tool{content: ChatMessageContent::Text(result), tool_call_id: j.2.clone()});
                     }
                 }
             },
@@ -841,6 +1012,7 @@ pub async fn run_tools(
 /// 不能再开启 sub-agent，避免无限递归调用，会自动把 sub_agent 工具过滤掉
 /// 也不能更新 goal 的状态，会自动把 update_goal_status 工具过滤掉
 pub async fn sub_agent(
+    snapshot: &Arc<ConfigSnapshot>,
     uuid: String,
     prompt: String,
     sender: Sender<Vec<u8>>,
@@ -859,7 +1031,6 @@ pub async fn sub_agent(
     let call_tool_limit = call_tool_count+100; // 调用工具的次数限制
     let mut try_count = 0;
     let mut is_first: bool;
-    let mut real_name: String; // 要调用的工具的真实名称，含有`_uuid第一部分`后缀
 
     //'outer: loop {
     loop {
@@ -871,39 +1042,56 @@ pub async fn sub_agent(
         match answer {
             CallToolResult::CallTool((raw_message, call_tool_result)) => { // (ChatMessage, Vec<(tool name, tool args, call tool id, content)>)
                 is_first = true;
-                for j in call_tool_result {
+                let real_names: Vec<String> = call_tool_result.iter().map(|j| get_real_tool_name(&uuid, &j.0, &tool_map, "sub-agent ")).collect(); // 要调用的工具的真实名称，含有`_uuid第一部分`后缀
+                let mut concurrent_results: VecDeque<Result<Result<(String, Option<String>), MyError>, MyError>> = VecDeque::new(); // 并发运行的调用结果，按调用顺序依次处理
+                for (idx, j) in call_tool_result.iter().enumerate() {
                     // call tool
-                    let mut name_id: Vec<&str> = j.0.split("__").collect();
-                    if name_id.len() < 2 && name_id[0] != "activate_skill" {
-                        if let Some(tools_vec) = tool_map.get(&j.0) {
-                            if tools_vec.len() > 1 {
-                                event!(Level::WARN, "{} sub-agent call tool error, llm only return tool name prefix `{}`, but this prefix have multiple tools: {}", uuid, j.0, tools_vec.join(", "));
-                            } else {
-                                real_name = tools_vec[0].clone();
-                                name_id = real_name.split("__").collect();
-                                event!(Level::WARN, "{} sub-agent call real tool `{}` by model returned `{}`", uuid, real_name, j.0);
-                            }
-                        } else {
-                            event!(Level::WARN, "{} sub-agent can't find real tool by model returned `{}`", uuid, j.0);
+                    let name_id: Vec<&str> = real_names[idx].split("__").collect();
+                    // 从当前调用开始，连续的只读且不需要确认的调用并发运行
+                    if concurrent_results.is_empty() {
+                        let num = concurrent_call_num(&snapshot, &real_names[idx..], &call_tool_result[idx..]);
+                        if num > 1 {
+                            event!(Level::INFO, "{} sub-agent run {} read-only tool calls concurrently", uuid, num);
+                            concurrent_results = run_concurrently(
+                                &snapshot,
+                                &uuid,
+                                &real_names[idx..idx+num],
+                                &call_tool_result[idx..idx+num],
+                                sender.clone(),
+                                model,
+                                None,
+                                None,
+                                None,
+                                false, // is main agent
+                                is_local,
+                            ).await.into();
                         }
                     }
-                    let (mut result, _, is_image) = match Box::pin(try_call_tool(
-                        &uuid,
-                        &name_id,
-                        &j.1,
-                        j.3.clone(),
-                        sender.clone(),
-                        model,
-                        None,
-                        None,
-                        None,
-                        false, // is main agent
-                        is_local,
-                    )).await {
+                    let call_result = match concurrent_results.pop_front() {
+                        Some(r) => r,
+                        None => Box::pin(try_call_tool(
+                            &snapshot,
+                            &uuid,
+                            &name_id,
+                            &j.1,
+                            j.3.clone(),
+                            sender.clone(),
+                            model,
+                            None,
+                            None,
+                            None,
+                            false, // is main agent
+                            is_local,
+                        )).await,
+                    };
+                    let mut attached_image: Option<String> = None; // 外部工具返回的图片，随文本结果发送给模型
+                    let (mut result, _, is_image) = match call_result {
                         Ok(inner_result) => {
                             match inner_result {
                                 Ok((result, file_option)) => {
                                     try_count = 0;
+                                    attached_image = external_tool_image(&snapshot, &name_id, &file_option);
+                                    let file_option = if attached_image.is_some() { None } else { file_option };
                                     if name_id[0].starts_with("load_image") {
                                         (result, get_file_language(file_option), true)
                                     } else {
@@ -980,10 +1168,27 @@ pub async fn sub_agent(
                                     },
                                 },
                             )]),
-                            tool_call_id: j.2,
+                            tool_call_id: j.2.clone(),
+                        });
+                    } else if let Some(image) = attached_image {
+                        history_messages.push(ChatMessage::Tool{
+                            content: ChatMessageContent::ContentPart(vec![
+                                ChatMessageContentPart::Text(ChatMessageTextContentPart {
+                                    r#type: "text".to_string(),
+                                    text: result,
+                                }),
+                                ChatMessageContentPart::Image(ChatMessageImageContentPart {
+                                    r#type: "image_url".to_string(),
+                                    image_url: ImageUrlType {
+                                        url: image,
+                                        detail: None,
+                                    },
+                                }),
+                            ]),
+                            tool_call_id: j.2.clone(),
                         });
                     } else {
-                        history_messages.push(ChatMessage::Tool{content: ChatMessageContent::Text(result), tool_call_id: j.2});
+                        history_messages.push(ChatMessage::Tool{content: ChatMessageContent::Text(result), tool_call_id: j.2.clone()});
                     }
                 }
             },
@@ -1007,6 +1212,7 @@ pub async fn sub_agent(
 
 /// 调用 sub-agent
 async fn run_sub_agent(
+    snapshot: &Arc<ConfigSnapshot>,
     uuid: &str,
     name_id: &[&str],
     paras: &str,
@@ -1018,7 +1224,7 @@ async fn run_sub_agent(
     indirect: bool, // 是否主 agent 间接调用，比如主 agent 调用读取大文件，改为通过 sub-agent 间接调用
     is_local: bool,
 ) -> Result<Result<(String, Option<String>), MyError>, MyError> {
-    match PARAS.tools.run(name_id[1], paras) {
+    match snapshot.tools.run(name_id[1], paras) {
         Ok((prompt_tools, _)) => {
             let parts: Vec<&str> = prompt_tools.split("---srx---").collect(); // [prompt, tool1, tool2, ...]
             let tool_map = if parts.len() > 1 {
@@ -1052,6 +1258,7 @@ async fn run_sub_agent(
                 HashMap::new()
             };
             match Box::pin(sub_agent(
+                snapshot,
                 uuid.to_string(),
                 parts[0].to_string(),
                 sender.clone(),
@@ -1082,8 +1289,138 @@ struct SkillParams {
     skill_name: String,
 }
 
+/// 模型只返回了工具名称前缀（没有`__id`后缀）时，根据前缀获取真实名称，无法确定则返回模型返回的名称
+/// agent: 日志前缀，主agent为空，sub-agent为`sub-agent `
+fn get_real_tool_name(uuid: &str, name: &str, tool_map: &HashMap<String, Vec<String>>, agent: &str) -> String {
+    if name.contains("__") || name == "activate_skill" {
+        return name.to_string()
+    }
+    match tool_map.get(name) {
+        Some(tools_vec) if tools_vec.len() > 1 => {
+            event!(Level::WARN, "{} {}call tool error, llm only return tool name prefix `{}`, but this prefix have multiple tools: {}", uuid, agent, name, tools_vec.join(", "));
+            name.to_string()
+        },
+        Some(tools_vec) => {
+            event!(Level::WARN, "{} {}call real tool `{}` by model returned `{}`", uuid, agent, tools_vec[0], name);
+            tools_vec[0].clone()
+        },
+        None => {
+            event!(Level::WARN, "{} {}can't find real tool by model returned `{}`", uuid, agent, name);
+            name.to_string()
+        },
+    }
+}
+
+/// 外部工具（`protocol: "json"`）返回的第一张图片（png、jpg），读取为base64，读取出错则只发送文本结果
+fn external_tool_image(snapshot: &ConfigSnapshot, name_id: &[&str], file: &Option<String>) -> Option<String> {
+    let file = file.as_ref()?;
+    let lowercase_name = file.to_lowercase();
+    if name_id.len() < 2 || !snapshot.tools.is_external(name_id[1]) || ![".png", ".jpg", ".jpeg"].iter().any(|e| lowercase_name.ends_with(e)) {
+        return None
+    }
+    match image_to_base64_helper(file) {
+        Ok(b64) => Some(b64),
+        Err(e) => {
+            event!(Level::WARN, "external tool {} image {} error: {}", name_id[0], file, e);
+            None
+        },
+    }
+}
+
+/// 工具是否标记为只读，`activate_skill`只读取技能内容，不存在的工具没有运行，也视为只读
+fn is_read_only(snapshot: &ConfigSnapshot, name_id: &[&str]) -> bool {
+    if name_id[0] == "activate_skill" || name_id.len() < 2 {
+        true
+    } else if snapshot.tools.contain_tool_id(name_id[1]) {
+        snapshot.tools.read_only(name_id[1])
+    } else if snapshot.mcp_servers.contain_server_id(name_id[1]) {
+        snapshot.mcp_servers.read_only(name_id)
+    } else {
+        true
+    }
+}
+
+/// 工具调用是否可以与相邻的调用并发运行：只读且不需要确认
+/// MCP工具匹配了命令策略时依次运行，因为策略的决定按uuid写入审计日志的确认记录
+fn can_run_concurrently(snapshot: &ConfigSnapshot, name_id: &[&str], args: &str) -> bool {
+    if name_id[0] == "activate_skill" {
+        true
+    } else if name_id.len() < 2 {
+        false
+    } else if snapshot.tools.contain_tool_id(name_id[1]) {
+        snapshot.tools.read_only(name_id[1]) && (PARAS.approval_all || matches!(snapshot.tools.get_approval(name_id[1], args, None, PARAS.english), Ok(None)))
+    } else if snapshot.mcp_servers.contain_server_id(name_id[1]) {
+        snapshot.mcp_servers.read_only(name_id) && snapshot.command_policy.decide_mcp(name_id[0], args).is_none()
+    } else {
+        false
+    }
+}
+
+/// 从第一个调用开始，连续的可以并发运行的调用数量，参数文件`parallel_tools.max_concurrent`为0或1时返回0
+/// real_names: 工具的真实名称，calls: Vec<(tool name, tool args, call tool id, content)>
+fn concurrent_call_num(snapshot: &ConfigSnapshot, real_names: &[String], calls: &[(String, String, String, Option<String>)]) -> usize {
+    if PARAS.parallel_tools.max_concurrent <= 1 {
+        return 0
+    }
+    real_names.iter().zip(calls).take_while(|(name, call)| {
+        let name_id: Vec<&str> = name.split("__").collect();
+        can_run_concurrently(snapshot, &name_id, &call.1)
+    }).count()
+}
+
+/// 并发运行多个工具调用，最多同时运行参数文件`parallel_tools.max_concurrent`个，结果按调用顺序返回
+async fn run_concurrently(
+    snapshot: &Arc<ConfigSnapshot>,
+    uuid: &str,
+    real_names: &[String],
+    calls: &[(String, String, String, Option<String>)],
+    sender: Sender<Vec<u8>>,
+    model: &str,
+    tool_schema: Option<Vec<ChatCompletionTool>>,
+    client: Option<Client>,
+    para_builder: Option<ChatCompletionParametersBuilder>,
+    is_main_agent: bool,
+    is_local: bool,
+) -> Vec<Result<Result<(String, Option<String>), MyError>, MyError>> {
+    stream::iter(real_names.iter().zip(calls)).map(|(name, call)| {
+        // 被buffered取出时才开始运行，同时运行的数量不超过上限
+        let handle = spawn_tool_call(snapshot.clone(), uuid.to_string(), name.clone(), call.1.clone(), call.3.clone(), sender.clone(), model.to_string(), tool_schema.clone(), client.clone(), para_builder.clone(), is_main_agent, is_local);
+        let name = name.clone();
+        async move {
+            match handle.await {
+                Ok(result) => result,
+                Err(e) => Ok(Err(MyError::OtherError{info: format!("call tool {} error: {}", name, e)})), // 任务panic则告知模型
+            }
+        }
+    }).buffered(PARAS.parallel_tools.max_concurrent).collect().await
+}
+
+/// 在单独的任务中运行工具调用，同步执行的内置工具也可以同时运行
+/// 不是async fn，`try_call_tool`通过sub-agent递归调用`run_concurrently`，避免检查Send时形成循环
+fn spawn_tool_call(
+    snapshot: Arc<ConfigSnapshot>,
+    uuid: String,
+    real_name: String,
+    args: String,
+    info: Option<String>,
+    sender: Sender<Vec<u8>>,
+    model: String,
+    tool_schema: Option<Vec<ChatCompletionTool>>,
+    client: Option<Client>,
+    para_builder: Option<ChatCompletionParametersBuilder>,
+    is_main_agent: bool,
+    is_local: bool,
+) -> JoinHandle<Result<Result<(String, Option<String>), MyError>, MyError>> {
+    tokio::spawn(async move {
+        let name_id: Vec<&str> = real_name.split("__").collect();
+        try_call_tool(&snapshot, &uuid, &name_id, &args, info, sender, &model, tool_schema, client, para_builder, is_main_agent, is_local).await
+    })
+}
+
 /// try run tool, if error not from call tool, return Err(), else return Ok(Ok()) or Ok(Err())
+/// 没有标记为只读的工具调用写入审计日志
 async fn try_call_tool(
+    snapshot: &Arc<ConfigSnapshot>,
     uuid: &str,
     name_id: &[&str],
     paras: &str,
@@ -1096,123 +1433,179 @@ async fn try_call_tool(
     is_main_agent: bool,
     is_local: bool,
 ) -> Result<Result<(String, Option<String>), MyError>, MyError> {
-    if name_id[0] == "activate_skill" {
-        //let params: SkillParams = serde_json::from_str(paras).map_err(|e| MyError::SerdeJsonFromStrError{error: e})?;
-        let params: SkillParams = parse_tool_args(paras, ArgFixSpec{ array_fields: None, object_fields: None })?;
-        Ok(Ok((PARAS.skills.get_skill_full_content(&params.skill_name)?, None)))
-    } else if name_id.len() < 2 {
-        return Ok(Err(MyError::ToolNotExistError{id: name_id[0].to_string(), info: "run_tools".to_string()}))
-    } else if PARAS.tools.contain_tool_id(name_id[1]) {
-        if PARAS.approval_all {
-            Ok(PARAS.tools.run(name_id[1], paras))
-        } else {
-            match PARAS.tools.get_approval(name_id[1], paras, info, PARAS.english)? {
-                Some(approval_msg) => {
-                    let approval_msg = if name_id[0] == "edit_file" {
-                        /*
-                        let mut params: Params = match serde_json::from_str(paras) {
-                            Ok(p) => p,
-                            Err(e) => return Ok(Err(MyError::SerdeJsonFromStrError{error: e})),
-                        };
-                        */
-                        let mut params: Params = match parse_tool_args(paras, ArgFixSpec{ array_fields: Some(vec!["edits".to_string()]), object_fields: None }) {
-                            Ok(p) => p,
-                            Err(e) => return Ok(Err(e)),
-                        };
+    let started = audit::start();
+    // 记录调用工具的用户，决定命令是否在沙箱中运行
+    let (result, approval) = sandbox::CALLER.scope(audit::get_user(uuid, is_local), call_tool(snapshot, uuid, name_id, paras, info, sender, model, tool_schema, client, para_builder, is_main_agent, is_local)).await;
+    if audit::is_audited(is_read_only(snapshot, name_id)) {
+        audit::record(uuid, is_local, name_id, paras, started, approval, audit::outcome(&result, |r| r.0.as_str()));
+    }
+    result
+}
+
+/// run tool, called by `try_call_tool`
+/// 同时返回该调用的确认结果，没有经过命令策略或确认则为None
+async fn call_tool(
+    snapshot: &Arc<ConfigSnapshot>,
+    uuid: &str,
+    name_id: &[&str],
+    paras: &str,
+    info: Option<String>,
+    sender: Sender<Vec<u8>>,
+    model: &str,
+    tool_schema: Option<Vec<ChatCompletionTool>>,
+    client: Option<Client>,
+    para_builder: Option<ChatCompletionParametersBuilder>,
+    is_main_agent: bool,
+    is_local: bool,
+) -> (Result<Result<(String, Option<String>), MyError>, MyError>, Option<Approval>) {
+    let mut approval: Option<Approval> = None; // 命令策略或确认的结果，与调用结果一起返回，写入审计日志
+    let result: Result<Result<(String, Option<String>), MyError>, MyError> = async {
+        if name_id[0] == "activate_skill" {
+            //let params: SkillParams = serde_json::from_str(paras).map_err(|e| MyError::SerdeJsonFromStrError{error: e})?;
+            let params: SkillParams = parse_tool_args(paras, ArgFixSpec{ array_fields: None, object_fields: None })?;
+            Ok(Ok((snapshot.skills.get_skill_full_content(&params.skill_name)?, None)))
+        } else if name_id.len() < 2 {
+            return Ok(Err(MyError::ToolNotExistError{id: name_id[0].to_string(), info: "run_tools".to_string()}))
+        } else if snapshot.tools.contain_tool_id(name_id[1]) {
+            // 命令策略：拒绝则告知模型，自动允许则不再确认，总是确认则即使指定了`approval_all`也要确认
+            let (policy, policy_approval) = apply_command_policy(uuid, &snapshot, name_id, paras);
+            approval = policy_approval;
+            let policy = match policy {
+                Ok(p) => p.map(|d| d.action),
+                Err(e) => return Ok(Err(e)),
+            };
+            if (PARAS.approval_all && policy != Some(PolicyAction::Ask)) || policy == Some(PolicyAction::Allow) {
+                Ok(snapshot.tools.run(name_id[1], paras))
+            } else {
+                match snapshot.tools.get_approval(name_id[1], paras, info, PARAS.english)? {
+                    Some(approval_msg) => {
+                        let approval_msg = if name_id[0] == "edit_file" {
+                            /*
+                            let mut params: Params = match serde_json::from_str(paras) {
+                                Ok(p) => p,
+                                Err(e) => return Ok(Err(MyError::SerdeJsonFromStrError{error: e})),
+                            };
+                            */
+                            let mut params: Params = match parse_tool_args(paras, ArgFixSpec{ array_fields: Some(vec!["edits".to_string()]), object_fields: None }) {
+                                Ok(p) => p,
+                                Err(e) => return Ok(Err(e)),
+                            };
 
-                        params.dry_run = Some(true);
-                        let dry_run_para = match serde_json::to_string(&params) {
-                            Ok(d) => d,
-                            Err(e) => return Ok(Err(MyError::JsonToStringError{error: e.into()})),
+                            params.dry_run = Some(true);
+                            let dry_run_para = match serde_json::to_string(&params) {
+                                Ok(d) => d,
+                                Err(e) => return Ok(Err(MyError::JsonToStringError{error: e.into()})),
+                            };
+                            match snapshot.tools.run(name_id[1], &dry_run_para) {
+                                Ok(r) => r.0,
+                                Err(e) => return Ok(Err(e)),
+                            }
+                        } else if name_id[0] == "write_file" {
+                            approval_msg.chars().take(100).collect() // 截取显示100个字符，否则弹窗很高，无法点击同意或拒绝
+                        } else {
+                            approval_msg
                         };
-                        match PARAS.tools.run(name_id[1], &dry_run_para) {
-                            Ok(r) => r.0,
-                            Err(e) => return Ok(Err(e)),
-                        }
-                    } else if name_id[0] == "write_file" {
-                        approval_msg.chars().take(100).collect() // 截取显示100个字符，否则弹窗很高，无法点击同意或拒绝
-                    } else {
-                        approval_msg
-                    };
-                    match ask_approval(uuid, approval_msg, name_id[0] == "edit_file", sender.clone()).await?.as_ref() {
-                        "true" => { // 允许
-                            if name_id[0] == "image_generation" {
-                                match PARAS.tools.run(name_id[1], paras) {
-                                    Ok((image_prompt, _)) => {
-                                        match image_generation(uuid, image_prompt, "gpt-image-2").await {
-                                            Ok(image_path) => Ok(Ok((image_path, None))),
-                                            Err(e) => Ok(Err(e)),
-                                        }
-                                    },
-                                    Err(e) => Ok(Err(e)),
-                                }
-                            } else if name_id[0] == "edit_image" {
-                                match PARAS.tools.run(name_id[1], paras) {
-                                    Ok((facial_prompt_image, _)) => {
-                                        let parts: Vec<&str> = facial_prompt_image.splitn(3, "---srx---").collect(); // [是否强调面部特征, prompt, 图片路径]
-                                        match edit_image(uuid, parts[0] == "true", parts[2].split("---srx---").map(|img| img.to_string()).collect::<Vec<String>>(), parts[1], "gpt-image-2").await {
-                                            Ok(image_path) => Ok(Ok((image_path, None))),
-                                            Err(e) => Ok(Err(e)),
-                                        }
-                                    },
-                                    Err(e) => Ok(Err(e)),
+                        let (decision, asked) = ask_approval(uuid, name_id[0], approval_msg, name_id[0] == "edit_file", sender.clone()).await?;
+                        approval = Some(asked);
+                        match decision.as_str() {
+                            "true" => { // 允许
+                                if name_id[0] == "image_generation" {
+                                    match snapshot.tools.run(name_id[1], paras) {
+                                        Ok((image_prompt, _)) => {
+                                            match image_generation(snapshot, uuid, image_prompt, "gpt-image-2").await {
+                                                Ok(image_path) => Ok(Ok((image_path, None))),
+                                                Err(e) => Ok(Err(e)),
+                                            }
+                                        },
+                                        Err(e) => Ok(Err(e)),
+                                    }
+                                } else if name_id[0] == "edit_image" {
+                                    match snapshot.tools.run(name_id[1], paras) {
+                                        Ok((facial_prompt_image, _)) => {
+                                            let parts: Vec<&str> = facial_prompt_image.splitn(3, "---srx---").collect(); // [是否强调面部特征, prompt, 图片路径]
+                                            match edit_image(snapshot, uuid, parts[0] == "true", parts[2].split("---srx---").map(|img| img.to_string()).collect::<Vec<String>>(), parts[1], "gpt-image-2").await {
+                                                Ok(image_path) => Ok(Ok((image_path, None))),
+                                                Err(e) => Ok(Err(e)),
+                                            }
+                                        },
+                                        Err(e) => Ok(Err(e)),
+                                    }
+                                } else if name_id[0] == "schedule_task" {
+                                    match snapshot.tools.run(name_id[1], paras) {
+                                        Ok(_) => Ok(run_schedule_task(snapshot, paras).await),
+                                        Err(e) => Ok(Err(e)),
+                                    }
+                                } else if name_id[0] == "sub_agent" {
+                                    run_sub_agent(
+                                        snapshot,
+                                        uuid,
+                                        name_id,
+                                        paras,
+                                        model,
+                                        tool_schema,
+                                        para_builder,
+                                        client,
+                                        sender,
+                                        false,
+                                        is_local,
+                                    ).await
+                                } else {
+                                    Ok(snapshot.tools.run(name_id[1], paras))
                                 }
-                            } else if name_id[0] == "schedule_task" {
-                                match PARAS.tools.run(name_id[1], paras) {
-                                    Ok(_) => Ok(run_schedule_task(paras).await),
+                            },
+                            "false" => return Err(MyError::ApprovalDeniedError{tool: name_id[0].to_string()}), // 不允许
+                            "skip" => return Err(MyError::PlanModeError{info: format!("skip, this tool has not been executed: {}", name_id[0])}), // 跳过
+                            new_prompt => return Err(MyError::PlanModeError{info: format!("skip, this tool has not been executed: {}\n{}", name_id[0], new_prompt)}), // 跳过的新指示
+                        }
+                    },
+                    None => if name_id[0] == "hacker_news" {
+                        match snapshot.tools.run(name_id[1], paras) {
+                            Ok((save_html, _)) => {
+                                match hacker_news_summaries(snapshot, &uuid, save_html == "true", model).await {
+                                    Ok(hn_summaries) => Ok(Ok((hn_summaries, None))),
                                     Err(e) => Ok(Err(e)),
                                 }
-                            } else if name_id[0] == "sub_agent" {
-                                run_sub_agent(
-                                    uuid,
-                                    name_id,
-                                    paras,
-                                    model,
-                                    tool_schema,
-                                    para_builder,
-                                    client,
-                                    sender,
-                                    false,
-                                    is_local,
-                                ).await
-                            } else {
-                                Ok(PARAS.tools.run(name_id[1], paras))
-                            }
-                        },
-                        "false" => return Err(MyError::PlanModeError{info: format!("Not allowed to call this tool: {}", name_id[0])}), // 不允许
-                        "skip" => return Err(MyError::PlanModeError{info: format!("skip, this tool has not been executed: {}", name_id[0])}), // 跳过
-                        new_prompt => return Err(MyError::PlanModeError{info: format!("skip, this tool has not been executed: {}\n{}", name_id[0], new_prompt)}), // 跳过的新指示
-                    }
-                },
-                None => if name_id[0] == "hacker_news" {
-                    match PARAS.tools.run(name_id[1], paras) {
-                        Ok((save_html, _)) => {
-                            match hacker_news_summaries(&uuid, save_html == "true", model).await {
-                                Ok(hn_summaries) => Ok(Ok((hn_summaries, None))),
-                                Err(e) => Ok(Err(e)),
-                            }
-                        },
-                        Err(e) => Ok(Err(e)),
-                    }
-                } else if is_main_agent && name_id[0] == "read_file" { // 读取大文件时转为调用 sub-agent
-                    let read_file_para: ReadFileParams = parse_tool_args(paras, ArgFixSpec{ array_fields: None, object_fields: None })?;
-                    // 小文件（<4000）和 md 文件可以直接读取，大文件则通过 sub_agent 读取
-                    let file_path = Path::new(&read_file_para.file_path);
-                    let ext = if let Some(ext) = file_path.extension() {
-                        Some(ext.to_ascii_lowercase().to_str().unwrap().to_string())
-                    } else {
-                        None
-                    };
-                    let metadata = file_path.metadata()?;
-                    if metadata.len() < 4000 || if let Some(e) = ext { e == "md" } else { false } { // 直接读取
-                        Ok(PARAS.tools.run(name_id[1], paras))
-                    } else { // 通过 sub_agent 读取
-                        event!(Level::INFO, "{} main agent read_file by sub-agent", uuid);
-                        let sub_agent_id = PARAS.tools.get_tool_id_by_name("sub_agent").unwrap();
+                            },
+                            Err(e) => Ok(Err(e)),
+                        }
+                    } else if is_main_agent && name_id[0] == "read_file" { // 读取大文件时转为调用 sub-agent
+                        let read_file_para: ReadFileParams = parse_tool_args(paras, ArgFixSpec{ array_fields: None, object_fields: None })?;
+                        // 小文件（<4000）和 md 文件可以直接读取，大文件则通过 sub_agent 读取
+                        let file_path = Path::new(&read_file_para.file_path);
+                        let ext = if let Some(ext) = file_path.extension() {
+                            Some(ext.to_ascii_lowercase().to_str().unwrap().to_string())
+                        } else {
+                            None
+                        };
+                        let metadata = file_path.metadata()?;
+                        if metadata.len() < 4000 || if let Some(e) = ext { e == "md" } else { false } { // 直接读取
+                            Ok(snapshot.tools.run(name_id[1], paras))
+                        } else { // 通过 sub_agent 读取
+                            event!(Level::INFO, "{} main agent read_file by sub-agent", uuid);
+                            let sub_agent_id = snapshot.tools.get_tool_id_by_name("sub_agent").unwrap();
+                            run_sub_agent(
+                                snapshot,
+                                uuid,
+                                &["sub_agent", &sub_agent_id],
+                                &format!("{{\"prompt\": \"{}read file: {}\", \"tools\": [\"{}\"]}}", LARGE_FILE_PROMPT.replace("\n", "\\n"), read_file_para.file_path.replace("\\", "\\\\"), name_id[0]), // 这里自己构建 json 字符串，换行符、双引号、文件路径的`\`都需要转义
+                                model,
+                                tool_schema,
+                                para_builder,
+                                client,
+                                sender,
+                                true,
+                                is_local,
+                            ).await
+                        }
+                    } else if is_main_agent && name_id[0] == "read_multiple_files" { // 读取多个文件时转为调用 sub-agent
+                        let read_multiple_files_para: ReadMultipleFilesParams = parse_tool_args(paras, ArgFixSpec{ array_fields: Some(vec!["paths".to_string()]), object_fields: None })?;
+                        let sub_agent_id = snapshot.tools.get_tool_id_by_name("sub_agent").unwrap();
+                        event!(Level::INFO, "{} main agent read_multiple_files by sub-agent", uuid);
                         run_sub_agent(
+                            snapshot,
                             uuid,
                             &["sub_agent", &sub_agent_id],
-                            &format!("{{\"prompt\": \"{}read file: {}\", \"tools\": [\"{}\"]}}", LARGE_FILE_PROMPT.replace("\n", "\\n"), read_file_para.file_path.replace("\\", "\\\\"), name_id[0]), // 这里自己构建 json 字符串，换行符、双引号、文件路径的`\`都需要转义
+                            &format!("{{\"prompt\": \"{}read files:\\n{}\", \"tools\": [\"{}\"]}}", LARGE_FILE_PROMPT.replace("\n", "\\n"), read_multiple_files_para.paths.join("\\n").replace("\\", "\\\\"), name_id[0]), // 这里自己构建 json 字符串，换行符、双引号、文件路径的`\`都需要转义
                             model,
                             tool_schema,
                             para_builder,
@@ -1221,38 +1614,46 @@ async fn try_call_tool(
                             true,
                             is_local,
                         ).await
+                    } else if name_id[0] == "get_all_memory" { // 获取所有记忆
+                        match snapshot.tools.run(name_id[1], paras) {
+                            Ok(_) => Ok(Ok((get_all_memory(if is_local { "local" } else { uuid }, &get_memory_namespaces(uuid)), None))),
+                            Err(e) => Ok(Err(e)),
+                        }
+                    } else {
+                        Ok(snapshot.tools.run(name_id[1], paras))
                     }
-                } else if is_main_agent && name_id[0] == "read_multiple_files" { // 读取多个文件时转为调用 sub-agent
-                    let read_multiple_files_para: ReadMultipleFilesParams = parse_tool_args(paras, ArgFixSpec{ array_fields: Some(vec!["paths".to_string()]), object_fields: None })?;
-                    let sub_agent_id = PARAS.tools.get_tool_id_by_name("sub_agent").unwrap();
-                    event!(Level::INFO, "{} main agent read_multiple_files by sub-agent", uuid);
-                    run_sub_agent(
-                        uuid,
-                        &["sub_agent", &sub_agent_id],
-                        &format!("{{\"prompt\": \"{}read files:\\n{}\", \"tools\": [\"{}\"]}}", LARGE_FILE_PROMPT.replace("\n", "\\n"), read_multiple_files_para.paths.join("\\n").replace("\\", "\\\\"), name_id[0]), // 这里自己构建 json 字符串，换行符、双引号、文件路径的`\`都需要转义
-                        model,
-                        tool_schema,
-                        para_builder,
-                        client,
-                        sender,
-                        true,
-                        is_local,
-                    ).await
-                } else if name_id[0] == "get_all_memory" { // 获取所有记忆
-                    match PARAS.tools.run(name_id[1], paras) {
-                        Ok(_) => Ok(Ok((get_all_memory(if is_local { "local" } else { uuid }), None))),
-                        Err(e) => Ok(Err(e)),
-                    }
+                }
+            }
+        } else if snapshot.mcp_servers.contain_server_id(name_id[1]) {
+            // 命令策略：拒绝则告知模型，总是确认则弹窗确认
+            let (policy, policy_approval) = apply_command_policy(uuid, &snapshot, name_id, paras);
+            approval = policy_approval;
+            let policy = match policy {
+                Ok(p) => p,
+                Err(e) => return Ok(Err(e)),
+            };
+            if policy.as_ref().map(|d| d.action) == Some(PolicyAction::Ask) {
+                let approval_msg = if PARAS.english {
+                    format!("Do you allow calling the {} tool?{}\n{:?}{}", name_id[0], info.unwrap_or_default(), paras, policy_note(&snapshot.command_policy, policy.as_ref(), true))
                 } else {
-                    Ok(PARAS.tools.run(name_id[1], paras))
+                    format!("是否允许调用 {} 工具？{}\n{:?}{}", name_id[0], info.unwrap_or_default(), paras, policy_note(&snapshot.command_policy, policy.as_ref(), false))
+                };
+                let (decision, asked) = ask_approval(uuid, name_id[0], approval_msg, false, sender.clone()).await?;
+                approval = Some(asked);
+                match decision.as_str() {
+                    "true" => Ok(snapshot.mcp_servers.run(&name_id, paras).await), // 允许
+                    "false" => return Err(MyError::ApprovalDeniedError{tool: name_id[0].to_string()}), // 不允许
+                    "skip" => return Err(MyError::PlanModeError{info: format!("skip, this tool has not been executed: {}", name_id[0])}), // 跳过
+                    new_prompt => return Err(MyError::PlanModeError{info: format!("skip, this tool has not been executed: {}\n{}", name_id[0], new_prompt)}), // 跳过的新指示
                 }
+            } else {
+                Ok(snapshot.mcp_servers.run(&name_id, paras).await)
             }
+        } else {
+            return Ok(Err(MyError::ToolNotExistError{id: name_id[1].to_string(), info: "run_tools".to_string()}))
         }
-    } else if PARAS.mcp_servers.contain_server_id(name_id[1]) {
-        Ok(PARAS.mcp_servers.run(&name_id, paras).await)
-    } else {
-        return Ok(Err(MyError::ToolNotExistError{id: name_id[1].to_string(), info: "run_tools".to_string()}))
-    }
+    }.await;
+    (result, approval)
 }
 
 /// https://github.com/microsoft/TaskWeaver/blob/main/taskweaver/planner/planner_prompt.yaml
@@ -1713,11 +2114,11 @@ impl Plan {
 /// https://api-docs.deepseek.com/zh-cn/guides/function_calling
 /// https://docs.bigmodel.cn/cn/guide/capabilities/function-calling
 /// https://platform.moonshot.cn/docs/api/tool-use
-pub async fn run_tools_with_plan(selected_tools: Option<SelectedTools>, uuid: String, sender: Sender<Vec<u8>>, client: Client, para_builder: ChatCompletionParametersBuilder, model: &str) -> Result<(), MyError> {
+pub async fn run_tools_with_plan(snapshot: Arc<ConfigSnapshot>, selected_tools: Option<SelectedTools>, uuid: String, sender: Sender<Vec<u8>>, client: Client, para_builder: ChatCompletionParametersBuilder, model: &str) -> Result<(), MyError> {
     // get built-in and external tools schame
-    let mut tool_schema = PARAS.tools.get_desc_and_schema(&selected_tools)?;
+    let mut tool_schema = snapshot.tools.get_desc_and_schema(&selected_tools)?;
     // get mcp tools schema
-    let mcp_schema = PARAS.mcp_servers.get_desc_and_schema(&selected_tools).await?;
+    let mcp_schema = snapshot.mcp_servers.get_desc_and_schema(&selected_tools).await?;
 
     tool_schema.extend(mcp_schema);
     //let tool_schema_json = serde_json::to_string(&tool_schema).map_err(|e| MyError::JsonToStringError{error: e.into()})?;
@@ -1792,6 +2193,7 @@ pub async fn run_tools_with_plan(selected_tools: Option<SelectedTools>, uuid: St
                             ];
                             for j in 0..3 {
                                 match function_calling(
+                                    &snapshot,
                                     step_tools.clone(),
                                     step_messages.clone(),
                                     &uuid,
@@ -1862,7 +2264,7 @@ pub async fn run_tools_with_plan(selected_tools: Option<SelectedTools>, uuid: St
 }
 
 /// call LLM
-async fn call_llm(messages: Vec<ChatMessage>, uuid: String, client: Client, mut para_builder: ChatCompletionParametersBuilder, model: &str) -> Result<String, MyError> {
+pub async fn call_llm(messages: Vec<ChatMessage>, uuid: String, client: Client, mut para_builder: ChatCompletionParametersBuilder, model: &str) -> Result<String, MyError> {
     para_builder.messages(messages);
     let parameters = para_builder.build().map_err(|e| MyError::ChatCompletionError{error: e})?;
     Ok(not_use_stream(uuid, client, parameters, model, false).await?.0)
@@ -1895,6 +2297,7 @@ async fn make_decision(uuid: String, client: Client, para_builder: ChatCompletio
 
 /// function calling
 async fn function_calling(
+    snapshot: &ConfigSnapshot,
     step_tools: Vec<ChatCompletionTool>,
     step_messages: Vec<ChatMessage>,
     uuid: &str,
@@ -1915,100 +2318,133 @@ async fn function_calling(
             for i in call_tool_result {
                 // call tool
                 let name_id: Vec<&str> = i.0.split("__").collect();
-                let result = if PARAS.tools.contain_tool_id(name_id[1]) {
-                    if PARAS.approval_all {
-                        match PARAS.tools.run(name_id[1], &i.1) {
-                            Ok(r) => r.0,
+                // 会修改本机的工具和MCP工具的调用写入审计日志
+                let started = audit::start();
+                let mut approval: Option<Approval> = None; // 命令策略或确认的结果，写入审计日志
+                let outcome = sandbox::CALLER.scope(audit::get_user(uuid, false), async {
+                    let result = if snapshot.tools.contain_tool_id(name_id[1]) {
+                        // 命令策略：拒绝则告知模型，自动允许则不再确认，总是确认则即使指定了`approval_all`也要确认
+                        let (policy, policy_approval) = apply_command_policy(uuid, &snapshot, &name_id, &i.1);
+                        approval = policy_approval;
+                        let policy = match policy {
+                            Ok(p) => p.map(|d| d.action),
                             Err(e) => return Ok(Err(e)),
-                        }
-                    } else if let Some(approval_msg) = PARAS.tools.get_approval(name_id[1], &i.1, i.3.clone(), PARAS.english)? {
-                        let approval_msg = if name_id[0] == "edit_file" {
-                            /*
-                            let mut params: Params = match serde_json::from_str(&i.1) {
-                                Ok(p) => p,
-                                Err(e) => return Ok(Err(MyError::SerdeJsonFromStrError{error: e})),
-                            };
-                            */
-                            let mut params: Params = match parse_tool_args(&i.1, ArgFixSpec{ array_fields: Some(vec!["edits".to_string()]), object_fields: None }) {
-                                Ok(p) => p,
-                                Err(e) => return Ok(Err(e)),
-                            };
-                            params.dry_run = Some(true);
-                            let dry_run_para = match serde_json::to_string(&params) {
-                                Ok(d) => d,
-                                Err(e) => return Ok(Err(MyError::JsonToStringError{error: e.into()})),
-                            };
-                            match PARAS.tools.run(name_id[1], &dry_run_para) {
+                        };
+                        if (PARAS.approval_all && policy != Some(PolicyAction::Ask)) || policy == Some(PolicyAction::Allow) {
+                            match snapshot.tools.run(name_id[1], &i.1) {
                                 Ok(r) => r.0,
                                 Err(e) => return Ok(Err(e)),
                             }
+                        } else if let Some(approval_msg) = snapshot.tools.get_approval(name_id[1], &i.1, i.3.clone(), PARAS.english)? {
+                            let approval_msg = if name_id[0] == "edit_file" {
+                                /*
+                                let mut params: Params = match serde_json::from_str(&i.1) {
+                                    Ok(p) => p,
+                                    Err(e) => return Ok(Err(MyError::SerdeJsonFromStrError{error: e})),
+                                };
+                                */
+                                let mut params: Params = match parse_tool_args(&i.1, ArgFixSpec{ array_fields: Some(vec!["edits".to_string()]), object_fields: None }) {
+                                    Ok(p) => p,
+                                    Err(e) => return Ok(Err(e)),
+                                };
+                                params.dry_run = Some(true);
+                                let dry_run_para = match serde_json::to_string(&params) {
+                                    Ok(d) => d,
+                                    Err(e) => return Ok(Err(MyError::JsonToStringError{error: e.into()})),
+                                };
+                                match snapshot.tools.run(name_id[1], &dry_run_para) {
+                                    Ok(r) => r.0,
+                                    Err(e) => return Ok(Err(e)),
+                                }
+                            } else {
+                                approval_msg
+                            };
+                            let (decision, asked) = ask_approval(uuid, name_id[0], approval_msg, name_id[0] == "edit_file", sender.clone()).await?;
+                            approval = Some(asked);
+                            match decision.as_str() {
+                                "true" => { // 允许
+                                    match snapshot.tools.run(name_id[1], &i.1) {
+                                        Ok(r) => r.0,
+                                        Err(e) => return Ok(Err(e)),
+                                    }
+                                },
+                                "false" => return Err(MyError::ApprovalDeniedError{tool: name_id[0].to_string()}), // 不允许
+                                "skip" => return Err(MyError::PlanModeError{info: format!("skip, this tool has not been executed: {}", name_id[0])}), // 跳过
+                                new_prompt => return Err(MyError::PlanModeError{info: format!("skip, this tool has not been executed: {}\n{}", name_id[0], new_prompt)}), // 跳过的新指示
+                            }
                         } else {
-                            approval_msg
-                        };
-                        match ask_approval(uuid, approval_msg, name_id[0] == "edit_file", sender.clone()).await?.as_ref() {
-                            "true" => { // 允许
-                                match PARAS.tools.run(name_id[1], &i.1) {
+                            if step_ask_approval {
+                                let approval_msg = if PARAS.english {
+                                    format!("Do you allow calling the {} tool?{}\n{:?}", name_id[0], i.3.clone().unwrap_or_default(), i.1)
+                                } else {
+                                    format!("是否允许调用 {} 工具？{}\n{:?}", name_id[0], i.3.clone().unwrap_or_default(), i.1)
+                                };
+                                let (decision, asked) = ask_approval(uuid, name_id[0], approval_msg, false, sender.clone()).await?;
+                                approval = Some(asked);
+                                match decision.as_str() {
+                                    "true" => { // 允许
+                                        match snapshot.tools.run(name_id[1], &i.1) {
+                                            Ok(r) => r.0,
+                                            Err(e) => return Ok(Err(e)),
+                                        }
+                                    },
+                                    "false" => return Err(MyError::ApprovalDeniedError{tool: name_id[0].to_string()}), // 不允许
+                                    "skip" => return Err(MyError::PlanModeError{info: format!("skip, this tool has not been executed: {}", name_id[0])}), // 跳过
+                                    new_prompt => return Err(MyError::PlanModeError{info: format!("skip, this tool has not been executed: {}\n{}", name_id[0], new_prompt)}), // 跳过的新指示
+                                }
+                            } else {
+                                match snapshot.tools.run(name_id[1], &i.1) {
                                     Ok(r) => r.0,
                                     Err(e) => return Ok(Err(e)),
                                 }
-                            },
-                            "false" => return Err(MyError::PlanModeError{info: format!("Not allowed to call this tool: {}", name_id[0])}), // 不允许
-                            "skip" => return Err(MyError::PlanModeError{info: format!("skip, this tool has not been executed: {}", name_id[0])}), // 跳过
-                            new_prompt => return Err(MyError::PlanModeError{info: format!("skip, this tool has not been executed: {}\n{}", name_id[0], new_prompt)}), // 跳过的新指示
+                            }
                         }
-                    } else {
-                        if step_ask_approval {
+                    } else if snapshot.mcp_servers.contain_server_id(name_id[1]) {
+                        // 命令策略：拒绝则告知模型，自动允许则不再确认，总是确认则即使指定了`approval_all`也要确认
+                        let (policy, policy_approval) = apply_command_policy(uuid, &snapshot, &name_id, &i.1);
+                        approval = policy_approval;
+                        let policy = match policy {
+                            Ok(p) => p,
+                            Err(e) => return Ok(Err(e)),
+                        };
+                        let action = policy.as_ref().map(|d| d.action);
+                        if (!PARAS.approval_all && step_ask_approval && action != Some(PolicyAction::Allow)) || action == Some(PolicyAction::Ask) {
                             let approval_msg = if PARAS.english {
-                                format!("Do you allow calling the {} tool?{}\n{:?}", name_id[0], i.3.clone().unwrap_or_default(), i.1)
+                                format!("Do you allow calling the {} tool?{}\n{:?}{}", name_id[0], i.3.clone().unwrap_or_default(), i.1, policy_note(&snapshot.command_policy, policy.as_ref(), true))
                             } else {
-                                format!("是否允许调用 {} 工具？{}\n{:?}", name_id[0], i.3.clone().unwrap_or_default(), i.1)
+                                format!("是否允许调用 {} 工具？{}\n{:?}{}", name_id[0], i.3.clone().unwrap_or_default(), i.1, policy_note(&snapshot.command_policy, policy.as_ref(), false))
                             };
-                            match ask_approval(uuid, approval_msg, false, sender.clone()).await?.as_ref() {
+                            let (decision, asked) = ask_approval(uuid, name_id[0], approval_msg, false, sender.clone()).await?;
+                            approval = Some(asked);
+                            match decision.as_str() {
                                 "true" => { // 允许
-                                    match PARAS.tools.run(name_id[1], &i.1) {
+                                    match snapshot.mcp_servers.run(&name_id, &i.1).await {
                                         Ok(r) => r.0,
                                         Err(e) => return Ok(Err(e)),
                                     }
                                 },
-                                "false" => return Err(MyError::PlanModeError{info: format!("Not allowed to call this tool: {}", name_id[0])}), // 不允许
+                                "false" => return Err(MyError::ApprovalDeniedError{tool: name_id[0].to_string()}), // 不允许
                                 "skip" => return Err(MyError::PlanModeError{info: format!("skip, this tool has not been executed: {}", name_id[0])}), // 跳过
                                 new_prompt => return Err(MyError::PlanModeError{info: format!("skip, this tool has not been executed: {}\n{}", name_id[0], new_prompt)}), // 跳过的新指示
                             }
                         } else {
-                            match PARAS.tools.run(name_id[1], &i.1) {
+                            match snapshot.mcp_servers.run(&name_id, &i.1).await {
                                 Ok(r) => r.0,
                                 Err(e) => return Ok(Err(e)),
                             }
                         }
-                    }
-                } else if PARAS.mcp_servers.contain_server_id(name_id[1]) {
-                    if !PARAS.approval_all && step_ask_approval {
-                        let approval_msg = if PARAS.english {
-                            format!("Do you allow calling the {} tool?{}\n{:?}", name_id[0], i.3.clone().unwrap_or_default(), i.1)
-                        } else {
-                            format!("是否允许调用 {} 工具？{}\n{:?}", name_id[0], i.3.clone().unwrap_or_default(), i.1)
-                        };
-                        match ask_approval(uuid, approval_msg, false, sender.clone()).await?.as_ref() {
-                            "true" => { // 允许
-                                match PARAS.mcp_servers.run(&name_id, &i.1).await {
-                                    Ok(r) => r.0,
-                                    Err(e) => return Ok(Err(e)),
-                                }
-                            },
-                            "false" => return Err(MyError::PlanModeError{info: format!("Not allowed to call this tool: {}", name_id[0])}), // 不允许
-                            "skip" => return Err(MyError::PlanModeError{info: format!("skip, this tool has not been executed: {}", name_id[0])}), // 跳过
-                            new_prompt => return Err(MyError::PlanModeError{info: format!("skip, this tool has not been executed: {}\n{}", name_id[0], new_prompt)}), // 跳过的新指示
-                        }
                     } else {
-                        match PARAS.mcp_servers.run(&name_id, &i.1).await {
-                            Ok(r) => r.0,
-                            Err(e) => return Ok(Err(e)),
-                        }
-                    }
-                } else {
-                    return Err(MyError::ToolNotExistError{id: name_id[1].to_string(), info: "run_tools".to_string()})
+                        return Err(MyError::ToolNotExistError{id: name_id[1].to_string(), info: "run_tools".to_string()})
+                    };
+                    Ok::<Result<String, MyError>, MyError>(Ok(result))
+                }).await;
+                if audit::is_audited(is_read_only(&snapshot, &name_id)) {
+                    audit::record(uuid, false, &name_id, &i.1, started, approval, audit::outcome(&outcome, |r| r.as_str()));
+                }
+                final_result = match outcome? {
+                    Ok(r) => r,
+                    Err(e) => return Ok(Err(e)),
                 };
-                final_result = result;
                 break
             }
         },
@@ -2050,25 +2486,46 @@ async fn send_and_record_message(uuid: &str, msg: String, step_num: usize, model
     Ok(())
 }
 
+/// 按命令策略决定run_command、run_script和MCP工具的调用，返回匹配的决定，没有匹配或不是这些工具则返回None
+/// 拒绝则返回错误，告知模型后继续回答，自动允许则调用者不再确认，拒绝和自动允许同时返回确认结果，写入审计日志
+fn apply_command_policy(uuid: &str, snapshot: &ConfigSnapshot, name_id: &[&str], args: &str) -> (Result<Option<PolicyDecision>, MyError>, Option<Approval>) {
+    let decision = if snapshot.mcp_servers.contain_server_id(name_id[1]) {
+        snapshot.command_policy.decide_mcp(name_id[0], args)
+    } else {
+        snapshot.tools.argv(name_id[1], args).and_then(|argv| snapshot.command_policy.decide_command(&argv))
+    };
+    if let Some(d) = &decision {
+        event!(Level::INFO, "{} call {}: {}", uuid, name_id[0], d.explain(true));
+        if d.action == PolicyAction::Deny {
+            return (Err(MyError::PolicyDeniedError{tool: name_id[0].to_string(), info: d.explain(true)}), Some(Approval::new("false", "command_policy")))
+        }
+    }
+    let approval = decision.as_ref().filter(|d| d.action == PolicyAction::Allow).map(|_| Approval::new("true", "command_policy"));
+    (Ok(decision), approval)
+}
+
 /// ask approval
-async fn ask_approval(uuid: &str, msg: String, is_diff: bool, sender: Sender<Vec<u8>>) -> Result<String, MyError> {
+/// 该uuid设置了确认规则（例如命令行`chatsong ask`）则直接按规则决定，不再等待页面确认
+/// 否则登记为等待确认，发起提问的页面、同一用户的其他页面、channel bot都可以回答，超时按参数文件`approval_timeout`的默认操作处理
+/// 返回确认结果，以及写入审计日志的确认结果、确认者和确认时间
+async fn ask_approval(uuid: &str, tool: &str, msg: String, is_diff: bool, sender: Sender<Vec<u8>>) -> Result<(String, Approval), MyError> {
+    if let Some(decision) = decide_by_policy(uuid, tool) {
+        event!(Level::INFO, "{} approval of {} decided by policy: {}", uuid, tool, decision);
+        let approval = Approval::new(&decision, "policy");
+        return Ok((decision, approval))
+    }
+    let user = sandbox::CALLER.try_with(|u| u.clone()).unwrap_or_else(|_| audit::get_user(uuid, false));
+    let (id, receiver) = pending_approval::register(uuid, &user, tool, &msg);
+    let _guard = pending_approval::PendingGuard(id.clone()); // 返回或被取消时移除
     let messages_num = get_messages_num(uuid); // 流式输出传输答案时，答案还未插入到服务端记录中，因此这里获取总消息数不需要减1
     if let Err(e) = sender.send(MainData::prepare_sse(uuid, messages_num, "".to_string(), true, false, false, false, false, None, Some(0), Some(msg.replace("\n", "srxtzn")), is_diff)?).await { // 传递数据以`data: `起始，以`\n\n`终止
         event!(Level::WARN, "ask approval error: {:?}", e);
         return Err(MyError::PlanModeError{info: format!("ask approval error: {:?}", e)})
     }
-    let tmp_approved: String; // false: 不允许, true: 允许, skip: 跳过, 其他信息: 跳过的新指示
-    update_approval(uuid, None);
-    loop {
-        if let Some(apv) = approved(uuid) {
-            tmp_approved = apv;
-            update_approval(uuid, None);
-            break
-        }
-        // sleep 2 seconds
-        sleep(Duration::from_secs(2)).await;
-    }
-    Ok(tmp_approved)
+    // 等待回答，false: 不允许, true: 允许, skip: 跳过, 其他信息: 跳过的新指示
+    let (decision, approver) = pending_approval::wait(&id, receiver).await;
+    let approval = Approval::new(&decision, &approver);
+    Ok((decision, approval))
 }
 
 /// markdown代码块标注语言，未包含的格式后缀直接转为小写即可
@@ -2135,6 +2592,21 @@ pub struct ArgFixSpec {
     pub object_fields: Option<Vec<String>>,
 }
 
+impl ArgFixSpec {
+    /// 按工具schema中参数的类型修复，外部工具和HTTP工具共用
+    pub fn from_schema(schema: &Value) -> Self {
+        Self{ array_fields: Some(fields_of_type(schema, "array")), object_fields: Some(fields_of_type(schema, "object")) }
+    }
+}
+
+/// schema中指定类型的参数名称
+fn fields_of_type(schema: &Value, ty: &str) -> Vec<String> {
+    match schema.get("properties") {
+        Some(Value::Object(props)) => props.iter().filter(|(_, v)| v.get("type").and_then(|t| t.as_str()) == Some(ty)).map(|(k, _)| k.clone()).collect(),
+        _ => Vec::new(),
+    }
+}
+
 /// 解析模型返回的 tool arguments
 /// 支持两类兼容修复：
 /// 1. arguments 整体被包成 JSON string
@@ -2204,3 +2676,86 @@ fn fix_string_field_to_json_type(
         map.insert(field.to_string(), parsed);
     }
 }
+
+/// 检查 tool 的参数 schema 是否是 function calling 可用的 JSON Schema，返回所有问题，没有问题返回空
+/// 顶层必须是 `{"type": "object", "properties": {...}}`，每个参数需要有 type（或 anyOf/oneOf/enum/$ref），required 中的参数必须在 properties 中
+pub fn check_json_schema(schema: &Value) -> Vec<String> {
+    let mut problems = Vec::new();
+    let Value::Object(map) = schema else {
+        problems.push(format!("schema must be a JSON object, not: {}", schema));
+        return problems
+    };
+    match map.get("type") {
+        Some(Value::String(t)) if t == "object" => (),
+        Some(t) => problems.push(format!("schema top-level \"type\" must be \"object\", not: {}", t)),
+        None => problems.push("schema missing top-level \"type\": \"object\"".to_string()),
+    }
+    let properties = match map.get("properties") {
+        Some(Value::Object(p)) => Some(p),
+        Some(p) => {
+            problems.push(format!("schema \"properties\" must be an object, not: {}", p));
+            None
+        },
+        None => None, // 没有参数的 tool
+    };
+    if let Some(props) = properties {
+        for (name, prop) in props {
+            check_schema_property(name, prop, &mut problems);
+        }
+    }
+    match map.get("required") {
+        Some(Value::Array(required)) => for r in required {
+            match r {
+                Value::String(name) => if !properties.map(|p| p.contains_key(name)).unwrap_or(false) {
+                    problems.push(format!("required parameter \"{}\" is not in \"properties\"", name));
+                },
+                _ => problems.push(format!("\"required\" must only contain strings, not: {}", r)),
+            }
+        },
+        Some(r) => problems.push(format!("\"required\" must be an array, not: {}", r)),
+        None => (),
+    }
+    problems
+}
+
+/// 检查 schema 中的单个参数，array 的 items 和 object 的 properties 会递归检查
+fn check_schema_property(name: &str, prop: &Value, problems: &mut Vec<String>) {
+    const TYPES: [&str; 7] = ["string", "number", "integer", "boolean", "array", "object", "null"];
+    let Value::Object(map) = prop else {
+        problems.push(format!("parameter \"{}\" must be an object, not: {}", name, prop));
+        return
+    };
+    if ["anyOf", "oneOf", "allOf", "enum", "$ref", "const"].iter().any(|k| map.contains_key(*k)) {
+        return
+    }
+    let types: Vec<&str> = match map.get("type") {
+        Some(Value::String(t)) => vec![t.as_str()],
+        Some(Value::Array(ts)) => ts.iter().filter_map(|t| t.as_str()).collect(),
+        Some(t) => {
+            problems.push(format!("parameter \"{}\" has invalid \"type\": {}", name, t));
+            return
+        },
+        None => {
+            problems.push(format!("parameter \"{}\" missing \"type\"", name));
+            return
+        },
+    };
+    for t in &types {
+        if !TYPES.contains(t) {
+            problems.push(format!("parameter \"{}\" has unknown type \"{}\"", name, t));
+        }
+    }
+    if types.contains(&"array") {
+        match map.get("items") {
+            Some(items) => check_schema_property(&format!("{}[]", name), items, problems),
+            None => problems.push(format!("array parameter \"{}\" missing \"items\"", name)),
+        }
+    }
+    if types.contains(&"object") {
+        if let Some(Value::Object(props)) = map.get("properties") {
+            for (k, v) in props {
+                check_schema_property(&format!("{}.{}", name, k), v, problems);
+            }
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::read_to_string;
+
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tracing::{event, Level};
+
+use crate::{
+    error::MyError,
+    tools::http_tools::SingleHttpTool,
+};
+
+// OpenAPI工具：读取OpenAPI 3的spec文件（json或yaml），每个operation生成一个HTTP工具，调用、确认、超时、审计同`http_tools`
+// path和query参数作为工具的参数，json请求体作为`body`参数，header和cookie参数不支持
+// 生成的工具在页面下拉选项中按spec分组，与MCP server一样可以选择一组的所有工具
+
+/// 工具名称的最大长度，加上`__id`后不超过模型api的限制
+const MAX_NAME_LEN: usize = 26;
+
+/// `$ref`最多展开的层数，避免循环引用
+const MAX_REF_DEPTH: usize = 8;
+
+/// 支持的请求方法，OpenAPI中path下的其他字段（parameters、summary等）不是operation
+const METHODS: [&str; 7] = ["get", "put", "post", "delete", "options", "head", "patch"];
+
+/// 参数文件`openapi_tools`中的一个spec
+#[derive(Clone, Deserialize)]
+pub struct OpenApiSpec {
+    #[serde(default)]
+    pub name:         String, // 分组名称，显示在下拉选项中，不指定则使用spec的`info.title`
+    pub spec:         String, // spec文件路径，json或yaml
+    #[serde(default)]
+    pub base_url:     String, // 服务地址，例如：`http://127.0.0.1:9000`，不指定则使用spec中的第一个`servers`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include_tags: Vec<String>, // 只生成含有这些tag的operation，为空则不限制
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_tags: Vec<String>, // 不生成含有这些tag的operation，优先于include_tags
+    #[serde(default)]
+    pub headers:      HashMap<String, String>, // 每个请求都带的请求头，`${环境变量}`替换为环境变量，例如认证
+    #[serde(default)]
+    pub approval:     bool, // ask for approval
+    #[serde(default)]
+    pub timeout:      Option<u64>, // 超时秒数，优先于参数文件中的`command_limit`
+}
+
+impl OpenApiSpec {
+    /// 读取spec文件，每个符合tag筛选的operation生成一个HTTP工具
+    pub fn to_http_tools(&self) -> Result<Vec<SingleHttpTool>, MyError> {
+        let content = read_to_string(&self.spec).map_err(|e| MyError::ReadFileError{file: self.spec.clone(), error: e})?;
+        let root: Value = if self.spec.ends_with(".json") {
+            serde_json::from_str(&content).map_err(|e| MyError::ParaError{para: format!("parse openapi spec {} error: {}", self.spec, e)})?
+        } else {
+            serde_yaml::from_str(&content).map_err(|e| MyError::ParaError{para: format!("parse openapi spec {} error: {}", self.spec, e)})?
+        };
+        if !root.get("openapi").and_then(|v| v.as_str()).is_some_and(|v| v.starts_with('3')) {
+            return Err(MyError::ParaError{para: format!("{} is not an OpenAPI 3 spec", self.spec)})
+        }
+        let group = match self.name.trim() {
+            "" => root.pointer("/info/title").and_then(|v| v.as_str()).unwrap_or(&self.spec).to_string(),
+            n => n.to_string(),
+        };
+        let base_url = match self.base_url.trim() {
+            "" => root.pointer("/servers/0/url").and_then(|v| v.as_str()).ok_or_else(|| MyError::ParaError{para: format!("openapi spec {} has no servers, please set base_url", self.spec)})?,
+            u => u,
+        }.trim_end_matches('/').to_string();
+        let Some(Value::Object(paths)) = root.get("paths") else {
+            return Ok(Vec::new())
+        };
+        let mut names: HashSet<String> = HashSet::new();
+        let mut tools = Vec::new();
+        for (path, item) in paths {
+            let item = resolve(item, &root, 0);
+            let shared_params = item.get("parameters").and_then(|p| p.as_array()).cloned().unwrap_or_default();
+            for method in METHODS {
+                let Some(op) = item.get(method) else {
+                    continue
+                };
+                let tags: Vec<&str> = op.get("tags").and_then(|t| t.as_array()).map(|t| t.iter().filter_map(|t| t.as_str()).collect()).unwrap_or_default();
+                if tags.iter().any(|t| self.exclude_tags.iter().any(|e| e == t)) || (!self.include_tags.is_empty() && !tags.iter().any(|t| self.include_tags.iter().any(|i| i == t))) {
+                    continue
+                }
+                let name = unique_name(op.get("operationId").and_then(|v| v.as_str()).map(|s| s.to_string()).unwrap_or(format!("{}_{}", method, path)), &mut names);
+                let description = ["summary", "description"].iter().filter_map(|k| op.get(*k).and_then(|v| v.as_str())).map(|s| s.trim()).find(|s| !s.is_empty()).map(|s| s.to_string()).unwrap_or(format!("{} {}", method.to_uppercase(), path));
+                let params: Vec<Value> = shared_params.iter().chain(op.get("parameters").and_then(|p| p.as_array()).into_iter().flatten()).map(|p| resolve(p, &root, 0)).collect();
+                let (schema, query, has_body) = build_schema(&params, op.get("requestBody").map(|b| resolve(b, &root, 0)).as_ref(), &root);
+                tools.push(SingleHttpTool {
+                    name,
+                    description,
+                    schema,
+                    method: method.to_uppercase(),
+                    url: format!("{}{}", base_url, path.replace('{', "{{").replace('}', "}}")),
+                    headers: self.headers.clone(),
+                    body: if has_body { Some("{{body}}".to_string()) } else { None },
+                    query,
+                    json_path: None,
+                    approval: self.approval,
+                    timeout: self.timeout,
+                    read_only: None,
+                    group: group.clone(),
+                });
+            }
+        }
+        event!(Level::INFO, "openapi spec {}: {} tools in group {}", self.spec, tools.len(), group);
+        Ok(tools)
+    }
+}
+
+/// 由path和query参数以及json请求体生成工具的schema，返回(schema, query参数名称, 是否有请求体)
+fn build_schema(params: &[Value], request_body: Option<&Value>, root: &Value) -> (Value, Vec<String>, bool) {
+    let mut properties = Map::new();
+    let mut required: Vec<Value> = Vec::new();
+    let mut query = Vec::new();
+    for p in params {
+        let (Some(name), Some(location)) = (p.get("name").and_then(|v| v.as_str()), p.get("in").and_then(|v| v.as_str())) else {
+            continue
+        };
+        if location != "path" && location != "query" {
+            continue
+        }
+        let mut prop = p.get("schema").map(|s| resolve(s, root, 0)).unwrap_or(json!({"type": "string"}));
+        if let (Some(desc), Value::Object(m)) = (p.get("description").and_then(|v| v.as_str()), &mut prop) {
+            m.insert("description".to_string(), Value::String(desc.to_string()));
+        }
+        properties.insert(name.to_string(), prop);
+        if location == "path" || p.get("required").and_then(|v| v.as_bool()).unwrap_or(false) {
+            required.push(Value::String(name.to_string()));
+        }
+        if location == "query" {
+            query.push(name.to_string());
+        }
+    }
+    // 只支持json请求体
+    let body_schema = request_body.and_then(|b| b.get("content")).and_then(|c| c.as_object()).and_then(|c| c.iter().find(|(k, _)| k.contains("json"))).and_then(|(_, v)| v.get("schema"));
+    let has_body = body_schema.is_some();
+    if let Some(s) = body_schema {
+        let mut prop = resolve(s, root, 0);
+        if let (Some(desc), Value::Object(m)) = (request_body.and_then(|b| b.get("description")).and_then(|v| v.as_str()), &mut prop) {
+            m.entry("description").or_insert(Value::String(desc.to_string()));
+        }
+        properties.insert("body".to_string(), prop);
+        if request_body.and_then(|b| b.get("required")).and_then(|v| v.as_bool()).unwrap_or(false) {
+            required.push(Value::String("body".to_string()));
+        }
+    }
+    (json!({"type": "object", "properties": properties, "required": required}), query, has_body)
+}
+
+/// 展开`#/components/...`这类`$ref`，超过层数的引用（通常是循环引用）替换为不限制字段的object
+fn resolve(value: &Value, root: &Value, depth: usize) -> Value {
+    match value {
+        Value::Object(m) => match m.get("$ref").and_then(|r| r.as_str()) {
+            Some(r) if depth >= MAX_REF_DEPTH => json!({"type": "object", "description": format!("see {}", r)}),
+            Some(r) => match r.strip_prefix('#').and_then(|p| root.pointer(p)) {
+                Some(target) => resolve(target, root, depth + 1),
+                None => json!({"type": "object", "description": format!("unresolved {}", r)}),
+            },
+            None => Value::Object(m.iter().map(|(k, v)| (k.clone(), resolve(v, root, depth))).collect()),
+        },
+        Value::Array(a) => Value::Array(a.iter().map(|v| resolve(v, root, depth)).collect()),
+        v => v.clone(),
+    }
+}
+
+/// operationId转为工具名称：只保留`a-zA-Z0-9_-`，不含`__`，不超过26个字符，重名则加序号
+fn unique_name(raw: String, names: &mut HashSet<String>) -> String {
+    let mut name = String::new();
+    for c in raw.chars() {
+        let c = if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' };
+        if !(c == '_' && (name.is_empty() || name.ends_with('_'))) {
+            name.push(c);
+        }
+    }
+    let name: String = match name.trim_end_matches('_') {
+        "" => "operation".to_string(),
+        n => n.chars().take(MAX_NAME_LEN).collect(),
+    };
+    let mut unique = name.clone();
+    let mut n = 2;
+    while names.contains(&unique) {
+        let suffix = format!("_{}", n);
+        unique = format!("{}{}", name.chars().take(MAX_NAME_LEN - suffix.len()).collect::<String>().trim_end_matches('_'), suffix);
+        n += 1;
+    }
+    names.insert(unique.clone());
+    unique
+}
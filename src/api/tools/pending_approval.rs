@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+use chrono::Local;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tokio::{
+    sync::oneshot,
+    time::{timeout, Duration},
+};
+use tracing::{event, Level};
+use uuid::Uuid;
+
+use crate::parse_paras::PARAS;
+
+// 等待确认的工具调用：`ask_approval`登记后等待回答唤醒，不再轮询
+// 发起提问的页面、同一用户的其他页面（`/v1/approvals/page`）、命令行、channel bot都可以回答
+// 超过参数文件`approval_timeout`的秒数没人回答，则按其默认操作处理，避免agent一直卡住
+
+/// 一个等待确认的工具调用
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingApproval {
+    pub id:      String, // 确认id，回答时使用
+    pub uuid:    String, // 对话uuid
+    pub user:    String, // 提问的用户，页面为客户端ip，命令行为`cli`
+    pub tool:    String, // 工具名称
+    pub message: String, // 确认弹窗的内容
+    pub time:    String, // 开始等待的时间，例如：2024-10-21 16:35:47
+    pub timeout: u64,    // 超时秒数，0表示一直等待
+}
+
+/// 所有等待确认的工具调用，回答时通过oneshot发送(结果, 确认者)
+static PENDING: Lazy<Mutex<HashMap<String, (PendingApproval, oneshot::Sender<(String, String)>)>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 登记等待确认的工具调用，返回确认id和接收回答的receiver
+pub fn register(uuid: &str, user: &str, tool: &str, message: &str) -> (String, oneshot::Receiver<(String, String)>) {
+    let id = Uuid::new_v4().to_string()[0..8].to_string();
+    let (sender, receiver) = oneshot::channel();
+    let pending = PendingApproval {
+        id: id.clone(),
+        uuid: uuid.to_string(),
+        user: user.to_string(),
+        tool: tool.to_string(),
+        message: message.to_string(),
+        time: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        timeout: PARAS.approval_timeout.secs,
+    };
+    PENDING.lock().unwrap().insert(id.clone(), (pending, sender));
+    (id, receiver)
+}
+
+/// 移除等待确认的工具调用，例如发送确认请求失败
+pub fn remove(id: &str) {
+    PENDING.lock().unwrap().remove(id);
+}
+
+/// drop时移除等待确认的工具调用，提问的连接断开、任务被取消时不会留下没人等待的确认
+pub struct PendingGuard(pub String);
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        remove(&self.0);
+    }
+}
+
+/// 等待回答，超时则返回参数文件`approval_timeout`的默认操作，确认者为`timeout`，调用者持有`PendingGuard`负责移除
+pub async fn wait(id: &str, receiver: oneshot::Receiver<(String, String)>) -> (String, String) {
+    let secs = PARAS.approval_timeout.secs;
+    let answer = if secs == 0 {
+        receiver.await.ok()
+    } else {
+        timeout(Duration::from_secs(secs), receiver).await.ok().and_then(|r| r.ok())
+    };
+    match answer {
+        Some(a) => a,
+        None => {
+            let decision = match PARAS.approval_timeout.default_action.as_str() {
+                "allow" => "true",
+                "skip" => "skip",
+                _ => "false",
+            };
+            event!(Level::WARN, "approval {} not answered in {} seconds, default action: {}", id, secs, PARAS.approval_timeout.default_action);
+            (decision.to_string(), "timeout".to_string())
+        },
+    }
+}
+
+/// 回答指定的确认，确认不存在或已超时返回false
+/// decision: `true`（允许）、`false`（拒绝）、`skip`（跳过）、其他信息（跳过的新指示），approver: 确认者
+pub fn answer(id: &str, decision: &str, approver: &str) -> bool {
+    match PENDING.lock().unwrap().remove(id) {
+        Some((pending, sender)) => {
+            event!(Level::INFO, "{} approval {} of {} answered by {}: {}", pending.uuid, id, pending.tool, approver, decision);
+            sender.send((decision.to_string(), approver.to_string())).is_ok()
+        },
+        None => false,
+    }
+}
+
+/// 回答指定uuid最早的确认，用于发起提问的页面和命令行
+pub fn answer_uuid(uuid: &str, decision: &str, approver: &str) -> bool {
+    let id = PENDING.lock().unwrap().values().filter(|(p, _)| p.uuid == uuid).min_by(|a, b| a.0.time.cmp(&b.0.time)).map(|(p, _)| p.id.clone());
+    match id {
+        Some(id) => answer(&id, decision, approver),
+        None => false,
+    }
+}
+
+/// 获取指定确认
+pub fn get(id: &str) -> Option<PendingApproval> {
+    PENDING.lock().unwrap().get(id).map(|(p, _)| p.clone())
+}
+
+/// 获取等待确认的工具调用，最早的在前，user为None则返回所有用户的
+pub fn list(user: Option<&str>) -> Vec<PendingApproval> {
+    let mut pending: Vec<PendingApproval> = PENDING.lock().unwrap().values().map(|(p, _)| p.clone()).filter(|p| user.map_or(true, |u| same_user(&p.user, u))).collect();
+    pending.sort_by(|a, b| a.time.cmp(&b.time));
+    pending
+}
+
+/// 是否为同一用户，本机的页面、命令行（`cli`）、本机请求（`local`）视为同一用户
+pub fn same_user(a: &str, b: &str) -> bool {
+    a == b || (is_this_machine(a) && is_this_machine(b))
+}
+
+/// 用户是否在服务端所在的电脑上
+fn is_this_machine(user: &str) -> bool {
+    match user {
+        "cli" | "local" => true,
+        ip => ip.parse::<IpAddr>().map(|ip| ip.is_loopback()).unwrap_or(false),
+    }
+}
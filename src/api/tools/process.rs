@@ -0,0 +1,247 @@
+use std::io::{Read, Write};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tracing::{event, Level};
+
+use crate::{
+    parse_paras::PARAS,
+    error::MyError,
+    tools::sandbox,
+};
+
+// 运行命令的工具（run_command、run_script、外部工具）统一通过这里执行，需要时在沙箱中运行
+// 超时则结束整个进程组（包括子进程），stdout和stderr各自最多保留`max_output_kb`，超出则保留开头和结尾各一半
+// 在tokio的阻塞线程中等待，不会卡住其他对话
+// 命令结束后后台子进程仍占用stdout或stderr时，最多等到超时（没有超时则等待`OUTPUT_GRACE`），之后结束整个进程组
+
+/// 没有设置超时时，命令结束后等待后台子进程关闭输出的时间
+const OUTPUT_GRACE: Duration = Duration::from_secs(5);
+
+/// 结束进程组后等待读取线程结束的时间，进程用`setsid`脱离了进程组时不再等待
+const KILL_GRACE: Duration = Duration::from_secs(2);
+
+/// 命令执行结果，stdout和stderr已按限制截断
+pub struct CapturedOutput {
+    pub status: ExitStatus,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// 获取指定工具的超时秒数，优先级：工具自己的设置 > 参数文件`command_limit.timeouts` > `command_limit.timeout_secs`，0表示不限制
+pub fn tool_timeout(tool: &str, tool_timeout: Option<u64>) -> Option<Duration> {
+    let secs = tool_timeout
+        .or_else(|| PARAS.command_limit.timeouts.get(tool).copied())
+        .unwrap_or(PARAS.command_limit.timeout_secs);
+    if secs == 0 {
+        None
+    } else {
+        Some(Duration::from_secs(secs))
+    }
+}
+
+/// 执行命令并捕获输出，超时则结束整个进程组
+/// tool: 工具名称，用于获取超时设置，tool_timeout: 工具自己设置的超时秒数，label: 错误信息中显示的命令
+pub fn run_limited(cmd: Command, tool: &str, tool_timeout_secs: Option<u64>, label: &str) -> Result<CapturedOutput, MyError> {
+    run_limited_with_stdin(cmd, tool, tool_timeout_secs, label, None, None)
+}
+
+/// 执行命令并把input写入stdin，其他同`run_limited`，input为None时stdin为空
+/// raw_stdout_limit: 不为None时stdout不按`max_output_kb`截断，保留开头的最多limit+1个字节，调用者解析完整的stdout，超过limit则视为出错
+pub fn run_limited_with_stdin(cmd: Command, tool: &str, tool_timeout_secs: Option<u64>, label: &str, input: Option<Vec<u8>>, raw_stdout_limit: Option<usize>) -> Result<CapturedOutput, MyError> {
+    let timeout = tool_timeout(tool, tool_timeout_secs);
+    // 多线程runtime中移交给阻塞线程执行，其他任务转移到别的worker上继续运行
+    match Handle::try_current() {
+        Ok(h) if h.runtime_flavor() == RuntimeFlavor::MultiThread => tokio::task::block_in_place(|| wait_with_limits(cmd, tool, timeout, label, input, raw_stdout_limit)),
+        _ => wait_with_limits(cmd, tool, timeout, label, input, raw_stdout_limit),
+    }
+}
+
+/// 按参数文件`command_limit.max_output_kb`截断文本，超出则保留开头和结尾各一半，用于解析完整stdout之后的结果
+pub fn limit_output(text: &str) -> String {
+    let max_bytes = PARAS.command_limit.max_output_kb * 1024;
+    if max_bytes == 0 || text.len() <= max_bytes {
+        return text.to_string()
+    }
+    let half = max_bytes / 2;
+    let head_end = (0..=half).rev().find(|i| text.is_char_boundary(*i)).unwrap_or(0);
+    let tail_start = (text.len() - half..text.len()).find(|i| text.is_char_boundary(*i)).unwrap_or(text.len());
+    format!(
+        "{}\n... [truncated {} bytes, output limit {} KB] ...\n{}",
+        &text[..head_end],
+        tail_start - head_end,
+        max_bytes / 1024,
+        &text[tail_start..],
+    )
+}
+
+/// 启动命令，在单独的线程写入stdin、读取stdout和stderr（避免管道缓冲区满导致死锁），等待结束或超时
+fn wait_with_limits(mut cmd: Command, tool: &str, timeout: Option<Duration>, label: &str, input: Option<Vec<u8>>, raw_stdout_limit: Option<usize>) -> Result<CapturedOutput, MyError> {
+    cmd.stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() }).stdout(Stdio::piped()).stderr(Stdio::piped());
+    // 新建进程组，超时时可以结束命令启动的所有子进程
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    // 参数文件`sandbox`指定了该工具或当前用户，则在沙箱中运行，规则在spawn之后释放
+    let sandbox_guard = sandbox::prepare(&mut cmd, tool)?;
+    let mut child = cmd.spawn().map_err(|e| MyError::CommandError{info: format!("failed to execute `{}`{}: {:?}", label, if sandbox_guard.is_some() { " in sandbox" } else { "" }, e)})?;
+    drop(sandbox_guard);
+    // 写完后关闭stdin，命令不读取stdin时写入失败也不影响执行
+    if let (Some(mut stdin), Some(input)) = (child.stdin.take(), input) {
+        thread::spawn(move || {
+            let _ = stdin.write_all(&input);
+        });
+    }
+    let max_bytes = PARAS.command_limit.max_output_kb * 1024;
+    let stdout = child.stdout.take().map(|s| match raw_stdout_limit {
+        Some(limit) => capture_raw(s, limit),
+        None => capture(s, max_bytes),
+    });
+    let stderr = child.stderr.take().map(|s| capture(s, max_bytes));
+
+    // 等待结束，超时则结束进程组
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status
+        }
+        if let Some(t) = timeout {
+            if start.elapsed() >= t {
+                event!(Level::WARN, "`{}` timed out after {} seconds, kill process group {}", label, t.as_secs(), child.id());
+                kill_process_group(&mut child);
+                let _ = child.wait();
+                let deadline = Instant::now() + KILL_GRACE;
+                let partial = join_output(receive(&stdout, deadline).unwrap_or_default(), receive(&stderr, deadline).unwrap_or_default());
+                return Err(MyError::CommandError{info: format!("execute `{}` timed out after {} seconds and was killed{}", label, t.as_secs(), if partial.is_empty() { String::new() } else { format!(", partial output:\n{}", partial) })})
+            }
+        }
+        thread::sleep(Duration::from_millis(100));
+    };
+    // 命令已结束，后台子进程可能仍占用管道，最多等到超时
+    let deadline = match timeout {
+        Some(t) => (start + t).max(Instant::now()),
+        None => Instant::now() + OUTPUT_GRACE,
+    };
+    let (mut out, mut err) = (receive(&stdout, deadline), receive(&stderr, deadline));
+    if out.is_none() || err.is_none() {
+        event!(Level::WARN, "`{}` exited but its background processes still hold the output, kill process group {}", label, child.id());
+        kill_process_group(&mut child);
+        let deadline = Instant::now() + KILL_GRACE;
+        out = out.or_else(|| receive(&stdout, deadline));
+        err = err.or_else(|| receive(&stderr, deadline));
+        let note = "[background processes holding the output were killed]";
+        err = Some(match err {
+            Some(e) if !e.is_empty() => format!("{}\n{}", e, note),
+            _ => note.to_string(),
+        });
+    }
+    Ok(CapturedOutput {
+        status,
+        stdout: out.unwrap_or_default(),
+        stderr: err.unwrap_or_default(),
+    })
+}
+
+/// 等待读取线程的结果，直到deadline，没有管道返回空字符串，超时返回None
+fn receive(rx: &Option<Receiver<String>>, deadline: Instant) -> Option<String> {
+    match rx {
+        Some(rx) => match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(s) => Some(s),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => Some(String::new()), // 已取走结果或线程panic
+        },
+        None => Some(String::new()),
+    }
+}
+
+/// 超时后合并已捕获的stdout和stderr
+fn join_output(stdout: String, stderr: String) -> String {
+    match (stdout.trim().is_empty(), stderr.trim().is_empty()) {
+        (false, false) => format!("stdout:\n{}\nstderr:\n{}", stdout.trim(), stderr.trim()),
+        (false, true) => format!("stdout:\n{}", stdout.trim()),
+        (true, false) => format!("stderr:\n{}", stderr.trim()),
+        (true, true) => String::new(),
+    }
+}
+
+/// 结束整个进程组，不支持则只结束命令本身
+/// 命令本身已结束时，进程组中还有其他进程则进程组id不会被重用，仍可以结束这些进程
+fn kill_process_group(child: &mut Child) {
+    #[cfg(unix)]
+    let killed = unsafe { libc::killpg(child.id() as libc::pid_t, libc::SIGKILL) } == 0;
+    #[cfg(windows)]
+    let killed = Command::new("taskkill").args(["/T", "/F", "/PID", &child.id().to_string()]).status().map(|s| s.success()).unwrap_or(false);
+    #[cfg(not(any(unix, windows)))]
+    let killed = false;
+    if !killed {
+        let _ = child.kill();
+    }
+}
+
+/// 在单独的线程读取管道，一直读到结束（不读完子进程会阻塞），只保留开头的limit+1个字节，调用者据此判断是否超出limit
+fn capture_raw<R: Read + Send + 'static>(mut reader: R, limit: usize) -> Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut kept: Vec<u8> = Vec::new();
+        let mut buf = [0u8; 8192];
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            let keep = (limit + 1).saturating_sub(kept.len()).min(n);
+            kept.extend_from_slice(&buf[..keep]);
+        }
+        let _ = tx.send(String::from_utf8_lossy(&kept).to_string());
+    });
+    rx
+}
+
+/// 在单独的线程读取管道，一直读到结束（不读完子进程会阻塞），最多保留max_bytes，超出则保留开头和结尾各一半，0表示不限制
+/// 结果通过channel返回，可以限时等待，后台子进程一直不关闭管道时不会卡住调用者
+fn capture<R: Read + Send + 'static>(mut reader: R, max_bytes: usize) -> Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let half = max_bytes / 2;
+        let mut head: Vec<u8> = Vec::new();
+        let mut tail: Vec<u8> = Vec::new();
+        let mut total = 0;
+        let mut buf = [0u8; 8192];
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            total += n;
+            if max_bytes == 0 || head.len() + n <= half {
+                head.extend_from_slice(&buf[..n]);
+                continue
+            }
+            let to_head = half.saturating_sub(head.len());
+            head.extend_from_slice(&buf[..to_head]);
+            tail.extend_from_slice(&buf[to_head..n]);
+            if tail.len() > half {
+                tail.drain(..tail.len() - half);
+            }
+        }
+        let output = if max_bytes == 0 || total <= max_bytes {
+            head.extend_from_slice(&tail);
+            String::from_utf8_lossy(&head).to_string()
+        } else {
+            format!(
+                "{}\n... [truncated {} bytes, output limit {} KB] ...\n{}",
+                String::from_utf8_lossy(&head),
+                total - head.len() - tail.len(),
+                max_bytes / 1024,
+                String::from_utf8_lossy(&tail),
+            )
+        };
+        let _ = tx.send(output);
+    });
+    rx
+}
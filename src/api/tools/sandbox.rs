@@ -0,0 +1,293 @@
+use std::net::IpAddr;
+use std::process::Command;
+
+use crate::{
+    parse_paras::PARAS,
+    error::MyError,
+    api::handlers::chat::is_local_request,
+};
+
+// 在Linux沙箱中运行命令的工具（run_command、run_script、外部工具），不需要额外的服务：
+// 1. Landlock：根目录只读，只能写入allowed_path（以及/dev/null）
+// 2. 新的user和network命名空间：默认断网，只有回环网卡，uid和gid映射为当前用户
+// 3. rlimit：限制CPU时间、数据段内存（堆和匿名映射，不含只预留的虚拟地址空间），进程数默认不限制
+// 参数文件`sandbox`的`tools`指定总在沙箱中运行的工具，`users`指定哪些用户调用的命令都在沙箱中运行
+
+tokio::task_local! {
+    /// 当前调用工具的用户（与审计日志的user相同），决定是否在沙箱中运行
+    pub static CALLER: String;
+}
+
+/// 用户是否来自本机或局域网，`cli`和`local`为本机，客户端ip根据地址判断
+fn is_local_user(user: &str) -> bool {
+    match user {
+        "cli" | "local" => true,
+        "remote" => false,
+        ip => ip.parse::<IpAddr>().map(|ip| is_local_request(&ip)).unwrap_or(false),
+    }
+}
+
+/// 指定工具在当前用户下是否在沙箱中运行
+pub fn is_sandboxed(tool: &str) -> bool {
+    let sandbox = &PARAS.sandbox;
+    if sandbox.tools.iter().any(|t| t == tool) {
+        return true
+    }
+    let user = CALLER.try_with(|u| u.clone()).unwrap_or("remote".to_string()); // 不知道用户时按非本机处理
+    sandbox.users.iter().any(|u| match u.as_str() {
+        "*" => true,
+        "remote" => !is_local_user(&user),
+        "local" => is_local_user(&user),
+        u => u == user,
+    })
+}
+
+/// 确认弹窗中说明该命令是否在沙箱中运行，没有设置沙箱则为空
+pub fn approval_note(tool: &str, is_en: bool) -> String {
+    let sandbox = &PARAS.sandbox;
+    if sandbox.tools.is_empty() && sandbox.users.is_empty() {
+        String::new()
+    } else if is_sandboxed(tool) {
+        match (is_en, sandbox.network) {
+            (true, false) => "\n🔒 runs in sandbox: read-only except allowed paths, no network".to_string(),
+            (true, true) => "\n🔒 runs in sandbox: read-only except allowed paths, network allowed".to_string(),
+            (false, false) => "\n🔒 在沙箱中运行：除允许的路径外只读，不能联网".to_string(),
+            (false, true) => "\n🔒 在沙箱中运行：除允许的路径外只读，可以联网".to_string(),
+        }
+    } else if is_en {
+        "\n⚠️ runs without sandbox".to_string()
+    } else {
+        "\n⚠️ 不在沙箱中运行".to_string()
+    }
+}
+
+/// 沙箱使用的Landlock规则，需要保留到命令启动之后
+pub struct SandboxGuard {
+    #[cfg(target_os = "linux")]
+    _ruleset: std::os::fd::OwnedFd,
+}
+
+/// 需要在沙箱中运行时，设置命令在子进程exec之前进入沙箱，返回的guard在spawn之后才能释放
+/// 沙箱无法创建时返回错误，不会退回到直接运行
+#[cfg(target_os = "linux")]
+pub fn prepare(cmd: &mut Command, tool: &str) -> Result<Option<SandboxGuard>, MyError> {
+    if !is_sandboxed(tool) {
+        return Ok(None)
+    }
+    let allowed: Vec<&std::path::Path> = PARAS.allowed_path.iter().map(|(p, _)| p.as_path()).collect();
+    apply(cmd, &PARAS.sandbox, &allowed).map(Some)
+}
+
+/// 设置命令在子进程exec之前进入沙箱，只能写入allowed中的路径
+/// 内存限制使用RLIMIT_DATA，RLIMIT_AS会让预留大量虚拟地址空间的程序（例如JVM、Go、ASan）无法启动
+#[cfg(target_os = "linux")]
+fn apply(cmd: &mut Command, config: &crate::parse_paras::SandboxConfig, allowed: &[&std::path::Path]) -> Result<SandboxGuard, MyError> {
+    use std::io;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::process::CommandExt;
+
+    let ruleset = landlock::ruleset(allowed)?;
+    let ruleset_fd = ruleset.as_raw_fd();
+    let network = config.network;
+    let limits = [
+        (libc::RLIMIT_CPU, config.cpu_secs),
+        (libc::RLIMIT_DATA, config.memory_mb * 1024 * 1024),
+        (libc::RLIMIT_NPROC, config.max_procs),
+    ];
+    // 在父进程准备好uid和gid映射，子进程fork之后不再分配内存
+    let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+    let uid_map = format!("{} {} 1", uid, uid);
+    let gid_map = format!("{} {} 1", gid, gid);
+    let pre_exec = move || -> io::Result<()> {
+        unsafe {
+            if !network {
+                if libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNET) != 0 {
+                    return Err(io::Error::last_os_error())
+                }
+                write_proc(c"/proc/self/uid_map", uid_map.as_bytes())?;
+                write_proc(c"/proc/self/setgroups", b"deny")?;
+                write_proc(c"/proc/self/gid_map", gid_map.as_bytes())?;
+            }
+            for (resource, limit) in limits {
+                if limit > 0 {
+                    let rlim = libc::rlimit{rlim_cur: limit as libc::rlim_t, rlim_max: limit as libc::rlim_t};
+                    if libc::setrlimit(resource, &rlim) != 0 {
+                        return Err(io::Error::last_os_error())
+                    }
+                }
+            }
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1 as libc::c_ulong, 0 as libc::c_ulong, 0 as libc::c_ulong, 0 as libc::c_ulong) != 0 {
+                return Err(io::Error::last_os_error())
+            }
+            if libc::syscall(libc::SYS_landlock_restrict_self, ruleset_fd, 0) != 0 {
+                return Err(io::Error::last_os_error())
+            }
+        }
+        Ok(())
+    };
+    unsafe { cmd.pre_exec(pre_exec); }
+    Ok(SandboxGuard{_ruleset: ruleset})
+}
+
+/// 非Linux系统不支持沙箱，需要在沙箱中运行时返回错误
+#[cfg(not(target_os = "linux"))]
+pub fn prepare(_cmd: &mut Command, tool: &str) -> Result<Option<SandboxGuard>, MyError> {
+    if is_sandboxed(tool) {
+        Err(MyError::CommandError{info: format!("{} should run in sandbox, but sandbox is only supported on Linux", tool)})
+    } else {
+        Ok(None)
+    }
+}
+
+/// 子进程中写入/proc文件，只使用系统调用
+#[cfg(target_os = "linux")]
+unsafe fn write_proc(file: &std::ffi::CStr, content: &[u8]) -> std::io::Result<()> {
+    unsafe {
+        let fd = libc::open(file.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error())
+        }
+        let n = libc::write(fd, content.as_ptr() as *const libc::c_void, content.len());
+        libc::close(fd);
+        if n != content.len() as isize {
+            return Err(std::io::Error::last_os_error())
+        }
+    }
+    Ok(())
+}
+
+/// Landlock规则：根目录只读，allowed_path可读写
+/// https://docs.kernel.org/userspace-api/landlock.html
+#[cfg(target_os = "linux")]
+mod landlock {
+    use std::fs::OpenOptions;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::path::Path;
+
+    use crate::error::MyError;
+
+    const CREATE_RULESET_VERSION: u32 = 1;
+    const RULE_PATH_BENEATH: libc::c_int = 1;
+
+    const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+    const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+    const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+    const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+    const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd:      i32,
+    }
+
+    /// 创建Landlock规则，allowed为可读写的路径，内核不支持则返回错误
+    pub fn ruleset(allowed: &[&Path]) -> Result<OwnedFd, MyError> {
+        let abi = unsafe { libc::syscall(libc::SYS_landlock_create_ruleset, std::ptr::null::<RulesetAttr>(), 0_usize, CREATE_RULESET_VERSION) };
+        if abi < 1 {
+            return Err(MyError::CommandError{info: format!("sandbox needs Landlock (Linux 5.13+ with landlock enabled): {}", std::io::Error::last_os_error())})
+        }
+        // ABI 1支持前13种权限，ABI 2增加REFER，ABI 3增加TRUNCATE
+        let handled = match abi {
+            1 => (1 << 13) - 1,
+            2 => (1 << 14) - 1,
+            _ => (1 << 15) - 1,
+        };
+        let file_access = (ACCESS_FS_EXECUTE | ACCESS_FS_WRITE_FILE | ACCESS_FS_READ_FILE | ACCESS_FS_TRUNCATE) & handled;
+        let attr = RulesetAttr{handled_access_fs: handled};
+        let fd = unsafe { libc::syscall(libc::SYS_landlock_create_ruleset, &attr as *const RulesetAttr, size_of::<RulesetAttr>(), 0_u32) };
+        if fd < 0 {
+            return Err(MyError::CommandError{info: format!("create landlock ruleset error: {}", std::io::Error::last_os_error())})
+        }
+        let ruleset = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+        add_rule(&ruleset, Path::new("/"), ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR)?;
+        for p in allowed {
+            if p.is_dir() {
+                add_rule(&ruleset, p, handled)?;
+            } else if p.exists() {
+                add_rule(&ruleset, p, file_access)?;
+            }
+        }
+        add_rule(&ruleset, Path::new("/dev/null"), file_access)?;
+        Ok(ruleset)
+    }
+
+    /// 允许访问指定路径及其子路径
+    fn add_rule(ruleset: &OwnedFd, path: &Path, access: u64) -> Result<(), MyError> {
+        let file = OpenOptions::new().read(true).custom_flags(libc::O_PATH).open(path).map_err(|e| MyError::CommandError{info: format!("open {} for landlock error: {}", path.display(), e)})?;
+        let attr = PathBeneathAttr{allowed_access: access, parent_fd: file.as_raw_fd()};
+        if unsafe { libc::syscall(libc::SYS_landlock_add_rule, ruleset.as_raw_fd(), RULE_PATH_BENEATH, &attr as *const PathBeneathAttr, 0_u32) } != 0 {
+            return Err(MyError::CommandError{info: format!("add landlock rule for {} error: {}", path.display(), std::io::Error::last_os_error())})
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all};
+    use crate::parse_paras::SandboxConfig;
+    use std::path::Path;
+    use std::process::Output;
+
+    /// 测试用的沙箱设置，不限制资源
+    fn config(network: bool) -> SandboxConfig {
+        SandboxConfig{tools: vec![], users: vec![], network, cpu_secs: 0, memory_mb: 0, max_procs: 0}
+    }
+
+    /// 在沙箱中运行sh脚本，内核不支持Landlock或不允许创建user命名空间时返回None，跳过测试
+    fn run_sandboxed(script: &str, network: bool, allowed: &[&Path]) -> Option<Output> {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(script);
+        let _guard = match apply(&mut cmd, &config(network), allowed) {
+            Ok(g) => g,
+            Err(e) => {
+                eprintln!("skip sandbox test: {}", e);
+                return None
+            },
+        };
+        match cmd.output() {
+            Ok(o) => Some(o),
+            Err(e) => {
+                eprintln!("skip sandbox test: {}", e);
+                None
+            },
+        }
+    }
+
+    #[test]
+    fn test_write_outside_allowed_path() {
+        let root = std::env::temp_dir().join(format!("chatsong_sandbox_test_{}", std::process::id()));
+        let allowed = root.join("allowed");
+        let outside = root.join("outside");
+        create_dir_all(&allowed).unwrap();
+        create_dir_all(&outside).unwrap();
+        let script = format!("echo a > {}/in.txt; echo b > {}/out.txt", allowed.display(), outside.display());
+        let output = run_sandboxed(&script, true, &[allowed.as_path()]);
+        let (written, escaped) = (allowed.join("in.txt").exists(), outside.join("out.txt").exists());
+        remove_dir_all(&root).unwrap();
+        if let Some(output) = output {
+            assert!(written);
+            assert!(!escaped);
+            assert!(!output.status.success());
+        }
+    }
+
+    #[test]
+    fn test_network_denied() {
+        // /proc/net/dev列出当前进程所在network命名空间的网卡，断网时只有回环网卡
+        if let Some(output) = run_sandboxed("cat /proc/net/dev", false, &[]) {
+            assert!(output.status.success());
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let interfaces: Vec<&str> = stdout.lines().skip(2).filter_map(|l| l.split_once(':')).map(|(n, _)| n.trim()).collect();
+            assert_eq!(interfaces, vec!["lo"]);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use axum::routing::{get, post};
+use axum::routing::{get, post, put};
 use axum::Router;
 use axum::extract::DefaultBodyLimit;
 /*
@@ -8,6 +8,8 @@ use tower_http::services::{
 };
 */
 
+use crate::parse_paras::PARAS;
+
 /// handlers: 每个路由的函数
 use crate::api::handlers::{
     hello::hello,
@@ -25,13 +27,19 @@ use crate::api::handlers::{
     save::{save_log, save_speech, save},
     upload::upload,
     usage::usage,
+    reload::reload,
+    prompts::{get_prompts, post_prompt, put_prompt, delete_prompt, export_prompts, import_prompts},
+    knowledge::{get_bases, post_base, reindex_base, upload_to_base, delete_base, attach_bases},
     fallback::fallback,
     delete_message::del_msg,
+    pin_message::pin_msg,
     microphone::microphone,
     incognito::incognito,
-    approval::approval,
+    approval::{approval, approvals, approvals_page},
     new_instruction::instruction,
     memory::memory,
+    memory_notes::{list_notes, update_note, delete_note, merge_notes, move_note, injections, namespaces, consolidate_notes, changelog},
+    audit::{audit, audit_page},
 };
 
 /// 创建version1的路由
@@ -59,14 +67,37 @@ pub fn configure() -> Router {
         .route("/save-speech", get(save_speech)) // GET /v1/save-speech，下载生成的音频文件
         .route("/save/:id", get(save)) // GET /v1/save/:id，下载生成图片或音频文件
         .route("/delmsg/:id", get(del_msg)) // GET /v1/delmsg/:id，url指定要删除的信息id，从服务端删除
+        .route("/pinmsg/:id", post(pin_msg)) // POST /v1/pinmsg/:id，url指定要固定或取消固定的信息id，固定的信息始终包含在上下文中，会修改服务端状态，因此使用post
         .route("/microphone", get(microphone)) // GET /v1/microphone，关闭语音模式
         .route("/incognito", get(incognito)) // GET /v1/incognito，更新is_incognito
-        .route("/approval", get(approval)) // GET /v1/approval，更新approval
+        .route("/approval", post(approval)) // POST /v1/approval，回答等待确认的工具调用
+        .route("/approvals", get(approvals)) // GET /v1/approvals，当前用户等待确认的工具调用
+        .route("/approvals/page", get(approvals_page)) // GET /v1/approvals/page，查看并回答等待确认的工具调用的页面
         .route("/instruction", get(instruction)) // GET /v1/instruction，插入新指令
         .route("/memory", get(memory)) // GET /v1/memory，记忆
+        .route("/memory/notes", get(list_notes)) // GET /v1/memory/notes，获取、搜索记忆
+        .route("/memory/notes/merge", post(merge_notes)) // POST /v1/memory/notes/merge，合并重复的记忆
+        .route("/memory/notes/:id", put(update_note).delete(delete_note)) // PUT,DELETE /v1/memory/notes/:id，修改、固定、删除记忆
+        .route("/memory/notes/:id/move", post(move_note)) // POST /v1/memory/notes/:id/move，在本地记忆和当前对话的记忆之间移动
+        .route("/memory/injections", get(injections)) // GET /v1/memory/injections，当前对话每次回答注入的记忆
+        .route("/memory/namespaces", get(namespaces)) // GET /v1/memory/namespaces，设置当前对话使用的记忆命名空间
+        .route("/memory/consolidate", post(consolidate_notes)) // POST /v1/memory/consolidate，用LLM整理记忆
+        .route("/memory/changelog", get(changelog)) // GET /v1/memory/changelog，整理记忆的变更记录
+        .route("/audit", get(audit)) // GET /v1/audit，筛选工具调用审计日志
+        .route("/audit/page", get(audit_page)) // GET /v1/audit/page，查看工具调用审计日志的页面
         .route("/goal", get(goal)) // GET /v1/goal，goal模式
         .route("/upload", post(upload)) // POST /v1/upload，上传文件
         .route("/usage", get(usage)) // GET /v1/usage，查看使用说明
-        .layer(DefaultBodyLimit::max(1024*1024*100)) // 设置上传文件大小限制为1024*1024*100=104857600=100M
+        .route("/reload", post(reload)) // POST /v1/reload，重新读取参数文件
+        .route("/prompts", get(get_prompts).post(post_prompt)) // GET,POST /v1/prompts，获取所有prompt、添加prompt到prompt库
+        .route("/prompts/export", get(export_prompts)) // GET /v1/prompts/export，导出prompt包
+        .route("/prompts/import", post(import_prompts)) // POST /v1/prompts/import，导入prompt包
+        .route("/prompts/:id", put(put_prompt).delete(delete_prompt)) // PUT,DELETE /v1/prompts/:id，修改、删除prompt库中的prompt
+        .route("/knowledge", get(get_bases).post(post_base)) // GET,POST /v1/knowledge，获取所有知识库、创建知识库或添加目录和文件
+        .route("/knowledge/attach", get(attach_bases)) // GET /v1/knowledge/attach，设置当前对话使用的知识库
+        .route("/knowledge/:name", axum::routing::delete(delete_base)) // DELETE /v1/knowledge/:name，删除知识库
+        .route("/knowledge/:name/reindex", post(reindex_base)) // POST /v1/knowledge/:name/reindex，重新索引知识库
+        .route("/knowledge/:name/upload", post(upload_to_base)) // POST /v1/knowledge/:name/upload，上传文件到知识库
+        .layer(DefaultBodyLimit::max((PARAS.upload_limit.uuid_mb * 1024 * 1024) as usize)) // 一次上传不超过每个对话的上传限制，单个文件和对话总大小在upload中检查
         .fallback(fallback) // 没有匹配到任何路由，执行fallback
 }
@@ -9,7 +9,7 @@ use tokio::sync::mpsc;
 use tokio::task;
 use tracing::{event, Level};
 use tracing_subscriber::{
-    filter::EnvFilter,
+    filter::{EnvFilter, LevelFilter},
     self,
     fmt::time::LocalTime,
 };
@@ -25,8 +25,10 @@ use chatsong::{
     api::{
         configure,
         tools::built_in_tools::schedule::start_scheduler,
+        memory::auto::start_memory_jobs,
     },
     ctrlc::wait_for_signal,
+    cli::run_command,
 };
 
 #[cfg(feature = "code-completion")]
@@ -52,11 +54,20 @@ async fn main() {
 
     // Start tracing
     //tracing_subscriber::registry().with(tracing_subscriber::fmt::layer()).init();
-    tracing_subscriber::fmt() // 限制输入级别，如果是TRACE则全部输出，会有很多信息，尤其联网搜索时特别多，这里限制为INFO，即INFO、WARN、ERROR的信息才输出，https://github.com/tokio-rs/tracing/blob/master/examples/examples/hyper-echo.rs
+    let subscriber = tracing_subscriber::fmt() // 限制输入级别，如果是TRACE则全部输出，会有很多信息，尤其联网搜索时特别多，这里限制为INFO，即INFO、WARN、ERROR的信息才输出，https://github.com/tokio-rs/tracing/blob/master/examples/examples/hyper-echo.rs
         .with_max_level(Level::INFO)
         .with_timer(LocalTime::rfc_3339()) // 使用本地时间，格式为 RFC 3339，需要在Cargo.toml的features中添加"local-time"
         .with_target(false)
-        .init();
+        .with_filter_reloading(); // 子命令需要修改输出级别
+    let reload_handle = subscriber.reload_handle();
+    subscriber.init();
+
+    // 子命令，例如`chatsong chat`在终端对话，不开启服务
+    if let Some(command) = &PARAS.command {
+        // 只输出警告和错误，避免日志打断对话内容
+        let _ = reload_handle.reload(LevelFilter::WARN);
+        exit(run_command(command).await);
+    }
 
     // start channel bot
     let mut handles = vec![];
@@ -109,6 +120,9 @@ async fn main() {
     // 初始化调度管道，65秒扫描一次任务，定时任务间隔最好不要少于1分钟
     start_scheduler(65); // 内部有个 loop 循环，每65秒检查一次定时任务，定时任务下次运行时间<当前扫描时间则执行，并监听增加、删除、查看任务
 
+    // 自动提取和整理记忆，参数文件中`memory`都为0则不开启
+    start_memory_jobs();
+
     // 测试不同Level（TRACE、DEBUG、INFO、WARN、ERROR），可以比较，TRACE最高，ERROR最低，越高则有越多的verbose
     //event!(Level::TRACE, "Running on http://{}:{}", PARAS.addr_str, PARAS.port); // 紫色，very low priority, often extremely verbose, information. The most fine-grained information, useful for detailed debugging.
     //event!(Level::DEBUG, "Running on http://{}:{}", PARAS.addr_str, PARAS.port); // 蓝色，lower priority information. Useful during development for debugging problems.